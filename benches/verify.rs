@@ -0,0 +1,118 @@
+//! Run with `cargo bench`. Keys and tokens are made up front, so only
+//! verification and parsing are measured.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use firebase_admin_auth_rs::jwk_auth::JwkParams;
+use firebase_admin_auth_rs::{Claims, Jwk, JwkAuth};
+use jsonwebtoken::{encode, EncodingKey, Header};
+use serde::Deserialize;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const PROJECT_ID: &str = "pj";
+const KID: &str = "kid-signing";
+const PRIVATE_KEY_PEM: &str = include_str!("../src/testdata/rsa_private.pem");
+const MODULUS: &str = "vpwg-OcFj5thOiyXIWJJ4-dIQMlRryHHOw_A6fciVsIlz-dl9erteeEWRjW9A3FgLDp4hfLtqFGLfpBqQXwO85u9nmU5FTAeAVxGUunxaH5bntyRDkhwrDcorZxekhLnts0yQVpVkeD3EinapBo8wYt78psJkDmm4LzuBq4VC306254CVLJ22JwpmCP3oslnAy5CyJ3ufqV4ykCnl6vDjMge1A9HSydW7tNDgGK2snVZa8UkwQ9EMMjWARi7kO2GumIuhh0G-xUi18lQclmvR67JZtISUFKX42paS4moBzvHR9FnYS8IyG5SaIWAKI_0YG-8vzJG0juFxQ_tcbR64w";
+const THREADS: usize = 8;
+const TOKENS_PER_THREAD: usize = 100;
+
+fn signing_key(kid: &str) -> Jwk {
+    Jwk {
+        alg: Some("RS256".to_string()),
+        kid: kid.to_string(),
+        r#use: Some("sig".to_string()),
+        params: JwkParams::Rsa {
+            n: MODULUS.to_string(),
+            e: "AQAB".to_string(),
+        },
+    }
+}
+
+fn token() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    let claims = Claims {
+        aud: PROJECT_ID.into(),
+        iss: format!("https://securetoken.google.com/{}", PROJECT_ID),
+        sub: "uid".to_string(),
+        iat: now,
+        exp: now + 3600,
+        ..Claims::default()
+    };
+    let mut header = Header::new(jsonwebtoken::Algorithm::RS256);
+    header.kid = Some(KID.to_string());
+    let key = EncodingKey::from_rsa_pem(PRIVATE_KEY_PEM.as_bytes()).unwrap();
+    encode(&header, &claims, &key).unwrap()
+}
+
+fn jwk_auth() -> JwkAuth {
+    JwkAuth::with_keys(vec![signing_key(KID)], PROJECT_ID.to_string()).unwrap()
+}
+
+fn bench_verify(c: &mut Criterion) {
+    let jwk_auth = jwk_auth();
+    let token = token();
+    c.bench_function("verify", |b| b.iter(|| jwk_auth.verify(&token).unwrap()));
+}
+
+fn bench_verify_concurrent(c: &mut Criterion) {
+    let jwk_auth = jwk_auth();
+    let token = token();
+    let mut group = c.benchmark_group("verify_concurrent");
+    group.throughput(Throughput::Elements((THREADS * TOKENS_PER_THREAD) as u64));
+    group.bench_function(format!("{}_threads", THREADS), |b| {
+        b.iter(|| {
+            thread::scope(|scope| {
+                for _ in 0..THREADS {
+                    scope.spawn(|| {
+                        for _ in 0..TOKENS_PER_THREAD {
+                            jwk_auth.verify(&token).unwrap();
+                        }
+                    });
+                }
+            })
+        })
+    });
+    group.finish();
+}
+
+fn bench_verify_cached(c: &mut Criterion) {
+    let jwk_auth = JwkAuth::builder()
+        .project_id(PROJECT_ID.to_string())
+        .token_cache(1024)
+        .build_with_keys(vec![signing_key(KID)])
+        .unwrap();
+    let token = token();
+    c.bench_function("verify_cached", |b| {
+        b.iter(|| jwk_auth.verify(&token).unwrap())
+    });
+}
+
+#[derive(Deserialize)]
+struct KeyResponse {
+    #[allow(dead_code)]
+    keys: Vec<Jwk>,
+}
+
+fn bench_parse_jwks(c: &mut Criterion) {
+    let keys: Vec<Jwk> = (0..4).map(|i| signing_key(&format!("kid-{}", i))).collect();
+    let body = serde_json::to_vec(&serde_json::json!({ "keys": keys })).unwrap();
+    c.bench_function("parse_jwks", |b| {
+        b.iter_batched(
+            || body.clone(),
+            |body| serde_json::from_slice::<KeyResponse>(&body).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_verify,
+    bench_verify_concurrent,
+    bench_verify_cached,
+    bench_parse_jwks
+);
+criterion_main!(benches);
@@ -1,64 +1,16 @@
 extern crate firebase_admin_auth_rs;
 use actix_web::{get, web, App, HttpServer, Responder};
-use firebase_admin_auth_rs::jwk_auth::JwkAuth;
+use firebase_admin_auth_rs::jwk_auth::{FirebaseUser, JwkAuth};
 
-use actix_web::error::ErrorUnauthorized;
-use actix_web::{dev::Payload, web::Data, Error, FromRequest, HttpRequest, HttpResponse, Result};
-use futures_util::future::{err, ok, Ready};
-use serde::{Deserialize, Serialize};
+use actix_web::{HttpRequest, HttpResponse};
 
 use actix_files::NamedFile;
 use dotenv::dotenv;
-use env_logger;
 use std::fs;
 
-#[derive(Deserialize, Serialize, Debug)]
-pub struct RequestUser {
-    pub uid: String,
-}
-
-#[cfg(not(test))]
-impl FromRequest for RequestUser {
-    type Error = Error;
-    type Future = Ready<Result<Self, Self::Error>>;
-
-    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
-        let token = match req.headers().get("Authorization") {
-            Some(auth_header) => match auth_header.to_str() {
-                Ok(v) => get_token_from_header(v),
-                _ => return err(ErrorUnauthorized("Could not parse auth header")),
-            },
-            _ => return err(ErrorUnauthorized("Could not parse auth header")),
-        };
-        if token.is_none() {
-            return err(ErrorUnauthorized("Could not parse auth header"));
-        }
-        let _token = token.unwrap();
-
-        // let jwk_auth = req.app_data::<Data<JwkAuth>>().expect("Could not get JwkAuth");
-        let jwk_auth = req.app_data::<Data<JwkAuth>>().unwrap();
-        let token_data = jwk_auth.verify(&_token);
-        match token_data {
-            Some(data) => ok(RequestUser {
-                uid: data.claims.sub,
-            }),
-            _ => err(ErrorUnauthorized("verification failed")),
-        }
-    }
-}
-
-fn get_token_from_header(header: &str) -> Option<String> {
-    let prefix_len = "Bearer ".len();
-
-    match header.len() {
-        l if l < prefix_len => None,
-        _ => Some(header[prefix_len..].to_string()),
-    }
-}
-
 #[get("/uid")]
-async fn uid(user: RequestUser) -> impl Responder {
-    user.uid.to_string()
+async fn uid(user: FirebaseUser) -> impl Responder {
+    user.uid
 }
 
 #[get("/{file}")]
@@ -73,7 +25,7 @@ async fn index(req: HttpRequest, path: web::Path<String>) -> impl Responder {
 }
 
 fn expect_env_var(name: &str, _default: &str) -> String {
-    return std::env::var(name).unwrap_or(_default.to_string());
+    std::env::var(name).unwrap_or(_default.to_string())
 }
 
 #[actix_web::main]
@@ -84,7 +36,11 @@ async fn main() -> std::io::Result<()> {
         .target(env_logger::Target::Stdout)
         .init();
 
-    let auth = web::Data::new(JwkAuth::new(expect_env_var("FIREBASE_PROJECT_ID", "")).await);
+    let auth = web::Data::new(
+        JwkAuth::new(expect_env_var("FIREBASE_PROJECT_ID", ""))
+            .await
+            .expect("Unable to fetch jwk keys"),
+    );
     HttpServer::new(move || {
         App::new()
             .app_data(auth.clone())
@@ -0,0 +1,228 @@
+use crate::identity_toolkit::{IdentityToolkit, IdentityToolkitError};
+use serde::{Deserialize, Serialize};
+
+/// Where the user is sent after completing an email action, and how mobile
+/// apps should handle the link.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ActionCodeSettings {
+    pub url: String,
+    pub handle_code_in_app: bool,
+    pub dynamic_link_domain: Option<String>,
+    pub ios_bundle_id: Option<String>,
+    pub android_package_name: Option<String>,
+    pub android_minimum_version: Option<String>,
+    pub android_install_app: bool,
+}
+
+impl ActionCodeSettings {
+    pub fn new(url: String) -> ActionCodeSettings {
+        ActionCodeSettings {
+            url,
+            ..ActionCodeSettings::default()
+        }
+    }
+    pub fn handle_code_in_app(mut self, handle_code_in_app: bool) -> Self {
+        self.handle_code_in_app = handle_code_in_app;
+        self
+    }
+    pub fn dynamic_link_domain(mut self, dynamic_link_domain: String) -> Self {
+        self.dynamic_link_domain = Some(dynamic_link_domain);
+        self
+    }
+    pub fn ios_bundle_id(mut self, ios_bundle_id: String) -> Self {
+        self.ios_bundle_id = Some(ios_bundle_id);
+        self
+    }
+    pub fn android_package_name(
+        mut self,
+        android_package_name: String,
+        minimum_version: Option<String>,
+        install_app: bool,
+    ) -> Self {
+        self.android_package_name = Some(android_package_name);
+        self.android_minimum_version = minimum_version;
+        self.android_install_app = install_app;
+        self
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+enum RequestType {
+    PasswordReset,
+    VerifyEmail,
+    EmailSignin,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SendOobCodeRequest<'a> {
+    request_type: RequestType,
+    email: &'a str,
+    return_oob_link: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    continue_url: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    can_handle_code_in_app: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dynamic_link_domain: Option<&'a str>,
+    #[serde(rename = "iOSBundleId", skip_serializing_if = "Option::is_none")]
+    ios_bundle_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    android_package_name: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    android_minimum_version: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    android_install_app: Option<bool>,
+}
+
+impl<'a> SendOobCodeRequest<'a> {
+    fn new(
+        request_type: RequestType,
+        email: &'a str,
+        settings: Option<&'a ActionCodeSettings>,
+    ) -> SendOobCodeRequest<'a> {
+        SendOobCodeRequest {
+            request_type,
+            email,
+            return_oob_link: true,
+            continue_url: settings.map(|s| s.url.as_str()),
+            can_handle_code_in_app: settings.map(|s| s.handle_code_in_app),
+            dynamic_link_domain: settings.and_then(|s| s.dynamic_link_domain.as_deref()),
+            ios_bundle_id: settings.and_then(|s| s.ios_bundle_id.as_deref()),
+            android_package_name: settings.and_then(|s| s.android_package_name.as_deref()),
+            android_minimum_version: settings.and_then(|s| s.android_minimum_version.as_deref()),
+            android_install_app: settings
+                .filter(|s| s.android_package_name.is_some())
+                .map(|s| s.android_install_app),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SendOobCodeResponse {
+    oob_link: String,
+}
+
+impl IdentityToolkit {
+    pub async fn generate_password_reset_link(
+        &self,
+        email: &str,
+        settings: Option<&ActionCodeSettings>,
+    ) -> Result<String, IdentityToolkitError> {
+        self.generate_email_action_link(RequestType::PasswordReset, email, settings)
+            .await
+    }
+    pub async fn generate_email_verification_link(
+        &self,
+        email: &str,
+        settings: Option<&ActionCodeSettings>,
+    ) -> Result<String, IdentityToolkitError> {
+        self.generate_email_action_link(RequestType::VerifyEmail, email, settings)
+            .await
+    }
+    /// Sign-in links must be opened by the app, so `settings` needs
+    /// `handle_code_in_app` set.
+    pub async fn generate_sign_in_with_email_link(
+        &self,
+        email: &str,
+        settings: &ActionCodeSettings,
+    ) -> Result<String, IdentityToolkitError> {
+        if !settings.handle_code_in_app {
+            return Err(IdentityToolkitError::InvalidArgument(
+                "sign-in links require handle_code_in_app".to_string(),
+            ));
+        }
+        self.generate_email_action_link(RequestType::EmailSignin, email, Some(settings))
+            .await
+    }
+    async fn generate_email_action_link(
+        &self,
+        request_type: RequestType,
+        email: &str,
+        settings: Option<&ActionCodeSettings>,
+    ) -> Result<String, IdentityToolkitError> {
+        if settings.is_some_and(|s| s.url.is_empty()) {
+            return Err(IdentityToolkitError::InvalidArgument(
+                "action code settings require a continue url".to_string(),
+            ));
+        }
+        let request = SendOobCodeRequest::new(request_type, email, settings);
+        let response: SendOobCodeResponse = self.post("/accounts:sendOobCode", &request).await?;
+        Ok(response.oob_link)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity_toolkit::tests::get_test_client;
+    use serde_json::json;
+    use wiremock::matchers::{body_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_generate_password_reset_link() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/projects/pj/accounts:sendOobCode"))
+            .and(body_json(json!({
+                "requestType": "PASSWORD_RESET",
+                "email": "user@example.com",
+                "returnOobLink": true,
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "email": "user@example.com",
+                "oobLink": "https://example.com/reset"
+            })))
+            .mount(&mock_server)
+            .await;
+        let link = get_test_client(&mock_server)
+            .generate_password_reset_link("user@example.com", None)
+            .await
+            .unwrap();
+        assert_eq!(link, "https://example.com/reset");
+    }
+
+    #[tokio::test]
+    async fn test_generate_sign_in_with_email_link() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/projects/pj/accounts:sendOobCode"))
+            .and(body_json(json!({
+                "requestType": "EMAIL_SIGNIN",
+                "email": "user@example.com",
+                "returnOobLink": true,
+                "continueUrl": "https://example.com/finish",
+                "canHandleCodeInApp": true,
+                "iOSBundleId": "com.example.ios",
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "oobLink": "https://example.com/signin"
+            })))
+            .mount(&mock_server)
+            .await;
+        let settings = ActionCodeSettings::new("https://example.com/finish".to_string())
+            .handle_code_in_app(true)
+            .ios_bundle_id("com.example.ios".to_string());
+        let link = get_test_client(&mock_server)
+            .generate_sign_in_with_email_link("user@example.com", &settings)
+            .await
+            .unwrap();
+        assert_eq!(link, "https://example.com/signin");
+    }
+
+    #[tokio::test]
+    async fn test_generate_sign_in_with_email_link_requires_in_app() {
+        let mock_server = MockServer::start().await;
+        let settings = ActionCodeSettings::new("https://example.com/finish".to_string());
+        let result = get_test_client(&mock_server)
+            .generate_sign_in_with_email_link("user@example.com", &settings)
+            .await;
+        assert!(matches!(
+            result,
+            Err(IdentityToolkitError::InvalidArgument(_))
+        ));
+    }
+}
@@ -0,0 +1,432 @@
+use crate::integration::{
+    is_under, FirebaseAuthRejection, FirebaseUser, OptionalFirebaseUser, RejectionResponse,
+};
+use crate::jwk_auth::{JwkAuth, RequireClaim};
+use crate::token::TokenExtractor;
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::WWW_AUTHENTICATE;
+use actix_web::http::StatusCode;
+use actix_web::web::Data;
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError};
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use std::rc::Rc;
+
+impl ResponseError for FirebaseAuthRejection {
+    fn status_code(&self) -> StatusCode {
+        let status = RejectionResponse::default().status_code(self);
+        StatusCode::from_u16(status).unwrap_or(StatusCode::UNAUTHORIZED)
+    }
+    fn error_response(&self) -> HttpResponse {
+        respond(self, &RejectionResponse::default())
+    }
+}
+
+fn respond(rejection: &FirebaseAuthRejection, config: &RejectionResponse) -> HttpResponse {
+    let status =
+        StatusCode::from_u16(config.status_code(rejection)).unwrap_or(StatusCode::UNAUTHORIZED);
+    let mut response = HttpResponse::build(status);
+    if let Some(challenge) = config.www_authenticate(rejection) {
+        response.insert_header((WWW_AUTHENTICATE, challenge));
+    }
+    match config.body(rejection) {
+        Some(body) => response.json(body),
+        None => response.finish(),
+    }
+}
+
+async fn authenticate(
+    req: &HttpRequest,
+    jwk_auth: &JwkAuth,
+    extractor: &TokenExtractor,
+) -> Result<FirebaseUser, FirebaseAuthRejection> {
+    let query = Some(req.query_string()).filter(|query| !query.is_empty());
+    let token_data = extractor
+        .extract_and_verify_async(
+            jwk_auth,
+            |name| {
+                req.headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+            },
+            query,
+        )
+        .await?;
+    Ok(FirebaseUser::from(token_data.claims))
+}
+
+/// Uses the user authenticated by [`AuthMiddleware`] if present, and
+/// otherwise verifies the token with the `web::Data<JwkAuth>`, looking for
+/// it where a `TokenExtractor` in the app data does, or in the bearer token.
+async fn firebase_user(req: HttpRequest) -> Result<FirebaseUser, FirebaseAuthRejection> {
+    if let Some(user) = req.extensions().get::<FirebaseUser>() {
+        return Ok(user.clone());
+    }
+    let jwk_auth = req
+        .app_data::<Data<JwkAuth>>()
+        .ok_or(FirebaseAuthRejection::MissingJwkAuth)?;
+    match req.app_data::<TokenExtractor>() {
+        Some(extractor) => authenticate(&req, jwk_auth, extractor).await,
+        None => authenticate(&req, jwk_auth, &TokenExtractor::default()).await,
+    }
+}
+
+impl FromRequest for FirebaseUser {
+    type Error = FirebaseAuthRejection;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        Box::pin(firebase_user(req.clone()))
+    }
+}
+
+impl FromRequest for OptionalFirebaseUser {
+    type Error = FirebaseAuthRejection;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let user = req.extensions().get::<OptionalFirebaseUser>().cloned();
+        let req = req.clone();
+        Box::pin(async move {
+            match user {
+                Some(user) => Ok(user),
+                None => OptionalFirebaseUser::from_result(firebase_user(req).await),
+            }
+        })
+    }
+}
+
+type ErrorHandler = dyn Fn(&FirebaseAuthRejection) -> HttpResponse;
+
+/// Rejects requests without a valid ID token and stores the [`FirebaseUser`]
+/// of the others in the request extensions.
+#[derive(Clone)]
+pub struct AuthMiddleware {
+    jwk_auth: Data<JwkAuth>,
+    extractor: Rc<TokenExtractor>,
+    excluded_paths: Rc<Vec<String>>,
+    optional: bool,
+    requirements: Rc<Vec<RequireClaim>>,
+    error_handler: Rc<ErrorHandler>,
+}
+
+impl AuthMiddleware {
+    pub fn new(jwk_auth: Data<JwkAuth>) -> AuthMiddleware {
+        AuthMiddleware {
+            jwk_auth,
+            extractor: Rc::default(),
+            excluded_paths: Rc::new(vec![]),
+            optional: false,
+            requirements: Rc::new(vec![]),
+            error_handler: Rc::new(|rejection| rejection.error_response()),
+        }
+    }
+    /// Lets requests whose path is `prefix`, or lies below it, through
+    /// unauthenticated: `/public` matches `/public/page` but not `/publications`.
+    pub fn exclude(mut self, prefix: String) -> Self {
+        Rc::make_mut(&mut self.excluded_paths).push(prefix);
+        self
+    }
+    /// Lets requests without a token through unauthenticated, so that
+    /// handlers can take an [`OptionalFirebaseUser`]. Requests with an invalid
+    /// token are still rejected.
+    pub fn optional(mut self, optional: bool) -> Self {
+        self.optional = optional;
+        self
+    }
+    /// Rejects authenticated users whose claims do not meet `requirement`
+    /// with [`FirebaseAuthRejection::Forbidden`].
+    pub fn require(mut self, requirement: RequireClaim) -> Self {
+        Rc::make_mut(&mut self.requirements).push(requirement);
+        self
+    }
+    /// Looks for the token where `extractor` does instead of only in the
+    /// `Authorization` header.
+    pub fn token_extractor(mut self, extractor: TokenExtractor) -> Self {
+        self.extractor = Rc::new(extractor);
+        self
+    }
+    /// Sets the status, `WWW-Authenticate` challenge and body of the response
+    /// sent when authentication fails. Replaces any `error_handler`.
+    pub fn rejection_response(mut self, config: RejectionResponse) -> Self {
+        self.error_handler = Rc::new(move |rejection| respond(rejection, &config));
+        self
+    }
+    /// Builds the response sent when authentication fails.
+    pub fn error_handler<F>(mut self, error_handler: F) -> Self
+    where
+        F: Fn(&FirebaseAuthRejection) -> HttpResponse + 'static,
+    {
+        self.error_handler = Rc::new(error_handler);
+        self
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for AuthMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = AuthMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AuthMiddlewareService {
+            service: Rc::new(service),
+            config: self.clone(),
+        }))
+    }
+}
+
+pub struct AuthMiddlewareService<S> {
+    service: Rc<S>,
+    config: AuthMiddleware,
+}
+
+impl<S, B> Service<ServiceRequest> for AuthMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let config = self.config.clone();
+        Box::pin(async move {
+            let excluded = config
+                .excluded_paths
+                .iter()
+                .any(|prefix| is_under(req.path(), prefix));
+            if !excluded {
+                let result = authenticate(req.request(), &config.jwk_auth, &config.extractor)
+                    .await
+                    .and_then(|user| {
+                        config
+                            .requirements
+                            .iter()
+                            .try_for_each(|requirement| user.require(requirement))?;
+                        Ok(user)
+                    });
+                match result {
+                    Ok(user) => {
+                        req.extensions_mut().insert(user);
+                    }
+                    Err(FirebaseAuthRejection::MissingToken) if config.optional => {
+                        req.extensions_mut().insert(OptionalFirebaseUser(None));
+                    }
+                    Err(rejection) => {
+                        let response = (config.error_handler)(&rejection);
+                        return Ok(req.into_response(response).map_into_right_body());
+                    }
+                }
+            }
+            let response = service.call(req).await?;
+            Ok(response.map_into_left_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use actix_web::http::header::{AUTHORIZATION, COOKIE};
+    use actix_web::{test, web, App};
+
+    async fn get_jwk_auth() -> Data<JwkAuth> {
+        let jwk_auth = get_session_cookie_jwk_auth().await;
+        Data::new(jwk_auth)
+    }
+
+    fn get_token() -> String {
+        let claims = get_test_claims("pj", "https://session.firebase.google.com/pj");
+        format!("Bearer {}", create_token(Some(SIGNING_KID), &claims))
+    }
+
+    async fn uid(user: FirebaseUser) -> String {
+        user.uid
+    }
+
+    async fn optional_uid(user: OptionalFirebaseUser) -> String {
+        user.0
+            .map_or_else(|| "anonymous".to_string(), |user| user.uid)
+    }
+
+    #[actix_web::test]
+    async fn test_firebase_user() {
+        let app = test::init_service(
+            App::new()
+                .app_data(get_jwk_auth().await)
+                .route("/uid", web::get().to(uid)),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/uid")
+            .insert_header((AUTHORIZATION, get_token()))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(body, "uid");
+
+        let req = test::TestRequest::get().uri("/uid").to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers().get(WWW_AUTHENTICATE).unwrap(), "Bearer");
+    }
+
+    #[actix_web::test]
+    async fn test_auth_middleware() {
+        let middleware = AuthMiddleware::new(get_jwk_auth().await)
+            .exclude("/public".to_string())
+            .error_handler(|_| HttpResponse::Forbidden().finish());
+        let app = test::init_service(
+            App::new()
+                .wrap(middleware)
+                .route("/uid", web::get().to(uid))
+                .route("/public", web::get().to(|| async { "public" }))
+                .route("/publications", web::get().to(|| async { "publications" })),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/uid")
+            .insert_header((AUTHORIZATION, get_token()))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(body, "uid");
+
+        let req = test::TestRequest::get().uri("/uid").to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let req = test::TestRequest::get().uri("/public").to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let req = test::TestRequest::get().uri("/publications").to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn test_optional_firebase_user() {
+        let middleware = AuthMiddleware::new(get_jwk_auth().await).optional(true);
+        let app = test::init_service(
+            App::new()
+                .wrap(middleware)
+                .route("/uid", web::get().to(optional_uid)),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/uid")
+            .insert_header((AUTHORIZATION, get_token()))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(body, "uid");
+
+        let req = test::TestRequest::get().uri("/uid").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(body, "anonymous");
+
+        let req = test::TestRequest::get()
+            .uri("/uid")
+            .insert_header((AUTHORIZATION, "Bearer invalid"))
+            .to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn test_auth_middleware_require() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    AuthMiddleware::new(get_jwk_auth().await).require(RequireClaim::role("admin")),
+                )
+                .route("/uid", web::get().to(uid)),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/uid")
+            .insert_header((AUTHORIZATION, get_token()))
+            .to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let req = test::TestRequest::get().uri("/uid").to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn test_auth_middleware_rejection_response() {
+        let config = RejectionResponse::new()
+            .realm("api".to_string())
+            .describe_errors(true)
+            .json_body(false);
+        let app = test::init_service(
+            App::new()
+                .wrap(AuthMiddleware::new(get_jwk_auth().await).rejection_response(config))
+                .route("/uid", web::get().to(uid)),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/uid")
+            .insert_header((AUTHORIZATION, "Bearer invalid"))
+            .to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers().get(WWW_AUTHENTICATE).unwrap(),
+            "Bearer realm=\"api\", error=\"invalid_token\", error_description=\"malformed token\""
+        );
+        assert!(test::read_body(response).await.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_token_extractor() {
+        let claims = get_test_claims("pj", "https://session.firebase.google.com/pj");
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let extractor = TokenExtractor::new().cookie("__session".to_string());
+        let app = test::init_service(
+            App::new()
+                .app_data(get_jwk_auth().await)
+                .app_data(extractor)
+                .route("/uid", web::get().to(uid))
+                .service(
+                    web::scope("/scope")
+                        .wrap(
+                            AuthMiddleware::new(get_jwk_auth().await)
+                                .token_extractor(TokenExtractor::new().query("token".to_string())),
+                        )
+                        .route("/uid", web::get().to(uid)),
+                ),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/uid")
+            .insert_header((COOKIE, format!("__session={}", token)))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(body, "uid");
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/scope/uid?token={}", token))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(body, "uid");
+
+        let req = test::TestRequest::get()
+            .uri("/scope/uid")
+            .insert_header((AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request();
+        let response = test::call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,272 @@
+use crate::jwk::{Fetcher, Jwk, JwkFetcher, KeyFetchError};
+use crate::jwk_auth::UNKNOWN_KID_REFRESH_INTERVAL;
+use crate::single_flight::SingleFlightFetcher;
+use crate::time::Instant;
+use crate::verifier::{ProjectIdentifier, VerifyError};
+use jsonwebtoken::{decode, decode_header, Algorithm, Validation};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use thiserror::Error;
+
+const APP_CHECK_JWKS_URL: &str = "https://firebaseappcheck.googleapis.com/v1/jwks";
+const APP_CHECK_ISSUER_URL: &str = "https://firebaseappcheck.googleapis.com/";
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+pub struct AppCheckClaims {
+    pub iss: String,
+    /// The Firebase App ID of the app the token was issued to.
+    pub sub: String,
+    pub aud: Vec<String>,
+    pub exp: i64,
+    pub iat: i64,
+}
+
+/// A verified App Check token along with which project identifier its `aud`
+/// named.
+#[derive(Debug, PartialEq, Clone)]
+pub struct VerifiedAppCheckToken {
+    pub claims: AppCheckClaims,
+    pub project_identifier: ProjectIdentifier,
+}
+
+#[derive(Debug, Error)]
+pub enum AppCheckError {
+    #[error("failed to fetch keys")]
+    KeyFetchError(#[source] KeyFetchError),
+    #[error("invalid app check token: {0}")]
+    VerifyError(#[from] VerifyError),
+}
+
+struct KeyCache {
+    keys: HashMap<String, Jwk>,
+    /// `None` if the validity is too long to represent, i.e. never.
+    expires_at: Option<Instant>,
+}
+
+impl KeyCache {
+    fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= Instant::now())
+    }
+}
+
+/// Verifies Firebase App Check tokens. Keys are fetched on first use and
+/// refetched once they expire or, at most every 30 seconds, when a token
+/// carries an unknown `kid`.
+pub struct AppCheckVerifier {
+    issuer: String,
+    /// The project number audience comes first.
+    audience: Vec<String>,
+    fetcher: SingleFlightFetcher,
+    cache: RwLock<Option<KeyCache>>,
+    /// When keys were last fetched because of an unknown kid.
+    last_kid_refresh: Mutex<Option<Instant>>,
+}
+
+impl AppCheckVerifier {
+    /// App Check tokens name the project by number; the project id is also
+    /// accepted as an audience when given.
+    pub fn new(project_number: String, project_id: Option<String>) -> AppCheckVerifier {
+        Self::with_fetcher(
+            project_number,
+            project_id,
+            JwkFetcher::new(APP_CHECK_JWKS_URL.to_string()),
+        )
+    }
+    pub fn with_fetcher<F>(
+        project_number: String,
+        project_id: Option<String>,
+        fetcher: F,
+    ) -> AppCheckVerifier
+    where
+        F: Fetcher + Send + Sync + 'static,
+    {
+        let mut audience = vec![format!("projects/{}", project_number)];
+        if let Some(project_id) = project_id {
+            audience.push(format!("projects/{}", project_id));
+        }
+        AppCheckVerifier {
+            issuer: format!("{}{}", APP_CHECK_ISSUER_URL, project_number),
+            audience,
+            fetcher: SingleFlightFetcher::wrap(Arc::new(fetcher)),
+            cache: RwLock::new(None),
+            last_kid_refresh: Mutex::new(None),
+        }
+    }
+    pub async fn verify(&self, token: &str) -> Result<AppCheckClaims, AppCheckError> {
+        let kid = match decode_header(token) {
+            Ok(header) if header.alg != Algorithm::RS256 => {
+                return Err(VerifyError::AlgorithmMismatch.into())
+            }
+            Ok(header) => header.kid.ok_or(VerifyError::MissingKid)?,
+            Err(_) => return Err(VerifyError::Malformed.into()),
+        };
+        let key = match self.cached_key(&kid) {
+            Some(key) => key,
+            None => {
+                if !self.keys_expired() && !self.may_refresh_for_unknown_kid() {
+                    return Err(VerifyError::UnknownKid(kid).into());
+                }
+                self.refresh_keys().await?;
+                self.cached_key(&kid)
+                    .ok_or_else(|| VerifyError::UnknownKid(kid.clone()))?
+            }
+        };
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_audience(&self.audience);
+        validation.set_issuer(&[&self.issuer]);
+        let key = key
+            .decoding_key()
+            .map_err(|_| AppCheckError::VerifyError(VerifyError::InvalidKey))?;
+        let token_data = decode::<AppCheckClaims>(token, &key, &validation)
+            .map_err(|e| AppCheckError::VerifyError(e.into()))?;
+        Ok(token_data.claims)
+    }
+    /// Like [`verify`](Self::verify), also telling whether the token named
+    /// the project by number or by id. Tokens naming both count as by number.
+    pub async fn verify_detailed(
+        &self,
+        token: &str,
+    ) -> Result<VerifiedAppCheckToken, AppCheckError> {
+        let claims = self.verify(token).await?;
+        let project_identifier = if claims.aud.contains(&self.audience[0]) {
+            ProjectIdentifier::Number
+        } else {
+            ProjectIdentifier::Id
+        };
+        Ok(VerifiedAppCheckToken {
+            claims,
+            project_identifier,
+        })
+    }
+    fn cached_key(&self, kid: &str) -> Option<Jwk> {
+        let cache = self.cache.read().unwrap();
+        cache
+            .as_ref()
+            .filter(|cache| !cache.is_expired())
+            .and_then(|cache| cache.keys.get(kid).cloned())
+    }
+    /// Whether no keys were fetched yet or they expired.
+    fn keys_expired(&self) -> bool {
+        let cache = self.cache.read().unwrap();
+        cache.as_ref().is_none_or(KeyCache::is_expired)
+    }
+    /// Whether valid keys may be refetched for an unknown kid, which is done
+    /// at most every 30 seconds so that tokens with made up kids do not cause
+    /// a fetch each.
+    fn may_refresh_for_unknown_kid(&self) -> bool {
+        let mut last_refresh = self.last_kid_refresh.lock().unwrap();
+        if last_refresh.is_some_and(|last| last.elapsed() < UNKNOWN_KID_REFRESH_INTERVAL) {
+            return false;
+        }
+        *last_refresh = Some(Instant::now());
+        true
+    }
+    async fn refresh_keys(&self) -> Result<(), AppCheckError> {
+        let jwks = self
+            .fetcher
+            .fetch_keys()
+            .await
+            .map_err(AppCheckError::KeyFetchError)?;
+        let keys = jwks
+            .keys
+            .into_iter()
+            .map(|key| (key.kid.clone(), key))
+            .collect();
+        *self.cache.write().unwrap() = Some(KeyCache {
+            keys,
+            expires_at: Instant::now().checked_add(jwks.validity),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use crate::time::Duration;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn get_app_check_claims(aud: &str) -> AppCheckClaims {
+        AppCheckClaims {
+            iss: format!("{}123", APP_CHECK_ISSUER_URL),
+            sub: "1:123:web:abc".to_string(),
+            aud: vec![aud.to_string()],
+            exp: now() + 3600,
+            iat: now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verify() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let verifier = AppCheckVerifier::with_fetcher(
+            "123".to_string(),
+            Some("pj".to_string()),
+            CountingFetcher::counting(&calls),
+        );
+        let audiences = [
+            ("projects/123", ProjectIdentifier::Number),
+            ("projects/pj", ProjectIdentifier::Id),
+        ];
+        for (aud, project_identifier) in audiences {
+            let claims = get_app_check_claims(aud);
+            let token = create_token(Some(SIGNING_KID), &claims);
+            assert_eq!(verifier.verify(&token).await.unwrap(), claims);
+            let verified = verifier.verify_detailed(&token).await.unwrap();
+            assert_eq!(verified.project_identifier, project_identifier);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_verify_wrong_audience() {
+        let verifier = AppCheckVerifier::with_fetcher(
+            "123".to_string(),
+            None,
+            CountingFetcher::new(String::new()),
+        );
+        let token = create_token(Some(SIGNING_KID), &get_app_check_claims("projects/pj"));
+        assert!(matches!(
+            verifier.verify(&token).await,
+            Err(AppCheckError::VerifyError(VerifyError::WrongAudience))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_verify_unknown_kid() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let verifier = AppCheckVerifier::with_fetcher(
+            "123".to_string(),
+            None,
+            CountingFetcher::counting(&calls),
+        );
+        let token = create_token(Some(SIGNING_KID), &get_app_check_claims("projects/123"));
+        assert!(verifier.verify(&token).await.is_ok());
+        for kid in ["kid-unknown", "kid-other", "kid-another"] {
+            let token = create_token(Some(kid), &get_app_check_claims("projects/123"));
+            assert!(matches!(
+                verifier.verify(&token).await,
+                Err(AppCheckError::VerifyError(VerifyError::UnknownKid(_)))
+            ));
+        }
+        // One fetch for the first token, one for the first unknown kid.
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_verify_unbounded_validity() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let verifier = AppCheckVerifier::with_fetcher(
+            "123".to_string(),
+            None,
+            CountingFetcher::counting(&calls).validity(Duration::MAX),
+        );
+        let token = create_token(Some(SIGNING_KID), &get_app_check_claims("projects/123"));
+        assert!(verifier.verify(&token).await.is_ok());
+        assert!(verifier.verify(&token).await.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,300 @@
+use crate::integration::{
+    FirebaseAuthRejection, FirebaseUser, OptionalFirebaseUser, RejectionResponse,
+};
+use crate::jwk_auth::{JwkAuth, JwkAuthHandle};
+use crate::token::TokenExtractor;
+use ::axum::async_trait;
+use ::axum::extract::{FromRef, FromRequestParts};
+use ::axum::http::header::WWW_AUTHENTICATE;
+use ::axum::http::request::Parts;
+use ::axum::http::{HeaderValue, StatusCode};
+use ::axum::response::{IntoResponse, Response};
+use ::axum::Json;
+use std::sync::Arc;
+
+/// Answers with the defaults of [`RejectionResponse`].
+impl IntoResponse for FirebaseAuthRejection {
+    fn into_response(self) -> Response {
+        let config = RejectionResponse::default();
+        let status =
+            StatusCode::from_u16(config.status_code(&self)).unwrap_or(StatusCode::UNAUTHORIZED);
+        let mut response = match config.body(&self) {
+            Some(body) => (status, Json(body)).into_response(),
+            None => status.into_response(),
+        };
+        if let Some(challenge) = config
+            .www_authenticate(&self)
+            .and_then(|challenge| HeaderValue::from_str(&challenge).ok())
+        {
+            response.headers_mut().insert(WWW_AUTHENTICATE, challenge);
+        }
+        response
+    }
+}
+
+impl FromRef<Arc<JwkAuth>> for JwkAuthHandle {
+    fn from_ref(jwk_auth: &Arc<JwkAuth>) -> Self {
+        JwkAuthHandle::from(Arc::clone(jwk_auth))
+    }
+}
+
+/// Router state the extractors can take the `JwkAuth` from. Implemented for
+/// any state a [`JwkAuthHandle`] can be taken from with `FromRef`, and for
+/// `()`, where the `JwkAuth` has to be in the request extensions. Implement
+/// it returning `None` for other states to keep using the extensions.
+pub trait FirebaseAuthState {
+    fn jwk_auth(&self) -> Option<Arc<JwkAuth>>;
+}
+
+impl FirebaseAuthState for () {
+    fn jwk_auth(&self) -> Option<Arc<JwkAuth>> {
+        None
+    }
+}
+
+impl<S> FirebaseAuthState for S
+where
+    JwkAuthHandle: FromRef<S>,
+{
+    fn jwk_auth(&self) -> Option<Arc<JwkAuth>> {
+        Some(JwkAuthHandle::from_ref(self).into())
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for FirebaseUser
+where
+    S: FirebaseAuthState + Send + Sync,
+{
+    type Rejection = FirebaseAuthRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        firebase_user(parts, state).await
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for OptionalFirebaseUser
+where
+    S: FirebaseAuthState + Send + Sync,
+{
+    type Rejection = FirebaseAuthRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(user) = parts.extensions.get::<OptionalFirebaseUser>() {
+            return Ok(user.clone());
+        }
+        OptionalFirebaseUser::from_result(firebase_user(parts, state).await)
+    }
+}
+
+async fn firebase_user<S: FirebaseAuthState>(
+    parts: &Parts,
+    state: &S,
+) -> Result<FirebaseUser, FirebaseAuthRejection> {
+    if let Some(user) = parts.extensions.get::<FirebaseUser>() {
+        return Ok(user.clone());
+    }
+    let jwk_auth = parts
+        .extensions
+        .get::<Arc<JwkAuth>>()
+        .cloned()
+        .or_else(|| state.jwk_auth())
+        .ok_or(FirebaseAuthRejection::MissingJwkAuth)?;
+    let default_extractor = TokenExtractor::default();
+    let extractor = parts
+        .extensions
+        .get::<TokenExtractor>()
+        .unwrap_or(&default_extractor);
+    let token_data = extractor
+        .extract_and_verify_async(
+            jwk_auth.as_ref(),
+            |name| {
+                parts
+                    .headers
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+            },
+            parts.uri.query(),
+        )
+        .await?;
+    Ok(FirebaseUser::from(token_data.claims))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jwk_auth::RequireClaim;
+    use crate::tests::*;
+    use ::axum::body::Body;
+    use ::axum::http::header::AUTHORIZATION;
+    use ::axum::http::Request;
+    use ::axum::routing::get;
+    use ::axum::{Extension, Router};
+    use tower::ServiceExt;
+
+    async fn get_router() -> Router {
+        let jwk_auth = get_session_cookie_jwk_auth().await;
+        Router::new()
+            .route("/uid", get(|user: FirebaseUser| async move { user.uid }))
+            .route(
+                "/optional",
+                get(|user: OptionalFirebaseUser| async move {
+                    user.0
+                        .map_or_else(|| "anonymous".to_string(), |user| user.uid)
+                }),
+            )
+            .route(
+                "/admin",
+                get(|user: FirebaseUser| async move {
+                    user.require(&RequireClaim::role("admin"))?;
+                    Ok::<_, FirebaseAuthRejection>(user.uid)
+                }),
+            )
+            .layer(Extension(Arc::new(jwk_auth)))
+    }
+
+    async fn call(router: Router, authorization: Option<&str>) -> StatusCode {
+        let mut request = Request::builder().uri("/uid");
+        if let Some(authorization) = authorization {
+            request = request.header(AUTHORIZATION, authorization);
+        }
+        let response = router
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        response.status()
+    }
+
+    #[tokio::test]
+    async fn test_firebase_user() {
+        let claims = get_test_claims("pj", "https://session.firebase.google.com/pj");
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let status = call(get_router().await, Some(&format!("Bearer {}", token))).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_firebase_user_forbidden() {
+        let claims = get_test_claims("pj", "https://session.firebase.google.com/pj");
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let request = Request::builder()
+            .uri("/admin")
+            .header(AUTHORIZATION, format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap();
+        let response = get_router().await.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_firebase_user_rejected() {
+        assert_eq!(
+            call(get_router().await, None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            call(get_router().await, Some("Bearer invalid")).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_firebase_user_challenge() {
+        let request = Request::builder()
+            .uri("/uid")
+            .header(AUTHORIZATION, "Bearer invalid")
+            .body(Body::empty())
+            .unwrap();
+        let response = get_router().await.oneshot(request).await.unwrap();
+        assert_eq!(
+            response.headers().get(WWW_AUTHENTICATE).unwrap(),
+            "Bearer error=\"invalid_token\""
+        );
+    }
+
+    #[tokio::test]
+    async fn test_optional_firebase_user() {
+        let claims = get_test_claims("pj", "https://session.firebase.google.com/pj");
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let call = |authorization: Option<String>| async move {
+            let mut request = Request::builder().uri("/optional");
+            if let Some(authorization) = authorization {
+                request = request.header(AUTHORIZATION, authorization);
+            }
+            let response = get_router()
+                .await
+                .oneshot(request.body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            let status = response.status();
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            (status, body)
+        };
+        assert_eq!(
+            call(Some(format!("Bearer {}", token))).await,
+            (StatusCode::OK, "uid".into())
+        );
+        assert_eq!(call(None).await, (StatusCode::OK, "anonymous".into()));
+        assert_eq!(
+            call(Some("Bearer invalid".to_string())).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_firebase_user_query_token() {
+        let claims = get_test_claims("pj", "https://session.firebase.google.com/pj");
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let router = get_router()
+            .await
+            .layer(Extension(TokenExtractor::new().query("token".to_string())));
+        let request = Request::builder()
+            .uri(format!("/uid?token={}", token))
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_firebase_user_from_state() {
+        #[derive(Clone)]
+        struct AppState {
+            jwk_auth: JwkAuthHandle,
+        }
+
+        impl FromRef<AppState> for JwkAuthHandle {
+            fn from_ref(state: &AppState) -> Self {
+                state.jwk_auth.clone()
+            }
+        }
+
+        let claims = get_test_claims("pj", "https://session.firebase.google.com/pj");
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let jwk_auth = get_session_cookie_jwk_auth().await.into_handle();
+        let handler = |user: FirebaseUser| async move { user.uid };
+        let with_handle = Router::new()
+            .route("/uid", get(handler))
+            .with_state(jwk_auth.clone());
+        let with_arc = Router::new()
+            .route("/uid", get(handler))
+            .with_state(Arc::<JwkAuth>::from(jwk_auth.clone()));
+        let with_app_state = Router::new()
+            .route("/uid", get(handler))
+            .with_state(AppState { jwk_auth });
+        for router in [with_handle, with_arc, with_app_state] {
+            let status = call(router, Some(&format!("Bearer {}", token))).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_firebase_user_missing_jwk_auth() {
+        let router = Router::new().route("/uid", get(|user: FirebaseUser| async move { user.uid }));
+        assert_eq!(
+            call(router, Some("Bearer token")).await,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+}
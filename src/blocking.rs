@@ -0,0 +1,291 @@
+//! A synchronous [`JwkAuth`](crate::jwk_auth::JwkAuth) counterpart built on
+//! `reqwest::blocking`, for services without an async runtime.
+//!
+//! Blocking calls must not be made from within an async runtime; use
+//! [`JwkAuth`](crate::jwk_auth::JwkAuth) there instead.
+
+use crate::header_parser::get_stale_while_revalidate;
+use crate::http::HttpResponse;
+use crate::jwk::{parse_keys, response_validity, KeyFormat, ValidityBounds, DEFAULT_VALIDITY};
+use crate::jwk_auth::{
+    Claims, DecodedToken, JwkAuth, JwkAuthError, Jwks, KeyFetchError, TokenVerifier, VerifyError,
+};
+use crate::telemetry::{record_keys, record_verification};
+use crate::verifier::{JwkConfig, JwkVerifier};
+#[cfg(not(feature = "tracing"))]
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+#[cfg(feature = "tracing")]
+use tracing::{info, warn};
+
+/// Fetches keys like the default [`Fetcher`](crate::jwk_auth::Fetcher),
+/// blocking the current thread.
+#[derive(Debug)]
+pub(crate) struct BlockingJwkFetcher {
+    pub url: String,
+    pub format: KeyFormat,
+    pub timeout: Option<Duration>,
+    pub validity_bounds: ValidityBounds,
+    pub fallback_validity: Duration,
+    pub client: reqwest::blocking::Client,
+}
+
+impl BlockingJwkFetcher {
+    pub fn with_format(url: String, format: KeyFormat) -> BlockingJwkFetcher {
+        BlockingJwkFetcher {
+            url,
+            format,
+            timeout: None,
+            validity_bounds: ValidityBounds::default(),
+            fallback_validity: DEFAULT_VALIDITY,
+            client: reqwest::blocking::Client::new(),
+        }
+    }
+    pub fn fetch_keys(&self) -> Result<Jwks, KeyFetchError> {
+        let mut request = self.client.get(&self.url);
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout);
+        }
+        let response = request
+            .send()
+            .map_err(|e| KeyFetchError::RequestError(Box::new(e)))?;
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let body = response
+            .bytes()
+            .map_err(|e| KeyFetchError::RequestError(Box::new(e)))?
+            .to_vec();
+        let response = HttpResponse {
+            status,
+            headers,
+            body,
+        };
+        if !response.is_success() {
+            return Err(KeyFetchError::StatusError(response.status));
+        }
+        let validity = response_validity(&response, &self.validity_bounds, self.fallback_validity);
+        let keys = parse_keys(self.format, &response.body)?;
+        Ok(Jwks {
+            keys,
+            validity,
+            stale_while_revalidate: get_stale_while_revalidate(&response).unwrap_or_default(),
+        })
+    }
+}
+
+/// Verifies ID tokens without an async runtime. Instead of refreshing in the
+/// background, the keys are refreshed by the first verification after they
+/// expire; if that fails, the current keys stay in use.
+pub struct BlockingJwkAuth {
+    verifier: RwLock<JwkVerifier>,
+    fetcher: BlockingJwkFetcher,
+    /// When the keys in use expire; `None` in emulator mode or if their
+    /// validity is too long to represent.
+    expires_at: Mutex<Option<Instant>>,
+    fetched_at: Mutex<Instant>,
+}
+
+impl BlockingJwkAuth {
+    pub fn new(project_id: String) -> Result<BlockingJwkAuth, JwkAuthError> {
+        JwkAuth::builder().project_id(project_id).build_blocking()
+    }
+    pub(crate) fn fetch(
+        fetcher: BlockingJwkFetcher,
+        config: JwkConfig,
+    ) -> Result<BlockingJwkAuth, JwkAuthError> {
+        let jwk_keys = fetcher.fetch_keys().map_err(JwkAuthError::KeyFetchError)?;
+        record_keys(jwk_keys.keys.len());
+        Ok(BlockingJwkAuth {
+            verifier: RwLock::new(JwkVerifier::with_config(jwk_keys.keys, config)),
+            fetcher,
+            expires_at: Mutex::new(Instant::now().checked_add(jwk_keys.validity)),
+            fetched_at: Mutex::new(Instant::now()),
+        })
+    }
+    pub(crate) fn emulator(fetcher: BlockingJwkFetcher, config: JwkConfig) -> BlockingJwkAuth {
+        BlockingJwkAuth {
+            verifier: RwLock::new(JwkVerifier::emulator_with_config(config)),
+            fetcher,
+            expires_at: Mutex::new(None),
+            fetched_at: Mutex::new(Instant::now()),
+        }
+    }
+    pub fn verify(&self, token: &str) -> Result<DecodedToken<Claims>, VerifyError> {
+        self.refresh_if_expired();
+        let result = self.verifier.read().unwrap().verify(token);
+        record_verification(token, &result, self.fetched_at.lock().unwrap().elapsed());
+        result
+    }
+    /// Verifies the token, then deserializes its claims into a caller-supplied type.
+    pub fn verify_into<C: DeserializeOwned>(
+        &self,
+        token: &str,
+    ) -> Result<DecodedToken<C>, VerifyError> {
+        self.refresh_if_expired();
+        let result = self.verifier.read().unwrap().verify_into(token);
+        record_verification(token, &result, self.fetched_at.lock().unwrap().elapsed());
+        result
+    }
+    /// Fetches and installs new keys right away.
+    pub fn refresh(&self) -> Result<(), KeyFetchError> {
+        let jwk_keys = self.fetcher.fetch_keys()?;
+        record_keys(jwk_keys.keys.len());
+        self.verifier.write().unwrap().set_keys(jwk_keys.keys);
+        *self.expires_at.lock().unwrap() = Instant::now().checked_add(jwk_keys.validity);
+        *self.fetched_at.lock().unwrap() = Instant::now();
+        Ok(())
+    }
+    /// Refreshes expired keys. Concurrent callers do not wait for the
+    /// refresh, and verify with the current keys meanwhile.
+    fn refresh_if_expired(&self) {
+        let mut expires_at = match self.expires_at.try_lock() {
+            Ok(expires_at) => expires_at,
+            Err(_) => return,
+        };
+        if !expires_at.is_some_and(|expires_at| Instant::now() >= expires_at) {
+            return;
+        }
+        match self.fetcher.fetch_keys() {
+            Ok(jwk_keys) => {
+                record_keys(jwk_keys.keys.len());
+                self.verifier.write().unwrap().set_keys(jwk_keys.keys);
+                *expires_at = Instant::now().checked_add(jwk_keys.validity);
+                *self.fetched_at.lock().unwrap() = Instant::now();
+                info!("Updated expired JWK Keys");
+            }
+            Err(error) => {
+                // Do not retry on every verification while the key server is down.
+                *expires_at = Some(Instant::now() + Duration::from_secs(5));
+                warn!(
+                    "Failed to refresh expired JWK Keys: {}. Keeping the current keys",
+                    error
+                );
+            }
+        }
+    }
+}
+
+impl TokenVerifier for BlockingJwkAuth {
+    fn verify(&self, token: &str) -> Result<DecodedToken<Claims>, VerifyError> {
+        BlockingJwkAuth::verify(self, token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jwk::KeyResponse;
+    use crate::tests::*;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn start<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn get_mock_signing_server(max_age: u64) -> MockServer {
+        start(async {
+            let mock_server = MockServer::start().await;
+            Mock::given(method("GET"))
+                .and(path(PATH))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .insert_header("Cache-Control", format!("max-age={}", max_age).as_str())
+                        .set_body_json(KeyResponse {
+                            keys: vec![get_signing_key()],
+                        }),
+                )
+                .mount(&mock_server)
+                .await;
+            mock_server
+        })
+    }
+
+    #[test]
+    fn test_blocking_fetch_keys() {
+        let mock_server = start(get_mock_server());
+        let fetcher = BlockingJwkFetcher::with_format(get_mock_url(&mock_server), KeyFormat::Jwk);
+        assert_eq!(
+            fetcher.fetch_keys().unwrap(),
+            Jwks {
+                keys: get_test_keys(),
+                validity: Duration::from_secs(MAXAGE),
+                stale_while_revalidate: Duration::ZERO,
+            }
+        );
+
+        let mock_server = start(get_mock_cert_server());
+        let fetcher = BlockingJwkFetcher::with_format(get_mock_url(&mock_server), KeyFormat::X509);
+        assert_eq!(fetcher.fetch_keys().unwrap().keys, vec![get_signing_key()]);
+    }
+
+    #[test]
+    fn test_blocking_jwk_auth_verify() {
+        let mock_server = get_mock_signing_server(3600);
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .pubkey_url(get_mock_url(&mock_server))
+            .emulator(false)
+            .build_blocking()
+            .unwrap();
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", "https://securetoken.google.com/pj"),
+        );
+        assert_eq!(jwk_auth.verify(&token).unwrap().claims.sub, "uid");
+        let wrong_audience = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("other", "https://securetoken.google.com/pj"),
+        );
+        assert_eq!(
+            jwk_auth.verify(&wrong_audience).unwrap_err(),
+            VerifyError::WrongAudience
+        );
+    }
+
+    #[test]
+    fn test_blocking_jwk_auth_refreshes_expired_keys() {
+        let mock_server = get_mock_signing_server(0);
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .pubkey_url(get_mock_url(&mock_server))
+            .validity_bounds(ValidityBounds {
+                min: Duration::ZERO,
+                ..ValidityBounds::default()
+            })
+            .emulator(false)
+            .build_blocking()
+            .unwrap();
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", "https://securetoken.google.com/pj"),
+        );
+        assert!(jwk_auth.verify(&token).is_ok());
+        assert!(jwk_auth.verify(&token).is_ok());
+        let requests = start(mock_server.received_requests()).unwrap();
+        assert_eq!(requests.len(), 3);
+    }
+
+    #[test]
+    fn test_build_blocking_unsupported_config() {
+        let result = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fallback_pubkey_url("https://example.com/keys".to_string(), KeyFormat::Jwk)
+            .emulator(false)
+            .build_blocking();
+        assert!(matches!(
+            result,
+            Err(JwkAuthError::UnsupportedConfig("fallback_pubkey_url"))
+        ));
+    }
+}
@@ -0,0 +1,577 @@
+use crate::http::{api_client, DEFAULT_REQUEST_TIMEOUT};
+use crate::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use async_trait::async_trait;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use thiserror::Error;
+use tokio::sync::Mutex;
+
+const GOOGLE_TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+const METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+const METADATA_PROJECT_ID_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/project/project-id";
+/// How long to wait for the metadata server, which outside of Google Cloud
+/// may not answer at all.
+const METADATA_TIMEOUT: Duration = Duration::from_secs(3);
+const CREDENTIALS_ENV: &str = "GOOGLE_APPLICATION_CREDENTIALS";
+const PROJECT_ENVS: &[&str] = &["GOOGLE_CLOUD_PROJECT", "GCLOUD_PROJECT"];
+const FIREBASE_CONFIG_ENV: &str = "FIREBASE_CONFIG";
+const JWT_BEARER_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+const ASSERTION_LIFETIME_SECS: u64 = 3600;
+/// Cached tokens are refreshed this long before they actually expire.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+/// Google access tokens last an hour; longer lifetimes announced by the
+/// token endpoint are not trusted.
+const MAX_TOKEN_LIFETIME: Duration = Duration::from_secs(3600);
+const SCOPES: &[&str] = &[
+    "https://www.googleapis.com/auth/cloud-platform",
+    "https://www.googleapis.com/auth/firebase",
+    "https://www.googleapis.com/auth/identitytoolkit",
+    "https://www.googleapis.com/auth/userinfo.email",
+];
+
+#[derive(Debug, Error)]
+pub enum CredentialsError {
+    #[error("unable to read credentials: {0}")]
+    IoError(#[source] std::io::Error),
+    #[error("invalid service account json: {0}")]
+    InvalidServiceAccount(#[source] serde_json::Error),
+    #[error("invalid private key: {0}")]
+    InvalidPrivateKey(#[source] jsonwebtoken::errors::Error),
+    #[error("request failed: {0}")]
+    RequestError(#[source] reqwest::Error),
+    #[error("invalid response body: {0}")]
+    ResponseBodyError(#[source] reqwest::Error),
+    #[error("token endpoint error ({status}): {message}")]
+    TokenEndpointError { status: u16, message: String },
+    #[error("invalid FIREBASE_CONFIG: {0}")]
+    InvalidFirebaseConfig(#[source] serde_json::Error),
+    /// Neither the environment nor the metadata server tell the project id.
+    #[error("unable to determine the project id")]
+    MissingProjectId,
+    /// Raised by user supplied [`TokenSource`] implementations.
+    #[error("{0}")]
+    Custom(#[source] Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// Provides OAuth2 access tokens for calls to Google APIs.
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+pub trait TokenSource {
+    async fn token(&self) -> Result<String, CredentialsError>;
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl<T> TokenSource for Box<T>
+where
+    T: TokenSource + Send + Sync + ?Sized,
+{
+    async fn token(&self) -> Result<String, CredentialsError> {
+        self.as_ref().token().await
+    }
+}
+
+/// A fixed access token, e.g. `"owner"` for the Firebase Auth emulator.
+#[derive(Debug, PartialEq, Clone)]
+pub struct StaticToken(pub String);
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl TokenSource for StaticToken {
+    async fn token(&self) -> Result<String, CredentialsError> {
+        Ok(self.0.clone())
+    }
+}
+
+/// The JSON key file downloaded for a service account.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct ServiceAccountKey {
+    pub project_id: String,
+    pub private_key_id: String,
+    pub private_key: String,
+    pub client_email: String,
+    #[serde(default = "default_token_uri")]
+    pub token_uri: String,
+}
+
+fn default_token_uri() -> String {
+    GOOGLE_TOKEN_URI.to_string()
+}
+
+impl ServiceAccountKey {
+    pub fn from_json(json: &str) -> Result<ServiceAccountKey, CredentialsError> {
+        serde_json::from_str(json).map_err(CredentialsError::InvalidServiceAccount)
+    }
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<ServiceAccountKey, CredentialsError> {
+        let json = std::fs::read_to_string(path).map_err(CredentialsError::IoError)?;
+        Self::from_json(&json)
+    }
+    /// Reads the key file pointed to by `GOOGLE_APPLICATION_CREDENTIALS`, if set.
+    pub fn from_env() -> Option<Result<ServiceAccountKey, CredentialsError>> {
+        std::env::var(CREDENTIALS_ENV)
+            .ok()
+            .filter(|path| !path.is_empty())
+            .map(Self::from_file)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+#[derive(Debug)]
+struct CachedToken {
+    access_token: String,
+    expires_at: Instant,
+}
+
+#[derive(Debug, Default)]
+struct TokenCache(Mutex<Option<CachedToken>>);
+
+impl TokenCache {
+    /// Returns the cached token, fetching a new one with `fetch` when the cache
+    /// is empty or about to expire. Concurrent callers wait for a single fetch.
+    async fn get_or_fetch<F, Fut>(&self, fetch: F) -> Result<String, CredentialsError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: std::future::Future<Output = Result<TokenResponse, CredentialsError>>,
+    {
+        let mut cached = self.0.lock().await;
+        if let Some(token) = cached.as_ref() {
+            if token.expires_at > Instant::now() + EXPIRY_MARGIN {
+                return Ok(token.access_token.clone());
+            }
+        }
+        let response = fetch().await?;
+        *cached = Some(CachedToken {
+            access_token: response.access_token.clone(),
+            expires_at: Instant::now()
+                + Duration::from_secs(response.expires_in).min(MAX_TOKEN_LIFETIME),
+        });
+        Ok(response.access_token)
+    }
+}
+
+async fn parse_token_response(
+    response: reqwest::Response,
+) -> Result<TokenResponse, CredentialsError> {
+    let status = response.status();
+    if !status.is_success() {
+        let message = response.text().await.unwrap_or_default();
+        return Err(CredentialsError::TokenEndpointError {
+            status: status.as_u16(),
+            message,
+        });
+    }
+    response
+        .json::<TokenResponse>()
+        .await
+        .map_err(CredentialsError::ResponseBodyError)
+}
+
+#[derive(Debug, Serialize)]
+struct AssertionClaims<'a> {
+    iss: &'a str,
+    scope: String,
+    aud: &'a str,
+    iat: u64,
+    exp: u64,
+}
+
+/// Exchanges a signed JWT assertion for access tokens (RFC 7523) and caches
+/// them until shortly before they expire.
+pub struct ServiceAccountCredentials {
+    key: ServiceAccountKey,
+    encoding_key: EncodingKey,
+    client: reqwest::Client,
+    cache: TokenCache,
+}
+
+impl ServiceAccountCredentials {
+    pub fn new(key: ServiceAccountKey) -> Result<ServiceAccountCredentials, CredentialsError> {
+        let encoding_key = EncodingKey::from_rsa_pem(key.private_key.as_bytes())
+            .map_err(CredentialsError::InvalidPrivateKey)?;
+        Ok(ServiceAccountCredentials {
+            key,
+            encoding_key,
+            client: api_client(DEFAULT_REQUEST_TIMEOUT),
+            cache: TokenCache::default(),
+        })
+    }
+    pub fn key(&self) -> &ServiceAccountKey {
+        &self.key
+    }
+    fn assertion(&self) -> Result<String, CredentialsError> {
+        let iat = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let claims = AssertionClaims {
+            iss: &self.key.client_email,
+            scope: SCOPES.join(" "),
+            aud: &self.key.token_uri,
+            iat,
+            exp: iat + ASSERTION_LIFETIME_SECS,
+        };
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(self.key.private_key_id.clone());
+        encode(&header, &claims, &self.encoding_key).map_err(CredentialsError::InvalidPrivateKey)
+    }
+    async fn fetch_token(&self) -> Result<TokenResponse, CredentialsError> {
+        let assertion = self.assertion()?;
+        let response = self
+            .client
+            .post(&self.key.token_uri)
+            .form(&[
+                ("grant_type", JWT_BEARER_GRANT_TYPE),
+                ("assertion", assertion.as_str()),
+            ])
+            .send()
+            .await
+            .map_err(CredentialsError::RequestError)?;
+        parse_token_response(response).await
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl TokenSource for ServiceAccountCredentials {
+    async fn token(&self) -> Result<String, CredentialsError> {
+        self.cache.get_or_fetch(|| self.fetch_token()).await
+    }
+}
+
+/// Access tokens for the default service account of a GCE, GKE, Cloud Run or
+/// Cloud Functions instance, fetched from the metadata server.
+pub struct MetadataServerCredentials {
+    url: String,
+    client: reqwest::Client,
+    cache: TokenCache,
+}
+
+impl Default for MetadataServerCredentials {
+    fn default() -> Self {
+        Self::with_url(METADATA_TOKEN_URL.to_string())
+    }
+}
+
+impl MetadataServerCredentials {
+    pub fn new() -> MetadataServerCredentials {
+        MetadataServerCredentials::default()
+    }
+    pub fn with_url(url: String) -> MetadataServerCredentials {
+        MetadataServerCredentials {
+            url,
+            client: api_client(METADATA_TIMEOUT),
+            cache: TokenCache::default(),
+        }
+    }
+    async fn fetch_token(&self) -> Result<TokenResponse, CredentialsError> {
+        let response = self
+            .client
+            .get(&self.url)
+            .header("Metadata-Flavor", "Google")
+            .send()
+            .await
+            .map_err(CredentialsError::RequestError)?;
+        parse_token_response(response).await
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl TokenSource for MetadataServerCredentials {
+    async fn token(&self) -> Result<String, CredentialsError> {
+        self.cache.get_or_fetch(|| self.fetch_token()).await
+    }
+}
+
+/// Application Default Credentials: the service account key file pointed to by
+/// `GOOGLE_APPLICATION_CREDENTIALS`, falling back to the metadata server.
+pub fn application_default() -> Result<Box<dyn TokenSource + Send + Sync>, CredentialsError> {
+    match ServiceAccountKey::from_env() {
+        Some(key) => Ok(Box::new(ServiceAccountCredentials::new(key?)?)),
+        None => Ok(Box::new(MetadataServerCredentials::new())),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FirebaseConfig {
+    project_id: Option<String>,
+}
+
+/// Reads the `projectId` of `FIREBASE_CONFIG`, which holds either the JSON
+/// config itself or the path to a file holding it.
+fn firebase_config_project_id(config: &str) -> Result<Option<String>, CredentialsError> {
+    let json = if config.trim_start().starts_with('{') {
+        config.to_string()
+    } else {
+        std::fs::read_to_string(config).map_err(CredentialsError::IoError)?
+    };
+    let config: FirebaseConfig =
+        serde_json::from_str(&json).map_err(CredentialsError::InvalidFirebaseConfig)?;
+    Ok(config
+        .project_id
+        .filter(|project_id| !project_id.is_empty()))
+}
+
+/// The id of the Google Cloud project the process runs in, looked up like the
+/// Firebase Admin SDKs do: `GOOGLE_CLOUD_PROJECT` (or `GCLOUD_PROJECT`), the
+/// `projectId` of `FIREBASE_CONFIG`, the service account key file pointed to
+/// by `GOOGLE_APPLICATION_CREDENTIALS`, and finally the metadata server.
+pub async fn default_project_id() -> Result<String, CredentialsError> {
+    resolve_project_id(
+        |name| std::env::var(name).ok(),
+        METADATA_PROJECT_ID_URL.to_string(),
+    )
+    .await
+}
+
+async fn resolve_project_id<E>(env: E, metadata_url: String) -> Result<String, CredentialsError>
+where
+    E: Fn(&str) -> Option<String>,
+{
+    let env = |name: &str| env(name).filter(|value| !value.is_empty());
+    if let Some(project_id) = PROJECT_ENVS.iter().find_map(|name| env(name)) {
+        return Ok(project_id);
+    }
+    if let Some(config) = env(FIREBASE_CONFIG_ENV) {
+        if let Some(project_id) = firebase_config_project_id(&config)? {
+            return Ok(project_id);
+        }
+    }
+    if let Some(path) = env(CREDENTIALS_ENV) {
+        return ServiceAccountKey::from_file(path).map(|key| key.project_id);
+    }
+    metadata_project_id(&metadata_url)
+        .await
+        .ok_or(CredentialsError::MissingProjectId)
+}
+
+async fn metadata_project_id(url: &str) -> Option<String> {
+    let request = reqwest::Client::new()
+        .get(url)
+        .header("Metadata-Flavor", "Google");
+    #[cfg(not(target_arch = "wasm32"))]
+    let request = request.timeout(METADATA_TIMEOUT);
+    let response = request.send().await.ok()?.error_for_status().ok()?;
+    let project_id = response.text().await.ok()?;
+    Some(project_id.trim().to_string()).filter(|project_id| !project_id.is_empty())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::tests::*;
+    use serde_json::json;
+    use wiremock::matchers::{body_string_contains, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    pub fn get_test_service_account(token_uri: &str) -> ServiceAccountKey {
+        ServiceAccountKey {
+            project_id: "pj".to_string(),
+            private_key_id: SIGNING_KID.to_string(),
+            private_key: PRIVATE_KEY_PEM.to_string(),
+            client_email: "firebase-adminsdk@pj.iam.gserviceaccount.com".to_string(),
+            token_uri: token_uri.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_service_account_key_from_json() {
+        let json = json!({
+            "type": "service_account",
+            "project_id": "pj",
+            "private_key_id": "key-id",
+            "private_key": "pem",
+            "client_email": "sa@pj.iam.gserviceaccount.com",
+            "client_id": "1234",
+        });
+        let key = ServiceAccountKey::from_json(&json.to_string()).unwrap();
+        assert_eq!(key.project_id, "pj");
+        assert_eq!(key.private_key_id, "key-id");
+        assert_eq!(key.client_email, "sa@pj.iam.gserviceaccount.com");
+        assert_eq!(key.token_uri, GOOGLE_TOKEN_URI);
+    }
+
+    #[test]
+    fn test_service_account_key_from_invalid_json() {
+        assert!(matches!(
+            ServiceAccountKey::from_json("{}"),
+            Err(CredentialsError::InvalidServiceAccount(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_service_account_token_is_cached() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .and(body_string_contains("grant_type=urn"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "access_token": "access-token",
+                "expires_in": 3599,
+                "token_type": "Bearer",
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let token_uri = format!("{}/token", mock_server.uri());
+        let credentials =
+            ServiceAccountCredentials::new(get_test_service_account(&token_uri)).unwrap();
+
+        assert_eq!(credentials.token().await.unwrap(), "access-token");
+        assert_eq!(credentials.token().await.unwrap(), "access-token");
+    }
+
+    #[tokio::test]
+    async fn test_token_cache_clamps_lifetime() {
+        let cache = TokenCache::default();
+        let token = cache
+            .get_or_fetch(|| async {
+                Ok(TokenResponse {
+                    access_token: "token".to_string(),
+                    expires_in: u64::MAX,
+                })
+            })
+            .await
+            .unwrap();
+        assert_eq!(token, "token");
+        let expires_at = cache.0.lock().await.as_ref().unwrap().expires_at;
+        assert!(expires_at <= Instant::now() + MAX_TOKEN_LIFETIME);
+    }
+
+    #[tokio::test]
+    async fn test_service_account_token_endpoint_error() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .respond_with(ResponseTemplate::new(400).set_body_string("invalid_grant"))
+            .mount(&mock_server)
+            .await;
+        let token_uri = format!("{}/token", mock_server.uri());
+        let credentials =
+            ServiceAccountCredentials::new(get_test_service_account(&token_uri)).unwrap();
+
+        assert!(matches!(
+            credentials.token().await,
+            Err(CredentialsError::TokenEndpointError { status: 400, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_metadata_server_token() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/token"))
+            .and(header("Metadata-Flavor", "Google"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "access_token": "metadata-token",
+                "expires_in": 3599,
+                "token_type": "Bearer",
+            })))
+            .mount(&mock_server)
+            .await;
+        let credentials =
+            MetadataServerCredentials::with_url(format!("{}/token", mock_server.uri()));
+
+        assert_eq!(credentials.token().await.unwrap(), "metadata-token");
+    }
+
+    #[tokio::test]
+    async fn test_metadata_server_timeout() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/token"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(30)))
+            .mount(&mock_server)
+            .await;
+        let credentials =
+            MetadataServerCredentials::with_url(format!("{}/token", mock_server.uri()));
+
+        let started = Instant::now();
+        assert!(matches!(
+            credentials.token().await,
+            Err(CredentialsError::RequestError(_))
+        ));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    fn env<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| {
+            vars.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_project_id() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/project-id"))
+            .and(header("Metadata-Flavor", "Google"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("from-metadata"))
+            .mount(&mock_server)
+            .await;
+        let metadata_url = format!("{}/project-id", mock_server.uri());
+        let resolve = |vars| resolve_project_id(env(vars), metadata_url.clone());
+
+        let key_file = std::env::temp_dir().join("firebase-admin-auth-rs-test-sa.json");
+        std::fs::write(
+            &key_file,
+            json!({
+                "project_id": "from-key",
+                "private_key_id": "key-id",
+                "private_key": "pem",
+                "client_email": "sa@from-key.iam.gserviceaccount.com",
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let key_path = key_file.to_str().unwrap();
+        let config = r#"{"projectId": "from-config", "storageBucket": "b"}"#;
+        let all = [
+            ("GOOGLE_CLOUD_PROJECT", "from-env"),
+            ("FIREBASE_CONFIG", config),
+            ("GOOGLE_APPLICATION_CREDENTIALS", key_path),
+        ];
+        assert_eq!(resolve(&all).await.unwrap(), "from-env");
+        assert_eq!(
+            resolve(&[("GCLOUD_PROJECT", "from-gcloud")]).await.unwrap(),
+            "from-gcloud"
+        );
+        assert_eq!(resolve(&all[1..]).await.unwrap(), "from-config");
+        let without_project = [("FIREBASE_CONFIG", "{}"), all[2]];
+        assert_eq!(resolve(&without_project).await.unwrap(), "from-key");
+        assert_eq!(resolve(&all[2..]).await.unwrap(), "from-key");
+        assert_eq!(
+            resolve(&[("GOOGLE_CLOUD_PROJECT", "")]).await.unwrap(),
+            "from-metadata"
+        );
+        assert!(matches!(
+            resolve(&[("FIREBASE_CONFIG", "{")]).await,
+            Err(CredentialsError::InvalidFirebaseConfig(_))
+        ));
+        std::fs::remove_file(&key_file).unwrap();
+
+        let unavailable = resolve_project_id(env(&[]), format!("{}/missing", mock_server.uri()));
+        assert!(matches!(
+            unavailable.await,
+            Err(CredentialsError::MissingProjectId)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_boxed_token_source() {
+        let token_source: Box<dyn TokenSource + Send + Sync> =
+            Box::new(StaticToken("owner".to_string()));
+        assert_eq!(token_source.token().await.unwrap(), "owner");
+    }
+}
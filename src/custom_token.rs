@@ -0,0 +1,217 @@
+use crate::credentials::ServiceAccountKey;
+use crate::time::{SystemTime, UNIX_EPOCH};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+const CUSTOM_TOKEN_AUDIENCE: &str =
+    "https://identitytoolkit.googleapis.com/google.identity.identitytoolkit.v1.IdentityToolkit";
+const TOKEN_LIFETIME_SECS: u64 = 3600;
+pub(crate) const MAX_UID_LENGTH: usize = 128;
+pub(crate) const RESERVED_CLAIMS: &[&str] = &[
+    "acr",
+    "amr",
+    "at_hash",
+    "aud",
+    "auth_time",
+    "azp",
+    "cnf",
+    "c_hash",
+    "exp",
+    "firebase",
+    "iat",
+    "iss",
+    "jti",
+    "nbf",
+    "nonce",
+    "sub",
+];
+
+#[derive(Debug, Error)]
+pub enum CustomTokenError {
+    #[error("uid must be a non-empty string of at most 128 characters")]
+    InvalidUid,
+    #[error("developer claim \"{0}\" is reserved")]
+    ReservedClaim(String),
+    #[error("invalid private key: {0}")]
+    InvalidPrivateKey(#[source] jsonwebtoken::errors::Error),
+    #[error("unable to sign token: {0}")]
+    SigningError(#[source] jsonwebtoken::errors::Error),
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct CustomTokenClaims {
+    pub aud: String,
+    pub iss: String,
+    pub sub: String,
+    pub iat: u64,
+    pub exp: u64,
+    pub uid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub claims: Option<Map<String, Value>>,
+}
+
+/// Mints Firebase custom tokens signed with a service account's private key.
+/// Clients exchange them for ID tokens via `signInWithCustomToken`.
+pub struct CustomTokenCreator {
+    client_email: String,
+    encoding_key: EncodingKey,
+    key_id: Option<String>,
+    tenant_id: Option<String>,
+}
+
+impl CustomTokenCreator {
+    /// `private_key` is the PEM encoded `private_key` of a service account.
+    pub fn new(client_email: String, private_key: &str) -> Result<Self, CustomTokenError> {
+        let encoding_key = EncodingKey::from_rsa_pem(private_key.as_bytes())
+            .map_err(CustomTokenError::InvalidPrivateKey)?;
+        Ok(CustomTokenCreator {
+            client_email,
+            encoding_key,
+            key_id: None,
+            tenant_id: None,
+        })
+    }
+    pub fn from_service_account(key: &ServiceAccountKey) -> Result<Self, CustomTokenError> {
+        Ok(Self::new(key.client_email.clone(), &key.private_key)?
+            .with_key_id(key.private_key_id.clone()))
+    }
+    /// Sets the `kid` header, usually the service account's `private_key_id`.
+    pub fn with_key_id(mut self, key_id: String) -> Self {
+        self.key_id = Some(key_id);
+        self
+    }
+    /// Mints tokens that sign users in to the given Identity Platform tenant.
+    pub fn with_tenant_id(mut self, tenant_id: String) -> Self {
+        self.tenant_id = Some(tenant_id);
+        self
+    }
+    pub fn create_custom_token(&self, uid: &str) -> Result<String, CustomTokenError> {
+        self.create_custom_token_with_claims(uid, Map::new())
+    }
+    /// `developer_claims` end up as top-level claims in the user's ID token.
+    pub fn create_custom_token_with_claims(
+        &self,
+        uid: &str,
+        developer_claims: Map<String, Value>,
+    ) -> Result<String, CustomTokenError> {
+        if uid.is_empty() || uid.chars().count() > MAX_UID_LENGTH {
+            return Err(CustomTokenError::InvalidUid);
+        }
+        if let Some(name) = developer_claims
+            .keys()
+            .find(|name| RESERVED_CLAIMS.contains(&name.as_str()))
+        {
+            return Err(CustomTokenError::ReservedClaim(name.clone()));
+        }
+        let iat = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let claims = CustomTokenClaims {
+            aud: CUSTOM_TOKEN_AUDIENCE.to_string(),
+            iss: self.client_email.clone(),
+            sub: self.client_email.clone(),
+            iat,
+            exp: iat + TOKEN_LIFETIME_SECS,
+            uid: uid.to_string(),
+            tenant_id: self.tenant_id.clone(),
+            claims: if developer_claims.is_empty() {
+                None
+            } else {
+                Some(developer_claims)
+            },
+        };
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = self.key_id.clone();
+        encode(&header, &claims, &self.encoding_key).map_err(CustomTokenError::SigningError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use jsonwebtoken::{decode, Validation};
+    use serde_json::json;
+
+    const CLIENT_EMAIL: &str = "firebase-adminsdk@pj.iam.gserviceaccount.com";
+
+    fn decode_custom_token(token: &str) -> CustomTokenClaims {
+        let key = get_signing_key().decoding_key().unwrap();
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_audience(&[CUSTOM_TOKEN_AUDIENCE]);
+        decode::<CustomTokenClaims>(token, &key, &validation)
+            .unwrap()
+            .claims
+    }
+
+    #[test]
+    fn test_create_custom_token() {
+        let creator = CustomTokenCreator::new(CLIENT_EMAIL.to_string(), PRIVATE_KEY_PEM).unwrap();
+        let claims = decode_custom_token(&creator.create_custom_token("uid").unwrap());
+        assert_eq!(claims.uid, "uid");
+        assert_eq!(claims.iss, CLIENT_EMAIL);
+        assert_eq!(claims.sub, CLIENT_EMAIL);
+        assert_eq!(claims.exp - claims.iat, TOKEN_LIFETIME_SECS);
+        assert_eq!(claims.claims, None);
+    }
+
+    #[test]
+    fn test_create_custom_token_with_claims() {
+        let creator = CustomTokenCreator::new(CLIENT_EMAIL.to_string(), PRIVATE_KEY_PEM)
+            .unwrap()
+            .with_tenant_id("tenant-1".to_string());
+        let developer_claims = json!({ "role": "admin" }).as_object().unwrap().clone();
+        let token = creator
+            .create_custom_token_with_claims("uid", developer_claims.clone())
+            .unwrap();
+        let claims = decode_custom_token(&token);
+        assert_eq!(claims.claims, Some(developer_claims));
+        assert_eq!(claims.tenant_id, Some("tenant-1".to_string()));
+    }
+
+    #[test]
+    fn test_create_custom_token_invalid_uid() {
+        let creator = CustomTokenCreator::new(CLIENT_EMAIL.to_string(), PRIVATE_KEY_PEM).unwrap();
+        assert!(matches!(
+            creator.create_custom_token(""),
+            Err(CustomTokenError::InvalidUid)
+        ));
+        assert!(matches!(
+            creator.create_custom_token(&"a".repeat(129)),
+            Err(CustomTokenError::InvalidUid)
+        ));
+    }
+
+    #[test]
+    fn test_create_custom_token_reserved_claim() {
+        let creator = CustomTokenCreator::new(CLIENT_EMAIL.to_string(), PRIVATE_KEY_PEM).unwrap();
+        let developer_claims = json!({ "sub": "other" }).as_object().unwrap().clone();
+        assert!(matches!(
+            creator.create_custom_token_with_claims("uid", developer_claims),
+            Err(CustomTokenError::ReservedClaim(name)) if name == "sub"
+        ));
+    }
+
+    #[test]
+    fn test_new_invalid_private_key() {
+        assert!(matches!(
+            CustomTokenCreator::new(CLIENT_EMAIL.to_string(), "not a key"),
+            Err(CustomTokenError::InvalidPrivateKey(_))
+        ));
+    }
+
+    #[test]
+    fn test_from_service_account() {
+        let key = crate::credentials::tests::get_test_service_account("");
+        let creator = CustomTokenCreator::from_service_account(&key).unwrap();
+        let token = creator.create_custom_token("uid").unwrap();
+        let header = jsonwebtoken::decode_header(&token).unwrap();
+        assert_eq!(header.kid, Some(key.private_key_id));
+        assert_eq!(decode_custom_token(&token).iss, key.client_email);
+    }
+}
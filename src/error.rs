@@ -0,0 +1,81 @@
+//! The error types of this crate, and [`Error`] wrapping all of them for
+//! callers that handle them alike, e.g. with `?` in one function.
+
+pub use crate::app_check::AppCheckError;
+#[cfg(feature = "reqwest")]
+pub use crate::credentials::CredentialsError;
+#[cfg(feature = "reqwest")]
+pub use crate::custom_token::CustomTokenError;
+pub use crate::header_parser::MaxAgeParseError;
+#[cfg(feature = "reqwest")]
+pub use crate::identity_toolkit::{FirebaseAuthError, IdentityToolkitError};
+pub use crate::integration::FirebaseAuthRejection;
+pub use crate::jwk::KeyFetchError;
+pub use crate::jwk_auth::JwkAuthError;
+#[cfg(feature = "reqwest")]
+pub use crate::token_exchange::TokenExchangeError;
+pub use crate::verifier::VerifyError;
+pub use crate::x509::CertParseError;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    JwkAuth(#[from] JwkAuthError),
+    #[error(transparent)]
+    KeyFetch(#[from] KeyFetchError),
+    #[error(transparent)]
+    Verify(#[from] VerifyError),
+    #[error(transparent)]
+    AppCheck(#[from] AppCheckError),
+    #[error(transparent)]
+    Rejection(#[from] FirebaseAuthRejection),
+    #[cfg(feature = "reqwest")]
+    #[error(transparent)]
+    Credentials(#[from] CredentialsError),
+    #[cfg(feature = "reqwest")]
+    #[error(transparent)]
+    CustomToken(#[from] CustomTokenError),
+    #[cfg(feature = "reqwest")]
+    #[error(transparent)]
+    IdentityToolkit(#[from] IdentityToolkitError),
+    #[cfg(feature = "reqwest")]
+    #[error(transparent)]
+    TokenExchange(#[from] TokenExchangeError),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    fn verify(token: &str) -> Result<()> {
+        if token.is_empty() {
+            return Err(VerifyError::Malformed.into());
+        }
+        Err(JwkAuthError::KeyFetchError(
+            KeyFetchError::InvalidCertificate(CertParseError::InvalidPem),
+        ))?
+    }
+
+    #[test]
+    fn test_error_conversion() {
+        let error = verify("").unwrap_err();
+        assert!(matches!(error, Error::Verify(VerifyError::Malformed)));
+        assert_eq!(error.to_string(), "malformed token");
+    }
+
+    #[test]
+    fn test_error_source_chain() {
+        let error = verify("token").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "unable to fetch jwk keys: invalid certificate: invalid PEM"
+        );
+        let source = error.source().unwrap();
+        assert_eq!(source.to_string(), "invalid certificate: invalid PEM");
+        assert_eq!(source.source().unwrap().to_string(), "invalid PEM");
+    }
+}
@@ -1,62 +1,158 @@
-use reqwest::header::HeaderValue;
-use reqwest::Response;
+use crate::http::HttpResponse;
 use std::time::Duration;
+use thiserror::Error;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Error)]
 pub enum MaxAgeParseError {
+    #[error("no max-age directive")]
     NoMaxAgeStr,
+    #[error("no Cache-Control header")]
     NoCacheControlKey,
+    #[error("empty Cache-Control header")]
     NoCacheControlValue,
+    #[error("directive value is not a number of seconds")]
     NotNumericValue,
 }
 
-pub fn get_max_age(response: &Response) -> Result<Duration, MaxAgeParseError> {
-    let headers = response.headers();
-    let cache_control = headers.get("Cache-Control");
-
-    match cache_control {
-        Some(cache_control_value) => parse_cache_control_value(cache_control_value),
+pub fn get_max_age(response: &HttpResponse) -> Result<Duration, MaxAgeParseError> {
+    match response.header("Cache-Control") {
+        Some(value) if value.trim().is_empty() => Err(MaxAgeParseError::NoCacheControlValue),
+        Some(value) => _parse_cache_control_value(value),
         None => Err(MaxAgeParseError::NoCacheControlKey),
     }
 }
 
-fn parse_cache_control_value(value: &HeaderValue) -> Result<Duration, MaxAgeParseError> {
-    match value.to_str() {
-        Ok(str_value) => _parse_cache_control_value(str_value),
-        Err(_) => Err(MaxAgeParseError::NoCacheControlValue),
-    }
+/// The `Age` header: how long the response has been held by caches.
+pub fn get_age(response: &HttpResponse) -> Option<Duration> {
+    response
+        .header("Age")?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// The `stale-while-revalidate` directive of the `Cache-Control` header.
+pub fn get_stale_while_revalidate(response: &HttpResponse) -> Option<Duration> {
+    CacheControl::parse(response.header("Cache-Control")?).stale_while_revalidate
 }
 
 fn _parse_cache_control_value(value: &str) -> Result<Duration, MaxAgeParseError> {
-    let tokens: Vec<&str> = value.split(",").collect();
-    for token in tokens {
-        let kv: Vec<&str> = token.split("=").map(|s| s.trim()).collect();
-        let key = kv.first().unwrap();
-        let value = kv.get(1);
-        if String::from("max-age").eq(&key.to_lowercase()) {
-            match value {
-                Some(value) => {
-                    return Ok(Duration::from_secs(
-                        value
-                            .parse()
-                            .map_err(|_| MaxAgeParseError::NotNumericValue)?,
-                    ))
-                }
-                None => {
-                    unreachable!();
+    if let Some(validity) = CacheControl::parse(value).validity() {
+        return Ok(validity);
+    }
+    let has_max_age = directives(value).any(|(name, _)| {
+        name.eq_ignore_ascii_case("max-age") || name.eq_ignore_ascii_case("s-maxage")
+    });
+    if has_max_age {
+        Err(MaxAgeParseError::NotNumericValue)
+    } else {
+        Err(MaxAgeParseError::NoMaxAgeStr)
+    }
+}
+
+/// The directives of a `Cache-Control` header that bear on how long keys
+/// may be used. Other directives are ignored.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct CacheControl {
+    pub max_age: Option<Duration>,
+    /// Takes precedence over `max_age` for shared caches, like this crate's.
+    pub s_maxage: Option<Duration>,
+    pub no_store: bool,
+    pub no_cache: bool,
+    /// How long stale keys may still be served while they are refreshed.
+    pub stale_while_revalidate: Option<Duration>,
+}
+
+impl CacheControl {
+    /// Parses a header value. Directive names are case-insensitive, values
+    /// may be quoted, and the first occurrence of a directive wins.
+    /// Directives whose value is not a number of seconds are skipped.
+    pub fn parse(value: &str) -> CacheControl {
+        let mut cache_control = CacheControl::default();
+        for (name, value) in directives(value) {
+            let seconds = |field: &mut Option<Duration>| {
+                if field.is_none() {
+                    *field = value
+                        .and_then(|value| value.parse().ok())
+                        .map(Duration::from_secs);
                 }
+            };
+            match name.to_ascii_lowercase().as_str() {
+                "max-age" => seconds(&mut cache_control.max_age),
+                "s-maxage" => seconds(&mut cache_control.s_maxage),
+                "stale-while-revalidate" => seconds(&mut cache_control.stale_while_revalidate),
+                "no-store" => cache_control.no_store = true,
+                "no-cache" => cache_control.no_cache = true,
+                _ => {}
             }
         }
+        cache_control
+    }
+    /// How long a response may be used without revalidation: zero with
+    /// `no-store` or `no-cache`, otherwise `s-maxage` or else `max-age`.
+    pub fn validity(&self) -> Option<Duration> {
+        if self.no_store || self.no_cache {
+            return Some(Duration::ZERO);
+        }
+        self.s_maxage.or(self.max_age)
     }
-    return Err(MaxAgeParseError::NoMaxAgeStr);
+}
+
+/// The names and unquoted values of the directives of a header value.
+fn directives(value: &str) -> impl Iterator<Item = (&str, Option<&str>)> {
+    split_directives(value)
+        .into_iter()
+        .map(|directive| match directive.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(unquote(value.trim()))),
+            None => (directive.trim(), None),
+        })
+}
+
+/// Splits at commas outside of quoted strings, skipping empty directives.
+fn split_directives(value: &str) -> Vec<&str> {
+    let mut directives = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (index, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                directives.push(&value[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    directives.push(&value[start..]);
+    directives
+        .into_iter()
+        .filter(|directive| !directive.trim().is_empty())
+        .collect()
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(feature = "reqwest")]
+    use crate::http::{HttpClient, ReqwestClient};
+    #[cfg(feature = "reqwest")]
     use crate::jwk::KeyResponse;
     use crate::tests::*;
+    use std::collections::HashMap;
+    #[cfg(feature = "reqwest")]
     use wiremock::matchers::{method, path};
+    #[cfg(feature = "reqwest")]
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
     #[tokio::test]
@@ -91,34 +187,121 @@ mod tests {
         assert_eq!(result, Err(MaxAgeParseError::NotNumericValue));
     }
 
+    #[test]
+    fn test_parse_cache_control_without_max_age_value_or_equals() {
+        assert_eq!(
+            _parse_cache_control_value("public, max-age"),
+            Err(MaxAgeParseError::NotNumericValue)
+        );
+    }
+
+    #[test]
+    fn test_cache_control_parse() {
+        let value = r#" Public ,MAX-AGE = "600",  s-maxage=300, stale-while-revalidate=60,,"#;
+        assert_eq!(
+            CacheControl::parse(value),
+            CacheControl {
+                max_age: Some(Duration::from_secs(600)),
+                s_maxage: Some(Duration::from_secs(300)),
+                stale_while_revalidate: Some(Duration::from_secs(60)),
+                ..CacheControl::default()
+            }
+        );
+        assert_eq!(
+            CacheControl::parse(value).validity(),
+            Some(Duration::from_secs(300))
+        );
+    }
+
+    #[test]
+    fn test_cache_control_quoted_commas() {
+        let cache_control = CacheControl::parse(r#"private="set-cookie, x-foo", max-age=120"#);
+        assert_eq!(cache_control.max_age, Some(Duration::from_secs(120)));
+        let cache_control = CacheControl::parse(r#"ext="a \"quoted, value\"", max-age=30"#);
+        assert_eq!(cache_control.max_age, Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_cache_control_no_store_and_no_cache() {
+        for value in ["no-store, max-age=600", "max-age=600, no-cache"] {
+            assert_eq!(CacheControl::parse(value).validity(), Some(Duration::ZERO));
+        }
+        assert_eq!(
+            CacheControl::parse(r#"no-cache="set-cookie""#).validity(),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn test_cache_control_first_directive_wins() {
+        let cache_control = CacheControl::parse("max-age=10, max-age=20");
+        assert_eq!(cache_control.max_age, Some(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn test_cache_control_skips_unparseable_directives() {
+        let cache_control = CacheControl::parse("s-maxage=soon, max-age=abc, max-age=60");
+        assert_eq!(cache_control.s_maxage, None);
+        assert_eq!(cache_control.validity(), Some(Duration::from_secs(60)));
+        assert_eq!(
+            _parse_cache_control_value("s-maxage=-1, max-age=60"),
+            Ok(Duration::from_secs(60))
+        );
+    }
+
+    fn response_with_cache_control(value: &str) -> HttpResponse {
+        HttpResponse {
+            status: 200,
+            headers: HashMap::from([("cache-control".to_string(), value.to_string())]),
+            body: Vec::new(),
+        }
+    }
+
     #[tokio::test]
     async fn test_parse_cache_control() {
-        let cc_header =
-            HeaderValue::from_static("public, max-age=20045, must-revalidate, no-transform");
+        let response =
+            response_with_cache_control("public, max-age=20045, must-revalidate, no-transform");
         assert_eq!(
-            parse_cache_control_value(&cc_header),
+            get_max_age(&response),
             Ok(std::time::Duration::from_secs(20045))
         );
     }
 
     #[tokio::test]
     async fn test_parse_cache_control_without_cache_control_value() {
-        let cc_header = HeaderValue::from_bytes(b"hello\xfa").unwrap();
+        let response = response_with_cache_control(" ");
         assert_eq!(
-            parse_cache_control_value(&cc_header),
+            get_max_age(&response),
             Err(MaxAgeParseError::NoCacheControlValue)
         );
     }
 
+    #[test]
+    fn test_get_age() {
+        let mut response = response_with_cache_control("max-age=100");
+        assert_eq!(get_age(&response), None);
+        response.headers.insert("age".to_string(), "42".to_string());
+        assert_eq!(get_age(&response), Some(Duration::from_secs(42)));
+        response
+            .headers
+            .insert("age".to_string(), "soon".to_string());
+        assert_eq!(get_age(&response), None);
+    }
+
+    #[cfg(feature = "reqwest")]
     #[tokio::test]
     async fn test_get_max_age_by_response() {
         let mock_server = get_mock_server().await;
-        let response = reqwest::get(&get_mock_url(&mock_server)).await.unwrap();
+        let response = ReqwestClient::default()
+            .get(&get_mock_url(&mock_server))
+            .await
+            .unwrap();
         assert_eq!(
             get_max_age(&response).unwrap(),
             std::time::Duration::from_secs(MAXAGE)
         )
     }
+    #[cfg(feature = "reqwest")]
     #[tokio::test]
     async fn test_get_max_age_by_response_without_cache_control() {
         let mock_server = MockServer::start().await;
@@ -129,7 +312,10 @@ mod tests {
             }))
             .mount(&mock_server)
             .await;
-        let response = reqwest::get(&get_mock_url(&mock_server)).await.unwrap();
+        let response = ReqwestClient::default()
+            .get(&get_mock_url(&mock_server))
+            .await
+            .unwrap();
         assert_eq!(
             get_max_age(&response),
             Err(MaxAgeParseError::NoCacheControlKey)
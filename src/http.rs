@@ -0,0 +1,309 @@
+//! The HTTP client keys are fetched with, replaceable to reuse an existing
+//! HTTP stack, e.g. hyper, ureq or a wasm backend, instead of reqwest.
+
+pub use crate::header_parser::{CacheControl, MaxAgeParseError};
+use crate::time::Duration;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub type HttpError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The timeout of each key request, unless configured otherwise.
+#[cfg(feature = "reqwest")]
+pub(crate) const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+#[cfg(feature = "reqwest")]
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct HttpResponse {
+    pub status: u16,
+    /// Header values by lowercase header name.
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    /// The value of the header `name`, matched case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// Sends the GET requests for keys and provider metadata.
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+pub trait HttpClient {
+    async fn get(&self, url: &str) -> Result<HttpResponse, HttpError>;
+    /// Like [`get`](Self::get), sending `headers` along, e.g. the validators of
+    /// a conditional request. Clients that ignore them only lose the savings
+    /// of `304 Not Modified` responses.
+    async fn get_with_headers(
+        &self,
+        url: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<HttpResponse, HttpError> {
+        let _ = headers;
+        self.get(url).await
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl<C: HttpClient + Send + Sync + ?Sized> HttpClient for Arc<C> {
+    async fn get(&self, url: &str) -> Result<HttpResponse, HttpError> {
+        self.as_ref().get(url).await
+    }
+    async fn get_with_headers(
+        &self,
+        url: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<HttpResponse, HttpError> {
+        self.as_ref().get_with_headers(url, headers).await
+    }
+}
+
+/// The default [`HttpClient`], built on reqwest.
+#[cfg(feature = "reqwest")]
+#[derive(Debug, Clone, Default)]
+pub struct ReqwestClient {
+    pub client: reqwest::Client,
+    /// Ignored on wasm32, where reqwest does not support timeouts.
+    pub timeout: Option<Duration>,
+}
+
+#[cfg(feature = "reqwest")]
+impl ReqwestClient {
+    pub fn new(client: reqwest::Client) -> ReqwestClient {
+        ReqwestClient {
+            client,
+            timeout: None,
+        }
+    }
+}
+
+#[cfg(feature = "reqwest")]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl HttpClient for ReqwestClient {
+    async fn get(&self, url: &str) -> Result<HttpResponse, HttpError> {
+        self.get_with_headers(url, &[]).await
+    }
+    async fn get_with_headers(
+        &self,
+        url: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<HttpResponse, HttpError> {
+        let request = headers
+            .iter()
+            .fold(self.client.get(url), |request, (name, value)| {
+                request.header(*name, *value)
+            });
+        #[cfg(not(target_arch = "wasm32"))]
+        let request = match self.timeout {
+            Some(timeout) => request.timeout(timeout),
+            None => request,
+        };
+        let response = request.send().await?;
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let body = response.bytes().await?.to_vec();
+        Ok(HttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+/// Connection settings of the reqwest client keys are fetched with. They are
+/// ignored on wasm32, where the browser's settings apply.
+#[cfg(feature = "reqwest")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpConfig {
+    pub connect_timeout: Option<Duration>,
+    /// Proxy URL for all requests, e.g. `http://proxy.internal:3128`.
+    pub proxy: Option<String>,
+    /// PEM encoded CA certificates trusted in addition to the system roots.
+    pub root_certificates: Vec<Vec<u8>>,
+}
+
+#[cfg(feature = "reqwest")]
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            connect_timeout: Some(DEFAULT_CONNECT_TIMEOUT),
+            proxy: None,
+            root_certificates: Vec::new(),
+        }
+    }
+}
+
+#[cfg(feature = "reqwest")]
+impl HttpConfig {
+    /// Fails on an invalid proxy URL or certificate.
+    pub fn build_client(&self) -> Result<reqwest::Client, HttpError> {
+        let builder = reqwest::Client::builder();
+        #[cfg(not(target_arch = "wasm32"))]
+        let builder = {
+            let mut builder = builder;
+            if let Some(connect_timeout) = self.connect_timeout {
+                builder = builder.connect_timeout(connect_timeout);
+            }
+            if let Some(proxy) = &self.proxy {
+                builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+            }
+            for certificate in &self.root_certificates {
+                builder =
+                    builder.add_root_certificate(reqwest::Certificate::from_pem(certificate)?);
+            }
+            builder
+        };
+        Ok(builder.build()?)
+    }
+    #[cfg(feature = "blocking")]
+    pub(crate) fn build_blocking_client(&self) -> Result<reqwest::blocking::Client, HttpError> {
+        let mut builder = reqwest::blocking::Client::builder();
+        if let Some(connect_timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+        }
+        for certificate in &self.root_certificates {
+            builder = builder.add_root_certificate(reqwest::Certificate::from_pem(certificate)?);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+/// Stands in for the default client when the `reqwest` feature is disabled.
+#[cfg(not(feature = "reqwest"))]
+struct MissingHttpClient;
+
+#[cfg(not(feature = "reqwest"))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl HttpClient for MissingHttpClient {
+    async fn get(&self, _url: &str) -> Result<HttpResponse, HttpError> {
+        Err("no HTTP client: enable the `reqwest` feature or set one with `JwkAuthBuilder::http_backend`".into())
+    }
+}
+
+/// A [`ReqwestClient`] with the default [`HttpConfig`], timing requests out
+/// after `timeout` or else 30 seconds, or a client failing every request
+/// without the `reqwest` feature.
+pub(crate) fn default_client(timeout: Option<Duration>) -> Arc<dyn HttpClient + Send + Sync> {
+    #[cfg(feature = "reqwest")]
+    return Arc::new(ReqwestClient {
+        client: HttpConfig::default()
+            .build_client()
+            .unwrap_or_else(|_| reqwest::Client::new()),
+        timeout: Some(timeout.unwrap_or(DEFAULT_REQUEST_TIMEOUT)),
+    });
+    #[cfg(not(feature = "reqwest"))]
+    {
+        let _ = timeout;
+        Arc::new(MissingHttpClient)
+    }
+}
+
+/// A reqwest client for the Google APIs called besides the key endpoints,
+/// with the default connect timeout and timing requests out after
+/// `timeout`, so that a stalled server surfaces as an error.
+#[cfg(feature = "reqwest")]
+pub(crate) fn api_client(timeout: Duration) -> reqwest::Client {
+    let builder = reqwest::Client::builder();
+    #[cfg(not(target_arch = "wasm32"))]
+    let builder = builder
+        .connect_timeout(DEFAULT_CONNECT_TIMEOUT)
+        .timeout(timeout);
+    #[cfg(target_arch = "wasm32")]
+    let _ = timeout;
+    builder.build().unwrap_or_else(|_| reqwest::Client::new())
+}
+
+#[cfg(all(test, feature = "reqwest"))]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_reqwest_client_get() {
+        let mock_server = get_mock_server().await;
+        let response = ReqwestClient::default()
+            .get(&get_mock_url(&mock_server))
+            .await
+            .unwrap();
+        assert!(response.is_success());
+        assert!(response
+            .header("Cache-Control")
+            .unwrap()
+            .contains(&format!("max-age={}", MAXAGE)));
+        let body: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+        assert!(body["keys"].is_array());
+    }
+
+    #[tokio::test]
+    async fn test_reqwest_client_timeout() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(PATH))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(5)))
+            .mount(&mock_server)
+            .await;
+        let client = ReqwestClient {
+            timeout: Some(Duration::from_millis(100)),
+            ..ReqwestClient::default()
+        };
+        assert!(client.get(&get_mock_url(&mock_server)).await.is_err());
+    }
+
+    #[test]
+    fn test_http_config_build_client() {
+        let config = HttpConfig {
+            root_certificates: vec![CERT_PEM.as_bytes().to_vec()],
+            proxy: Some("http://proxy.internal:3128".to_string()),
+            ..HttpConfig::default()
+        };
+        assert!(config.build_client().is_ok());
+
+        let invalid_certificate = HttpConfig {
+            root_certificates: vec![b"not a certificate".to_vec()],
+            ..HttpConfig::default()
+        };
+        assert!(invalid_certificate.build_client().is_err());
+        let invalid_proxy = HttpConfig {
+            proxy: Some("not a url".to_string()),
+            ..HttpConfig::default()
+        };
+        assert!(invalid_proxy.build_client().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_http_config_proxy() {
+        let proxy = get_mock_server().await;
+        let client = ReqwestClient::new(
+            HttpConfig {
+                proxy: Some(proxy.uri()),
+                ..HttpConfig::default()
+            }
+            .build_client()
+            .unwrap(),
+        );
+        let response = client.get("http://keys.invalid/test").await.unwrap();
+        assert!(response.is_success());
+        assert_eq!(proxy.received_requests().await.unwrap().len(), 1);
+    }
+}
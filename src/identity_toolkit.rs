@@ -0,0 +1,735 @@
+use crate::credentials::{CredentialsError, TokenSource};
+use crate::http::{api_client, DEFAULT_REQUEST_TIMEOUT};
+use crate::rate_limit::{parse_retry_after, RateLimit, RateLimiter};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+
+const IDENTITY_TOOLKIT_URL: &str = "https://identitytoolkit.googleapis.com/v1";
+const IDENTITY_TOOLKIT_ADMIN_URL: &str = "https://identitytoolkit.googleapis.com/v2";
+const MIN_SESSION_COOKIE_DURATION: Duration = Duration::from_secs(5 * 60);
+const MAX_SESSION_COOKIE_DURATION: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
+#[derive(Debug, Error)]
+pub enum IdentityToolkitError {
+    #[error("credentials error: {0}")]
+    CredentialsError(#[source] CredentialsError),
+    #[error("request failed: {0}")]
+    RequestError(#[source] reqwest::Error),
+    #[error("invalid response body: {0}")]
+    ResponseBodyError(#[source] reqwest::Error),
+    #[error("identity toolkit error ({status}): {message}")]
+    ApiError { status: u16, message: String },
+    #[error("user not found")]
+    UserNotFound,
+    #[error("email already exists")]
+    EmailAlreadyExists,
+    #[error("uid already exists")]
+    UidAlreadyExists,
+    #[error("phone number already exists")]
+    PhoneNumberAlreadyExists,
+    #[error("provider configuration not found")]
+    ProviderConfigNotFound,
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+}
+
+/// The error code at the start of an API error message, e.g. `EMAIL_EXISTS`
+/// in `EMAIL_EXISTS : The email address is already in use`.
+pub(crate) fn server_code(message: &str) -> Option<&str> {
+    message.split([' ', ':']).next()
+}
+
+/// The documented Firebase Auth error an Identity Toolkit error maps to, as
+/// reported by [`IdentityToolkitError::auth_error`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum FirebaseAuthError {
+    BillingNotEnabled,
+    ClaimsTooLarge,
+    ConfigurationExists,
+    ConfigurationNotFound,
+    EmailAlreadyExists,
+    EmailNotFound,
+    ForbiddenClaim,
+    IdTokenExpired,
+    InsufficientPermission,
+    InternalError,
+    InvalidClaims,
+    InvalidConfig,
+    InvalidContinueUri,
+    InvalidCredential,
+    InvalidDynamicLinkDomain,
+    InvalidEmail,
+    InvalidIdToken,
+    InvalidPageToken,
+    InvalidPassword,
+    InvalidPhoneNumber,
+    InvalidProjectId,
+    InvalidProviderId,
+    InvalidSessionCookieDuration,
+    InvalidTenantId,
+    MissingAndroidPackageName,
+    MissingIosBundleId,
+    MissingUid,
+    OperationNotAllowed,
+    PhoneNumberAlreadyExists,
+    ProjectNotFound,
+    QuotaExceeded,
+    ResetPasswordExceedLimit,
+    TenantNotFound,
+    UidAlreadyExists,
+    UnauthorizedContinueUri,
+    UnsupportedTenantOperation,
+    UserDisabled,
+    UserNotFound,
+}
+
+impl FirebaseAuthError {
+    /// Maps a server error code like `EMAIL_EXISTS` to its error.
+    pub fn from_server_code(code: &str) -> Option<FirebaseAuthError> {
+        use FirebaseAuthError::*;
+        let error = match code {
+            "BILLING_NOT_ENABLED" => BillingNotEnabled,
+            "CLAIMS_TOO_LARGE" => ClaimsTooLarge,
+            "CONFIGURATION_EXISTS" => ConfigurationExists,
+            "CONFIGURATION_NOT_FOUND" => ConfigurationNotFound,
+            "DUPLICATE_EMAIL" | "EMAIL_EXISTS" => EmailAlreadyExists,
+            "DUPLICATE_LOCAL_ID" => UidAlreadyExists,
+            "EMAIL_NOT_FOUND" => EmailNotFound,
+            "FORBIDDEN_CLAIM" => ForbiddenClaim,
+            "INSUFFICIENT_PERMISSION" | "PERMISSION_DENIED" => InsufficientPermission,
+            "INTERNAL_ERROR" => InternalError,
+            "INVALID_CLAIMS" => InvalidClaims,
+            "INVALID_CONFIG" => InvalidConfig,
+            "INVALID_CONFIG_ID" => InvalidProviderId,
+            "INVALID_CONTINUE_URI" => InvalidContinueUri,
+            "INVALID_DURATION" => InvalidSessionCookieDuration,
+            "INVALID_DYNAMIC_LINK_DOMAIN" => InvalidDynamicLinkDomain,
+            "INVALID_EMAIL" => InvalidEmail,
+            "INVALID_ID_TOKEN" => InvalidIdToken,
+            "INVALID_PAGE_SELECTION" => InvalidPageToken,
+            "INVALID_PHONE_NUMBER" => InvalidPhoneNumber,
+            "INVALID_PROJECT_ID" => InvalidProjectId,
+            "INVALID_SERVICE_ACCOUNT" => InvalidCredential,
+            "INVALID_TENANT_ID" => InvalidTenantId,
+            "MISSING_ANDROID_PACKAGE_NAME" => MissingAndroidPackageName,
+            "MISSING_IOS_BUNDLE_ID" => MissingIosBundleId,
+            "MISSING_LOCAL_ID" | "MISSING_USER_ACCOUNT" => MissingUid,
+            "OPERATION_NOT_ALLOWED" => OperationNotAllowed,
+            "PHONE_NUMBER_EXISTS" => PhoneNumberAlreadyExists,
+            "PROJECT_NOT_FOUND" => ProjectNotFound,
+            "QUOTA_EXCEEDED" => QuotaExceeded,
+            "RESET_PASSWORD_EXCEED_LIMIT" => ResetPasswordExceedLimit,
+            "TENANT_NOT_FOUND" => TenantNotFound,
+            "TOKEN_EXPIRED" => IdTokenExpired,
+            "UNAUTHORIZED_DOMAIN" => UnauthorizedContinueUri,
+            "UNSUPPORTED_TENANT_OPERATION" => UnsupportedTenantOperation,
+            "USER_DISABLED" => UserDisabled,
+            "USER_NOT_FOUND" => UserNotFound,
+            "WEAK_PASSWORD" => InvalidPassword,
+            _ => return None,
+        };
+        Some(error)
+    }
+    /// The code firebase-admin documents the error as, e.g.
+    /// `auth/email-already-exists`.
+    pub fn code(&self) -> &'static str {
+        use FirebaseAuthError::*;
+        match self {
+            BillingNotEnabled => "auth/billing-not-enabled",
+            ClaimsTooLarge => "auth/claims-too-large",
+            ConfigurationExists => "auth/configuration-exists",
+            ConfigurationNotFound => "auth/configuration-not-found",
+            EmailAlreadyExists => "auth/email-already-exists",
+            EmailNotFound => "auth/email-not-found",
+            ForbiddenClaim => "auth/forbidden-claim",
+            IdTokenExpired => "auth/id-token-expired",
+            InsufficientPermission => "auth/insufficient-permission",
+            InternalError => "auth/internal-error",
+            InvalidClaims => "auth/invalid-claims",
+            InvalidConfig => "auth/invalid-config",
+            InvalidContinueUri => "auth/invalid-continue-uri",
+            InvalidCredential => "auth/invalid-credential",
+            InvalidDynamicLinkDomain => "auth/invalid-dynamic-link-domain",
+            InvalidEmail => "auth/invalid-email",
+            InvalidIdToken => "auth/invalid-id-token",
+            InvalidPageToken => "auth/invalid-page-token",
+            InvalidPassword => "auth/invalid-password",
+            InvalidPhoneNumber => "auth/invalid-phone-number",
+            InvalidProjectId => "auth/invalid-project-id",
+            InvalidProviderId => "auth/invalid-provider-id",
+            InvalidSessionCookieDuration => "auth/invalid-session-cookie-duration",
+            InvalidTenantId => "auth/invalid-tenant-id",
+            MissingAndroidPackageName => "auth/missing-android-pkg-name",
+            MissingIosBundleId => "auth/missing-ios-bundle-id",
+            MissingUid => "auth/missing-uid",
+            OperationNotAllowed => "auth/operation-not-allowed",
+            PhoneNumberAlreadyExists => "auth/phone-number-already-exists",
+            ProjectNotFound => "auth/project-not-found",
+            QuotaExceeded => "auth/quota-exceeded",
+            ResetPasswordExceedLimit => "auth/reset-password-exceed-limit",
+            TenantNotFound => "auth/tenant-not-found",
+            UidAlreadyExists => "auth/uid-already-exists",
+            UnauthorizedContinueUri => "auth/unauthorized-continue-uri",
+            UnsupportedTenantOperation => "auth/unsupported-tenant-operation",
+            UserDisabled => "auth/user-disabled",
+            UserNotFound => "auth/user-not-found",
+        }
+    }
+    /// Whether the same request may succeed later. Only quota and transient
+    /// server errors are; the others need the request or project fixed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            FirebaseAuthError::InternalError
+                | FirebaseAuthError::QuotaExceeded
+                | FirebaseAuthError::ResetPasswordExceedLimit
+        )
+    }
+}
+
+impl fmt::Display for FirebaseAuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl IdentityToolkitError {
+    /// The documented error this error maps to, if any.
+    pub fn auth_error(&self) -> Option<FirebaseAuthError> {
+        match self {
+            IdentityToolkitError::UserNotFound => Some(FirebaseAuthError::UserNotFound),
+            IdentityToolkitError::EmailAlreadyExists => Some(FirebaseAuthError::EmailAlreadyExists),
+            IdentityToolkitError::UidAlreadyExists => Some(FirebaseAuthError::UidAlreadyExists),
+            IdentityToolkitError::PhoneNumberAlreadyExists => {
+                Some(FirebaseAuthError::PhoneNumberAlreadyExists)
+            }
+            IdentityToolkitError::ProviderConfigNotFound => {
+                Some(FirebaseAuthError::ConfigurationNotFound)
+            }
+            IdentityToolkitError::ApiError { message, .. } => {
+                server_code(message).and_then(FirebaseAuthError::from_server_code)
+            }
+            _ => None,
+        }
+    }
+    /// Whether the same request may succeed later: failed connections, rate
+    /// limiting, unavailable servers and the retryable
+    /// [`FirebaseAuthError`]s.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            IdentityToolkitError::RequestError(error) => error.is_timeout() || error.is_connect(),
+            IdentityToolkitError::ApiError { status, .. }
+                if *status == 429 || *status == 500 || *status == 503 =>
+            {
+                true
+            }
+            _ => self.auth_error().is_some_and(|error| error.is_retryable()),
+        }
+    }
+    /// Maps the error code at the start of an API error message to a typed
+    /// error where one exists.
+    fn from_api_error(status: u16, message: String) -> IdentityToolkitError {
+        let code = server_code(&message);
+        match code {
+            Some("USER_NOT_FOUND") => IdentityToolkitError::UserNotFound,
+            Some("EMAIL_EXISTS") => IdentityToolkitError::EmailAlreadyExists,
+            Some("DUPLICATE_LOCAL_ID") => IdentityToolkitError::UidAlreadyExists,
+            Some("PHONE_NUMBER_EXISTS") => IdentityToolkitError::PhoneNumberAlreadyExists,
+            Some("CONFIGURATION_NOT_FOUND") => IdentityToolkitError::ProviderConfigNotFound,
+            _ => IdentityToolkitError::ApiError { status, message },
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct UserRecord {
+    pub local_id: String,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub display_name: Option<String>,
+    pub photo_url: Option<String>,
+    pub phone_number: Option<String>,
+    #[serde(default)]
+    pub disabled: bool,
+    /// Seconds since epoch before which issued tokens are considered revoked.
+    pub valid_since: Option<String>,
+    /// Custom claims as the JSON-encoded object stored by the server.
+    pub custom_attributes: Option<String>,
+}
+
+impl UserRecord {
+    /// Decodes the user's custom claims into `T`, or `None` if none are set.
+    pub fn custom_claims<T: DeserializeOwned>(&self) -> Result<Option<T>, serde_json::Error> {
+        self.custom_attributes
+            .as_deref()
+            .map(serde_json::from_str)
+            .transpose()
+    }
+    pub fn tokens_valid_after_time(&self) -> Option<i64> {
+        self.valid_since.as_ref().and_then(|v| v.parse().ok())
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LookupRequest<'a> {
+    local_id: Vec<&'a str>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LookupResponse {
+    #[serde(default)]
+    users: Vec<UserRecord>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateSessionCookieRequest<'a> {
+    id_token: &'a str,
+    valid_duration: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateSessionCookieResponse {
+    session_cookie: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: ErrorBody,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    message: String,
+}
+
+/// The message of an error response, or its status without one.
+pub(crate) async fn error_message(response: reqwest::Response) -> String {
+    let status = response.status();
+    match response.json::<ErrorResponse>().await {
+        Ok(error) => error.error.message,
+        Err(_) => status.to_string(),
+    }
+}
+
+/// A client for the Identity Toolkit (Firebase Auth) admin REST API.
+#[derive(Clone)]
+pub struct IdentityToolkit {
+    project_id: String,
+    tenant_id: Option<String>,
+    base_url: String,
+    /// The v2 API, where Identity Platform configuration is managed.
+    admin_base_url: String,
+    client: reqwest::Client,
+    token_source: Arc<dyn TokenSource + Send + Sync>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+impl IdentityToolkit {
+    pub fn new<T>(project_id: String, token_source: T) -> IdentityToolkit
+    where
+        T: TokenSource + Send + Sync + 'static,
+    {
+        IdentityToolkit {
+            project_id,
+            tenant_id: None,
+            base_url: IDENTITY_TOOLKIT_URL.to_string(),
+            admin_base_url: IDENTITY_TOOLKIT_ADMIN_URL.to_string(),
+            client: api_client(DEFAULT_REQUEST_TIMEOUT),
+            token_source: Arc::new(token_source),
+            rate_limiter: None,
+        }
+    }
+    /// Points the client at another endpoint, e.g.
+    /// `http://localhost:9099/identitytoolkit.googleapis.com/v1` for the emulator.
+    /// A trailing `/v1` is replaced with `/v2` for the configuration endpoints.
+    pub fn with_base_url(mut self, base_url: String) -> IdentityToolkit {
+        self.admin_base_url = match base_url.strip_suffix("/v1") {
+            Some(base) => format!("{}/v2", base),
+            None => base_url.clone(),
+        };
+        self.base_url = base_url;
+        self
+    }
+    /// Replaces the default client, which times requests out after 30 seconds
+    /// so that a stalled API fails revocation checks instead of hanging them.
+    pub fn with_client(mut self, client: reqwest::Client) -> IdentityToolkit {
+        self.client = client;
+        self
+    }
+    /// Limits the rate and concurrency of requests, and retries requests
+    /// answered with 429. Clones of the client share the limits.
+    pub fn with_rate_limit(mut self, rate_limit: RateLimit) -> IdentityToolkit {
+        self.rate_limiter = Some(Arc::new(RateLimiter::new(rate_limit)));
+        self
+    }
+    /// Scopes every request to the users of an Identity Platform tenant.
+    pub fn with_tenant_id(mut self, tenant_id: String) -> IdentityToolkit {
+        self.tenant_id = Some(tenant_id);
+        self
+    }
+    fn url(&self, path: &str) -> String {
+        self.resource_url(&self.base_url, path)
+    }
+    fn resource_url(&self, base_url: &str, path: &str) -> String {
+        match &self.tenant_id {
+            Some(tenant_id) => format!(
+                "{}/projects/{}/tenants/{}{}",
+                base_url, self.project_id, tenant_id, path
+            ),
+            None => format!("{}/projects/{}{}", base_url, self.project_id, path),
+        }
+    }
+    /// A request to the v2 API, to [`send`](Self::send).
+    pub(crate) fn admin_request(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+    ) -> reqwest::RequestBuilder {
+        let url = self.resource_url(&self.admin_base_url, path);
+        self.client.request(method, &url)
+    }
+    pub(crate) async fn post<B, R>(&self, path: &str, body: &B) -> Result<R, IdentityToolkitError>
+    where
+        B: Serialize + ?Sized,
+        R: DeserializeOwned,
+    {
+        let url = self.url(path);
+        self.send(self.client.post(&url).json(body)).await
+    }
+    pub(crate) async fn get<Q, R>(&self, path: &str, query: &Q) -> Result<R, IdentityToolkitError>
+    where
+        Q: Serialize + ?Sized,
+        R: DeserializeOwned,
+    {
+        let url = self.url(path);
+        self.send(self.client.get(&url).query(query)).await
+    }
+    pub(crate) async fn send<R>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<R, IdentityToolkitError>
+    where
+        R: DeserializeOwned,
+    {
+        let token = self
+            .token_source
+            .token()
+            .await
+            .map_err(IdentityToolkitError::CredentialsError)?;
+        let request = request.bearer_auth(token);
+        let response = match &self.rate_limiter {
+            Some(rate_limiter) => Self::send_limited(rate_limiter, request).await?,
+            None => request
+                .send()
+                .await
+                .map_err(IdentityToolkitError::RequestError)?,
+        };
+        let status = response.status();
+        if !status.is_success() {
+            let message = error_message(response).await;
+            return Err(IdentityToolkitError::from_api_error(
+                status.as_u16(),
+                message,
+            ));
+        }
+        response
+            .json::<R>()
+            .await
+            .map_err(IdentityToolkitError::ResponseBodyError)
+    }
+    /// Sends `request` within the rate limit, waiting and sending it again
+    /// while it is answered with 429 and retries are left.
+    async fn send_limited(
+        rate_limiter: &RateLimiter,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, IdentityToolkitError> {
+        let mut attempt = 0;
+        loop {
+            let permit = rate_limiter.acquire().await;
+            let attempt_request = match request.try_clone() {
+                Some(attempt_request) => attempt_request,
+                // Streamed bodies cannot be sent twice.
+                None => {
+                    return request
+                        .send()
+                        .await
+                        .map_err(IdentityToolkitError::RequestError)
+                }
+            };
+            let response = attempt_request
+                .send()
+                .await
+                .map_err(IdentityToolkitError::RequestError)?;
+            drop(permit);
+            if response.status() != reqwest::StatusCode::TOO_MANY_REQUESTS
+                || attempt >= rate_limiter.limit.max_retries
+            {
+                return Ok(response);
+            }
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_retry_after);
+            rate_limiter
+                .sleep(rate_limiter.limit.retry_delay(attempt, retry_after))
+                .await;
+            attempt += 1;
+        }
+    }
+    pub async fn get_user(&self, uid: &str) -> Result<UserRecord, IdentityToolkitError> {
+        let request = LookupRequest {
+            local_id: vec![uid],
+        };
+        let response: LookupResponse = self.post("/accounts:lookup", &request).await?;
+        response
+            .users
+            .into_iter()
+            .next()
+            .ok_or(IdentityToolkitError::UserNotFound)
+    }
+    /// Exchanges an ID token for a session cookie valid for `expires_in`,
+    /// which must be between 5 minutes and 14 days.
+    pub async fn create_session_cookie(
+        &self,
+        id_token: &str,
+        expires_in: Duration,
+    ) -> Result<String, IdentityToolkitError> {
+        if id_token.is_empty() {
+            return Err(IdentityToolkitError::InvalidArgument(
+                "id_token must be a non-empty string".to_string(),
+            ));
+        }
+        if expires_in < MIN_SESSION_COOKIE_DURATION || expires_in > MAX_SESSION_COOKIE_DURATION {
+            return Err(IdentityToolkitError::InvalidArgument(
+                "expires_in must be between 5 minutes and 14 days".to_string(),
+            ));
+        }
+        let request = CreateSessionCookieRequest {
+            id_token,
+            valid_duration: expires_in.as_secs(),
+        };
+        let response: CreateSessionCookieResponse =
+            self.post(":createSessionCookie", &request).await?;
+        Ok(response.session_cookie)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::credentials::StaticToken;
+    use serde_json::json;
+    use wiremock::matchers::{body_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    pub async fn get_mock_lookup_server(user: serde_json::Value) -> MockServer {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/projects/pj/accounts:lookup"))
+            .and(header("Authorization", "Bearer owner"))
+            .and(body_json(json!({ "localId": ["uid"] })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "users": [user] })))
+            .mount(&mock_server)
+            .await;
+        mock_server
+    }
+
+    pub fn get_test_client(mock_server: &MockServer) -> IdentityToolkit {
+        IdentityToolkit::new("pj".to_string(), StaticToken("owner".to_string()))
+            .with_base_url(mock_server.uri())
+    }
+
+    #[tokio::test]
+    async fn test_get_user() {
+        let mock_server = get_mock_lookup_server(json!({
+            "localId": "uid",
+            "email": "user@example.com",
+            "disabled": true,
+            "validSince": "1600000000",
+            "customAttributes": "{\"role\":\"admin\"}"
+        }))
+        .await;
+        let user = get_test_client(&mock_server).get_user("uid").await.unwrap();
+        assert_eq!(user.local_id, "uid");
+        assert_eq!(user.email, Some("user@example.com".to_string()));
+        assert!(user.disabled);
+        assert_eq!(user.tokens_valid_after_time(), Some(1600000000));
+        let claims: serde_json::Value = user.custom_claims().unwrap().unwrap();
+        assert_eq!(claims, json!({ "role": "admin" }));
+    }
+
+    #[tokio::test]
+    async fn test_get_user_with_tenant() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/projects/pj/tenants/tenant-1/accounts:lookup"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "users": [{ "localId": "uid", "tenantId": "tenant-1" }]
+            })))
+            .mount(&mock_server)
+            .await;
+        let user = get_test_client(&mock_server)
+            .with_tenant_id("tenant-1".to_string())
+            .get_user("uid")
+            .await
+            .unwrap();
+        assert_eq!(user.local_id, "uid");
+    }
+
+    #[tokio::test]
+    async fn test_get_user_not_found() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/projects/pj/accounts:lookup"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .mount(&mock_server)
+            .await;
+        let result = get_test_client(&mock_server).get_user("uid").await;
+        assert!(matches!(result, Err(IdentityToolkitError::UserNotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_get_user_api_error() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/projects/pj/accounts:lookup"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "error": { "code": 400, "message": "INVALID_ID_TOKEN" }
+            })))
+            .mount(&mock_server)
+            .await;
+        let result = get_test_client(&mock_server).get_user("uid").await;
+        match result {
+            Err(IdentityToolkitError::ApiError { status, message }) => {
+                assert_eq!(status, 400);
+                assert_eq!(message, "INVALID_ID_TOKEN");
+            }
+            _ => panic!("expected an api error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_retry() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/projects/pj/accounts:lookup"))
+            .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "0"))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/projects/pj/accounts:lookup"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "users": [{ "localId": "uid" }]
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let client = get_test_client(&mock_server).with_rate_limit(
+            RateLimit::new()
+                .max_concurrent_requests(2)
+                .requests_per_second(100.0, 1),
+        );
+        let user = client.get_user("uid").await.unwrap();
+        assert_eq!(user.local_id, "uid");
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_retries_exhausted() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/projects/pj/accounts:lookup"))
+            .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "0"))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+        let client = get_test_client(&mock_server).with_rate_limit(RateLimit::new().max_retries(1));
+        let result = client.get_user("uid").await;
+        assert!(matches!(
+            result,
+            Err(IdentityToolkitError::ApiError { status: 429, .. })
+        ));
+    }
+
+    #[test]
+    fn test_auth_error() {
+        let api_error = |status, message: &str| IdentityToolkitError::ApiError {
+            status,
+            message: message.to_string(),
+        };
+        let error = api_error(400, "INVALID_DYNAMIC_LINK_DOMAIN : not allowed");
+        assert_eq!(
+            error.auth_error(),
+            Some(FirebaseAuthError::InvalidDynamicLinkDomain)
+        );
+        assert!(!error.is_retryable());
+        assert_eq!(
+            IdentityToolkitError::EmailAlreadyExists
+                .auth_error()
+                .map(|error| error.code()),
+            Some("auth/email-already-exists")
+        );
+
+        let quota = api_error(400, "QUOTA_EXCEEDED");
+        assert_eq!(quota.auth_error(), Some(FirebaseAuthError::QuotaExceeded));
+        assert!(quota.is_retryable());
+
+        let unavailable = api_error(503, "503 Service Unavailable");
+        assert_eq!(unavailable.auth_error(), None);
+        assert!(unavailable.is_retryable());
+        assert!(!IdentityToolkitError::InvalidArgument(String::new()).is_retryable());
+    }
+
+    #[tokio::test]
+    async fn test_create_session_cookie() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/projects/pj:createSessionCookie"))
+            .and(header("Authorization", "Bearer owner"))
+            .and(body_json(
+                json!({ "idToken": "id-token", "validDuration": 3600 }),
+            ))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(json!({ "sessionCookie": "cookie" })),
+            )
+            .mount(&mock_server)
+            .await;
+        let cookie = get_test_client(&mock_server)
+            .create_session_cookie("id-token", Duration::from_secs(3600))
+            .await
+            .unwrap();
+        assert_eq!(cookie, "cookie");
+    }
+
+    #[tokio::test]
+    async fn test_create_session_cookie_invalid_duration() {
+        let mock_server = MockServer::start().await;
+        let client = get_test_client(&mock_server);
+        let result = client
+            .create_session_cookie("id-token", Duration::from_secs(60))
+            .await;
+        assert!(matches!(
+            result,
+            Err(IdentityToolkitError::InvalidArgument(_))
+        ));
+        let result = client
+            .create_session_cookie("id-token", Duration::from_secs(15 * 24 * 60 * 60))
+            .await;
+        assert!(matches!(
+            result,
+            Err(IdentityToolkitError::InvalidArgument(_))
+        ));
+    }
+}
@@ -0,0 +1,290 @@
+//! The types shared by the web framework integrations. Each framework takes
+//! the [`JwkAuth`](crate::jwk_auth::JwkAuth) the way it shares state:
+//!
+//! - actix: `web::Data<JwkAuth>` in the app data, or given to `AuthMiddleware`
+//! - axum: a `JwkAuthHandle` or `Arc<JwkAuth>` taken from the router state
+//! - poem: `Arc<JwkAuth>` in the endpoint data
+//! - salvo, tonic, tower and warp: `Arc<JwkAuth>` given to their middleware
+//!   or filters
+//!
+//! A [`JwkAuthHandle`](crate::jwk_auth::JwkAuthHandle) converts into
+//! `Arc<JwkAuth>`, and with `Data::from` into actix's `Data<JwkAuth>`.
+
+use crate::verifier::{Claims, RequireClaim, VerifyError};
+use thiserror::Error;
+
+/// The verified user of a request, as produced by the web framework integrations.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FirebaseUser {
+    pub uid: String,
+    pub claims: Claims,
+}
+
+impl FirebaseUser {
+    /// Checks `requirement`, failing with [`FirebaseAuthRejection::Forbidden`],
+    /// e.g. `user.require(&RequireClaim::role("admin"))?` in a handler.
+    pub fn require(&self, requirement: &RequireClaim) -> Result<(), FirebaseAuthRejection> {
+        if requirement.check(&self.claims) {
+            Ok(())
+        } else {
+            Err(FirebaseAuthRejection::Forbidden)
+        }
+    }
+}
+
+impl From<Claims> for FirebaseUser {
+    fn from(claims: Claims) -> Self {
+        FirebaseUser {
+            uid: claims.sub.clone(),
+            claims,
+        }
+    }
+}
+
+/// The verified user of a request, or `None` if it carries no token, for
+/// routes open to anonymous users. A request with an invalid token is still
+/// rejected, so that clients learn that their token expired rather than
+/// being served as anonymous.
+#[derive(Debug, PartialEq, Clone)]
+pub struct OptionalFirebaseUser(pub Option<FirebaseUser>);
+
+impl OptionalFirebaseUser {
+    pub fn claims(&self) -> Option<&Claims> {
+        self.0.as_ref().map(|user| &user.claims)
+    }
+    /// Treats a missing token as an anonymous request.
+    #[cfg(any(
+        feature = "actix",
+        feature = "axum",
+        feature = "poem",
+        feature = "salvo"
+    ))]
+    pub(crate) fn from_result(
+        result: Result<FirebaseUser, FirebaseAuthRejection>,
+    ) -> Result<OptionalFirebaseUser, FirebaseAuthRejection> {
+        match result {
+            Ok(user) => Ok(OptionalFirebaseUser(Some(user))),
+            Err(FirebaseAuthRejection::MissingToken) => Ok(OptionalFirebaseUser(None)),
+            Err(rejection) => Err(rejection),
+        }
+    }
+}
+
+/// Why a web framework integration refused to authenticate a request.
+#[derive(Debug, PartialEq, Error)]
+pub enum FirebaseAuthRejection {
+    #[error("missing bearer token")]
+    MissingToken,
+    #[error("verification failed: {0}")]
+    InvalidToken(#[source] VerifyError),
+    /// No `JwkAuth` was made available to the integration.
+    #[error("authentication is not configured")]
+    MissingJwkAuth,
+    /// The token is valid, but its claims do not meet a [`RequireClaim`].
+    #[error("insufficient permissions")]
+    Forbidden,
+}
+
+/// How the web framework integrations answer a [`FirebaseAuthRejection`]:
+/// its status code, an RFC 6750 `WWW-Authenticate` challenge so that OAuth
+/// clients know to get a new token, and an optional JSON body.
+///
+/// The extractors answer with the defaults; set it on the actix
+/// `AuthMiddleware`, the tower `FirebaseAuthLayer`, the tonic
+/// `FirebaseAuthInterceptor` or warp's `rejection_handler` to change them.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RejectionResponse {
+    /// The `realm` of the challenge, if any.
+    pub realm: Option<String>,
+    /// Whether to tell clients why their token was rejected, e.g. that it
+    /// expired, in the `error_description` and the body. Off by default.
+    pub describe_errors: bool,
+    /// Whether to send a `{"error": ...}` JSON body.
+    pub json_body: bool,
+}
+
+impl Default for RejectionResponse {
+    fn default() -> Self {
+        RejectionResponse {
+            realm: None,
+            describe_errors: false,
+            json_body: true,
+        }
+    }
+}
+
+impl RejectionResponse {
+    pub fn new() -> RejectionResponse {
+        RejectionResponse::default()
+    }
+    pub fn realm(mut self, realm: String) -> Self {
+        self.realm = Some(realm);
+        self
+    }
+    pub fn describe_errors(mut self, describe_errors: bool) -> Self {
+        self.describe_errors = describe_errors;
+        self
+    }
+    pub fn json_body(mut self, json_body: bool) -> Self {
+        self.json_body = json_body;
+        self
+    }
+    /// 401, 403 for [`FirebaseAuthRejection::Forbidden`], 500 if
+    /// authentication is not configured, or 503 if the token could not be
+    /// checked because the keys or the revocation backend are unavailable.
+    pub fn status_code(&self, rejection: &FirebaseAuthRejection) -> u16 {
+        match rejection {
+            FirebaseAuthRejection::MissingJwkAuth => 500,
+            FirebaseAuthRejection::Forbidden => 403,
+            FirebaseAuthRejection::InvalidToken(error) if is_unavailable(error) => 503,
+            _ => 401,
+        }
+    }
+    /// The value of the `WWW-Authenticate` header, e.g.
+    /// `Bearer error="invalid_token", error_description="token expired"`.
+    /// Requests without a token get a challenge without an error code, as
+    /// RFC 6750 asks. Server-side failures get no challenge, so that clients
+    /// do not discard a token that is fine.
+    pub fn www_authenticate(&self, rejection: &FirebaseAuthRejection) -> Option<String> {
+        if let FirebaseAuthRejection::InvalidToken(error) = rejection {
+            if is_unavailable(error) {
+                return None;
+            }
+        }
+        let mut params = Vec::new();
+        if let Some(realm) = &self.realm {
+            params.push(format!("realm=\"{}\"", quote(realm)));
+        }
+        let (error, description) = match rejection {
+            FirebaseAuthRejection::MissingToken => (None, None),
+            FirebaseAuthRejection::InvalidToken(error) => {
+                (Some("invalid_token"), Some(error.to_string()))
+            }
+            FirebaseAuthRejection::Forbidden => {
+                (Some("insufficient_scope"), Some(rejection.to_string()))
+            }
+            FirebaseAuthRejection::MissingJwkAuth => return None,
+        };
+        if let Some(error) = error {
+            params.push(format!("error=\"{}\"", error));
+        }
+        if let Some(description) = description.filter(|_| self.describe_errors) {
+            params.push(format!("error_description=\"{}\"", quote(&description)));
+        }
+        if params.is_empty() {
+            Some("Bearer".to_string())
+        } else {
+            Some(format!("Bearer {}", params.join(", ")))
+        }
+    }
+    /// The JSON body, if enabled.
+    pub fn body(&self, rejection: &FirebaseAuthRejection) -> Option<serde_json::Value> {
+        if !self.json_body {
+            return None;
+        }
+        Some(serde_json::json!({ "error": self.message(rejection) }))
+    }
+    /// The error message, without the reason the token was rejected unless
+    /// errors are described.
+    pub fn message(&self, rejection: &FirebaseAuthRejection) -> String {
+        match rejection {
+            FirebaseAuthRejection::InvalidToken(error) if is_unavailable(error) => {
+                "authentication unavailable".to_string()
+            }
+            FirebaseAuthRejection::InvalidToken(_) if !self.describe_errors => {
+                "invalid token".to_string()
+            }
+            _ => rejection.to_string(),
+        }
+    }
+}
+
+/// Whether `error` is a failure on our side, e.g. a key or revocation
+/// backend outage, rather than a problem with the token.
+fn is_unavailable(error: &VerifyError) -> bool {
+    matches!(
+        error,
+        VerifyError::StaleKeys
+            | VerifyError::KeyFetchFailed(_)
+            | VerifyError::RevocationCheckUnavailable
+            | VerifyError::RevocationCheckFailed(_)
+    )
+}
+
+/// Keeps a quoted-string parameter within the characters RFC 6750 allows.
+fn quote(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '\'',
+            ' '..='~' => c,
+            _ => '?',
+        })
+        .collect()
+}
+
+/// Whether `path` is `prefix` or lies below it, so that `/public` matches
+/// `/public/page` but not `/publications`.
+#[cfg(any(feature = "actix", feature = "tower"))]
+pub(crate) fn is_under(path: &str, prefix: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/') || prefix.ends_with('/'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(any(feature = "actix", feature = "tower"))]
+    #[test]
+    fn test_is_under() {
+        assert!(is_under("/public", "/public"));
+        assert!(is_under("/public/page", "/public"));
+        assert!(is_under("/public/page", "/public/"));
+        assert!(!is_under("/publications", "/public"));
+        assert!(!is_under("/public", "/public/"));
+        assert!(!is_under("/private", "/public"));
+    }
+
+    #[test]
+    fn test_rejection_response() {
+        let expired = FirebaseAuthRejection::InvalidToken(VerifyError::Expired);
+        let config = RejectionResponse::new().realm("my \"app\"".to_string());
+        assert_eq!(
+            config.www_authenticate(&expired).unwrap(),
+            "Bearer realm=\"my 'app'\", error=\"invalid_token\""
+        );
+        assert_eq!(config.message(&expired), "invalid token");
+        let config = config.describe_errors(true).json_body(false);
+        assert_eq!(
+            config.www_authenticate(&expired).unwrap(),
+            "Bearer realm=\"my 'app'\", error=\"invalid_token\", error_description=\"token expired\""
+        );
+        assert_eq!(config.body(&expired), None);
+        assert_eq!(config.status_code(&FirebaseAuthRejection::Forbidden), 403);
+        assert_eq!(
+            config.www_authenticate(&FirebaseAuthRejection::MissingJwkAuth),
+            None
+        );
+    }
+
+    #[test]
+    fn test_rejection_response_server_side_failures() {
+        let config = RejectionResponse::new();
+        for error in [
+            VerifyError::StaleKeys,
+            VerifyError::KeyFetchFailed("unavailable".to_string()),
+            VerifyError::RevocationCheckUnavailable,
+            VerifyError::RevocationCheckFailed("unavailable".to_string()),
+        ] {
+            let rejection = FirebaseAuthRejection::InvalidToken(error);
+            assert_eq!(config.status_code(&rejection), 503);
+            assert_eq!(config.www_authenticate(&rejection), None);
+            assert_eq!(config.message(&rejection), "authentication unavailable");
+        }
+        let revoked = FirebaseAuthRejection::InvalidToken(VerifyError::Revoked);
+        assert_eq!(config.status_code(&revoked), 401);
+    }
+}
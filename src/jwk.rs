@@ -1,10 +1,27 @@
-use crate::header_parser::get_max_age;
+use crate::header_parser::{get_age, get_max_age, get_stale_while_revalidate};
+use crate::http::{default_client, HttpClient, HttpError, HttpResponse};
+use crate::runtime::{default_runtime, Runtime};
+use crate::time::Duration;
+use crate::x509::{cert_to_jwk, CertParseError};
 use async_trait::async_trait;
-use reqwest;
+use jsonwebtoken::errors::Error as JwtError;
+use jsonwebtoken::DecodingKey;
+#[cfg(not(feature = "tracing"))]
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
-const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+#[cfg(feature = "tracing")]
+use tracing::{info, warn};
+/// The key validity when the key server does not announce one.
+pub(crate) const DEFAULT_VALIDITY: Duration = Duration::from_secs(5 * 60);
+const DISCOVERY_PATH: &str = "/.well-known/openid-configuration";
+const NOT_MODIFIED: u16 = 304;
 
+#[cfg(test)]
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct KeyResponse {
     pub keys: Vec<Jwk>,
@@ -12,68 +29,556 @@ pub struct KeyResponse {
 
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
 pub struct Jwk {
-    pub e: String,
-    pub alg: String,
-    pub kty: String,
+    /// The algorithm the key is meant for. Optional per RFC 7517; when absent,
+    /// the allowed algorithms matching the key type are accepted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alg: Option<String>,
     pub kid: String,
-    pub n: String,
-    pub r#use: String,
+    /// `sig` for signing keys. Optional per RFC 7517; keys with any other
+    /// use are ignored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub r#use: Option<String>,
+    #[serde(flatten)]
+    pub params: JwkParams,
+}
+
+/// The public key parameters of a [`Jwk`], by key type (`kty`).
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(tag = "kty")]
+pub enum JwkParams {
+    #[serde(rename = "RSA")]
+    Rsa { n: String, e: String },
+    /// An elliptic curve key, e.g. for ES256.
+    #[serde(rename = "EC")]
+    Ec { crv: String, x: String, y: String },
+    /// An octet key pair, e.g. an Ed25519 key for EdDSA.
+    #[serde(rename = "OKP")]
+    Okp { crv: String, x: String },
+}
+
+impl Jwk {
+    pub fn decoding_key(&self) -> Result<DecodingKey, JwtError> {
+        match &self.params {
+            JwkParams::Rsa { n, e } => DecodingKey::from_rsa_components(n, e),
+            JwkParams::Ec { x, y, .. } => DecodingKey::from_ec_components(x, y),
+            JwkParams::Okp { x, .. } => DecodingKey::from_ed_components(x),
+        }
+    }
+    /// Whether the key may verify signatures, i.e. its `use` is `sig` or unset.
+    pub fn is_signing_key(&self) -> bool {
+        self.r#use.as_deref().is_none_or(|r#use| r#use == "sig")
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Jwks {
     pub keys: Vec<Jwk>,
     pub validity: Duration,
+    /// How long past `validity` the keys may still be used while they are
+    /// refreshed before they count as stale, from `stale-while-revalidate`.
+    pub stale_while_revalidate: Duration,
+}
+
+impl Jwks {
+    /// Parses a JWK set, `{"keys": [...]}`, valid for `validity`. Meant for
+    /// keys pushed to [`JwkAuth::apply_keys`](crate::jwk_auth::JwkAuth::apply_keys),
+    /// e.g. from a Pub/Sub message mirroring Google's keys.
+    pub fn from_json(body: &[u8], validity: Duration) -> Result<Jwks, KeyFetchError> {
+        Ok(Jwks {
+            keys: parse_keys(KeyFormat::Jwk, body)?,
+            validity,
+            stale_while_revalidate: Duration::ZERO,
+        })
+    }
+}
+
+/// The fields of an OpenID provider configuration document this crate uses.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub jwks_uri: String,
+}
+
+/// Fetches the OpenID provider configuration published under `issuer_url`.
+pub async fn fetch_provider_metadata(
+    client: &(dyn HttpClient + Send + Sync),
+    issuer_url: &str,
+) -> Result<ProviderMetadata, KeyFetchError> {
+    let url = format!("{}{}", issuer_url.trim_end_matches('/'), DISCOVERY_PATH);
+    let response = get(client, &url, &[]).await?;
+    serde_json::from_slice(&response.body).map_err(KeyFetchError::ResponseBodyError)
+}
+
+/// Sends a GET request, failing on error statuses. `304 Not Modified` only
+/// answers conditional requests, and is passed on to handle.
+async fn get(
+    client: &(dyn HttpClient + Send + Sync),
+    url: &str,
+    headers: &[(&str, &str)],
+) -> Result<HttpResponse, KeyFetchError> {
+    let response = client
+        .get_with_headers(url, headers)
+        .await
+        .map_err(KeyFetchError::RequestError)?;
+    if !response.is_success() && response.status != NOT_MODIFIED {
+        return Err(KeyFetchError::StatusError(response.status));
+    }
+    Ok(response)
+}
+
+/// How long the keys in `response` stay valid: its `max-age`, or `fallback`
+/// without one, minus the time the response already spent in caches
+/// according to its `Age` header, within `bounds`.
+pub(crate) fn response_validity(
+    response: &HttpResponse,
+    bounds: &ValidityBounds,
+    fallback: Duration,
+) -> Duration {
+    let max_age = get_max_age(response).unwrap_or(fallback);
+    bounds.clamp(max_age.saturating_sub(get_age(response).unwrap_or_default()))
+}
+
+/// Limits the key validity announced by the key server, so that
+/// pathological `Cache-Control` headers neither cause a refresh on every
+/// request nor keep keys around for too long. By default keys are refreshed
+/// at most every 5 minutes and at least every 24 hours.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ValidityBounds {
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl Default for ValidityBounds {
+    fn default() -> Self {
+        ValidityBounds {
+            min: Duration::from_secs(5 * 60),
+            max: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+impl ValidityBounds {
+    /// Limits `validity` to the bounds; `max` wins if `min` exceeds it.
+    pub fn clamp(&self, validity: Duration) -> Duration {
+        validity.max(self.min).min(self.max)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum KeyFormat {
+    /// A JWK set: `{"keys": [...]}`.
+    Jwk,
+    /// A map of key id to PEM encoded x509 certificate.
+    X509,
+}
+
+/// Groups of HTTP status codes a key fetch is retried on.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum StatusClass {
+    /// Any 5xx status.
+    ServerError,
+    /// 429 Too Many Requests.
+    TooManyRequests,
+    /// 408 Request Timeout.
+    RequestTimeout,
+}
+
+impl StatusClass {
+    pub fn contains(self, status: u16) -> bool {
+        match self {
+            StatusClass::ServerError => (500..600).contains(&status),
+            StatusClass::TooManyRequests => status == 429,
+            StatusClass::RequestTimeout => status == 408,
+        }
+    }
+}
+
+/// How a single key fetch is retried on request errors and the statuses in
+/// `retry_on`. The delay between attempts doubles from `base_backoff` up to
+/// `max_backoff`, and is randomly shortened by up to `jitter` (0 to 1) of it.
+///
+/// This is the only retry applied to the built-in fetcher: a fetch makes at
+/// most `max_attempts` requests to a key URL and waits less than
+/// `(max_attempts - 1) * max_backoff` between them, on top of the request
+/// timeout of every attempt.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FetchRetryPolicy {
+    /// Including the first attempt; 1 disables retries.
+    pub max_attempts: u32,
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+    pub jitter: f64,
+    pub retry_on: Vec<StatusClass>,
+}
+
+impl Default for FetchRetryPolicy {
+    fn default() -> Self {
+        FetchRetryPolicy {
+            max_attempts: 3,
+            base_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(2),
+            jitter: 0.5,
+            retry_on: vec![
+                StatusClass::ServerError,
+                StatusClass::TooManyRequests,
+                StatusClass::RequestTimeout,
+            ],
+        }
+    }
+}
+
+impl FetchRetryPolicy {
+    /// A policy making a single attempt.
+    pub fn none() -> FetchRetryPolicy {
+        FetchRetryPolicy {
+            max_attempts: 1,
+            ..FetchRetryPolicy::default()
+        }
+    }
+    fn is_retryable(&self, error: &KeyFetchError) -> bool {
+        match error {
+            KeyFetchError::RequestError(_) => true,
+            KeyFetchError::StatusError(status) => {
+                self.retry_on.iter().any(|class| class.contains(*status))
+            }
+            _ => false,
+        }
+    }
+    /// The delay before retry number `retry`, counting from 0.
+    fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self
+            .base_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        backoff.mul_f64(random_between(1.0 - jitter, 1.0))
+    }
+}
+
+/// A pseudo-random number in `[low, high]`, good enough to spread out timers.
+pub(crate) fn random_between(low: f64, high: f64) -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    low + (high - low) * (random % 1001) as f64 / 1000.0
 }
 
-#[derive(Debug, PartialEq)]
+/// The validators of the last full key response, along with its keys to
+/// reuse when the key server answers `304 Not Modified`.
+#[derive(Debug, Clone)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    keys: Vec<Jwk>,
+}
+
+/// Fetches keys over HTTP, sending conditional requests once the key server
+/// has returned an `ETag` or `Last-Modified` header.
 pub struct JwkFetcher {
     pub url: String,
+    pub format: KeyFormat,
+    /// Reused for every key fetch, including background refreshes.
+    pub client: Arc<dyn HttpClient + Send + Sync>,
+    pub retry_policy: FetchRetryPolicy,
+    pub validity_bounds: ValidityBounds,
+    /// The key validity when the key server's `Cache-Control` header has no
+    /// usable `max-age`.
+    pub fallback_validity: Duration,
+    /// Sleeps between retries.
+    pub runtime: Arc<dyn Runtime + Send + Sync>,
+    validators: Mutex<Option<Validators>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Error)]
 pub enum KeyFetchError {
-    RequestError(reqwest::Error),
-    ReponseBodyError(reqwest::Error),
+    #[error("request failed: {0}")]
+    RequestError(#[source] HttpError),
+    /// The key server answered with a non-success status code.
+    #[error("unexpected status: {0}")]
+    StatusError(u16),
+    #[error("invalid response body: {0}")]
+    ResponseBodyError(#[source] serde_json::Error),
+    #[error("invalid certificate: {0}")]
+    InvalidCertificate(#[source] CertParseError),
+    /// The keys are not the ones pinned with
+    /// [`JwkAuthBuilder::key_pinning`](crate::jwk_auth::JwkAuthBuilder::key_pinning).
+    #[error("unexpected keys: {0}")]
+    UnexpectedKeys(String),
+    /// The error of a fetch shared by concurrent callers.
+    #[error(transparent)]
+    Shared(Arc<KeyFetchError>),
+    /// Raised by user supplied [`Fetcher`] implementations.
+    #[error(transparent)]
+    Custom(Box<dyn std::error::Error + Send + Sync>),
 }
 
-#[async_trait]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
 pub trait Fetcher {
-    fn new(url: String) -> Self;
+    fn new(url: String) -> Self
+    where
+        Self: Sized;
     async fn fetch_keys(&self) -> Result<Jwks, KeyFetchError>;
+    /// Fetches the keys from their source, bypassing any cache, e.g. because
+    /// a token was signed with a key the cached set lacks.
+    async fn fetch_fresh_keys(&self) -> Result<Jwks, KeyFetchError> {
+        self.fetch_keys().await
+    }
+    /// Where the last fetched keys came from, e.g. their URL, if known.
+    fn key_source(&self) -> Option<String> {
+        None
+    }
 }
 
-#[async_trait]
+impl JwkFetcher {
+    pub fn with_format(url: String, format: KeyFormat) -> JwkFetcher {
+        JwkFetcher {
+            url,
+            format,
+            client: default_client(None),
+            retry_policy: FetchRetryPolicy::default(),
+            validity_bounds: ValidityBounds::default(),
+            fallback_validity: DEFAULT_VALIDITY,
+            runtime: default_runtime(),
+            validators: Mutex::new(None),
+        }
+    }
+    async fn fetch_with_retry(
+        &self,
+        headers: &[(&str, &str)],
+    ) -> Result<HttpResponse, KeyFetchError> {
+        let mut attempt = 1;
+        loop {
+            match get(self.client.as_ref(), &self.url, headers).await {
+                Ok(response) => return Ok(response),
+                Err(error)
+                    if attempt < self.retry_policy.max_attempts
+                        && self.retry_policy.is_retryable(&error) =>
+                {
+                    let backoff = self.retry_policy.backoff(attempt - 1);
+                    warn!(
+                        "Failed to fetch JWK Keys from {} (attempt {}/{}): {}. Retrying in {:?}",
+                        self.url, attempt, self.retry_policy.max_attempts, error, backoff
+                    );
+                    self.runtime.sleep(backoff).await;
+                    attempt += 1;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+/// A JWK set whose keys are parsed one by one.
+#[derive(Deserialize)]
+struct RawKeyResponse {
+    keys: Vec<serde_json::Value>,
+}
+
+/// Parses a key server response body in `format`. Keys that cannot be
+/// parsed, e.g. of an unsupported key type, are skipped with a warning so
+/// that one odd key does not fail the whole set, unless no key is left.
+pub(crate) fn parse_keys(format: KeyFormat, body: &[u8]) -> Result<Vec<Jwk>, KeyFetchError> {
+    match format {
+        KeyFormat::Jwk => {
+            let response = serde_json::from_slice::<RawKeyResponse>(body)
+                .map_err(KeyFetchError::ResponseBodyError)?;
+            collect_keys(response.keys.into_iter().map(|key| {
+                serde_json::from_value::<Jwk>(key).map_err(KeyFetchError::ResponseBodyError)
+            }))
+        }
+        KeyFormat::X509 => {
+            let certs = serde_json::from_slice::<HashMap<String, String>>(body)
+                .map_err(KeyFetchError::ResponseBodyError)?;
+            collect_keys(certs.iter().map(|(kid, cert)| {
+                cert_to_jwk(kid, cert).map_err(KeyFetchError::InvalidCertificate)
+            }))
+        }
+    }
+}
+
+fn collect_keys(
+    results: impl Iterator<Item = Result<Jwk, KeyFetchError>>,
+) -> Result<Vec<Jwk>, KeyFetchError> {
+    let mut keys = Vec::new();
+    let mut first_error = None;
+    for result in results {
+        match result {
+            Ok(key) => keys.push(key),
+            Err(error) => {
+                warn!("Skipping a key that cannot be parsed: {}", error);
+                first_error.get_or_insert(error);
+            }
+        }
+    }
+    match first_error {
+        Some(error) if keys.is_empty() => Err(error),
+        _ => Ok(keys),
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
 impl Fetcher for JwkFetcher {
     fn new(url: String) -> JwkFetcher {
-        JwkFetcher { url: url }
+        JwkFetcher::with_format(url, KeyFormat::Jwk)
+    }
+    fn key_source(&self) -> Option<String> {
+        Some(self.url.clone())
     }
     async fn fetch_keys(&self) -> Result<Jwks, KeyFetchError> {
-        let response = reqwest::get(&self.url)
-            .await
-            .map_err(|e| KeyFetchError::RequestError(e))?;
-        let max_age = get_max_age(&response).unwrap_or(DEFAULT_TIMEOUT);
-        let response_body = response
-            .json::<KeyResponse>()
-            .await
-            .map_err(|e| KeyFetchError::ReponseBodyError(e))?;
-        return Ok(Jwks {
-            keys: response_body.keys,
-            validity: max_age,
-        });
+        let validators = self.validators.lock().unwrap().clone();
+        let mut headers = Vec::new();
+        if let Some(validators) = &validators {
+            if let Some(etag) = &validators.etag {
+                headers.push(("If-None-Match", etag.as_str()));
+            }
+            if let Some(last_modified) = &validators.last_modified {
+                headers.push(("If-Modified-Since", last_modified.as_str()));
+            }
+        }
+        let response = self.fetch_with_retry(&headers).await?;
+        let validity = response_validity(&response, &self.validity_bounds, self.fallback_validity);
+        let stale_while_revalidate = get_stale_while_revalidate(&response).unwrap_or_default();
+        if response.status == NOT_MODIFIED {
+            return match validators {
+                Some(validators) => Ok(Jwks {
+                    keys: validators.keys,
+                    validity,
+                    stale_while_revalidate,
+                }),
+                None => Err(KeyFetchError::StatusError(NOT_MODIFIED)),
+            };
+        }
+        let keys = parse_keys(self.format, &response.body)?;
+        let etag = response.header("ETag").map(str::to_string);
+        let last_modified = response.header("Last-Modified").map(str::to_string);
+        *self.validators.lock().unwrap() = if etag.is_some() || last_modified.is_some() {
+            Some(Validators {
+                etag,
+                last_modified,
+                keys: keys.clone(),
+            })
+        } else {
+            None
+        };
+        Ok(Jwks {
+            keys,
+            validity,
+            stale_while_revalidate,
+        })
+    }
+}
+
+/// Fetches the keys from the first of several sources that answers, e.g.
+/// the JWK endpoint, then the x509 endpoint or an internal mirror. A failing
+/// source is not retried before the next one is tried; instead, once every
+/// source failed, all are tried again according to `retry_policy`. A fetch
+/// thus makes at most `max_attempts` requests to each source. The URL of the
+/// source that answered is kept as the [`key_source`](Fetcher::key_source).
+pub(crate) struct FallbackFetcher {
+    /// Making a single attempt each.
+    fetchers: Vec<JwkFetcher>,
+    retry_policy: FetchRetryPolicy,
+    runtime: Arc<dyn Runtime + Send + Sync>,
+    source: Mutex<Option<String>>,
+}
+
+impl FallbackFetcher {
+    /// Sources are tried in the order given. Their own retry policies are
+    /// replaced by `retry_policy`, which applies to the sources as a whole.
+    pub(crate) fn with_fetchers(
+        fetchers: Vec<JwkFetcher>,
+        retry_policy: FetchRetryPolicy,
+        runtime: Arc<dyn Runtime + Send + Sync>,
+    ) -> FallbackFetcher {
+        let fetchers = fetchers
+            .into_iter()
+            .map(|mut fetcher| {
+                fetcher.retry_policy = FetchRetryPolicy::none();
+                fetcher
+            })
+            .collect();
+        FallbackFetcher {
+            fetchers,
+            retry_policy,
+            runtime,
+            source: Mutex::new(None),
+        }
+    }
+    /// Tries every source once, returning the first keys and whether the
+    /// failure of the last source may be retried.
+    async fn fetch_from_any(&self) -> Result<Jwks, (KeyFetchError, bool)> {
+        let mut last_error = None;
+        for (index, fetcher) in self.fetchers.iter().enumerate() {
+            match fetcher.fetch_keys().await {
+                Ok(jwks) => {
+                    if index > 0 {
+                        info!("Fetched JWK Keys from fallback {}", fetcher.url);
+                    }
+                    *self.source.lock().unwrap() = Some(fetcher.url.clone());
+                    return Ok(jwks);
+                }
+                Err(error) => {
+                    warn!("Failed to fetch JWK Keys from {}: {}", fetcher.url, error);
+                    let retryable = self.retry_policy.is_retryable(&error);
+                    last_error = Some((error, retryable));
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| (KeyFetchError::Custom("no key sources".into()), false)))
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl Fetcher for FallbackFetcher {
+    fn new(url: String) -> FallbackFetcher {
+        FallbackFetcher::with_fetchers(
+            vec![JwkFetcher::new(url)],
+            FetchRetryPolicy::default(),
+            default_runtime(),
+        )
+    }
+    async fn fetch_keys(&self) -> Result<Jwks, KeyFetchError> {
+        let mut attempt = 1;
+        loop {
+            match self.fetch_from_any().await {
+                Ok(jwks) => return Ok(jwks),
+                Err((error, true)) if attempt < self.retry_policy.max_attempts => {
+                    let backoff = self.retry_policy.backoff(attempt - 1);
+                    warn!(
+                        "Failed to fetch JWK Keys from every source (attempt {}/{}): {}. Retrying in {:?}",
+                        attempt, self.retry_policy.max_attempts, error, backoff
+                    );
+                    self.runtime.sleep(backoff).await;
+                    attempt += 1;
+                }
+                Err((error, _)) => return Err(error),
+            }
+        }
+    }
+    fn key_source(&self) -> Option<String> {
+        self.source.lock().unwrap().clone()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(feature = "reqwest")]
+    use crate::http::ReqwestClient;
     use crate::tests::*;
+    use std::cmp::min;
+    #[cfg(feature = "reqwest")]
+    use wiremock::matchers::{header, method, path};
+    #[cfg(feature = "reqwest")]
+    use wiremock::{Mock, MockServer, ResponseTemplate};
 
     #[tokio::test]
     async fn test_new_with_url() {
         let url = "http://example/test".to_string();
         let result = JwkFetcher::new(url.clone());
         assert_eq!(result.url, url);
+        assert_eq!(result.format, KeyFormat::Jwk);
     }
 
     #[tokio::test]
@@ -87,8 +592,25 @@ mod tests {
         assert_eq!(
             result.unwrap(),
             Jwks {
-                keys: keys,
-                validity: Duration::from_secs(20045)
+                keys,
+                validity: Duration::from_secs(20045),
+                stale_while_revalidate: Duration::ZERO,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_keys_x509() {
+        let mock_server = get_mock_cert_server().await;
+        let result = JwkFetcher::with_format(get_mock_url(&mock_server), KeyFormat::X509)
+            .fetch_keys()
+            .await;
+        assert_eq!(
+            result.unwrap(),
+            Jwks {
+                keys: vec![get_signing_key()],
+                validity: Duration::from_secs(MAXAGE),
+                stale_while_revalidate: Duration::ZERO,
             }
         );
     }
@@ -101,6 +623,111 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn test_fallback_fetcher() {
+        let invalid_server = get_mock_server_invalid_response().await;
+        let cert_server = get_mock_cert_server().await;
+        let fetcher = FallbackFetcher::with_fetchers(
+            vec![
+                JwkFetcher::new(get_mock_url(&invalid_server)),
+                JwkFetcher::with_format(get_mock_url(&cert_server), KeyFormat::X509),
+            ],
+            fast_retry_policy(),
+            default_runtime(),
+        );
+        assert_eq!(fetcher.key_source(), None);
+        let jwks = fetcher.fetch_keys().await.unwrap();
+        assert_eq!(jwks.keys, vec![get_signing_key()]);
+        assert_eq!(fetcher.key_source(), Some(get_mock_url(&cert_server)));
+
+        let failing = FallbackFetcher::with_fetchers(
+            vec![JwkFetcher::new(get_mock_url(&invalid_server))],
+            fast_retry_policy(),
+            default_runtime(),
+        );
+        assert!(matches!(
+            failing.fetch_keys().await,
+            Err(KeyFetchError::ResponseBodyError(_))
+        ));
+        assert!(
+            FallbackFetcher::with_fetchers(Vec::new(), fast_retry_policy(), default_runtime())
+                .fetch_keys()
+                .await
+                .is_err()
+        );
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn test_fallback_fetcher_falls_through_on_first_failure() {
+        let failing_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(2)
+            .mount(&failing_server)
+            .await;
+        let cert_server = get_mock_cert_server().await;
+        let mut primary = JwkFetcher::new(get_mock_url(&failing_server));
+        primary.retry_policy = fast_retry_policy();
+        let fetcher = FallbackFetcher::with_fetchers(
+            vec![
+                primary,
+                JwkFetcher::with_format(get_mock_url(&cert_server), KeyFormat::X509),
+            ],
+            fast_retry_policy(),
+            default_runtime(),
+        );
+        // One request to the failing source per fetch, then the fallback.
+        assert!(fetcher.fetch_keys().await.is_ok());
+        assert!(fetcher.fetch_keys().await.is_ok());
+        assert_eq!(fetcher.key_source(), Some(get_mock_url(&cert_server)));
+        failing_server.verify().await;
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn test_fetch_keys_with_client() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(PATH))
+            .and(header("x-test", "custom-client"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(KeyResponse {
+                keys: get_test_keys(),
+            }))
+            .mount(&mock_server)
+            .await;
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("x-test", "custom-client".parse().unwrap());
+        let mut fetcher = JwkFetcher::new(get_mock_url(&mock_server));
+        fetcher.client = Arc::new(ReqwestClient::new(
+            reqwest::Client::builder()
+                .default_headers(headers)
+                .build()
+                .unwrap(),
+        ));
+        let result = fetcher.fetch_keys().await;
+        assert_eq!(result.unwrap().keys, get_test_keys());
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn test_fetch_keys_timeout() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(PATH))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(5)))
+            .mount(&mock_server)
+            .await;
+        let mut fetcher = JwkFetcher::new(get_mock_url(&mock_server));
+        fetcher.client = Arc::new(ReqwestClient {
+            timeout: Some(Duration::from_millis(100)),
+            ..ReqwestClient::default()
+        });
+        let result = fetcher.fetch_keys().await;
+        assert!(matches!(result, Err(KeyFetchError::RequestError(_))));
+    }
+
     #[tokio::test]
     async fn test_fetch_keys_invalid_response() {
         let mock_server = get_mock_server_invalid_response().await;
@@ -109,4 +736,371 @@ mod tests {
             .await;
         assert!(result.is_err());
     }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn test_fetch_keys_conditional_request() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(PATH))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("ETag", "\"v1\"")
+                    .insert_header("Cache-Control", "max-age=600")
+                    .set_body_json(KeyResponse {
+                        keys: get_test_keys(),
+                    }),
+            )
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(PATH))
+            .and(header("if-none-match", "\"v1\""))
+            .respond_with(
+                ResponseTemplate::new(304)
+                    .insert_header("Cache-Control", "max-age=1200, stale-while-revalidate=60"),
+            )
+            .mount(&mock_server)
+            .await;
+        let fetcher = JwkFetcher::new(get_mock_url(&mock_server));
+        assert_eq!(
+            fetcher.fetch_keys().await.unwrap().validity,
+            Duration::from_secs(600)
+        );
+        assert_eq!(
+            fetcher.fetch_keys().await.unwrap(),
+            Jwks {
+                keys: get_test_keys(),
+                validity: Duration::from_secs(1200),
+                stale_while_revalidate: Duration::from_secs(60),
+            }
+        );
+        assert_eq!(mock_server.received_requests().await.unwrap().len(), 2);
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn test_fetch_keys_subtracts_age() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(PATH))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Cache-Control", "max-age=3600")
+                    .insert_header("Age", "600")
+                    .set_body_json(KeyResponse {
+                        keys: get_test_keys(),
+                    }),
+            )
+            .mount(&mock_server)
+            .await;
+        let result = JwkFetcher::new(get_mock_url(&mock_server))
+            .fetch_keys()
+            .await;
+        assert_eq!(result.unwrap().validity, Duration::from_secs(3000));
+    }
+
+    #[test]
+    fn test_validity_bounds_clamp() {
+        let bounds = ValidityBounds {
+            min: Duration::from_secs(300),
+            max: Duration::from_secs(3600),
+        };
+        assert_eq!(bounds.clamp(Duration::from_secs(1)), bounds.min);
+        assert_eq!(
+            bounds.clamp(Duration::from_secs(600)),
+            Duration::from_secs(600)
+        );
+        assert_eq!(bounds.clamp(Duration::MAX), bounds.max);
+        let inverted = ValidityBounds {
+            min: bounds.max,
+            max: bounds.min,
+        };
+        assert_eq!(inverted.clamp(Duration::from_secs(600)), bounds.min);
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn test_fetch_keys_clamps_validity() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(PATH))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Cache-Control", "max-age=1")
+                    .set_body_json(KeyResponse {
+                        keys: get_test_keys(),
+                    }),
+            )
+            .mount(&mock_server)
+            .await;
+        let mut fetcher = JwkFetcher::new(get_mock_url(&mock_server));
+        assert_eq!(
+            fetcher.fetch_keys().await.unwrap().validity,
+            Duration::from_secs(300)
+        );
+        fetcher.validity_bounds.min = Duration::from_secs(600);
+        assert_eq!(
+            fetcher.fetch_keys().await.unwrap().validity,
+            Duration::from_secs(600)
+        );
+    }
+
+    #[test]
+    fn test_response_validity_default_bounds() {
+        let response = |cache_control: &str| HttpResponse {
+            status: 200,
+            headers: [("cache-control".to_string(), cache_control.to_string())].into(),
+            body: Vec::new(),
+        };
+        let bounds = ValidityBounds::default();
+        for cache_control in ["max-age=0", "no-cache", "no-store, max-age=600"] {
+            assert_eq!(
+                response_validity(&response(cache_control), &bounds, DEFAULT_VALIDITY),
+                Duration::from_secs(300)
+            );
+        }
+        assert_eq!(
+            response_validity(&response("max-age=999999"), &bounds, DEFAULT_VALIDITY),
+            Duration::from_secs(24 * 60 * 60)
+        );
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn test_fetch_keys_fallback_validity() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(PATH))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Cache-Control", "public, must-revalidate")
+                    .set_body_json(KeyResponse {
+                        keys: get_test_keys(),
+                    }),
+            )
+            .mount(&mock_server)
+            .await;
+        let mut fetcher = JwkFetcher::new(get_mock_url(&mock_server));
+        assert_eq!(
+            fetcher.fetch_keys().await.unwrap().validity,
+            DEFAULT_VALIDITY
+        );
+        fetcher.fallback_validity = Duration::from_secs(900);
+        assert_eq!(
+            fetcher.fetch_keys().await.unwrap().validity,
+            Duration::from_secs(900)
+        );
+    }
+
+    #[cfg(feature = "reqwest")]
+    fn fast_retry_policy() -> FetchRetryPolicy {
+        FetchRetryPolicy {
+            base_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+            ..FetchRetryPolicy::default()
+        }
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn test_fetch_keys_retries_transient_status() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(PATH))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(PATH))
+            .respond_with(ResponseTemplate::new(200).set_body_json(KeyResponse {
+                keys: get_test_keys(),
+            }))
+            .mount(&mock_server)
+            .await;
+        let mut fetcher = JwkFetcher::new(get_mock_url(&mock_server));
+        fetcher.retry_policy = fast_retry_policy();
+        assert_eq!(fetcher.fetch_keys().await.unwrap().keys, get_test_keys());
+        assert_eq!(mock_server.received_requests().await.unwrap().len(), 3);
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn test_fetch_keys_gives_up_after_max_attempts() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(PATH))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&mock_server)
+            .await;
+        let mut fetcher = JwkFetcher::new(get_mock_url(&mock_server));
+        fetcher.retry_policy = fast_retry_policy();
+        let result = fetcher.fetch_keys().await;
+        assert!(matches!(result, Err(KeyFetchError::StatusError(500))));
+        assert_eq!(mock_server.received_requests().await.unwrap().len(), 3);
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn test_fetch_keys_does_not_retry_client_errors() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(PATH))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&mock_server)
+            .await;
+        let mut fetcher = JwkFetcher::new(get_mock_url(&mock_server));
+        fetcher.retry_policy = fast_retry_policy();
+        let result = fetcher.fetch_keys().await;
+        assert!(matches!(result, Err(KeyFetchError::StatusError(404))));
+        assert_eq!(mock_server.received_requests().await.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_fetch_retry_policy_backoff() {
+        let policy = FetchRetryPolicy {
+            base_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+            jitter: 0.5,
+            ..FetchRetryPolicy::default()
+        };
+        for retry in 0..5 {
+            let expected = min(Duration::from_secs(1 << retry), Duration::from_secs(5));
+            let backoff = policy.backoff(retry);
+            assert!(backoff >= expected / 2 && backoff <= expected);
+        }
+        let policy = FetchRetryPolicy {
+            jitter: 0.0,
+            ..policy
+        };
+        assert_eq!(policy.backoff(1), Duration::from_secs(2));
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_status_class_contains() {
+        assert!(StatusClass::ServerError.contains(503));
+        assert!(!StatusClass::ServerError.contains(404));
+        assert!(StatusClass::TooManyRequests.contains(429));
+        assert!(StatusClass::RequestTimeout.contains(408));
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn test_fetch_provider_metadata() {
+        let mock_server = MockServer::start().await;
+        let metadata = ProviderMetadata {
+            issuer: mock_server.uri(),
+            jwks_uri: get_mock_url(&mock_server),
+        };
+        Mock::given(method("GET"))
+            .and(path("/.well-known/openid-configuration"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(&metadata))
+            .mount(&mock_server)
+            .await;
+        let client = ReqwestClient::default();
+        let issuer_url = format!("{}/", mock_server.uri());
+        let result = fetch_provider_metadata(&client, &issuer_url).await;
+        assert_eq!(result.unwrap(), metadata);
+
+        let result = fetch_provider_metadata(&client, "http://example/test").await;
+        assert!(matches!(result, Err(KeyFetchError::RequestError(_))));
+    }
+
+    #[test]
+    fn test_deserialize_key_types() {
+        let keys: KeyResponse = serde_json::from_value(serde_json::json!({
+            "keys": [
+                {
+                    "kty": "EC",
+                    "alg": "ES256",
+                    "kid": EC_SIGNING_KID,
+                    "use": "sig",
+                    "crv": "P-256",
+                    "x": "1zrK-c4m7BVv-TN6B74grsezv0AHcTZixBde2m_swDo",
+                    "y": "UoEm6J8JWkGCS6--AftpT0SsYhJB7j7HIoV1awLh5pc"
+                },
+                {
+                    "kty": "OKP",
+                    "alg": "EdDSA",
+                    "kid": ED_SIGNING_KID,
+                    "use": "sig",
+                    "crv": "Ed25519",
+                    "x": "7wwzDbZkquX5huE4DZKeKOzv3Me4hmwUYpoOPbuKT98"
+                }
+            ]
+        }))
+        .unwrap();
+        assert_eq!(keys.keys, vec![get_ec_signing_key(), get_ed_signing_key()]);
+    }
+
+    #[test]
+    fn test_deserialize_key_without_alg_and_use() {
+        let key: Jwk = serde_json::from_value(serde_json::json!({
+            "kty": "OKP",
+            "kid": ED_SIGNING_KID,
+            "crv": "Ed25519",
+            "x": "7wwzDbZkquX5huE4DZKeKOzv3Me4hmwUYpoOPbuKT98"
+        }))
+        .unwrap();
+        assert_eq!(key.alg, None);
+        assert_eq!(key.r#use, None);
+        assert!(key.is_signing_key());
+        assert_eq!(serde_json::to_value(&key).unwrap().get("alg"), None);
+    }
+
+    #[test]
+    fn test_parse_keys_skips_invalid_keys() {
+        let mut valid = serde_json::to_value(get_signing_key()).unwrap();
+        valid["x5t"] = "unknown field".into();
+        let body = serde_json::json!({
+            "keys": [
+                {
+                    "kty": "oct",
+                    "alg": "HS256",
+                    "kid": "symmetric",
+                    "use": "sig",
+                    "k": "c2VjcmV0"
+                },
+                {
+                    "kty": "RSA",
+                    "kid": "missing-fields"
+                },
+                valid
+            ]
+        })
+        .to_string();
+        let keys = parse_keys(KeyFormat::Jwk, body.as_bytes()).unwrap();
+        assert_eq!(keys, vec![get_signing_key()]);
+
+        let body = r#"{"keys": [{"kty": "oct", "kid": "symmetric"}]}"#;
+        assert!(matches!(
+            parse_keys(KeyFormat::Jwk, body.as_bytes()),
+            Err(KeyFetchError::ResponseBodyError(_))
+        ));
+        assert_eq!(
+            parse_keys(KeyFormat::Jwk, br#"{"keys": []}"#).unwrap(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_parse_x509_keys_skips_invalid_certificates() {
+        let body = serde_json::json!({
+            "valid": CERT_PEM,
+            "invalid": "not a certificate",
+        })
+        .to_string();
+        let keys = parse_keys(KeyFormat::X509, body.as_bytes()).unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].kid, "valid");
+
+        let body = serde_json::json!({ "invalid": "not a certificate" }).to_string();
+        assert!(matches!(
+            parse_keys(KeyFormat::X509, body.as_bytes()),
+            Err(KeyFetchError::InvalidCertificate(_))
+        ));
+    }
 }
@@ -1,94 +1,1801 @@
-use crate::jwk::{Fetcher, JwkFetcher};
-use crate::verifier::{Claims, JwkVerifier};
-use jsonwebtoken::TokenData;
-use log::info;
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
-use tokio::task::JoinHandle;
-use tokio::time::sleep;
-
-const ISSUER_URL: &'static str = "https://securetoken.google.com/";
-const DEFAULT_PUBKEY_URL: &'static str =
+#[cfg(feature = "reqwest")]
+use crate::credentials::{default_project_id, CredentialsError};
+#[cfg(not(feature = "reqwest"))]
+use crate::http::default_client;
+use crate::http::{HttpClient, HttpError};
+#[cfg(feature = "reqwest")]
+use crate::http::{HttpConfig, ReqwestClient, DEFAULT_REQUEST_TIMEOUT};
+#[cfg(feature = "reqwest")]
+use crate::identity_toolkit::IdentityToolkit;
+pub use crate::integration::{
+    FirebaseAuthRejection, FirebaseUser, OptionalFirebaseUser, RejectionResponse,
+};
+use crate::jwk::{
+    fetch_provider_metadata, random_between, FallbackFetcher, JwkFetcher, DEFAULT_VALIDITY,
+};
+pub use crate::jwk::{
+    FetchRetryPolicy, Fetcher, Jwk, JwkParams, Jwks, KeyFetchError, KeyFormat, StatusClass,
+    ValidityBounds,
+};
+use crate::key_cache::{CachingFetcher, KeyCache};
+use crate::key_pinning::{KeyPinning, PinningFetcher};
+use crate::runtime::default_runtime;
+pub use crate::runtime::Runtime;
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::runtime::TokioRuntime;
+#[cfg(target_arch = "wasm32")]
+pub use crate::runtime::WasmRuntime;
+use crate::single_flight::SingleFlightFetcher;
+pub use crate::telemetry::VerificationRecord;
+use crate::telemetry::{record_keys, record_verification, InstrumentedFetcher};
+use crate::time::{Duration, Instant, SystemTime};
+pub use crate::verifier::{
+    decode_unverified, Algorithm, Audience, Claims, ClaimsPolicy, DecodedToken, FirebaseClaims,
+    ProjectIdentifier, RequireClaim, StrictMode, TokenHeader, VerifiedToken, VerifyError,
+};
+use crate::verifier::{into_token_data, unverified_audience, JwkConfig, JwkVerifier};
+use futures_util::future::{AbortHandle, Abortable};
+use futures_util::stream::{Stream, StreamExt};
+#[cfg(not(feature = "tracing"))]
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+use std::cmp::{max, min};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex, RwLock};
+use thiserror::Error;
+use tokio::sync::oneshot;
+#[cfg(feature = "tracing")]
+use tracing::{info, warn};
+
+const ISSUER_URL: &str = "https://securetoken.google.com/";
+const DEFAULT_PUBKEY_URL: &str =
     "https://www.googleapis.com/service_accounts/v1/jwk/securetoken@system.gserviceaccount.com";
+const X509_PUBKEY_URL: &str =
+    "https://www.googleapis.com/robot/v1/metadata/x509/securetoken@system.gserviceaccount.com";
+const SESSION_COOKIE_ISSUER_URL: &str = "https://session.firebase.google.com/";
+const SESSION_COOKIE_PUBKEY_URL: &str =
+    "https://www.googleapis.com/identitytoolkit/v3/relyingparty/publicKeys";
+const IAP_ISSUER_URL: &str = "https://cloud.google.com/iap";
+const IAP_PUBKEY_URL: &str = "https://www.gstatic.com/iap/verify/public_key-jwk";
+const GOOGLE_ISSUER_URL: &str = "https://accounts.google.com";
+const GOOGLE_PUBKEY_URL: &str = "https://www.googleapis.com/oauth2/v3/certs";
+const EMULATOR_HOST_ENV: &str = "FIREBASE_AUTH_EMULATOR_HOST";
+/// Minimum time between key fetches triggered by tokens with an unknown kid.
+pub(crate) const UNKNOWN_KID_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+/// Bounds of the backoff between failed background refreshes.
+const REFRESH_RETRY_INITIAL_BACKOFF: Duration = Duration::from_secs(5);
+const REFRESH_RETRY_MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Error)]
+pub enum JwkAuthError {
+    #[error("unable to fetch jwk keys: {0}")]
+    KeyFetchError(#[source] KeyFetchError),
+    #[error("missing configuration: {0}")]
+    MissingConfig(&'static str),
+    /// The OpenID provider configuration could not be fetched.
+    #[error("unable to discover issuer: {0}")]
+    DiscoveryError(#[source] KeyFetchError),
+    /// The discovered issuer differs from the URL it was discovered from.
+    #[error("discovered issuer does not match: {0}")]
+    IssuerMismatch(String),
+    /// The HTTP client could not be created from its [`HttpConfig`](crate::http::HttpConfig).
+    #[error("unable to create HTTP client: {0}")]
+    HttpClientError(#[source] HttpError),
+    /// An option set on the builder does not apply to what is being built,
+    /// e.g. a custom fetcher to [`JwkAuthBuilder::build_blocking`].
+    #[error("unsupported configuration: {0}")]
+    UnsupportedConfig(&'static str),
+    /// The project id could not be found by [`JwkAuth::from_env`].
+    #[cfg(feature = "reqwest")]
+    #[error("unable to determine the project id: {0}")]
+    ProjectIdError(#[source] CredentialsError),
+}
+
+/// How often the initial key fetch is attempted before construction fails.
+/// The delay between attempts doubles up to `max_backoff`. With the built-in
+/// fetcher this sets the attempts and backoff of its [`FetchRetryPolicy`];
+/// only a custom [`Fetcher`] is retried as a whole.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+async fn fetch_with_retry(
+    fetcher: &(dyn Fetcher + Send + Sync),
+    retry_policy: &RetryPolicy,
+    runtime: &(dyn Runtime + Send + Sync),
+) -> Result<Jwks, KeyFetchError> {
+    let mut attempt = 1;
+    let mut backoff = retry_policy.initial_backoff;
+    loop {
+        match fetcher.fetch_keys().await {
+            Ok(jwk_keys) => return Ok(jwk_keys),
+            Err(error) if attempt >= retry_policy.max_attempts => return Err(error),
+            Err(error) => {
+                warn!(
+                    "Failed to fetch JWK Keys (attempt {}/{}): {}. Retrying in {:?}",
+                    attempt, retry_policy.max_attempts, error, backoff
+                );
+                runtime.sleep(backoff).await;
+                backoff = min(backoff * 2, retry_policy.max_backoff);
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// A random delay between half of `backoff` and `backoff`, so that instances
+/// failing at the same time do not retry in lockstep.
+fn with_jitter(backoff: Duration) -> Duration {
+    backoff.mul_f64(random_between(0.5, 1.0))
+}
+
+/// When the background task refreshes the keys, relative to the validity
+/// (`max-age`) announced by the key server. Refreshing before the keys expire
+/// keeps the first requests after expiry from racing a slow fetch. Use
+/// [`JwkAuthBuilder::periodic_refresh`] to disable background refreshes.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RefreshStrategy {
+    /// Keys are refreshed after a random fraction of their validity between
+    /// `min_fraction` and `max_fraction`.
+    pub min_fraction: f64,
+    pub max_fraction: f64,
+    pub min_interval: Duration,
+    pub max_interval: Duration,
+    /// The delay, once limited to the intervals, is randomly shortened by up
+    /// to `jitter` (0 to 1) of it, so that instances started together, e.g.
+    /// by a deploy, keep refreshing apart even at `min_interval`.
+    pub jitter: f64,
+}
+
+impl Default for RefreshStrategy {
+    fn default() -> Self {
+        RefreshStrategy {
+            min_fraction: 0.5,
+            max_fraction: 0.8,
+            min_interval: Duration::from_secs(60),
+            max_interval: Duration::from_secs(24 * 60 * 60),
+            jitter: 0.1,
+        }
+    }
+}
+
+impl RefreshStrategy {
+    /// The delay until the next refresh of keys valid for `validity`.
+    pub fn next_refresh(&self, validity: Duration) -> Duration {
+        let delay = validity.mul_f64(random_between(self.min_fraction, self.max_fraction));
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        delay
+            .clamp(self.min_interval, self.max_interval)
+            .mul_f64(random_between(1.0 - jitter, 1.0))
+    }
+}
+
+/// What to do with tokens once the keys are past their validity because
+/// refreshing them keeps failing, set with [`JwkAuthBuilder::staleness_policy`].
+/// Rejected tokens fail with `VerifyError::StaleKeys`.
+///
+/// Keys only count as stale once a refresh failed: staleness is measured from
+/// their expiry or, if later, the first failed refresh. Expired keys whose
+/// refresh is pending or not yet attempted, e.g. keys declared valid for no
+/// time at all, are still used. Keys served with `stale-while-revalidate`
+/// only count as stale once that grace period past their validity ran out.
+#[derive(Clone, Default)]
+pub enum StalenessPolicy {
+    /// Keep verifying with the stale keys until a refresh succeeds.
+    #[default]
+    FailOpen,
+    /// Reject every token as soon as the keys are stale.
+    FailClosed,
+    /// Keep verifying with the stale keys for this long past their validity,
+    /// then reject every token.
+    FailOpenFor(Duration),
+    /// Asks the callback, with how long the keys have been stale, whether to
+    /// keep verifying. Called on every verification while the keys are stale.
+    Decide(Arc<dyn Fn(Duration) -> bool + Send + Sync>),
+}
+
+impl StalenessPolicy {
+    /// Whether tokens may be verified with keys stale for `staleness`.
+    pub fn allows(&self, staleness: Duration) -> bool {
+        if staleness.is_zero() {
+            return true;
+        }
+        match self {
+            StalenessPolicy::FailOpen => true,
+            StalenessPolicy::FailClosed => false,
+            StalenessPolicy::FailOpenFor(max) => staleness <= *max,
+            StalenessPolicy::Decide(decide) => decide(staleness),
+        }
+    }
+}
+
+impl std::fmt::Debug for StalenessPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StalenessPolicy::FailOpen => f.write_str("FailOpen"),
+            StalenessPolicy::FailClosed => f.write_str("FailClosed"),
+            StalenessPolicy::FailOpenFor(max) => f.debug_tuple("FailOpenFor").field(max).finish(),
+            StalenessPolicy::Decide(_) => f.write_str("Decide(..)"),
+        }
+    }
+}
+
+/// When the current keys were fetched, how long they were declared valid,
+/// and whether refreshing them has failed since.
+#[derive(Debug, Clone, Copy)]
+struct KeyFreshness {
+    fetched_at: Instant,
+    /// `fetched_at` as wall clock time, for reporting.
+    fetched_at_time: SystemTime,
+    validity: Duration,
+    /// How long past `validity` the keys may be used before they count as
+    /// stale, from `stale-while-revalidate`.
+    grace: Duration,
+    /// When refreshing the keys first failed, if it has.
+    failing_since: Option<Instant>,
+}
+
+impl KeyFreshness {
+    fn new(validity: Duration, grace: Duration) -> KeyFreshness {
+        KeyFreshness {
+            fetched_at: Instant::now(),
+            fetched_at_time: SystemTime::now(),
+            validity,
+            grace,
+            failing_since: None,
+        }
+    }
+    /// Whether the keys are past their validity.
+    fn is_expired(&self) -> bool {
+        self.fetched_at.elapsed() >= self.validity
+    }
+    fn refresh_failed(&mut self) {
+        self.failing_since.get_or_insert_with(Instant::now);
+    }
+    /// How long the keys have been used since their validity and grace period
+    /// ran out and a refresh failed; zero while no refresh failed.
+    fn staleness(&self) -> Duration {
+        match self.failing_since {
+            Some(failing_since) => min(
+                self.fetched_at
+                    .elapsed()
+                    .saturating_sub(self.validity.saturating_add(self.grace)),
+                failing_since.elapsed(),
+            ),
+            None => Duration::ZERO,
+        }
+    }
+}
+
+type KeysUpdatedCallback = Box<dyn Fn(&Jwks) + Send + Sync>;
+type FetchErrorCallback = Box<dyn Fn(&KeyFetchError) + Send + Sync>;
+type VerificationCallback = Box<dyn Fn(&VerificationRecord) + Send + Sync>;
+
+/// The callbacks registered with [`JwkAuth::on_keys_updated`],
+/// [`JwkAuth::on_fetch_error`] and [`JwkAuth::on_verification`].
+#[derive(Default)]
+struct Observers {
+    keys_updated: RwLock<Vec<KeysUpdatedCallback>>,
+    fetch_error: RwLock<Vec<FetchErrorCallback>>,
+    verification: RwLock<Vec<VerificationCallback>>,
+}
+
+impl Observers {
+    fn keys_updated(&self, jwk_keys: &Jwks) {
+        for callback in self.keys_updated.read().unwrap().iter() {
+            callback(jwk_keys);
+        }
+    }
+    fn fetch_error(&self, error: &KeyFetchError) {
+        for callback in self.fetch_error.read().unwrap().iter() {
+            callback(error);
+        }
+    }
+    fn verification<T>(&self, token: &str, result: &Result<T, VerifyError>) {
+        let callbacks = self.verification.read().unwrap();
+        if callbacks.is_empty() {
+            return;
+        }
+        let record = VerificationRecord::of(token, result);
+        for callback in callbacks.iter() {
+            callback(&record);
+        }
+    }
+}
+
+fn install_keys(
+    verifier: &RwLock<JwkVerifier>,
+    freshness: &Mutex<KeyFreshness>,
+    observers: &Observers,
+    jwk_keys: Jwks,
+) {
+    record_keys(jwk_keys.keys.len());
+    let changed = !verifier.read().unwrap().has_keys(&jwk_keys.keys);
+    verifier.write().unwrap().set_keys(jwk_keys.keys.clone());
+    *freshness.lock().unwrap() =
+        KeyFreshness::new(jwk_keys.validity, jwk_keys.stale_while_revalidate);
+    if changed {
+        observers.keys_updated(&jwk_keys);
+    }
+}
+
+/// Verifies ID tokens. Implemented by [`JwkAuth`] and its counterparts, so
+/// that code verifying tokens, e.g. web handlers, can be tested with a stand-in
+/// such as `MockTokenVerifier` of the `test-utils` feature.
+pub trait TokenVerifier {
+    fn verify(&self, token: &str) -> Result<DecodedToken<Claims>, VerifyError>;
+}
+
+impl<V: TokenVerifier + ?Sized> TokenVerifier for Arc<V> {
+    fn verify(&self, token: &str) -> Result<DecodedToken<Claims>, VerifyError> {
+        self.as_ref().verify(token)
+    }
+}
+
+impl TokenVerifier for JwkAuth {
+    fn verify(&self, token: &str) -> Result<DecodedToken<Claims>, VerifyError> {
+        JwkAuth::verify(self, token)
+    }
+}
+
+impl TokenVerifier for TenantAwareAuth<'_> {
+    fn verify(&self, token: &str) -> Result<DecodedToken<Claims>, VerifyError> {
+        TenantAwareAuth::verify(self, token)
+    }
+}
+
+/// A spawned background refresh task.
+struct RefreshTask {
+    abort_handle: AbortHandle,
+    /// Completes, or is closed, once the task has stopped.
+    finished: oneshot::Receiver<()>,
+    /// When the task fetches the keys next.
+    next_refresh: Arc<Mutex<Instant>>,
+}
+
+impl RefreshTask {
+    fn is_running(&mut self) -> bool {
+        matches!(
+            self.finished.try_recv(),
+            Err(oneshot::error::TryRecvError::Empty)
+        )
+    }
+}
 
 pub struct JwkAuth {
-    verifier: Arc<Mutex<JwkVerifier>>,
-    fetcher: Arc<JwkFetcher>,
-    task_handler: Arc<Mutex<Box<JoinHandle<()>>>>,
+    verifier: Arc<RwLock<JwkVerifier>>,
+    fetcher: Arc<dyn Fetcher + Send + Sync>,
+    #[cfg(feature = "reqwest")]
+    identity_toolkit: Option<IdentityToolkit>,
+    runtime: Arc<dyn Runtime + Send + Sync>,
+    /// The background refresh task, if running.
+    task_handler: Mutex<Option<RefreshTask>>,
+    /// Used to (re)start the background refresh; `None` in emulator mode.
+    refresh_strategy: Option<RefreshStrategy>,
+    /// When keys were last fetched because of an unknown kid.
+    last_kid_refresh: Mutex<Option<Instant>>,
+    freshness: Arc<Mutex<KeyFreshness>>,
+    staleness_policy: StalenessPolicy,
+    lazy_refresh: bool,
+    /// Held while a lazy refresh is in flight; holds the time before which a
+    /// failed lazy refresh is not retried.
+    lazy_refresh_lock: tokio::sync::Mutex<Option<Instant>>,
+    observers: Arc<Observers>,
+}
+
+fn emulator_enabled() -> bool {
+    let enabled = std::env::var(EMULATOR_HOST_ENV).is_ok_and(|host| !host.is_empty());
+    if enabled {
+        warn!(
+            "{} is set: accepting unsigned emulator tokens without verifying signatures",
+            EMULATOR_HOST_ENV
+        );
+    }
+    enabled
+}
+
+/// Rejects tokens of disabled users and tokens issued before the user's
+/// refresh tokens were revoked.
+#[cfg(feature = "reqwest")]
+async fn check_user(
+    identity_toolkit: Option<&IdentityToolkit>,
+    token_data: DecodedToken<Claims>,
+) -> Result<DecodedToken<Claims>, VerifyError> {
+    let identity_toolkit = identity_toolkit.ok_or(VerifyError::RevocationCheckUnavailable)?;
+    let user = identity_toolkit
+        .get_user(&token_data.claims.sub)
+        .await
+        .map_err(|e| VerifyError::RevocationCheckFailed(e.to_string()))?;
+    if user.disabled {
+        return Err(VerifyError::UserDisabled);
+    }
+    let auth_time = token_data.claims.auth_time.unwrap_or(token_data.claims.iat);
+    match user.tokens_valid_after_time() {
+        Some(valid_after) if auth_time < valid_after => Err(VerifyError::Revoked),
+        _ => Ok(token_data),
+    }
 }
 
 impl Drop for JwkAuth {
     fn drop(&mut self) {
-        let handler = self.task_handler.lock().unwrap();
-        handler.abort();
+        self.pause_refresh();
     }
 }
 
-impl JwkAuth {
-    pub async fn new(project_id: String) -> JwkAuth {
-        let pubkey_url = DEFAULT_PUBKEY_URL.to_string();
-        Self::_new(project_id, pubkey_url).await
-    }
-    pub async fn _new(project_id: String, pubkey_url: String) -> JwkAuth {
-        let issuer = format!("{}{}", ISSUER_URL, project_id.clone());
-        let audience = project_id;
-        let fetcher = JwkFetcher::new(pubkey_url);
-        let jwk_key_result = fetcher.fetch_keys().await;
-        let jwk_keys = match jwk_key_result {
-            Ok(keys) => keys,
-            Err(_) => {
-                panic!("Unable to fetch jwk keys!")
+/// Configures and constructs a [`JwkAuth`].
+///
+/// Either a project id, or both an issuer and at least one audience, must be set.
+#[derive(Clone)]
+pub struct JwkAuthBuilder {
+    project_id: Option<String>,
+    project_number: Option<String>,
+    pubkey_url: Option<String>,
+    issuer: Option<String>,
+    audiences: Vec<String>,
+    audience_override: Option<String>,
+    session_cookie: bool,
+    key_format: Option<KeyFormat>,
+    fallback_pubkey_urls: Vec<(String, KeyFormat)>,
+    emulator: Option<bool>,
+    timeout: Option<Duration>,
+    #[cfg(feature = "reqwest")]
+    http_client: Option<reqwest::Client>,
+    #[cfg(feature = "reqwest")]
+    http_config: HttpConfig,
+    http_backend: Option<Arc<dyn HttpClient + Send + Sync>>,
+    fetcher: Option<Arc<dyn Fetcher + Send + Sync>>,
+    key_cache: Option<Arc<dyn KeyCache + Send + Sync>>,
+    key_pinning: Option<KeyPinning>,
+    runtime: Arc<dyn Runtime + Send + Sync>,
+    #[cfg(feature = "reqwest")]
+    identity_toolkit: Option<IdentityToolkit>,
+    retry_policy: RetryPolicy,
+    fetch_retry_policy: FetchRetryPolicy,
+    validity_bounds: ValidityBounds,
+    fallback_validity: Duration,
+    periodic_refresh: bool,
+    lazy_refresh: bool,
+    refresh_strategy: RefreshStrategy,
+    leeway: Duration,
+    staleness_policy: StalenessPolicy,
+    claims_policy: ClaimsPolicy,
+    tenant_id: Option<String>,
+    allowed_algorithms: Vec<Algorithm>,
+    strict_mode: Option<StrictMode>,
+    token_cache: Option<usize>,
+}
+
+impl Default for JwkAuthBuilder {
+    fn default() -> Self {
+        JwkAuthBuilder {
+            project_id: None,
+            project_number: None,
+            pubkey_url: None,
+            issuer: None,
+            audiences: Vec::new(),
+            audience_override: None,
+            session_cookie: false,
+            key_format: None,
+            fallback_pubkey_urls: Vec::new(),
+            emulator: None,
+            timeout: None,
+            #[cfg(feature = "reqwest")]
+            http_client: None,
+            #[cfg(feature = "reqwest")]
+            http_config: HttpConfig::default(),
+            http_backend: None,
+            fetcher: None,
+            key_cache: None,
+            key_pinning: None,
+            runtime: default_runtime(),
+            #[cfg(feature = "reqwest")]
+            identity_toolkit: None,
+            retry_policy: RetryPolicy::default(),
+            fetch_retry_policy: FetchRetryPolicy::default(),
+            validity_bounds: ValidityBounds::default(),
+            fallback_validity: DEFAULT_VALIDITY,
+            periodic_refresh: true,
+            lazy_refresh: false,
+            refresh_strategy: RefreshStrategy::default(),
+            leeway: Duration::from_secs(0),
+            staleness_policy: StalenessPolicy::default(),
+            claims_policy: ClaimsPolicy::default(),
+            tenant_id: None,
+            allowed_algorithms: vec![Algorithm::RS256],
+            strict_mode: None,
+            token_cache: None,
+        }
+    }
+}
+
+impl JwkAuthBuilder {
+    pub fn new() -> JwkAuthBuilder {
+        JwkAuthBuilder::default()
+    }
+    pub fn project_id(mut self, project_id: String) -> Self {
+        self.project_id = Some(project_id);
+        self
+    }
+    /// Also accept tokens whose `aud` is the project number rather than the
+    /// project id; [`JwkAuth::verify_detailed`] tells which one matched.
+    pub fn project_number(mut self, project_number: String) -> Self {
+        self.project_number = Some(project_number);
+        self
+    }
+    pub fn pubkey_url(mut self, pubkey_url: String) -> Self {
+        self.pubkey_url = Some(pubkey_url);
+        self
+    }
+    /// The format of the keys at `pubkey_url`. Defaults to a JWK set, or to
+    /// x509 certificates for session cookies.
+    pub fn key_format(mut self, key_format: KeyFormat) -> Self {
+        self.key_format = Some(key_format);
+        self
+    }
+    /// Fetches the ID token keys as x509 certificates from the endpoint the
+    /// official Admin SDKs use, instead of as a JWK set.
+    pub fn x509_endpoint(self) -> Self {
+        self.pubkey_url(X509_PUBKEY_URL.to_string())
+            .key_format(KeyFormat::X509)
+    }
+    /// Fetches the keys from `pubkey_url` in `key_format` when fetching them
+    /// from `pubkey_url`, and any fallback added before, fails. Fallbacks are
+    /// tried in the order added, e.g. the x509 endpoint and then a mirror,
+    /// each right after the first failure of the one before. The
+    /// [`fetch_retry_policy`](Self::fetch_retry_policy) then applies to the
+    /// sources as a whole, so a fetch makes at most `max_attempts` requests
+    /// to each. [`JwkAuth::key_source`] tells which source answered.
+    pub fn fallback_pubkey_url(mut self, pubkey_url: String, key_format: KeyFormat) -> Self {
+        self.fallback_pubkey_urls.push((pubkey_url, key_format));
+        self
+    }
+    /// Sets the issuer, when there is no project id to derive it from.
+    /// See [`issuer_override`](Self::issuer_override) otherwise.
+    pub fn issuer(mut self, issuer: String) -> Self {
+        self.issuer = Some(issuer);
+        self
+    }
+    /// Adds an accepted audience, overriding the one derived from the project
+    /// id. Call repeatedly to accept tokens for any of several audiences.
+    pub fn audience(mut self, audience: String) -> Self {
+        self.audiences.push(audience);
+        self
+    }
+    /// Replaces the issuer derived from the project id, e.g. for tokens
+    /// passed through a gateway that mirrors `securetoken.google.com` under
+    /// its own issuer. The project id still applies everywhere else,
+    /// including the audience unless
+    /// [`audience_override`](Self::audience_override) is set as well.
+    pub fn issuer_override(self, issuer: String) -> Self {
+        self.issuer(issuer)
+    }
+    /// Replaces the audience derived from the project id, and any added
+    /// with [`audience`](Self::audience) before or after, with `audience`.
+    pub fn audience_override(mut self, audience: String) -> Self {
+        self.audience_override = Some(audience);
+        self
+    }
+    /// Verify session cookies instead of ID tokens.
+    pub fn session_cookie(mut self, session_cookie: bool) -> Self {
+        self.session_cookie = session_cookie;
+        self
+    }
+    /// Forces emulator mode on or off. By default it is enabled when
+    /// `FIREBASE_AUTH_EMULATOR_HOST` is set.
+    pub fn emulator(mut self, emulator: bool) -> Self {
+        self.emulator = Some(emulator);
+        self
+    }
+    /// Timeout for each HTTP request made while fetching keys, 30 seconds by
+    /// default. Does not apply to an [`http_backend`](Self::http_backend).
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+    /// Uses `client` for all key fetches instead of a default `reqwest::Client`,
+    /// e.g. to configure proxies, TLS roots or connection pooling.
+    #[cfg(feature = "reqwest")]
+    pub fn http_client(mut self, client: reqwest::Client) -> Self {
+        self.http_client = Some(client);
+        self
+    }
+    /// Connect timeout, proxy and additional CA certificates of the default
+    /// HTTP client. Does not apply to an `http_client` or `http_backend`.
+    #[cfg(feature = "reqwest")]
+    pub fn http_config(mut self, http_config: HttpConfig) -> Self {
+        self.http_config = http_config;
+        self
+    }
+    /// Sends key and discovery requests through `client` instead of reqwest,
+    /// e.g. to reuse an existing HTTP stack. Takes precedence over
+    /// `http_client`, and is required without the `reqwest` feature.
+    pub fn http_backend<C>(mut self, client: C) -> Self
+    where
+        C: HttpClient + Send + Sync + 'static,
+    {
+        self.http_backend = Some(Arc::new(client));
+        self
+    }
+    /// Loads keys through a custom [`Fetcher`], e.g. one reading from a file or
+    /// an internal mirror. `pubkey_url`, `timeout`, `http_client` and
+    /// `http_backend` are ignored.
+    pub fn fetcher<F>(mut self, fetcher: F) -> Self
+    where
+        F: Fetcher + Send + Sync + 'static,
+    {
+        self.fetcher = Some(Arc::new(fetcher));
+        self
+    }
+    /// Shares fetched keys through `cache`: keys are taken from the cache
+    /// while they are valid, and fetched and stored in it otherwise. This
+    /// includes the refreshes done for unknown kids.
+    pub fn key_cache<C>(mut self, cache: C) -> Self
+    where
+        C: KeyCache + Send + Sync + 'static,
+    {
+        self.key_cache = Some(Arc::new(cache));
+        self
+    }
+    /// Checks every fetched key set against `key_pinning`, logging or, if
+    /// enforced, refusing unexpected ones. Keys taken from the
+    /// [`key_cache`](Self::key_cache) are not checked again.
+    pub fn key_pinning(mut self, key_pinning: KeyPinning) -> Self {
+        self.key_pinning = Some(key_pinning);
+        self
+    }
+    /// Runs the key refresh on another executor than tokio, or, given a
+    /// `tokio::runtime::Handle`, on that runtime rather than the current one.
+    pub fn runtime<R>(mut self, runtime: R) -> Self
+    where
+        R: Runtime + Send + Sync + 'static,
+    {
+        self.runtime = Arc::new(runtime);
+        self
+    }
+    /// Client used by [`JwkAuth::verify_checked`] to look up users.
+    #[cfg(feature = "reqwest")]
+    pub fn identity_toolkit(mut self, identity_toolkit: IdentityToolkit) -> Self {
+        self.identity_toolkit = Some(identity_toolkit);
+        self
+    }
+    /// How often the initial key fetch is attempted. A custom
+    /// [`fetcher`](Self::fetcher) is called up to `max_attempts` times; the
+    /// built-in fetcher, which retries each request by itself, instead takes
+    /// `max_attempts` and the backoff into its
+    /// [`fetch_retry_policy`](Self::fetch_retry_policy), so that requests are
+    /// never retried twice. Whichever of the two is set last wins.
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.fetch_retry_policy = FetchRetryPolicy {
+            max_attempts: retry_policy.max_attempts,
+            base_backoff: retry_policy.initial_backoff,
+            max_backoff: retry_policy.max_backoff,
+            ..self.fetch_retry_policy
+        };
+        self.retry_policy = retry_policy;
+        self
+    }
+    /// How each key request is retried on request errors and transient
+    /// statuses, including during background refreshes. Does not apply to a
+    /// custom [`fetcher`](Self::fetcher). The initial fetch is not retried on
+    /// top of it, so with the defaults it makes at most 3 requests per key
+    /// URL, about 0.6 seconds of backoff apart in total, plus the request
+    /// timeout of each.
+    pub fn fetch_retry_policy(mut self, fetch_retry_policy: FetchRetryPolicy) -> Self {
+        self.fetch_retry_policy = fetch_retry_policy;
+        self
+    }
+    /// Limits the key validity derived from the key server's `Cache-Control`
+    /// header. By default keys are valid for at least 5 minutes and at most
+    /// 24 hours. Does not
+    /// apply to a custom [`fetcher`](Self::fetcher).
+    pub fn validity_bounds(mut self, validity_bounds: ValidityBounds) -> Self {
+        self.validity_bounds = validity_bounds;
+        self
+    }
+    /// The key validity when the key server's `Cache-Control` header has no
+    /// usable `max-age`, 5 minutes by default. Subject to the
+    /// [`validity_bounds`](Self::validity_bounds). Does not apply to a custom
+    /// [`fetcher`](Self::fetcher).
+    pub fn fallback_validity(mut self, fallback_validity: Duration) -> Self {
+        self.fallback_validity = fallback_validity;
+        self
+    }
+    /// Whether keys are refreshed in the background. Enabled by default.
+    pub fn periodic_refresh(mut self, periodic_refresh: bool) -> Self {
+        self.periodic_refresh = periodic_refresh;
+        self
+    }
+    /// Instead of running a background task, refresh expired keys in
+    /// [`JwkAuth::verify_async`], which the web framework integrations other
+    /// than tonic use. Suits serverless environments where background tasks
+    /// are undesirable. Implies `periodic_refresh(false)`.
+    pub fn lazy_refresh(mut self, lazy_refresh: bool) -> Self {
+        self.lazy_refresh = lazy_refresh;
+        self
+    }
+    pub fn refresh_strategy(mut self, refresh_strategy: RefreshStrategy) -> Self {
+        self.refresh_strategy = refresh_strategy;
+        self
+    }
+    /// Clock skew tolerated when checking the token's `exp`, `nbf` and `iat`.
+    pub fn leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway;
+        self
+    }
+    /// Refuse to verify tokens with `VerifyError::StaleKeys` once the keys
+    /// have not been refreshed for this long past their validity, counted
+    /// from the first failed refresh at the earliest. Shorthand
+    /// for `staleness_policy(StalenessPolicy::FailOpenFor(max_staleness))`.
+    pub fn max_staleness(self, max_staleness: Duration) -> Self {
+        self.staleness_policy(StalenessPolicy::FailOpenFor(max_staleness))
+    }
+    /// What to do once the keys are stale. By default stale keys are used
+    /// until a refresh succeeds.
+    pub fn staleness_policy(mut self, staleness_policy: StalenessPolicy) -> Self {
+        self.staleness_policy = staleness_policy;
+        self
+    }
+    pub fn required_claim(mut self, claim: String) -> Self {
+        self.claims_policy.required_claims.push(claim);
+        self
+    }
+    /// Rejects tokens of users who signed in with a single factor with
+    /// `VerifyError::MultiFactorRequired`, e.g. for an admin panel.
+    pub fn require_mfa(mut self, require_mfa: bool) -> Self {
+        self.claims_policy.require_mfa = require_mfa;
+        self
+    }
+    /// Replaces the claims policy, including claims added with `required_claim`.
+    pub fn claims_policy(mut self, claims_policy: ClaimsPolicy) -> Self {
+        self.claims_policy = claims_policy;
+        self
+    }
+    /// Only accept tokens issued to users of this Identity Platform tenant.
+    pub fn tenant_id(mut self, tenant_id: String) -> Self {
+        self.tenant_id = Some(tenant_id);
+        self
+    }
+    /// Signing algorithms accepted for tokens and keys. Defaults to RS256, the
+    /// only algorithm Firebase uses.
+    pub fn allowed_algorithms(mut self, allowed_algorithms: Vec<Algorithm>) -> Self {
+        self.allowed_algorithms = allowed_algorithms;
+        self
+    }
+    /// Additionally reject oversized tokens, tokens with a `crit` header or a
+    /// `typ` other than `JWT`, and tokens whose `alg` is not their key's `alg`.
+    /// See [`StrictMode`].
+    pub fn strict_mode(mut self, strict_mode: StrictMode) -> Self {
+        self.strict_mode = Some(strict_mode);
+        self
+    }
+    /// Remembers up to `capacity` tokens verified by [`JwkAuth::verify`] until
+    /// they expire, allowing for the [`leeway`](Self::leeway), so that
+    /// verifying them again skips the signature check.
+    /// The cache is cleared when the keys change, and a token is dropped from
+    /// it once `verify_checked` finds it revoked. Disabled by default.
+    pub fn token_cache(mut self, capacity: usize) -> Self {
+        self.token_cache = Some(capacity);
+        self
+    }
+    /// Sets the issuer and key URL from the OpenID provider configuration
+    /// published at `issuer_url`/.well-known/openid-configuration. The
+    /// audience still has to be set. Emulator mode is disabled.
+    pub async fn discover(mut self, issuer_url: String) -> Result<Self, JwkAuthError> {
+        let metadata = fetch_provider_metadata(self.http()?.as_ref(), &issuer_url)
+            .await
+            .map_err(JwkAuthError::DiscoveryError)?;
+        if metadata.issuer.trim_end_matches('/') != issuer_url.trim_end_matches('/') {
+            return Err(JwkAuthError::IssuerMismatch(metadata.issuer));
+        }
+        self.issuer = Some(metadata.issuer);
+        self.pubkey_url = Some(metadata.jwks_uri);
+        self.emulator = Some(false);
+        Ok(self)
+    }
+    fn config(&self) -> Result<JwkConfig, JwkAuthError> {
+        let issuer_url = if self.session_cookie {
+            SESSION_COOKIE_ISSUER_URL
+        } else {
+            ISSUER_URL
+        };
+        let issuer = match (&self.issuer, &self.project_id) {
+            (Some(issuer), _) => issuer.clone(),
+            (None, Some(project_id)) => format!("{}{}", issuer_url, project_id),
+            (None, None) => return Err(JwkAuthError::MissingConfig("issuer")),
+        };
+        // The override applies last, whatever the order of the calls.
+        let audiences = match (&self.audience_override, &self.project_id) {
+            (Some(audience), _) => vec![audience.clone()],
+            (None, _) if !self.audiences.is_empty() => self.audiences.clone(),
+            (None, Some(project_id)) => vec![project_id.clone()],
+            (None, None) => return Err(JwkAuthError::MissingConfig("audience")),
+        };
+        Ok(JwkConfig {
+            audiences,
+            issuer,
+            leeway: self.leeway,
+            policy: self.claims_policy.clone(),
+            tenant_id: self.tenant_id.clone(),
+            allowed_algorithms: self.allowed_algorithms.clone(),
+            strict: self.strict_mode.clone(),
+            project_number: self.project_number.clone(),
+        })
+    }
+    /// The URL and format of the keys.
+    fn key_source(&self) -> (String, KeyFormat) {
+        let (default_pubkey_url, format) = if self.session_cookie {
+            (SESSION_COOKIE_PUBKEY_URL, KeyFormat::X509)
+        } else {
+            (DEFAULT_PUBKEY_URL, KeyFormat::Jwk)
+        };
+        let pubkey_url = self
+            .pubkey_url
+            .clone()
+            .unwrap_or_else(|| default_pubkey_url.to_string());
+        (pubkey_url, self.key_format.unwrap_or(format))
+    }
+    fn default_fetcher(&self) -> Result<Arc<dyn Fetcher + Send + Sync>, JwkAuthError> {
+        let (pubkey_url, format) = self.key_source();
+        let client = self.http()?;
+        let jwk_fetcher = |pubkey_url: String, format: KeyFormat| {
+            let mut fetcher = JwkFetcher::with_format(pubkey_url, format);
+            fetcher.client = Arc::clone(&client);
+            fetcher.retry_policy = self.fetch_retry_policy.clone();
+            fetcher.validity_bounds = self.validity_bounds;
+            fetcher.fallback_validity = self.fallback_validity;
+            fetcher.runtime = Arc::clone(&self.runtime);
+            fetcher
+        };
+        if self.fallback_pubkey_urls.is_empty() {
+            return Ok(Arc::new(jwk_fetcher(pubkey_url, format)));
+        }
+        let fetchers = std::iter::once((pubkey_url, format))
+            .chain(self.fallback_pubkey_urls.iter().cloned())
+            .map(|(pubkey_url, format)| jwk_fetcher(pubkey_url, format))
+            .collect();
+        Ok(Arc::new(FallbackFetcher::with_fetchers(
+            fetchers,
+            self.fetch_retry_policy.clone(),
+            Arc::clone(&self.runtime),
+        )))
+    }
+    fn http(&self) -> Result<Arc<dyn HttpClient + Send + Sync>, JwkAuthError> {
+        if let Some(client) = &self.http_backend {
+            return Ok(Arc::clone(client));
+        }
+        #[cfg(feature = "reqwest")]
+        let client: Arc<dyn HttpClient + Send + Sync> = Arc::new(ReqwestClient {
+            client: match &self.http_client {
+                Some(client) => client.clone(),
+                None => self
+                    .http_config
+                    .build_client()
+                    .map_err(JwkAuthError::HttpClientError)?,
+            },
+            timeout: Some(self.timeout.unwrap_or(DEFAULT_REQUEST_TIMEOUT)),
+        });
+        #[cfg(not(feature = "reqwest"))]
+        let client = default_client(self.timeout);
+        Ok(client)
+    }
+    pub async fn build(self) -> Result<JwkAuth, JwkAuthError> {
+        let config = self.config()?;
+        if self.emulator.unwrap_or_else(emulator_enabled) {
+            let mut instance = JwkAuth::emulator(config);
+            #[cfg(feature = "reqwest")]
+            {
+                instance.identity_toolkit = self.tenant_identity_toolkit();
             }
+            instance.runtime = self.runtime;
+            instance.staleness_policy = self.staleness_policy;
+            return Ok(instance);
+        }
+        let fetcher = self.key_fetcher()?;
+        // The built-in fetcher already retries each request.
+        let jwk_keys = if self.fetcher.is_some() {
+            fetch_with_retry(fetcher.as_ref(), &self.retry_policy, self.runtime.as_ref()).await
+        } else {
+            fetcher.fetch_keys().await
+        }
+        .map_err(JwkAuthError::KeyFetchError)?;
+        let periodic_refresh = self.periodic_refresh && !self.lazy_refresh;
+        let instance = self.into_jwk_auth(config, fetcher, jwk_keys);
+        if periodic_refresh {
+            instance.resume_refresh();
+        }
+        Ok(instance)
+    }
+    /// Builds a [`BlockingJwkAuth`](crate::blocking::BlockingJwkAuth),
+    /// fetching the keys with `reqwest::blocking`. Fails with
+    /// [`JwkAuthError::UnsupportedConfig`] if a fetcher, HTTP client or
+    /// backend, fallback key URL, key cache, key pinning or fetch retry policy
+    /// is set. The runtime and refresh settings do not apply to it.
+    #[cfg(feature = "blocking")]
+    pub fn build_blocking(self) -> Result<crate::blocking::BlockingJwkAuth, JwkAuthError> {
+        use crate::blocking::{BlockingJwkAuth, BlockingJwkFetcher};
+        if let Some(option) = self.unsupported_by_blocking() {
+            return Err(JwkAuthError::UnsupportedConfig(option));
+        }
+        let config = self.config()?;
+        let (pubkey_url, format) = self.key_source();
+        let mut fetcher = BlockingJwkFetcher::with_format(pubkey_url, format);
+        fetcher.client = self
+            .http_config
+            .build_blocking_client()
+            .map_err(JwkAuthError::HttpClientError)?;
+        fetcher.timeout = Some(self.timeout.unwrap_or(DEFAULT_REQUEST_TIMEOUT));
+        fetcher.validity_bounds = self.validity_bounds;
+        fetcher.fallback_validity = self.fallback_validity;
+        if self.emulator.unwrap_or_else(emulator_enabled) {
+            return Ok(BlockingJwkAuth::emulator(fetcher, config));
+        }
+        BlockingJwkAuth::fetch(fetcher, config)
+    }
+    /// The first option set that [`build_blocking`](Self::build_blocking)
+    /// cannot honour.
+    #[cfg(feature = "blocking")]
+    fn unsupported_by_blocking(&self) -> Option<&'static str> {
+        if self.fetcher.is_some() {
+            Some("fetcher")
+        } else if self.http_client.is_some() {
+            Some("http_client")
+        } else if self.http_backend.is_some() {
+            Some("http_backend")
+        } else if !self.fallback_pubkey_urls.is_empty() {
+            Some("fallback_pubkey_url")
+        } else if self.key_cache.is_some() {
+            Some("key_cache")
+        } else if self.key_pinning.is_some() {
+            Some("key_pinning")
+        } else if self.fetch_retry_policy != FetchRetryPolicy::default() {
+            Some("fetch_retry_policy")
+        } else {
+            None
+        }
+    }
+    /// Builds with `keys` instead of fetching them, without any network call.
+    /// Emulator mode does not apply, and keys are only refreshed once
+    /// [`JwkAuth::resume_refresh`] is called.
+    pub fn build_with_keys(self, keys: Vec<Jwk>) -> Result<JwkAuth, JwkAuthError> {
+        let config = self.config()?;
+        let fetcher = self.key_fetcher()?;
+        let jwk_keys = Jwks {
+            keys,
+            validity: Duration::MAX,
+            stale_while_revalidate: Duration::ZERO,
+        };
+        Ok(self.into_jwk_auth(config, fetcher, jwk_keys))
+    }
+    #[cfg(feature = "reqwest")]
+    fn tenant_identity_toolkit(&self) -> Option<IdentityToolkit> {
+        match (self.identity_toolkit.clone(), &self.tenant_id) {
+            (Some(client), Some(tenant_id)) => Some(client.with_tenant_id(tenant_id.clone())),
+            (identity_toolkit, _) => identity_toolkit,
+        }
+    }
+    fn key_fetcher(&self) -> Result<Arc<dyn Fetcher + Send + Sync>, JwkAuthError> {
+        let mut fetcher: Arc<dyn Fetcher + Send + Sync> = Arc::new(InstrumentedFetcher {
+            fetcher: match &self.fetcher {
+                Some(fetcher) => Arc::clone(fetcher),
+                None => self.default_fetcher()?,
+            },
+        });
+        // Pinning comes before the cache so that refused keys are not cached.
+        if let Some(pinning) = &self.key_pinning {
+            fetcher = Arc::new(PinningFetcher {
+                fetcher,
+                pinning: pinning.clone(),
+                previous: Mutex::new(None),
+            });
+        }
+        if let Some(cache) = &self.key_cache {
+            fetcher = Arc::new(CachingFetcher {
+                fetcher,
+                cache: Arc::clone(cache),
+            });
+        }
+        Ok(Arc::new(SingleFlightFetcher::wrap(fetcher)))
+    }
+    fn into_jwk_auth(
+        self,
+        config: JwkConfig,
+        fetcher: Arc<dyn Fetcher + Send + Sync>,
+        jwk_keys: Jwks,
+    ) -> JwkAuth {
+        let freshness = KeyFreshness::new(jwk_keys.validity, jwk_keys.stale_while_revalidate);
+        record_keys(jwk_keys.keys.len());
+        let mut verifier = JwkVerifier::with_config(jwk_keys.keys, config);
+        if let Some(capacity) = self.token_cache {
+            verifier.cache_tokens(capacity);
+        }
+        JwkAuth {
+            verifier: Arc::new(RwLock::new(verifier)),
+            fetcher,
+            #[cfg(feature = "reqwest")]
+            identity_toolkit: self.tenant_identity_toolkit(),
+            runtime: self.runtime,
+            task_handler: Mutex::new(None),
+            refresh_strategy: Some(self.refresh_strategy),
+            last_kid_refresh: Mutex::new(None),
+            freshness: Arc::new(Mutex::new(freshness)),
+            staleness_policy: self.staleness_policy,
+            lazy_refresh: self.lazy_refresh,
+            lazy_refresh_lock: tokio::sync::Mutex::new(None),
+            observers: Arc::default(),
+        }
+    }
+}
+
+impl JwkAuth {
+    pub fn builder() -> JwkAuthBuilder {
+        JwkAuthBuilder::new()
+    }
+    /// Turns this into a cheaply cloneable [`JwkAuthHandle`].
+    pub fn into_handle(self) -> JwkAuthHandle {
+        JwkAuthHandle::from(self)
+    }
+    /// Falls back to emulator mode when `FIREBASE_AUTH_EMULATOR_HOST` is set,
+    /// in which signatures are **not verified** and anyone can forge tokens:
+    /// make sure it is never set in production, or use the builder with
+    /// `.emulator(false)`.
+    pub async fn new(project_id: String) -> Result<JwkAuth, JwkAuthError> {
+        Self::builder().project_id(project_id).build().await
+    }
+    /// Like [`new`](Self::new), for the project the process runs in, as found
+    /// by [`default_project_id`](crate::credentials::default_project_id).
+    #[cfg(feature = "reqwest")]
+    pub async fn from_env() -> Result<JwkAuth, JwkAuthError> {
+        let project_id = default_project_id()
+            .await
+            .map_err(JwkAuthError::ProjectIdError)?;
+        Self::new(project_id).await
+    }
+    pub async fn new_with_retry(
+        project_id: String,
+        retry_policy: RetryPolicy,
+    ) -> Result<JwkAuth, JwkAuthError> {
+        Self::builder()
+            .project_id(project_id)
+            .retry_policy(retry_policy)
+            .build()
+            .await
+    }
+    #[deprecated(note = "use `JwkAuth::builder().pubkey_url(..)` instead")]
+    pub async fn _new(project_id: String, pubkey_url: String) -> Result<JwkAuth, JwkAuthError> {
+        Self::builder()
+            .project_id(project_id)
+            .pubkey_url(pubkey_url)
+            .emulator(false)
+            .build()
+            .await
+    }
+    #[deprecated(note = "use `JwkAuth::builder().pubkey_url(..).retry_policy(..)` instead")]
+    pub async fn _new_with_retry(
+        project_id: String,
+        pubkey_url: String,
+        retry_policy: RetryPolicy,
+    ) -> Result<JwkAuth, JwkAuthError> {
+        Self::builder()
+            .project_id(project_id)
+            .pubkey_url(pubkey_url)
+            .retry_policy(retry_policy)
+            .emulator(false)
+            .build()
+            .await
+    }
+    /// Verifies session cookies created by `createSessionCookie` instead of ID tokens.
+    pub async fn new_session_cookie(project_id: String) -> Result<JwkAuth, JwkAuthError> {
+        Self::builder()
+            .project_id(project_id)
+            .session_cookie(true)
+            .build()
+            .await
+    }
+    #[deprecated(note = "use `JwkAuth::builder().session_cookie(true).pubkey_url(..)` instead")]
+    pub async fn _new_session_cookie(
+        project_id: String,
+        pubkey_url: String,
+    ) -> Result<JwkAuth, JwkAuthError> {
+        Self::builder()
+            .project_id(project_id)
+            .pubkey_url(pubkey_url)
+            .session_cookie(true)
+            .emulator(false)
+            .build()
+            .await
+    }
+    /// Configures issuer and keys from the OpenID provider configuration of
+    /// `issuer_url`, see [`JwkAuthBuilder::discover`].
+    pub async fn from_discovery(
+        issuer_url: String,
+        audience: String,
+    ) -> Result<JwkAuth, JwkAuthError> {
+        Self::builder()
+            .audience(audience)
+            .discover(issuer_url)
+            .await?
+            .build()
+            .await
+    }
+    /// Verifies ID tokens of `project_id` with the given keys, without any
+    /// network call. Call [`resume_refresh`](Self::resume_refresh) to keep
+    /// them up to date from Google afterwards. Shorthand for
+    /// [`JwkAuthBuilder::build_with_keys`] with only the project id set.
+    pub fn with_keys(keys: Vec<Jwk>, project_id: String) -> Result<JwkAuth, JwkAuthError> {
+        Self::builder().project_id(project_id).build_with_keys(keys)
+    }
+    /// Accepts the unsigned tokens issued by the Firebase Auth emulator.
+    /// Never use this in production: token signatures are not checked.
+    pub fn new_emulator(project_id: String) -> JwkAuth {
+        let issuer = format!("{}{}", ISSUER_URL, project_id);
+        Self::emulator(JwkConfig::new(project_id, issuer))
+    }
+    fn emulator(config: JwkConfig) -> JwkAuth {
+        JwkAuth {
+            verifier: Arc::new(RwLock::new(JwkVerifier::emulator_with_config(config))),
+            fetcher: Arc::new(JwkFetcher::new(DEFAULT_PUBKEY_URL.to_string())),
+            #[cfg(feature = "reqwest")]
+            identity_toolkit: None,
+            runtime: default_runtime(),
+            task_handler: Mutex::new(None),
+            refresh_strategy: None,
+            last_kid_refresh: Mutex::new(None),
+            freshness: Arc::new(Mutex::new(KeyFreshness::new(Duration::MAX, Duration::ZERO))),
+            staleness_policy: StalenessPolicy::default(),
+            lazy_refresh: false,
+            lazy_refresh_lock: tokio::sync::Mutex::new(None),
+            observers: Arc::default(),
+        }
+    }
+    pub fn verify(&self, token: &str) -> Result<DecodedToken<Claims>, VerifyError> {
+        self.verify_with(token, |verifier| verifier.verify(token))
+    }
+    /// Like [`verify`](Self::verify), also telling which key and algorithm
+    /// verified the token, and when.
+    pub fn verify_detailed(&self, token: &str) -> Result<VerifiedToken, VerifyError> {
+        let token_data = self.verify(token)?;
+        Ok(VerifiedToken::new(
+            token_data,
+            self.verifier.read().unwrap().config(),
+        ))
+    }
+    /// Runs `verify` with the current keys unless they are too stale, and
+    /// records the outcome.
+    fn verify_with<T>(
+        &self,
+        token: &str,
+        verify: impl FnOnce(&JwkVerifier) -> Result<T, VerifyError>,
+    ) -> Result<T, VerifyError> {
+        let result = self
+            .check_freshness()
+            .and_then(|_| verify(&self.verifier.read().unwrap()));
+        record_verification(token, &result, self.keys_age());
+        self.observers.verification(token, &result);
+        result
+    }
+    /// Time since the keys in use were fetched.
+    pub fn keys_age(&self) -> Duration {
+        self.freshness.lock().unwrap().fetched_at.elapsed()
+    }
+    /// Whether the keys in use are past the validity announced by the key
+    /// server, i.e. they have not been refreshed in time.
+    pub fn is_stale(&self) -> bool {
+        self.freshness.lock().unwrap().is_expired()
+    }
+    /// The ids of the keys in use, sorted. Empty in emulator mode.
+    pub fn key_ids(&self) -> Vec<String> {
+        self.verifier.read().unwrap().key_ids()
+    }
+    /// When the keys in use were fetched, or given to
+    /// [`with_keys`](Self::with_keys).
+    pub fn last_refresh_time(&self) -> SystemTime {
+        self.freshness.lock().unwrap().fetched_at_time
+    }
+    /// The URL the keys were last fetched from, e.g. to tell when a
+    /// [`fallback_pubkey_url`](JwkAuthBuilder::fallback_pubkey_url) is in use.
+    /// `None` before the first fetch or if a custom fetcher does not say.
+    pub fn key_source(&self) -> Option<String> {
+        self.fetcher.key_source()
+    }
+    /// Time until the background refresh fetches the keys next, or `None` if
+    /// it is not running, e.g. with `periodic_refresh(false)` or after
+    /// [`pause_refresh`](Self::pause_refresh).
+    pub fn next_refresh_in(&self) -> Option<Duration> {
+        let mut handler = self.task_handler.lock().unwrap();
+        let task = handler.as_mut()?;
+        if !task.is_running() {
+            return None;
+        }
+        let next_refresh = *task.next_refresh.lock().unwrap();
+        Some(next_refresh.saturating_duration_since(Instant::now()))
+    }
+    /// Whether tokens are verified with up to date keys: there are keys, and
+    /// they are either fresh or still allowed by a staleness policy other than
+    /// the default `FailOpen`. Always true in emulator mode.
+    pub fn is_healthy(&self) -> bool {
+        if self.refresh_strategy.is_none() {
+            return true;
+        }
+        let staleness = self.freshness.lock().unwrap().staleness();
+        let allowed = match self.staleness_policy {
+            StalenessPolicy::FailOpen => staleness.is_zero(),
+            ref policy => policy.allows(staleness),
+        };
+        !self.key_ids().is_empty() && allowed
+    }
+    fn check_freshness(&self) -> Result<(), VerifyError> {
+        let staleness = self.freshness.lock().unwrap().staleness();
+        if self.staleness_policy.allows(staleness) {
+            Ok(())
+        } else {
+            Err(VerifyError::StaleKeys)
+        }
+    }
+    /// Like [`verify_refreshing`](Self::verify_refreshing), but with
+    /// `lazy_refresh` enabled first refreshes the keys if they are expired.
+    /// Only one caller refreshes at a time; concurrent callers verify with
+    /// the current keys meanwhile. Keys are refreshed at most every
+    /// `RefreshStrategy::min_interval`, however short their validity.
+    pub async fn verify_async(&self, token: &str) -> Result<DecodedToken<Claims>, VerifyError> {
+        if self.lazy_refresh && self.is_stale() {
+            self.refresh_expired_keys().await;
+        }
+        self.verify_refreshing(token).await
+    }
+    async fn refresh_expired_keys(&self) {
+        let mut retry_after = match self.lazy_refresh_lock.try_lock() {
+            Ok(retry_after) => retry_after,
+            Err(_) => return,
         };
-        let mut instance = JwkAuth {
-            verifier: Arc::new(Mutex::new(JwkVerifier::new(
-                jwk_keys.keys,
-                audience,
-                issuer,
-            ))),
-            fetcher: Arc::new(fetcher),
-            task_handler: Arc::new(Mutex::new(Box::new(tokio::spawn(async {})))),
+        if !self.is_stale() || retry_after.is_some_and(|instant| Instant::now() < instant) {
+            return;
+        }
+        match self.fetcher.fetch_keys().await {
+            Ok(jwk_keys) => {
+                // Keys declared valid for little or no time would otherwise
+                // be re-fetched on every verification.
+                let min_interval = self.refresh_strategy.as_ref().map_or_else(
+                    || RefreshStrategy::default().min_interval,
+                    |strategy| strategy.min_interval,
+                );
+                let delay = max(jwk_keys.validity, min_interval);
+                install_keys(&self.verifier, &self.freshness, &self.observers, jwk_keys);
+                *retry_after = Some(Instant::now() + delay);
+                info!("Updated expired JWK Keys");
+            }
+            Err(error) => {
+                self.observers.fetch_error(&error);
+                self.freshness.lock().unwrap().refresh_failed();
+                let delay = with_jitter(REFRESH_RETRY_INITIAL_BACKOFF);
+                *retry_after = Some(Instant::now() + delay);
+                warn!(
+                    "Failed to refresh expired JWK Keys: {}. Keeping the current keys and retrying in {:?}",
+                    error, delay
+                );
+            }
+        }
+    }
+    /// Like [`verify`](Self::verify), but if the token's kid is unknown, e.g.
+    /// because the keys were rotated early, re-fetches the keys and retries once.
+    /// Such fetches happen at most every 30 seconds. If the fetch fails, the
+    /// token is rejected with `VerifyError::KeyFetchFailed`.
+    pub async fn verify_refreshing(
+        &self,
+        token: &str,
+    ) -> Result<DecodedToken<Claims>, VerifyError> {
+        match self.verify(token) {
+            Err(VerifyError::UnknownKid(kid)) => {
+                if !self.refresh_keys_for_unknown_kid().await? {
+                    return Err(VerifyError::UnknownKid(kid));
+                }
+                self.verify(token)
+            }
+            result => result,
+        }
+    }
+    /// Fetches and installs new keys unless this was done recently. Returns
+    /// whether the keys were updated.
+    async fn refresh_keys_for_unknown_kid(&self) -> Result<bool, VerifyError> {
+        {
+            let mut last_refresh = self.last_kid_refresh.lock().unwrap();
+            if last_refresh.is_some_and(|last| last.elapsed() < UNKNOWN_KID_REFRESH_INTERVAL) {
+                return Ok(false);
+            }
+            *last_refresh = Some(Instant::now());
+        }
+        // The cache, if any, may hold the very keys that lack the kid.
+        match self.fetcher.fetch_fresh_keys().await {
+            Ok(jwk_keys) => {
+                install_keys(&self.verifier, &self.freshness, &self.observers, jwk_keys);
+                info!("Updated JWK Keys after encountering an unknown kid");
+                Ok(true)
+            }
+            Err(error) => {
+                self.observers.fetch_error(&error);
+                self.freshness.lock().unwrap().refresh_failed();
+                warn!("Failed to refresh JWK Keys for an unknown kid: {}", error);
+                Err(VerifyError::KeyFetchFailed(error.to_string()))
+            }
+        }
+    }
+    /// Verifies the token, then deserializes its claims into a caller-supplied type.
+    pub fn verify_into<C: DeserializeOwned>(
+        &self,
+        token: &str,
+    ) -> Result<DecodedToken<C>, VerifyError> {
+        self.verify_with(token, |verifier| verifier.verify_into(token))
+    }
+    /// Returns a view of this instance that verifies tokens of `tenant_id`,
+    /// sharing its keys and refresh task.
+    pub fn for_tenant(&self, tenant_id: String) -> TenantAwareAuth<'_> {
+        #[cfg(feature = "reqwest")]
+        let identity_toolkit = self
+            .identity_toolkit
+            .clone()
+            .map(|client| client.with_tenant_id(tenant_id.clone()));
+        TenantAwareAuth {
+            auth: self,
+            tenant_id,
+            #[cfg(feature = "reqwest")]
+            identity_toolkit,
+        }
+    }
+    /// Like [`verify`](Self::verify), and with `check_revoked` also looks the
+    /// user up to reject disabled users and tokens issued before the user's
+    /// refresh tokens were revoked.
+    #[cfg(feature = "reqwest")]
+    pub async fn verify_checked(
+        &self,
+        token: &str,
+        check_revoked: bool,
+    ) -> Result<DecodedToken<Claims>, VerifyError> {
+        let token_data = self.verify(token)?;
+        if !check_revoked {
+            return Ok(token_data);
+        }
+        let result = check_user(self.identity_toolkit.as_ref(), token_data).await;
+        if let Err(VerifyError::Revoked | VerifyError::UserDisabled) = result {
+            self.verifier.read().unwrap().forget_token(token);
+        }
+        result
+    }
+    /// Stops the background key refresh. The current keys stay in use.
+    pub fn pause_refresh(&self) {
+        if let Some(task) = self.task_handler.lock().unwrap().take() {
+            task.abort_handle.abort();
+        }
+    }
+    /// Starts the background key refresh again, fetching new keys right away.
+    /// Also starts it if it was disabled with `periodic_refresh(false)`. Does
+    /// nothing in emulator mode or if the refresh is already running.
+    pub fn resume_refresh(&self) {
+        let refresh_strategy = match &self.refresh_strategy {
+            Some(refresh_strategy) => refresh_strategy.clone(),
+            None => return,
         };
-        instance.start_periodic_key_update();
-        instance
+        let mut handler = self.task_handler.lock().unwrap();
+        if handler.as_mut().is_some_and(|task| task.is_running()) {
+            return;
+        }
+        *handler = Some(self.spawn_refresh_task(refresh_strategy));
+    }
+    /// Stops the background key refresh and waits for the task to finish.
+    pub async fn shutdown(&self) {
+        let task = self.task_handler.lock().unwrap().take();
+        if let Some(task) = task {
+            task.abort_handle.abort();
+            let _ = task.finished.await;
+        }
     }
-    pub fn verify(&self, token: &String) -> Option<TokenData<Claims>> {
-        let verifier = self.verifier.lock().unwrap();
-        verifier.verify(token)
+    /// Calls `callback` with the new keys whenever a refresh changes the key
+    /// set, e.g. to invalidate caches derived from it.
+    pub fn on_keys_updated<F: Fn(&Jwks) + Send + Sync + 'static>(&self, callback: F) {
+        self.observers
+            .keys_updated
+            .write()
+            .unwrap()
+            .push(Box::new(callback));
     }
-    fn start_periodic_key_update(&mut self) {
-        let verifier_ref = Arc::clone(&self.verifier);
+    /// Calls `callback` whenever refreshing the keys fails, e.g. to alert on
+    /// repeated failures. The current keys stay in use meanwhile.
+    pub fn on_fetch_error<F: Fn(&KeyFetchError) + Send + Sync + 'static>(&self, callback: F) {
+        self.observers
+            .fetch_error
+            .write()
+            .unwrap()
+            .push(Box::new(callback));
+    }
+    /// Calls `callback` with a record of every verification attempt, whether
+    /// it succeeded or not, e.g. to forward to an audit log. Callbacks run on
+    /// the verifying thread, so they should hand the record off rather than
+    /// block.
+    pub fn on_verification<F: Fn(&VerificationRecord) + Send + Sync + 'static>(&self, callback: F) {
+        self.observers
+            .verification
+            .write()
+            .unwrap()
+            .push(Box::new(callback));
+    }
+    /// Replaces the keys in use with pushed ones, e.g. from a Pub/Sub
+    /// subscription or a config service mirroring Google's keys, so that not
+    /// every instance has to poll Google. The keys are trusted as given: key
+    /// pinning and the key cache do not apply to them.
+    ///
+    /// A set without signing keys is refused, as it would reject every token.
+    pub fn apply_keys(&self, jwks: Jwks) -> Result<(), KeyFetchError> {
+        if !jwks.keys.iter().any(Jwk::is_signing_key) {
+            return Err(KeyFetchError::UnexpectedKeys(
+                "the pushed key set has no signing keys".to_string(),
+            ));
+        }
+        install_keys(&self.verifier, &self.freshness, &self.observers, jwks);
+        info!("Applied pushed JWK Keys");
+        Ok(())
+    }
+    /// Listener mode: stops the background refresh and applies the keys of
+    /// `updates` as they arrive, until the stream ends. Keys that cannot be
+    /// applied are reported to the [`on_fetch_error`](Self::on_fetch_error)
+    /// callbacks. Once the stream ends, e.g. because the subscription was
+    /// closed, the background refresh is resumed so that keys do not go stale.
+    ///
+    /// Meant to be spawned alongside the server, with the `JwkAuth` in an
+    /// `Arc`.
+    pub async fn listen_for_keys<S: Stream<Item = Jwks>>(&self, updates: S) {
+        self.pause_refresh();
+        let mut updates = std::pin::pin!(updates);
+        while let Some(jwks) = updates.next().await {
+            if let Err(error) = self.apply_keys(jwks) {
+                self.observers.fetch_error(&error);
+                warn!("Ignoring pushed JWK Keys: {}", error);
+            }
+        }
+        warn!("The JWK Keys update stream ended. Falling back to polling");
+        self.resume_refresh();
+    }
+    /// The task only holds weak references to the keys, so it ends once the
+    /// `JwkAuth` is gone even if it was never dropped properly.
+    fn spawn_refresh_task(&self, refresh_strategy: RefreshStrategy) -> RefreshTask {
+        let verifier_ref = Arc::downgrade(&self.verifier);
         let fetcher_ref = Arc::clone(&self.fetcher);
-        let task = tokio::spawn(async move {
+        let freshness_ref = Arc::downgrade(&self.freshness);
+        let observers_ref = Arc::downgrade(&self.observers);
+        let runtime = Arc::clone(&self.runtime);
+        let next_refresh = Arc::new(Mutex::new(Instant::now()));
+        let next_refresh_ref = Arc::clone(&next_refresh);
+        let refresh_loop = async move {
+            let mut backoff = REFRESH_RETRY_INITIAL_BACKOFF;
             loop {
                 let fetch_result = fetcher_ref.fetch_keys().await;
+                let (verifier, freshness, observers) = match (
+                    verifier_ref.upgrade(),
+                    freshness_ref.upgrade(),
+                    observers_ref.upgrade(),
+                ) {
+                    (Some(verifier), Some(freshness), Some(observers)) => {
+                        (verifier, freshness, observers)
+                    }
+                    _ => break,
+                };
                 let delay = match fetch_result {
                     Ok(jwk_keys) => {
-                        {
-                            let mut verifier = verifier_ref.lock().unwrap();
-                            verifier.set_keys(jwk_keys.keys);
-                        }
-                        info!(
-                            "Updated JWK Keys. Next refresh will be in {:?}",
-                            jwk_keys.validity
+                        let delay = refresh_strategy.next_refresh(jwk_keys.validity);
+                        install_keys(&verifier, &freshness, &observers, jwk_keys);
+                        info!("Updated JWK Keys. Next refresh will be in {:?}", delay);
+                        backoff = REFRESH_RETRY_INITIAL_BACKOFF;
+                        delay
+                    }
+                    Err(error) => {
+                        observers.fetch_error(&error);
+                        freshness.lock().unwrap().refresh_failed();
+                        let delay = with_jitter(backoff);
+                        warn!(
+                            "Failed to refresh JWK Keys: {}. Keeping the current keys and retrying in {:?}",
+                            error, delay
                         );
-                        jwk_keys.validity
+                        backoff = min(backoff * 2, REFRESH_RETRY_MAX_BACKOFF);
+                        delay
                     }
-                    Err(_) => Duration::from_secs(60),
                 };
-                sleep(delay).await;
+                // Do not keep the keys alive while sleeping.
+                drop((verifier, freshness, observers));
+                *next_refresh_ref.lock().unwrap() = Instant::now() + delay;
+                runtime.sleep(delay).await;
             }
-        });
-        let mut handler = self.task_handler.lock().unwrap();
-        *handler = Box::new(task);
+        };
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        let (finished_sender, finished) = oneshot::channel();
+        self.runtime.spawn(Box::pin(async move {
+            let _ = Abortable::new(refresh_loop, abort_registration).await;
+            let _ = finished_sender.send(());
+        }));
+        RefreshTask {
+            abort_handle,
+            finished,
+            next_refresh,
+        }
+    }
+}
+
+/// A shared [`JwkAuth`], e.g. for axum's `State` or to move into spawned
+/// tasks. Clones are cheap and share the keys and the background refresh,
+/// which only stops once the last clone is dropped. Dereferences to the
+/// [`JwkAuth`], and converts into the `Arc<JwkAuth>` the web framework
+/// integrations take.
+#[derive(Clone)]
+pub struct JwkAuthHandle {
+    auth: Arc<JwkAuth>,
+}
+
+impl Deref for JwkAuthHandle {
+    type Target = JwkAuth;
+    fn deref(&self) -> &JwkAuth {
+        &self.auth
+    }
+}
+
+impl From<JwkAuth> for JwkAuthHandle {
+    fn from(auth: JwkAuth) -> Self {
+        JwkAuthHandle {
+            auth: Arc::new(auth),
+        }
+    }
+}
+
+impl From<Arc<JwkAuth>> for JwkAuthHandle {
+    fn from(auth: Arc<JwkAuth>) -> Self {
+        JwkAuthHandle { auth }
+    }
+}
+
+impl From<JwkAuthHandle> for Arc<JwkAuth> {
+    fn from(handle: JwkAuthHandle) -> Self {
+        handle.auth
+    }
+}
+
+impl TokenVerifier for JwkAuthHandle {
+    fn verify(&self, token: &str) -> Result<DecodedToken<Claims>, VerifyError> {
+        self.auth.verify(token)
+    }
+}
+
+/// Where [`verify_id_token`] gets the keys to verify with.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub enum KeySource {
+    /// Fetches Google's keys for Firebase ID tokens.
+    #[default]
+    Fetch,
+    /// Fetches the keys from another URL, e.g. a mirror.
+    FetchFrom(String),
+    /// Uses the given keys without any network call.
+    Keys(Vec<Jwk>),
+}
+
+/// Verifies a single Firebase ID token of `project_id` and returns its claims,
+/// without keeping a [`JwkAuth`] or refreshing keys in the background. Meant
+/// for one-shot uses like serverless functions; anything verifying more than
+/// one token should keep a `JwkAuth` instead of fetching keys every time.
+///
+/// As with [`JwkAuth::new`], tokens are only checked for their format when
+/// `FIREBASE_AUTH_EMULATOR_HOST` is set and the keys are fetched.
+pub async fn verify_id_token(
+    token: &str,
+    project_id: &str,
+    keys: &KeySource,
+) -> Result<Claims, crate::Error> {
+    let builder = JwkAuth::builder()
+        .project_id(project_id.to_string())
+        .periodic_refresh(false);
+    let jwk_auth = match keys {
+        KeySource::Fetch => builder.build().await?,
+        KeySource::FetchFrom(url) => builder.pubkey_url(url.clone()).build().await?,
+        KeySource::Keys(keys) => builder.build_with_keys(keys.clone())?,
+    };
+    Ok(jwk_auth.verify(token)?.claims)
+}
+
+/// Constructors for a [`JwkAuth`] validating tokens of any OIDC provider, e.g.
+/// Auth0, Keycloak or Cognito, instead of Firebase, with presets for the
+/// Google-issued tokens often verified alongside Firebase ones.
+///
+/// None of the Firebase presets apply: the issuer, audience and JWKS URL are
+/// used as given, and emulator mode is never enabled. Dereferences to the
+/// underlying [`JwkAuth`], which can also be taken out with `JwkAuth::from`
+/// to use it with the web framework integrations.
+pub struct GenericJwkAuth {
+    auth: JwkAuth,
+}
+
+impl GenericJwkAuth {
+    pub async fn new(
+        jwks_url: String,
+        issuer: String,
+        audience: String,
+    ) -> Result<GenericJwkAuth, JwkAuthError> {
+        let auth = Self::builder(jwks_url, issuer, audience).build().await?;
+        Ok(GenericJwkAuth { auth })
+    }
+    /// A builder preset with the provider's endpoints, for further configuration.
+    pub fn builder(jwks_url: String, issuer: String, audience: String) -> JwkAuthBuilder {
+        JwkAuth::builder()
+            .pubkey_url(jwks_url)
+            .issuer(issuer)
+            .audience(audience)
+            .emulator(false)
+    }
+    /// Verifies the JWTs Cloud IAP sends in the `x-goog-iap-jwt-assertion`
+    /// header. `audience` is `/projects/PROJECT_NUMBER/apps/PROJECT_ID` on
+    /// App Engine, and
+    /// `/projects/PROJECT_NUMBER/global/backendServices/SERVICE_ID` behind a
+    /// load balancer.
+    pub async fn iap(audience: String) -> Result<GenericJwkAuth, JwkAuthError> {
+        let auth = Self::iap_builder(audience).build().await?;
+        Ok(GenericJwkAuth { auth })
+    }
+    /// A builder preset for Cloud IAP JWTs, which are signed with ES256.
+    pub fn iap_builder(audience: String) -> JwkAuthBuilder {
+        Self::builder(
+            IAP_PUBKEY_URL.to_string(),
+            IAP_ISSUER_URL.to_string(),
+            audience,
+        )
+        .allowed_algorithms(vec![Algorithm::ES256])
+    }
+    /// Verifies Google-signed ID tokens, e.g. those of service accounts
+    /// calling a Cloud Run service, whose `audience` is the URL of the
+    /// receiving service.
+    pub async fn google_id_token(audience: String) -> Result<GenericJwkAuth, JwkAuthError> {
+        let auth = Self::google_id_token_builder(audience).build().await?;
+        Ok(GenericJwkAuth { auth })
+    }
+    /// A builder preset for Google-signed ID tokens, with the
+    /// `https://accounts.google.com` issuer.
+    pub fn google_id_token_builder(audience: String) -> JwkAuthBuilder {
+        Self::builder(
+            GOOGLE_PUBKEY_URL.to_string(),
+            GOOGLE_ISSUER_URL.to_string(),
+            audience,
+        )
+    }
+}
+
+impl Deref for GenericJwkAuth {
+    type Target = JwkAuth;
+    fn deref(&self) -> &JwkAuth {
+        &self.auth
+    }
+}
+
+impl From<GenericJwkAuth> for JwkAuth {
+    fn from(generic: GenericJwkAuth) -> Self {
+        generic.auth
+    }
+}
+
+/// Verifies ID tokens of several Firebase projects with one shared key cache,
+/// picking the project by the token's `aud` claim.
+pub struct MultiProjectJwkAuth {
+    auth: JwkAuth,
+    projects: Vec<(String, JwkConfig)>,
+}
+
+impl MultiProjectJwkAuth {
+    pub async fn new(project_ids: Vec<String>) -> Result<MultiProjectJwkAuth, JwkAuthError> {
+        Self::from_builder(JwkAuth::builder(), project_ids).await
+    }
+    /// Derives each project's config from `builder` with its project id set,
+    /// so options like the leeway or claims policy apply to every project.
+    pub async fn from_builder(
+        builder: JwkAuthBuilder,
+        project_ids: Vec<String>,
+    ) -> Result<MultiProjectJwkAuth, JwkAuthError> {
+        let projects = project_ids
+            .into_iter()
+            .map(|project_id| {
+                let config = builder.clone().project_id(project_id.clone()).config()?;
+                Ok((project_id, config))
+            })
+            .collect::<Result<Vec<_>, JwkAuthError>>()?;
+        let (project_id, _) = projects
+            .first()
+            .ok_or(JwkAuthError::MissingConfig("project_id"))?;
+        let auth = builder.project_id(project_id.clone()).build().await?;
+        Ok(MultiProjectJwkAuth { auth, projects })
+    }
+    /// Returns the id of the project the token was issued for along with the
+    /// verified token. Tokens of unknown projects fail with `WrongAudience`.
+    pub fn verify(&self, token: &str) -> Result<(&str, DecodedToken<Claims>), VerifyError> {
+        self.auth.verify_with(token, |verifier| {
+            let audience = unverified_audience(token)?;
+            let (project_id, config) = self
+                .projects
+                .iter()
+                .find(|(_, config)| config.accepts_audience(&audience))
+                .ok_or(VerifyError::WrongAudience)?;
+            let token_data =
+                verifier.verify_with_config(token, config, config.tenant_id.as_deref())?;
+            Ok((project_id.as_str(), token_data))
+        })
+    }
+}
+
+/// A tenant-scoped view of a [`JwkAuth`], created by [`JwkAuth::for_tenant`].
+pub struct TenantAwareAuth<'a> {
+    auth: &'a JwkAuth,
+    tenant_id: String,
+    #[cfg(feature = "reqwest")]
+    identity_toolkit: Option<IdentityToolkit>,
+}
+
+impl TenantAwareAuth<'_> {
+    pub fn tenant_id(&self) -> &str {
+        &self.tenant_id
+    }
+    pub fn verify(&self, token: &str) -> Result<DecodedToken<Claims>, VerifyError> {
+        self.auth.verify_with(token, |verifier| {
+            verifier.verify_for_tenant(token, Some(&self.tenant_id))
+        })
+    }
+    pub fn verify_detailed(&self, token: &str) -> Result<VerifiedToken, VerifyError> {
+        let token_data = self.verify(token)?;
+        Ok(VerifiedToken::new(
+            token_data,
+            self.auth.verifier.read().unwrap().config(),
+        ))
+    }
+    pub fn verify_into<C: DeserializeOwned>(
+        &self,
+        token: &str,
+    ) -> Result<DecodedToken<C>, VerifyError> {
+        let token_data = self.verify(token)?;
+        into_token_data(token, token_data)
+    }
+    #[cfg(feature = "reqwest")]
+    pub async fn verify_checked(
+        &self,
+        token: &str,
+        check_revoked: bool,
+    ) -> Result<DecodedToken<Claims>, VerifyError> {
+        let token_data = self.verify(token)?;
+        if !check_revoked {
+            return Ok(token_data);
+        }
+        let result = check_user(self.identity_toolkit.as_ref(), token_data).await;
+        if let Err(VerifyError::Revoked | VerifyError::UserDisabled) = result {
+            self.auth.verifier.read().unwrap().forget_token(token);
+        }
+        result
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::http::{HttpError, HttpResponse};
+    #[cfg(feature = "reqwest")]
+    use crate::identity_toolkit::tests::{get_mock_lookup_server, get_test_client};
+    use crate::key_cache::{CachedJwks, FileKeyCache, MemoryKeyCache};
     use crate::tests::*;
-    use crate::verifier::JwkConfig;
+    use async_trait::async_trait;
+    use futures_util::future::BoxFuture;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::time::sleep;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
 
     #[tokio::test]
     async fn test_jwk_auth_new() {
@@ -96,17 +1803,1726 @@ mod tests {
         let mock_server = get_mock_server().await;
         let project_id = "pj".to_string();
 
-        let jwk_auth = JwkAuth::_new(project_id.clone(), get_mock_url(&mock_server)).await;
-        let verifier = jwk_auth.verifier.lock().unwrap();
+        let jwk_auth = JwkAuth::builder()
+            .project_id(project_id.clone())
+            .pubkey_url(get_mock_url(&mock_server))
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        let verifier = jwk_auth.verifier.read().unwrap();
 
         assert_eq!(verifier.get_key("kid-0"), Some(&keys[0]));
         assert_eq!(verifier.get_key("kid-1"), Some(&keys[1]));
         assert_eq!(
             verifier.get_config(),
-            Some(&JwkConfig {
-                audience: project_id.clone(),
-                issuer: format!("{}{}", ISSUER_URL, project_id.clone())
-            })
+            Some(&JwkConfig::new(
+                project_id.clone(),
+                format!("{}{}", ISSUER_URL, project_id.clone())
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_jwk_auth_new_session_cookie() {
+        let mock_server = get_mock_cert_server().await;
+        let project_id = "pj".to_string();
+        let issuer = format!("{}{}", SESSION_COOKIE_ISSUER_URL, project_id);
+
+        let jwk_auth = JwkAuth::builder()
+            .project_id(project_id.clone())
+            .pubkey_url(get_mock_url(&mock_server))
+            .session_cookie(true)
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        let cookie = create_token(Some(SIGNING_KID), &get_test_claims(&project_id, &issuer));
+        assert!(jwk_auth.verify(&cookie).is_ok());
+
+        let id_token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims(&project_id, &format!("{}{}", ISSUER_URL, project_id)),
+        );
+        assert_eq!(
+            jwk_auth.verify(&id_token).unwrap_err(),
+            VerifyError::WrongIssuer
+        );
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn test_builder_x509_key_format() {
+        let mock_server = get_mock_cert_server().await;
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .pubkey_url(get_mock_url(&mock_server))
+            .key_format(KeyFormat::X509)
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+        assert!(jwk_auth.verify(&token).is_ok());
+
+        let builder = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .x509_endpoint();
+        assert_eq!(
+            builder.key_source(),
+            (X509_PUBKEY_URL.to_string(), KeyFormat::X509)
+        );
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn test_builder_fallback_pubkey_url() {
+        let invalid_server = get_mock_server_invalid_response().await;
+        let cert_server = get_mock_cert_server().await;
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .pubkey_url(get_mock_url(&invalid_server))
+            .fallback_pubkey_url(get_mock_url(&cert_server), KeyFormat::X509)
+            .emulator(false)
+            .periodic_refresh(false)
+            .build()
+            .await
+            .unwrap();
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+        assert!(jwk_auth.verify(&token).is_ok());
+        assert_eq!(jwk_auth.key_source(), Some(get_mock_url(&cert_server)));
+    }
+
+    #[test]
+    fn test_jwk_auth_new_emulator() {
+        let project_id = "pj".to_string();
+        let issuer = format!("{}{}", ISSUER_URL, project_id);
+
+        let jwk_auth = JwkAuth::new_emulator(project_id.clone());
+        let token = create_unsigned_token(&get_test_claims(&project_id, &issuer));
+        assert!(jwk_auth.verify(&token).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_jwk_auth_new_fetch_error() {
+        let mock_server = get_mock_server_invalid_response().await;
+        let retry_policy = RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        };
+
+        let result = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .pubkey_url(get_mock_url(&mock_server))
+            .retry_policy(retry_policy)
+            .emulator(false)
+            .build()
+            .await;
+        assert!(matches!(result, Err(JwkAuthError::KeyFetchError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_jwk_auth_new_retries_fetch() {
+        let mock_server = get_mock_server().await;
+        Mock::given(method("GET"))
+            .and(path(PATH))
+            .respond_with(ResponseTemplate::new(500))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&mock_server)
+            .await;
+        let retry_policy = RetryPolicy {
+            max_attempts: 2,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(10),
+        };
+
+        let result = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .pubkey_url(get_mock_url(&mock_server))
+            .retry_policy(retry_policy)
+            .emulator(false)
+            .build()
+            .await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_jwk_auth_new_retries_each_request_once() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(PATH))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+        let retry_policy = RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(10),
+        };
+
+        let result = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .pubkey_url(get_mock_url(&mock_server))
+            .retry_policy(retry_policy)
+            .emulator(false)
+            .build()
+            .await;
+        assert!(matches!(result, Err(JwkAuthError::KeyFetchError(_))));
+        mock_server.verify().await;
+    }
+
+    #[test]
+    fn test_jwk_auth_with_keys() {
+        let jwk_auth = JwkAuth::with_keys(vec![get_signing_key()], "pj".to_string()).unwrap();
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+        assert!(jwk_auth.verify(&token).is_ok());
+        assert!(!jwk_auth.is_stale());
+    }
+
+    #[test]
+    fn test_jwk_auth_verify_detailed() {
+        let jwk_auth = JwkAuth::with_keys(vec![get_signing_key()], "pj".to_string()).unwrap();
+        let claims = get_test_claims("pj", &format!("{}pj", ISSUER_URL));
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let before = SystemTime::now();
+        let verified = jwk_auth.verify_detailed(&token).unwrap();
+        assert_eq!(verified.claims, claims);
+        assert_eq!(verified.kid.as_deref(), Some(SIGNING_KID));
+        assert_eq!(verified.algorithm, Some(Algorithm::RS256));
+        assert_eq!(verified.header.kid, verified.kid);
+        assert!(verified.verified_at >= before);
+        assert_eq!(verified.project_identifier, ProjectIdentifier::Id);
+        let token_data: DecodedToken<Claims> = verified.into();
+        assert_eq!(token_data.claims, jwk_auth.verify(&token).unwrap().claims);
+
+        let tenant = jwk_auth.for_tenant("tenant-1".to_string());
+        assert_eq!(
+            tenant.verify_detailed(&token).unwrap_err(),
+            VerifyError::WrongTenant
+        );
+    }
+
+    #[test]
+    fn test_builder_project_number() {
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .project_number("123".to_string())
+            .build_with_keys(vec![get_signing_key()])
+            .unwrap();
+        let issuer = format!("{}pj", ISSUER_URL);
+        let by_number = create_token(Some(SIGNING_KID), &get_test_claims("123", &issuer));
+        let verified = jwk_auth.verify_detailed(&by_number).unwrap();
+        assert_eq!(verified.project_identifier, ProjectIdentifier::Number);
+        let by_id = create_token(Some(SIGNING_KID), &get_test_claims("pj", &issuer));
+        let verified = jwk_auth.verify_detailed(&by_id).unwrap();
+        assert_eq!(verified.project_identifier, ProjectIdentifier::Id);
+        let other = create_token(Some(SIGNING_KID), &get_test_claims("456", &issuer));
+        assert_eq!(
+            jwk_auth.verify(&other).unwrap_err(),
+            VerifyError::WrongAudience
+        );
+    }
+
+    #[tokio::test]
+    async fn test_builder_build_with_keys() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let jwk_auth = JwkAuth::builder()
+            .issuer("https://issuer.example.com".to_string())
+            .audience("my-audience".to_string())
+            .fetcher(CountingFetcher::counting(&calls))
+            .build_with_keys(get_test_keys())
+            .unwrap();
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("my-audience", "https://issuer.example.com"),
+        );
+        assert_eq!(
+            jwk_auth.verify(&token).unwrap_err(),
+            VerifyError::UnknownKid(SIGNING_KID.to_string())
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        jwk_auth.resume_refresh();
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(jwk_auth.verify(&token).is_ok());
+    }
+
+    #[test]
+    fn test_token_cache_cleared_on_key_rotation() {
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fetcher(CountingFetcher::new(String::new()))
+            .token_cache(10)
+            .build_with_keys(vec![get_signing_key()])
+            .unwrap();
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+        assert!(jwk_auth.verify(&token).is_ok());
+        assert!(jwk_auth.verify(&token).is_ok());
+
+        install_keys(
+            &jwk_auth.verifier,
+            &jwk_auth.freshness,
+            &jwk_auth.observers,
+            Jwks {
+                keys: get_test_keys(),
+                validity: Duration::from_secs(3600),
+                stale_while_revalidate: Duration::ZERO,
+            },
+        );
+        assert_eq!(
+            jwk_auth.verify(&token).unwrap_err(),
+            VerifyError::UnknownKid(SIGNING_KID.to_string())
+        );
+    }
+
+    #[test]
+    fn test_builder_issuer_override_with_project_id() {
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .issuer_override("https://tokens.example.com/pj".to_string())
+            .emulator(false)
+            .build_with_keys(vec![get_signing_key()])
+            .unwrap();
+        let mirrored = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", "https://tokens.example.com/pj"),
+        );
+        assert!(jwk_auth.verify(&mirrored).is_ok());
+        let google = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+        assert_eq!(
+            jwk_auth.verify(&google).unwrap_err(),
+            VerifyError::WrongIssuer
+        );
+        let other_project = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("other", "https://tokens.example.com/pj"),
+        );
+        assert_eq!(
+            jwk_auth.verify(&other_project).unwrap_err(),
+            VerifyError::WrongAudience
+        );
+    }
+
+    #[test]
+    fn test_builder_audience_override() {
+        let builder = || {
+            JwkAuth::builder()
+                .project_id("pj".to_string())
+                .issuer_override("https://tokens.example.com/pj".to_string())
+                .emulator(false)
+        };
+        let before = builder()
+            .audience("first".to_string())
+            .audience_override("gateway".to_string());
+        let after = builder()
+            .audience_override("gateway".to_string())
+            .audience("first".to_string());
+        for builder in [before, after] {
+            let jwk_auth = builder.build_with_keys(vec![get_signing_key()]).unwrap();
+            let mirrored = create_token(
+                Some(SIGNING_KID),
+                &get_test_claims("gateway", "https://tokens.example.com/pj"),
+            );
+            assert!(jwk_auth.verify(&mirrored).is_ok());
+            for audience in ["pj", "first"] {
+                let token = create_token(
+                    Some(SIGNING_KID),
+                    &get_test_claims(audience, "https://tokens.example.com/pj"),
+                );
+                assert_eq!(
+                    jwk_auth.verify(&token).unwrap_err(),
+                    VerifyError::WrongAudience
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_builder_missing_config() {
+        let result = JwkAuth::builder().build().await;
+        assert!(matches!(result, Err(JwkAuthError::MissingConfig("issuer"))));
+    }
+
+    #[tokio::test]
+    async fn test_builder_custom_issuer_and_audience() {
+        let mock_server = get_mock_server().await;
+        let jwk_auth = JwkAuth::builder()
+            .issuer("https://issuer.example.com".to_string())
+            .audience("my-audience".to_string())
+            .audience("other-audience".to_string())
+            .pubkey_url(get_mock_url(&mock_server))
+            .leeway(Duration::from_secs(30))
+            .required_claim("email".to_string())
+            .periodic_refresh(false)
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        let verifier = jwk_auth.verifier.read().unwrap();
+
+        assert_eq!(
+            verifier.get_config(),
+            Some(&JwkConfig {
+                audiences: vec!["my-audience".to_string(), "other-audience".to_string()],
+                issuer: "https://issuer.example.com".to_string(),
+                leeway: Duration::from_secs(30),
+                policy: ClaimsPolicy::new().require_claim("email".to_string()),
+                tenant_id: None,
+                allowed_algorithms: vec![Algorithm::RS256],
+                strict: None,
+                project_number: None,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_id_token() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(PATH))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "keys": [get_signing_key()]
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+        let fetched = KeySource::FetchFrom(get_mock_url(&mock_server));
+        let claims = verify_id_token(&token, "pj", &fetched).await.unwrap();
+        assert_eq!(claims.sub, "uid");
+
+        let keys = KeySource::Keys(vec![get_signing_key()]);
+        assert_eq!(
+            verify_id_token(&token, "pj", &keys).await.unwrap().sub,
+            "uid"
+        );
+        assert!(matches!(
+            verify_id_token(&token, "other", &keys).await,
+            Err(crate::Error::Verify(VerifyError::WrongIssuer))
+        ));
+        assert!(matches!(
+            verify_id_token(&token, "pj", &KeySource::Keys(vec![])).await,
+            Err(crate::Error::Verify(VerifyError::UnknownKid(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_generic_jwk_auth() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(PATH))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "keys": [get_signing_key()]
+            })))
+            .mount(&mock_server)
+            .await;
+        let issuer = "https://tenant.auth0.example.com/";
+        let jwk_auth = GenericJwkAuth::new(
+            get_mock_url(&mock_server),
+            issuer.to_string(),
+            "https://api.example.com".to_string(),
+        )
+        .await
+        .unwrap();
+
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("https://api.example.com", issuer),
+        );
+        assert!(jwk_auth.verify(&token).is_ok());
+        let mut claims = get_test_claims("https://api.example.com", issuer);
+        claims.aud = Audience::Multiple(vec![
+            "https://tenant.auth0.example.com/userinfo".to_string(),
+            "https://api.example.com".to_string(),
+        ]);
+        let token = create_token(Some(SIGNING_KID), &claims);
+        assert_eq!(jwk_auth.verify(&token).unwrap().claims.aud, claims.aud);
+        claims.aud = Audience::Multiple(vec!["https://other.example.com".to_string()]);
+        assert_eq!(
+            jwk_auth
+                .verify(&create_token(Some(SIGNING_KID), &claims))
+                .unwrap_err(),
+            VerifyError::WrongAudience
+        );
+        let firebase_token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+        assert_eq!(
+            jwk_auth.verify(&firebase_token).unwrap_err(),
+            VerifyError::WrongIssuer
+        );
+    }
+
+    #[tokio::test]
+    async fn test_google_presets() {
+        let audience = "https://service-abc.a.run.app";
+        let google = GenericJwkAuth::google_id_token_builder(audience.to_string())
+            .build_with_keys(vec![get_signing_key()])
+            .unwrap();
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims(audience, GOOGLE_ISSUER_URL),
+        );
+        assert!(google.verify(&token).is_ok());
+
+        let audience = "/projects/123/apps/pj";
+        let iap = GenericJwkAuth::iap_builder(audience.to_string())
+            .build_with_keys(vec![get_signing_key(), get_ec_signing_key()])
+            .unwrap();
+        let claims = get_test_claims(audience, IAP_ISSUER_URL);
+        let mut header = jsonwebtoken::Header::new(Algorithm::ES256.into());
+        header.kid = Some(EC_SIGNING_KID.to_string());
+        let key = jsonwebtoken::EncodingKey::from_ec_pem(EC_PRIVATE_KEY_PEM.as_bytes()).unwrap();
+        let token = jsonwebtoken::encode(&header, &claims, &key).unwrap();
+        assert!(iap.verify(&token).is_ok());
+        assert_eq!(
+            iap.verify(&create_token(Some(SIGNING_KID), &claims))
+                .unwrap_err(),
+            VerifyError::AlgorithmNotAllowed(Algorithm::RS256)
+        );
+    }
+
+    async fn get_mock_discovery_server(issuer: Option<&str>) -> MockServer {
+        let mock_server = MockServer::start().await;
+        let issuer = issuer
+            .map(|issuer| issuer.to_string())
+            .unwrap_or_else(|| mock_server.uri());
+        Mock::given(method("GET"))
+            .and(path("/.well-known/openid-configuration"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "issuer": issuer,
+                "jwks_uri": get_mock_url(&mock_server),
+            })))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(PATH))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "keys": [get_signing_key()]
+            })))
+            .mount(&mock_server)
+            .await;
+        mock_server
+    }
+
+    #[tokio::test]
+    async fn test_jwk_auth_from_discovery() {
+        let mock_server = get_mock_discovery_server(None).await;
+        let jwk_auth = JwkAuth::from_discovery(mock_server.uri(), "my-audience".to_string())
+            .await
+            .unwrap();
+
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("my-audience", &mock_server.uri()),
+        );
+        assert!(jwk_auth.verify(&token).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_jwk_auth_from_discovery_issuer_mismatch() {
+        let mock_server = get_mock_discovery_server(Some("https://evil.example.com")).await;
+        let result = JwkAuth::from_discovery(mock_server.uri(), "my-audience".to_string()).await;
+        assert!(matches!(result, Err(JwkAuthError::IssuerMismatch(_))));
+    }
+
+    struct StaticHttpClient {
+        urls: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl HttpClient for StaticHttpClient {
+        async fn get(&self, url: &str) -> Result<HttpResponse, HttpError> {
+            self.urls.lock().unwrap().push(url.to_string());
+            Ok(HttpResponse {
+                status: 200,
+                headers: [("cache-control".to_string(), "max-age=3600".to_string())].into(),
+                body: serde_json::to_vec(&json!({ "keys": [get_signing_key()] })).unwrap(),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_jwk_auth_with_http_backend() {
+        let urls = Arc::new(Mutex::new(Vec::new()));
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .pubkey_url("https://keys.example.com/jwks".to_string())
+            .http_backend(StaticHttpClient {
+                urls: Arc::clone(&urls),
+            })
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(
+            *urls.lock().unwrap(),
+            vec!["https://keys.example.com/jwks".to_string()]
+        );
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", "https://securetoken.google.com/pj"),
+        );
+        assert!(jwk_auth.verify(&token).is_ok());
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn test_jwk_auth_invalid_http_config() {
+        let result = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .http_config(HttpConfig {
+                proxy: Some("not a url".to_string()),
+                ..HttpConfig::default()
+            })
+            .emulator(false)
+            .build()
+            .await;
+        assert!(matches!(result, Err(JwkAuthError::HttpClientError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_multi_project_jwk_auth() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let fetcher = CountingFetcher::counting(&calls);
+        let builder = JwkAuth::builder().fetcher(fetcher).emulator(false);
+        let jwk_auth = MultiProjectJwkAuth::from_builder(
+            builder,
+            vec!["pj-1".to_string(), "pj-2".to_string()],
+        )
+        .await
+        .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        for project_id in ["pj-1", "pj-2"] {
+            let issuer = format!("{}{}", ISSUER_URL, project_id);
+            let claims = get_test_claims(project_id, &issuer);
+            let token = create_token(Some(SIGNING_KID), &claims);
+            let (verified_project, token_data) = jwk_auth.verify(&token).unwrap();
+            assert_eq!(verified_project, project_id);
+            assert_eq!(token_data.claims, claims);
+        }
+
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj-3", &format!("{}pj-3", ISSUER_URL)),
+        );
+        assert_eq!(
+            jwk_auth.verify(&token).unwrap_err(),
+            VerifyError::WrongAudience
+        );
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj-1", &format!("{}pj-2", ISSUER_URL)),
+        );
+        assert_eq!(
+            jwk_auth.verify(&token).unwrap_err(),
+            VerifyError::WrongIssuer
+        );
+    }
+
+    #[tokio::test]
+    async fn test_multi_project_jwk_auth_without_projects() {
+        let result = MultiProjectJwkAuth::new(vec![]).await;
+        assert!(matches!(
+            result,
+            Err(JwkAuthError::MissingConfig("project_id"))
+        ));
+    }
+
+    struct RotatingFetcher {
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Fetcher for RotatingFetcher {
+        fn new(_url: String) -> Self {
+            RotatingFetcher {
+                calls: Arc::new(AtomicUsize::new(0)),
+            }
+        }
+        async fn fetch_keys(&self) -> Result<Jwks, KeyFetchError> {
+            let keys = match self.calls.fetch_add(1, Ordering::SeqCst) {
+                0 => get_test_keys(),
+                _ => vec![get_signing_key()],
+            };
+            Ok(Jwks {
+                keys,
+                validity: Duration::from_secs(3600),
+                stale_while_revalidate: Duration::ZERO,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verify_refreshing() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fetcher(RotatingFetcher {
+                calls: Arc::clone(&calls),
+            })
+            .periodic_refresh(false)
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+        assert_eq!(
+            jwk_auth.verify(&token).unwrap_err(),
+            VerifyError::UnknownKid(SIGNING_KID.to_string())
+        );
+        assert!(jwk_auth.verify_refreshing(&token).await.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let unknown = create_token(
+            Some("kid-unknown"),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+        assert_eq!(
+            jwk_auth.verify_refreshing(&unknown).await.unwrap_err(),
+            VerifyError::UnknownKid("kid-unknown".to_string())
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_verify_async_unknown_kid() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fetcher(RotatingFetcher {
+                calls: Arc::clone(&calls),
+            })
+            .periodic_refresh(false)
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+        assert!(jwk_auth.verify_async(&token).await.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    struct FailingFetcher {
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Fetcher for FailingFetcher {
+        fn new(_url: String) -> Self {
+            FailingFetcher {
+                calls: Arc::new(AtomicUsize::new(0)),
+            }
+        }
+        async fn fetch_keys(&self) -> Result<Jwks, KeyFetchError> {
+            match self.calls.fetch_add(1, Ordering::SeqCst) {
+                0 => Ok(Jwks {
+                    keys: vec![get_signing_key()],
+                    validity: Duration::from_millis(50),
+                    stale_while_revalidate: Duration::ZERO,
+                }),
+                _ => Err(KeyFetchError::Custom("unavailable".into())),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_health_accessors() {
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fetcher(CountingFetcher::new(String::new()))
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(jwk_auth.key_ids(), vec![SIGNING_KID.to_string()]);
+        let since_refresh = SystemTime::now()
+            .duration_since(jwk_auth.last_refresh_time())
+            .unwrap();
+        assert!(since_refresh < Duration::from_secs(1));
+        let next_refresh_in = jwk_auth.next_refresh_in().unwrap();
+        assert!(next_refresh_in > Duration::from_secs(1700));
+        assert!(next_refresh_in <= Duration::from_secs(2880));
+        assert!(jwk_auth.is_healthy());
+        jwk_auth.pause_refresh();
+        assert_eq!(jwk_auth.next_refresh_in(), None);
+
+        let failing = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fetcher(FailingFetcher::new(String::new()))
+            .refresh_strategy(RefreshStrategy {
+                min_interval: Duration::from_millis(0),
+                ..RefreshStrategy::default()
+            })
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        assert!(failing.is_healthy());
+        sleep(Duration::from_millis(100)).await;
+        assert!(!failing.is_healthy());
+
+        let emulator = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .emulator(true)
+            .build()
+            .await
+            .unwrap();
+        assert!(emulator.key_ids().is_empty());
+        assert!(emulator.is_healthy());
+        assert_eq!(emulator.next_refresh_in(), None);
+    }
+
+    #[tokio::test]
+    async fn test_observers() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fetcher(RotatingFetcher {
+                calls: Arc::clone(&calls),
+            })
+            .periodic_refresh(false)
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let updates_ref = Arc::clone(&updates);
+        jwk_auth.on_keys_updated(move |jwks| updates_ref.lock().unwrap().push(jwks.keys.clone()));
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+        assert!(jwk_auth.verify_refreshing(&token).await.is_ok());
+        assert_eq!(*updates.lock().unwrap(), vec![vec![get_signing_key()]]);
+
+        let unchanged = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fetcher(CountingFetcher::new(String::new()))
+            .periodic_refresh(false)
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        let updates_ref = Arc::clone(&updates);
+        unchanged.on_keys_updated(move |jwks| updates_ref.lock().unwrap().push(jwks.keys.clone()));
+        let unknown = create_token(
+            Some("kid-unknown"),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+        assert!(unchanged.verify_refreshing(&unknown).await.is_err());
+        assert_eq!(updates.lock().unwrap().len(), 1);
+
+        let failing = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fetcher(FailingFetcher::new(String::new()))
+            .periodic_refresh(false)
+            .lazy_refresh(true)
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        let errors = Arc::new(AtomicUsize::new(0));
+        let errors_ref = Arc::clone(&errors);
+        failing.on_fetch_error(move |_| {
+            errors_ref.fetch_add(1, Ordering::SeqCst);
+        });
+        sleep(Duration::from_millis(100)).await;
+        assert!(failing.verify_async(&token).await.is_ok());
+        assert_eq!(errors.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_verify_refreshing_fetch_failed() {
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fetcher(FailingFetcher::new(String::new()))
+            .periodic_refresh(false)
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        let unknown = create_token(
+            Some("kid-unknown"),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+        assert!(matches!(
+            jwk_auth.verify_refreshing(&unknown).await.unwrap_err(),
+            VerifyError::KeyFetchFailed(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_apply_keys() {
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fetcher(FailingFetcher::new(String::new()))
+            .build_with_keys(get_test_keys())
+            .unwrap();
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+        assert!(jwk_auth.verify(&token).is_err());
+
+        let body = serde_json::to_vec(&json!({ "keys": [get_signing_key()] })).unwrap();
+        let jwks = Jwks::from_json(&body, Duration::from_secs(3600)).unwrap();
+        jwk_auth.apply_keys(jwks).unwrap();
+        assert!(jwk_auth.verify(&token).is_ok());
+        assert!(!jwk_auth.is_stale());
+
+        let mut encryption_key = get_signing_key();
+        encryption_key.r#use = Some("enc".to_string());
+        let refused = Jwks {
+            keys: vec![encryption_key],
+            validity: Duration::from_secs(3600),
+            stale_while_revalidate: Duration::ZERO,
+        };
+        assert!(matches!(
+            jwk_auth.apply_keys(refused),
+            Err(KeyFetchError::UnexpectedKeys(_))
+        ));
+        assert!(jwk_auth.verify(&token).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_listen_for_keys() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fetcher(CountingFetcher::counting(&calls))
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        let errors = Arc::new(AtomicUsize::new(0));
+        let errors_ref = Arc::clone(&errors);
+        jwk_auth.on_fetch_error(move |_| {
+            errors_ref.fetch_add(1, Ordering::SeqCst);
+        });
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let updates = futures_util::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|jwks| (jwks, receiver))
+        });
+        let jwk_auth = Arc::new(jwk_auth);
+        let listener = tokio::spawn({
+            let jwk_auth = Arc::clone(&jwk_auth);
+            async move { jwk_auth.listen_for_keys(updates).await }
+        });
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(jwk_auth.next_refresh_in(), None);
+        let fetches = calls.load(Ordering::SeqCst);
+
+        for keys in [vec![], get_test_keys()] {
+            sender
+                .send(Jwks {
+                    keys,
+                    validity: Duration::from_secs(3600),
+                    stale_while_revalidate: Duration::ZERO,
+                })
+                .unwrap();
+        }
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(jwk_auth.key_ids(), vec!["kid-0", "kid-1"]);
+        assert_eq!(errors.load(Ordering::SeqCst), 1);
+        assert_eq!(calls.load(Ordering::SeqCst), fetches);
+
+        drop(sender);
+        listener.await.unwrap();
+        assert!(jwk_auth.next_refresh_in().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_stale_keys() {
+        let build = |max_staleness: Option<Duration>| {
+            let mut builder = JwkAuth::builder()
+                .project_id("pj".to_string())
+                .fetcher(FailingFetcher::new(String::new()))
+                .refresh_strategy(RefreshStrategy {
+                    min_interval: Duration::from_millis(0),
+                    ..RefreshStrategy::default()
+                })
+                .emulator(false);
+            if let Some(max_staleness) = max_staleness {
+                builder = builder.max_staleness(max_staleness);
+            }
+            builder.build()
+        };
+        let lenient = build(None).await.unwrap();
+        let strict = build(Some(Duration::from_millis(20))).await.unwrap();
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+        assert!(!strict.is_stale());
+        assert!(strict.verify(&token).is_ok());
+
+        sleep(Duration::from_millis(100)).await;
+        assert!(strict.is_stale());
+        assert!(strict.keys_age() >= Duration::from_millis(100));
+        assert_eq!(strict.verify(&token).unwrap_err(), VerifyError::StaleKeys);
+        assert!(lenient.is_stale());
+        assert!(lenient.verify(&token).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_stale_while_revalidate_delays_staleness() {
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fetcher(FailingFetcher::new(String::new()))
+            .refresh_strategy(RefreshStrategy {
+                min_interval: Duration::from_millis(0),
+                ..RefreshStrategy::default()
+            })
+            .staleness_policy(StalenessPolicy::FailClosed)
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        jwk_auth
+            .apply_keys(Jwks {
+                keys: vec![get_signing_key()],
+                validity: Duration::from_millis(50),
+                stale_while_revalidate: Duration::from_secs(3600),
+            })
+            .unwrap();
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+
+        sleep(Duration::from_millis(200)).await;
+        assert!(jwk_auth.is_stale());
+        assert!(jwk_auth.verify(&token).is_ok());
+        assert!(jwk_auth.is_healthy());
+    }
+
+    #[tokio::test]
+    async fn test_staleness_policy() {
+        let build = |staleness_policy: StalenessPolicy| {
+            JwkAuth::builder()
+                .project_id("pj".to_string())
+                .fetcher(FailingFetcher::new(String::new()))
+                .refresh_strategy(RefreshStrategy {
+                    min_interval: Duration::from_millis(0),
+                    ..RefreshStrategy::default()
+                })
+                .staleness_policy(staleness_policy)
+                .emulator(false)
+                .build()
+        };
+        let fail_closed = build(StalenessPolicy::FailClosed).await.unwrap();
+        let fail_open = build(StalenessPolicy::FailOpenFor(Duration::from_secs(3600)))
+            .await
+            .unwrap();
+        let decisions = Arc::new(AtomicUsize::new(0));
+        let decisions_ref = Arc::clone(&decisions);
+        let decide = build(StalenessPolicy::Decide(Arc::new(move |staleness| {
+            decisions_ref.fetch_add(1, Ordering::SeqCst);
+            staleness < Duration::from_millis(100)
+        })))
+        .await
+        .unwrap();
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+        assert!(decide.verify(&token).is_ok());
+        assert_eq!(decisions.load(Ordering::SeqCst), 0);
+
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(
+            fail_closed.verify(&token).unwrap_err(),
+            VerifyError::StaleKeys
+        );
+        assert!(!fail_closed.is_healthy());
+        assert!(fail_open.verify(&token).is_ok());
+        assert!(fail_open.is_healthy());
+        assert!(decide.verify(&token).is_ok());
+        assert_eq!(decisions.load(Ordering::SeqCst), 1);
+
+        sleep(Duration::from_millis(150)).await;
+        assert_eq!(decide.verify(&token).unwrap_err(), VerifyError::StaleKeys);
+    }
+
+    #[tokio::test]
+    async fn test_on_verification() {
+        let jwk_auth = JwkAuth::with_keys(vec![get_signing_key()], "pj".to_string()).unwrap();
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let records_ref = Arc::clone(&records);
+        jwk_auth.on_verification(move |record| records_ref.lock().unwrap().push(record.clone()));
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+        let wrong_audience = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("other", &format!("{}pj", ISSUER_URL)),
+        );
+        let started = SystemTime::now();
+        assert!(jwk_auth.verify(&token).is_ok());
+        assert!(jwk_auth.verify(&wrong_audience).is_err());
+        assert!(jwk_auth.verify("invalid").is_err());
+
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 3);
+        assert!(records[0].is_success());
+        assert!(records[0].timestamp >= started);
+        assert_eq!(records[0].kid.as_deref(), Some(SIGNING_KID));
+        assert_eq!(records[0].uid.as_deref(), Some("uid"));
+        assert_eq!(records[0].failure_reason(), None);
+        let audience_hash = records[0].audience_hash.as_ref().unwrap();
+        assert_eq!(audience_hash.len(), 64);
+        assert!(!audience_hash.contains("pj"));
+
+        assert!(!records[1].is_success());
+        assert_eq!(records[1].error, Some(VerifyError::WrongAudience));
+        assert_eq!(records[1].failure_reason(), Some("wrong_audience"));
+        assert_eq!(records[1].uid, None);
+        assert_ne!(records[1].audience_hash, records[0].audience_hash);
+
+        assert_eq!(records[2].kid, None);
+        assert_eq!(records[2].audience_hash, None);
+        assert_eq!(records[2].failure_reason(), Some("malformed"));
+    }
+
+    #[test]
+    fn test_refresh_strategy_next_refresh() {
+        let strategy = RefreshStrategy {
+            jitter: 0.0,
+            ..RefreshStrategy::default()
+        };
+        let validity = Duration::from_secs(3600);
+        for _ in 0..100 {
+            let delay = strategy.next_refresh(validity);
+            assert!(delay >= validity / 2 && delay <= validity.mul_f64(0.8));
+        }
+        assert_eq!(
+            strategy.next_refresh(Duration::from_secs(0)),
+            strategy.min_interval
+        );
+        assert_eq!(
+            strategy.next_refresh(Duration::from_secs(365 * 24 * 60 * 60)),
+            strategy.max_interval
+        );
+    }
+
+    #[test]
+    fn test_refresh_strategy_jitter() {
+        let strategy = RefreshStrategy::default();
+        let delays: Vec<Duration> = (0..100)
+            .map(|_| strategy.next_refresh(Duration::from_secs(0)))
+            .collect();
+        assert!(delays
+            .iter()
+            .all(|delay| *delay <= strategy.min_interval
+                && *delay >= strategy.min_interval.mul_f64(0.9)));
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+    }
+
+    #[tokio::test]
+    async fn test_expired_keys_are_not_stale_until_refresh_fails() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fetcher(CountingFetcher::counting(&calls).validity(Duration::ZERO))
+            .staleness_policy(StalenessPolicy::FailClosed)
+            .lazy_refresh(true)
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+        assert!(jwk_auth.is_stale());
+        assert!(jwk_auth.verify(&token).is_ok());
+        assert!(jwk_auth.verify_async(&token).await.is_ok());
+        assert!(jwk_auth.is_healthy());
+    }
+
+    #[tokio::test]
+    async fn test_lazy_refresh_throttles_short_lived_keys() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fetcher(CountingFetcher::counting(&calls).validity(Duration::ZERO))
+            .lazy_refresh(true)
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+        for _ in 0..10 {
+            assert!(jwk_auth.verify_async(&token).await.is_ok());
+        }
+        // The fetch at build time and a single lazy refresh.
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_lazy_refresh() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let fetcher = CountingFetcher::counting(&calls).validity(Duration::from_millis(50));
+        let jwk_auth = Arc::new(
+            JwkAuth::builder()
+                .project_id("pj".to_string())
+                .fetcher(fetcher)
+                .lazy_refresh(true)
+                .emulator(false)
+                .build()
+                .await
+                .unwrap(),
+        );
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+        assert!(jwk_auth.verify_async(&token).await.is_ok());
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(jwk_auth.is_stale());
+
+        let verifications = (0..10).map(|_| {
+            let jwk_auth = Arc::clone(&jwk_auth);
+            let token = token.clone();
+            tokio::spawn(async move { jwk_auth.verify_async(&token).await })
+        });
+        for verification in verifications.collect::<Vec<_>>() {
+            assert!(verification.await.unwrap().is_ok());
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(!jwk_auth.is_stale());
+    }
+
+    #[test]
+    fn test_with_jitter() {
+        let backoff = Duration::from_secs(10);
+        for _ in 0..100 {
+            let delay = with_jitter(backoff);
+            assert!(delay >= backoff / 2 && delay <= backoff);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_builder_custom_fetcher() {
+        let project_id = "pj".to_string();
+        let issuer = format!("{}{}", ISSUER_URL, project_id);
+        let jwk_auth = JwkAuth::builder()
+            .project_id(project_id.clone())
+            .fetcher(CountingFetcher::new(String::new()))
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+
+        let token = create_token(Some(SIGNING_KID), &get_test_claims(&project_id, &issuer));
+        assert!(jwk_auth.verify(&token).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_builder_key_pinning() {
+        let builder = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fetcher(CountingFetcher::new(String::new()))
+            .periodic_refresh(false)
+            .emulator(false);
+        let pinned = KeyPinning::new().allow_kid(SIGNING_KID.to_string());
+        assert!(builder
+            .clone()
+            .key_pinning(pinned.clone())
+            .build()
+            .await
+            .is_ok());
+        let unexpected = KeyPinning::new().allow_kid("other".to_string());
+        assert!(builder
+            .clone()
+            .key_pinning(unexpected.clone())
+            .build()
+            .await
+            .is_ok());
+        assert!(matches!(
+            builder.key_pinning(unexpected.enforce()).build().await,
+            Err(JwkAuthError::KeyFetchError(KeyFetchError::UnexpectedKeys(
+                _
+            )))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_builder_key_cache() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let cache = Arc::new(MemoryKeyCache::new());
+        let build = || {
+            JwkAuth::builder()
+                .project_id("pj".to_string())
+                .fetcher(CountingFetcher::counting(&calls))
+                .key_cache(Arc::clone(&cache))
+                .periodic_refresh(false)
+                .emulator(false)
+                .build()
+        };
+        let first = build().await.unwrap();
+        let second = build().await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+        assert!(first.verify(&token).is_ok());
+        assert!(second.verify(&token).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_builder_key_cache_unknown_kid() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let cache = Arc::new(MemoryKeyCache::new());
+        cache
+            .put(&CachedJwks::new(&Jwks {
+                keys: get_test_keys(),
+                validity: Duration::from_secs(3600),
+                stale_while_revalidate: Duration::ZERO,
+            }))
+            .await
+            .unwrap();
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fetcher(CountingFetcher::counting(&calls))
+            .key_cache(Arc::clone(&cache))
+            .periodic_refresh(false)
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+        assert!(jwk_auth.verify_refreshing(&token).await.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            cache.get().await.unwrap().unwrap().keys,
+            vec![get_signing_key()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_builder_key_snapshot_during_outage() {
+        let path = std::env::temp_dir().join(format!("jwks-outage-{}.json", std::process::id()));
+        let snapshot = FileKeyCache::new(&path);
+        snapshot
+            .put(&CachedJwks::new(&Jwks {
+                keys: vec![get_signing_key()],
+                validity: Duration::from_secs(3600),
+                stale_while_revalidate: Duration::ZERO,
+            }))
+            .await
+            .unwrap();
+        let mock_server = get_mock_server_invalid_response().await;
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .pubkey_url(get_mock_url(&mock_server))
+            .retry_policy(RetryPolicy {
+                max_attempts: 1,
+                ..RetryPolicy::default()
+            })
+            .key_cache(snapshot)
+            .periodic_refresh(false)
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+        assert!(jwk_auth.verify(&token).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_periodic_key_update() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let fetcher = CountingFetcher::counting(&calls).validity(Duration::from_millis(10));
+        let _jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fetcher(fetcher)
+            .refresh_strategy(RefreshStrategy {
+                min_interval: Duration::from_millis(0),
+                ..RefreshStrategy::default()
+            })
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+
+        sleep(Duration::from_millis(100)).await;
+        assert!(calls.load(Ordering::SeqCst) > 2);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_lifecycle() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let fetcher = CountingFetcher::counting(&calls).validity(Duration::from_millis(10));
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fetcher(fetcher)
+            .refresh_strategy(RefreshStrategy {
+                min_interval: Duration::from_millis(0),
+                ..RefreshStrategy::default()
+            })
+            .periodic_refresh(false)
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        jwk_auth.resume_refresh();
+        sleep(Duration::from_millis(50)).await;
+        jwk_auth.pause_refresh();
+        let paused_calls = calls.load(Ordering::SeqCst);
+        assert!(paused_calls > 2);
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), paused_calls);
+
+        jwk_auth.resume_refresh();
+        sleep(Duration::from_millis(50)).await;
+        jwk_auth.shutdown().await;
+        let stopped_calls = calls.load(Ordering::SeqCst);
+        assert!(stopped_calls > paused_calls);
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), stopped_calls);
+    }
+
+    #[derive(Clone, Default)]
+    struct CountingRuntime {
+        spawns: Arc<AtomicUsize>,
+        sleeps: Arc<AtomicUsize>,
+    }
+
+    impl Runtime for CountingRuntime {
+        fn spawn(&self, task: BoxFuture<'static, ()>) {
+            self.spawns.fetch_add(1, Ordering::SeqCst);
+            TokioRuntime.spawn(task);
+        }
+        fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+            self.sleeps.fetch_add(1, Ordering::SeqCst);
+            TokioRuntime.sleep(duration)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_builder_custom_runtime() {
+        let runtime = CountingRuntime::default();
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fetcher(CountingFetcher::new(String::new()))
+            .runtime(runtime.clone())
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(runtime.spawns.load(Ordering::SeqCst), 1);
+        assert_eq!(runtime.sleeps.load(Ordering::SeqCst), 1);
+
+        jwk_auth.shutdown().await;
+        jwk_auth.resume_refresh();
+        assert_eq!(runtime.spawns.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_builder_runtime_handle() {
+        let (handle_tx, handle_rx) = std::sync::mpsc::channel();
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let background = std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_time()
+                .build()
+                .unwrap();
+            handle_tx.send(runtime.handle().clone()).unwrap();
+            runtime.block_on(async {
+                let _ = stop_rx.await;
+            });
+        });
+        let handle = handle_rx.recv().unwrap();
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let fetcher = CountingFetcher::counting(&calls).validity(Duration::from_millis(10));
+        let build_runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let jwk_auth = build_runtime
+            .block_on(
+                JwkAuth::builder()
+                    .project_id("pj".to_string())
+                    .fetcher(fetcher)
+                    .refresh_strategy(RefreshStrategy {
+                        min_interval: Duration::from_millis(0),
+                        ..RefreshStrategy::default()
+                    })
+                    .runtime(handle)
+                    .emulator(false)
+                    .build(),
+            )
+            .unwrap();
+        // The refresh keeps running without the runtime that built `jwk_auth`.
+        drop(build_runtime);
+        let built_calls = calls.load(Ordering::SeqCst);
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert!(calls.load(Ordering::SeqCst) > built_calls);
+
+        drop(jwk_auth);
+        stop_tx.send(()).unwrap();
+        background.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_refresh_task_ends_without_jwk_auth() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let fetcher = CountingFetcher::counting(&calls).validity(Duration::from_millis(10));
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fetcher(fetcher)
+            .refresh_strategy(RefreshStrategy {
+                min_interval: Duration::from_millis(0),
+                ..RefreshStrategy::default()
+            })
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        // Detach the task so that dropping `jwk_auth` does not abort it.
+        let task = jwk_auth.task_handler.lock().unwrap().take().unwrap();
+        drop(jwk_auth);
+        tokio::time::timeout(Duration::from_millis(100), task.finished)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_jwk_auth_handle() {
+        fn assert_shareable<T: Clone + Send + Sync + 'static>() {}
+        assert_shareable::<JwkAuthHandle>();
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let fetcher = CountingFetcher::counting(&calls).validity(Duration::from_millis(10));
+        let handle = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fetcher(fetcher)
+            .refresh_strategy(RefreshStrategy {
+                min_interval: Duration::from_millis(0),
+                ..RefreshStrategy::default()
+            })
+            .emulator(false)
+            .build()
+            .await
+            .unwrap()
+            .into_handle();
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+        let clone = handle.clone();
+        let verified = tokio::spawn(async move { clone.verify(&token).map(|t| t.claims.sub) })
+            .await
+            .unwrap();
+        assert_eq!(verified.unwrap(), "uid");
+
+        // The spawned task dropped its clone; the refresh keeps running.
+        let dropped_calls = calls.load(Ordering::SeqCst);
+        sleep(Duration::from_millis(50)).await;
+        assert!(calls.load(Ordering::SeqCst) > dropped_calls);
+        assert!(handle.task_handler.lock().unwrap().is_some());
+
+        let jwk_auth: Arc<JwkAuth> = handle.into();
+        drop(jwk_auth);
+        let stopped_calls = calls.load(Ordering::SeqCst);
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), stopped_calls);
+    }
+
+    #[cfg(feature = "reqwest")]
+    async fn get_checked_jwk_auth(user: serde_json::Value) -> (JwkAuth, String, MockServer) {
+        let mock_server = get_mock_lookup_server(user).await;
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fetcher(CountingFetcher::new(String::new()))
+            .identity_toolkit(get_test_client(&mock_server))
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        let mut claims = get_test_claims("pj", &format!("{}pj", ISSUER_URL));
+        claims.auth_time = Some(now() - 60);
+        (
+            jwk_auth,
+            create_token(Some(SIGNING_KID), &claims),
+            mock_server,
+        )
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn test_verify_checked() {
+        let (jwk_auth, token, _mock_server) =
+            get_checked_jwk_auth(json!({ "localId": "uid", "validSince": "1600000000" })).await;
+        assert!(jwk_auth.verify_checked(&token, true).await.is_ok());
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn test_verify_checked_revoked() {
+        let valid_since = (now() + 60).to_string();
+        let (jwk_auth, token, _mock_server) =
+            get_checked_jwk_auth(json!({ "localId": "uid", "validSince": valid_since })).await;
+        assert_eq!(
+            jwk_auth.verify_checked(&token, true).await.unwrap_err(),
+            VerifyError::Revoked
+        );
+        assert!(jwk_auth.verify_checked(&token, false).await.is_ok());
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn test_verify_checked_disabled() {
+        let (jwk_auth, token, _mock_server) =
+            get_checked_jwk_auth(json!({ "localId": "uid", "disabled": true })).await;
+        assert_eq!(
+            jwk_auth.verify_checked(&token, true).await.unwrap_err(),
+            VerifyError::UserDisabled
+        );
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn test_verify_checked_without_identity_toolkit() {
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fetcher(CountingFetcher::new(String::new()))
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", &format!("{}pj", ISSUER_URL)),
+        );
+        assert_eq!(
+            jwk_auth.verify_checked(&token, true).await.unwrap_err(),
+            VerifyError::RevocationCheckUnavailable
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_tenant() {
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fetcher(CountingFetcher::new(String::new()))
+            .tenant_id("tenant-1".to_string())
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        let mut claims = get_test_claims("pj", &format!("{}pj", ISSUER_URL));
+        let project_token = create_token(Some(SIGNING_KID), &claims);
+        claims.firebase = Some(FirebaseClaims {
+            sign_in_provider: "password".to_string(),
+            tenant: Some("tenant-1".to_string()),
+            ..FirebaseClaims::default()
+        });
+        let tenant_token = create_token(Some(SIGNING_KID), &claims);
+
+        assert!(jwk_auth.verify(&tenant_token).is_ok());
+        assert_eq!(
+            jwk_auth.verify(&project_token).unwrap_err(),
+            VerifyError::WrongTenant
+        );
+        let other_tenant = jwk_auth.for_tenant("tenant-2".to_string());
+        assert_eq!(
+            other_tenant.verify(&tenant_token).unwrap_err(),
+            VerifyError::WrongTenant
+        );
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn test_tenant_aware_auth_verify_checked() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/projects/pj/tenants/tenant-1/accounts:lookup"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "users": [{ "localId": "uid", "disabled": true }]
+            })))
+            .mount(&mock_server)
+            .await;
+        let jwk_auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .fetcher(CountingFetcher::new(String::new()))
+            .identity_toolkit(get_test_client(&mock_server))
+            .emulator(false)
+            .build()
+            .await
+            .unwrap();
+        let mut claims = get_test_claims("pj", &format!("{}pj", ISSUER_URL));
+        claims.firebase = Some(FirebaseClaims {
+            sign_in_provider: "password".to_string(),
+            tenant: Some("tenant-1".to_string()),
+            ..FirebaseClaims::default()
+        });
+        let token = create_token(Some(SIGNING_KID), &claims);
+
+        assert_eq!(
+            jwk_auth.verify(&token).unwrap_err(),
+            VerifyError::WrongTenant
+        );
+        let tenant_auth = jwk_auth.for_tenant("tenant-1".to_string());
+        assert!(tenant_auth.verify(&token).is_ok());
+        assert_eq!(
+            tenant_auth.verify_checked(&token, true).await.unwrap_err(),
+            VerifyError::UserDisabled
         );
     }
 }
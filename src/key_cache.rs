@@ -0,0 +1,342 @@
+//! Caches for fetched keys, shared between [`JwkAuth`](crate::jwk_auth::JwkAuth)
+//! instances, e.g. across a fleet of servers so that cold starts do not all
+//! hit the key server.
+
+use crate::jwk::{Fetcher, Jwk, JwkFetcher, Jwks, KeyFetchError};
+use crate::time::{Duration, SystemTime, UNIX_EPOCH};
+use async_trait::async_trait;
+#[cfg(not(feature = "tracing"))]
+use log::warn;
+use serde::{Deserialize, Serialize};
+#[cfg(not(target_arch = "wasm32"))]
+use std::io;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "tracing")]
+use tracing::warn;
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Fetched keys along with when they were fetched and for how long they are
+/// valid, as stored in a [`KeyCache`].
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct CachedJwks {
+    pub keys: Vec<Jwk>,
+    /// Unix timestamp in seconds.
+    pub fetched_at: u64,
+    /// Validity in seconds from `fetched_at`.
+    pub validity: u64,
+    /// `stale-while-revalidate` in seconds past the validity.
+    #[serde(default)]
+    pub stale_while_revalidate: u64,
+}
+
+impl CachedJwks {
+    pub fn new(jwks: &Jwks) -> CachedJwks {
+        CachedJwks {
+            keys: jwks.keys.clone(),
+            fetched_at: unix_timestamp(),
+            validity: jwks.validity.as_secs(),
+            stale_while_revalidate: jwks.stale_while_revalidate.as_secs(),
+        }
+    }
+    /// How much longer the keys are valid, or `None` if they are expired.
+    pub fn remaining_validity(&self) -> Option<Duration> {
+        let expires_at = self.fetched_at.saturating_add(self.validity);
+        match expires_at.checked_sub(unix_timestamp()) {
+            Some(remaining) if remaining > 0 => Some(Duration::from_secs(remaining)),
+            _ => None,
+        }
+    }
+}
+
+/// Where fetched keys are shared, see
+/// [`JwkAuthBuilder::key_cache`](crate::jwk_auth::JwkAuthBuilder::key_cache).
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+pub trait KeyCache {
+    async fn get(&self) -> Result<Option<CachedJwks>, KeyFetchError>;
+    async fn put(&self, jwks: &CachedJwks) -> Result<(), KeyFetchError>;
+}
+
+/// Keeps the keys in process memory; share it between instances with
+/// `Arc`.
+#[derive(Debug, Default)]
+pub struct MemoryKeyCache {
+    jwks: Mutex<Option<CachedJwks>>,
+}
+
+impl MemoryKeyCache {
+    pub fn new() -> MemoryKeyCache {
+        MemoryKeyCache::default()
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl KeyCache for MemoryKeyCache {
+    async fn get(&self) -> Result<Option<CachedJwks>, KeyFetchError> {
+        Ok(self.jwks.lock().unwrap().clone())
+    }
+    async fn put(&self, jwks: &CachedJwks) -> Result<(), KeyFetchError> {
+        *self.jwks.lock().unwrap() = Some(jwks.clone());
+        Ok(())
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl<C: KeyCache + Send + Sync + ?Sized> KeyCache for Arc<C> {
+    async fn get(&self) -> Result<Option<CachedJwks>, KeyFetchError> {
+        self.as_ref().get().await
+    }
+    async fn put(&self, jwks: &CachedJwks) -> Result<(), KeyFetchError> {
+        self.as_ref().put(jwks).await
+    }
+}
+
+/// Snapshots the keys as JSON to a local file, so that a restarted process
+/// can verify tokens with still valid keys even if the key server is down.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone)]
+pub struct FileKeyCache {
+    path: PathBuf,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileKeyCache {
+    pub fn new<P: Into<PathBuf>>(path: P) -> FileKeyCache {
+        FileKeyCache { path: path.into() }
+    }
+    /// A temporary file next to the snapshot, unique to this write so that
+    /// concurrent writers, in this process or others, do not clobber it.
+    fn temporary_path(&self) -> PathBuf {
+        static WRITES: AtomicUsize = AtomicUsize::new(0);
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(format!(
+            ".{}.{}.tmp",
+            std::process::id(),
+            WRITES.fetch_add(1, Ordering::Relaxed)
+        ));
+        temporary.into()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[async_trait]
+impl KeyCache for FileKeyCache {
+    async fn get(&self) -> Result<Option<CachedJwks>, KeyFetchError> {
+        let contents = match tokio::fs::read(&self.path).await {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(KeyFetchError::Custom(Box::new(error))),
+        };
+        serde_json::from_slice(&contents)
+            .map(Some)
+            .map_err(|e| KeyFetchError::Custom(Box::new(e)))
+    }
+    /// Writes to a temporary file first, so readers never see a partial snapshot.
+    async fn put(&self, jwks: &CachedJwks) -> Result<(), KeyFetchError> {
+        let contents = serde_json::to_vec(jwks).map_err(|e| KeyFetchError::Custom(Box::new(e)))?;
+        let temporary = self.temporary_path();
+        let result = match tokio::fs::write(&temporary, contents).await {
+            Ok(()) => tokio::fs::rename(&temporary, &self.path).await,
+            Err(error) => Err(error),
+        };
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&temporary).await;
+        }
+        result.map_err(|e| KeyFetchError::Custom(Box::new(e)))
+    }
+}
+
+/// Stores the keys as JSON under a single Redis key, expiring with them.
+#[cfg(feature = "redis")]
+pub struct RedisKeyCache {
+    client: redis::Client,
+    key: String,
+}
+
+#[cfg(feature = "redis")]
+impl RedisKeyCache {
+    pub fn new(client: redis::Client, key: String) -> RedisKeyCache {
+        RedisKeyCache { client, key }
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl KeyCache for RedisKeyCache {
+    async fn get(&self) -> Result<Option<CachedJwks>, KeyFetchError> {
+        let mut connection = self
+            .client
+            .get_async_connection()
+            .await
+            .map_err(|e| KeyFetchError::Custom(Box::new(e)))?;
+        let value: Option<String> = redis::cmd("GET")
+            .arg(&self.key)
+            .query_async(&mut connection)
+            .await
+            .map_err(|e| KeyFetchError::Custom(Box::new(e)))?;
+        value
+            .map(|value| serde_json::from_str(&value))
+            .transpose()
+            .map_err(|e| KeyFetchError::Custom(Box::new(e)))
+    }
+    async fn put(&self, jwks: &CachedJwks) -> Result<(), KeyFetchError> {
+        let ttl = match jwks.remaining_validity() {
+            Some(ttl) => ttl.as_secs(),
+            None => return Ok(()),
+        };
+        let value = serde_json::to_string(jwks).map_err(|e| KeyFetchError::Custom(Box::new(e)))?;
+        let mut connection = self
+            .client
+            .get_async_connection()
+            .await
+            .map_err(|e| KeyFetchError::Custom(Box::new(e)))?;
+        redis::cmd("SET")
+            .arg(&self.key)
+            .arg(value)
+            .arg("EX")
+            .arg(ttl)
+            .query_async::<_, ()>(&mut connection)
+            .await
+            .map_err(|e| KeyFetchError::Custom(Box::new(e)))
+    }
+}
+
+/// Serves keys from the cache while they are valid, and otherwise fetches
+/// them and stores them in the cache. Fresh fetches skip the cache read but
+/// still store their keys. Cache failures are logged and ignored.
+pub(crate) struct CachingFetcher {
+    pub fetcher: Arc<dyn Fetcher + Send + Sync>,
+    pub cache: Arc<dyn KeyCache + Send + Sync>,
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl Fetcher for CachingFetcher {
+    fn new(url: String) -> Self {
+        CachingFetcher {
+            fetcher: Arc::new(JwkFetcher::new(url)),
+            cache: Arc::new(MemoryKeyCache::new()),
+        }
+    }
+    async fn fetch_keys(&self) -> Result<Jwks, KeyFetchError> {
+        match self.cache.get().await {
+            Ok(Some(cached)) => {
+                if let Some(validity) = cached.remaining_validity() {
+                    return Ok(Jwks {
+                        keys: cached.keys,
+                        validity,
+                        stale_while_revalidate: Duration::from_secs(cached.stale_while_revalidate),
+                    });
+                }
+            }
+            Ok(None) => {}
+            Err(error) => warn!("Failed to read JWK Keys from the cache: {}", error),
+        }
+        self.fetch_fresh_keys().await
+    }
+    async fn fetch_fresh_keys(&self) -> Result<Jwks, KeyFetchError> {
+        let jwks = self.fetcher.fetch_fresh_keys().await?;
+        if let Err(error) = self.cache.put(&CachedJwks::new(&jwks)).await {
+            warn!("Failed to store JWK Keys in the cache: {}", error);
+        }
+        Ok(jwks)
+    }
+    /// The source of the last keys fetched rather than read from the cache.
+    fn key_source(&self) -> Option<String> {
+        self.fetcher.key_source()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_cached_jwks_remaining_validity() {
+        let jwks = Jwks {
+            keys: get_test_keys(),
+            validity: Duration::from_secs(3600),
+            stale_while_revalidate: Duration::ZERO,
+        };
+        let mut cached = CachedJwks::new(&jwks);
+        let remaining = cached.remaining_validity().unwrap();
+        assert!(remaining > Duration::from_secs(3590) && remaining <= jwks.validity);
+
+        cached.fetched_at -= 3600;
+        assert_eq!(cached.remaining_validity(), None);
+    }
+
+    #[test]
+    fn test_cached_jwks_serialization() {
+        let cached = CachedJwks::new(&Jwks {
+            keys: get_test_keys(),
+            validity: Duration::from_secs(3600),
+            stale_while_revalidate: Duration::ZERO,
+        });
+        let json = serde_json::to_string(&cached).unwrap();
+        assert_eq!(serde_json::from_str::<CachedJwks>(&json).unwrap(), cached);
+    }
+
+    #[tokio::test]
+    async fn test_file_key_cache() {
+        let path = std::env::temp_dir().join(format!("jwks-{}.json", std::process::id()));
+        let cache = FileKeyCache::new(&path);
+        assert_eq!(cache.get().await.unwrap(), None);
+
+        let cached = CachedJwks::new(&Jwks {
+            keys: get_test_keys(),
+            validity: Duration::from_secs(3600),
+            stale_while_revalidate: Duration::ZERO,
+        });
+        cache.put(&cached).await.unwrap();
+        assert_eq!(
+            FileKeyCache::new(&path).get().await.unwrap(),
+            Some(cached.clone())
+        );
+        let (first, second) = tokio::join!(cache.put(&cached), cache.put(&cached));
+        assert!(first.is_ok() && second.is_ok());
+        assert_ne!(cache.temporary_path(), cache.temporary_path());
+
+        fs::write(&path, "not json").unwrap();
+        assert!(cache.get().await.is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_caching_fetcher() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let cache = Arc::new(MemoryKeyCache::new());
+        let fetcher = CachingFetcher {
+            fetcher: Arc::new(CountingFetcher::counting(&calls).keys(get_test_keys())),
+            cache: cache.clone(),
+        };
+
+        let fetched = fetcher.fetch_keys().await.unwrap();
+        assert_eq!(fetched.keys, get_test_keys());
+        assert_eq!(cache.get().await.unwrap().unwrap().keys, get_test_keys());
+        let cached = fetcher.fetch_keys().await.unwrap();
+        assert_eq!(cached.keys, get_test_keys());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let mut expired = cache.get().await.unwrap().unwrap();
+        expired.fetched_at -= 3600;
+        cache.put(&expired).await.unwrap();
+        fetcher.fetch_keys().await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,230 @@
+//! Checks of fetched key sets against the keys expected to be served, to
+//! notice a compromised or misconfigured key server or proxy in front of it.
+
+use crate::jwk::{Fetcher, Jwk, JwkFetcher, Jwks, KeyFetchError};
+use async_trait::async_trait;
+#[cfg(not(feature = "tracing"))]
+use log::warn;
+use ring::digest::{digest, SHA256};
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "tracing")]
+use tracing::warn;
+
+/// The SHA-256 fingerprint of a key set as lowercase hex, independent of the
+/// order the keys are served in.
+pub fn jwks_fingerprint(keys: &[Jwk]) -> String {
+    let mut keys: Vec<&Jwk> = keys.iter().collect();
+    keys.sort_by(|a, b| a.kid.cmp(&b.kid));
+    let json = serde_json::to_vec(&keys).unwrap_or_default();
+    digest(&SHA256, &json)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// The key sets a key server is expected to serve. Unexpected key sets are
+/// logged, and with [`enforce`](Self::enforce) refused like a failed fetch,
+/// so that the current keys stay in use and
+/// [`JwkAuth::on_fetch_error`](crate::jwk_auth::JwkAuth::on_fetch_error)
+/// callbacks are called.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct KeyPinning {
+    /// Key ids that may be served; any if empty.
+    pub allowed_kids: Vec<String>,
+    /// [`jwks_fingerprint`]s of the key sets that may be served; any if empty.
+    pub allowed_fingerprints: Vec<String>,
+    /// Whether a key set must share a key with the previously fetched one.
+    /// Normal rotations publish a new key before retiring the old one, so a
+    /// key set replaced all at once is suspicious.
+    pub require_overlap: bool,
+    /// Whether to refuse unexpected key sets rather than only log them.
+    pub enforce: bool,
+}
+
+impl KeyPinning {
+    pub fn new() -> KeyPinning {
+        KeyPinning::default()
+    }
+    pub fn allow_kid(mut self, kid: String) -> Self {
+        self.allowed_kids.push(kid);
+        self
+    }
+    pub fn allow_fingerprint(mut self, fingerprint: String) -> Self {
+        self.allowed_fingerprints.push(fingerprint.to_lowercase());
+        self
+    }
+    pub fn require_overlap(mut self) -> Self {
+        self.require_overlap = true;
+        self
+    }
+    pub fn enforce(mut self) -> Self {
+        self.enforce = true;
+        self
+    }
+    /// Why `keys` are not expected, given the previously fetched `previous`.
+    fn check(&self, keys: &[Jwk], previous: Option<&[Jwk]>) -> Result<(), String> {
+        if let Some(key) = keys
+            .iter()
+            .find(|key| !self.allowed_kids.is_empty() && !self.allowed_kids.contains(&key.kid))
+        {
+            return Err(format!("key id {} is not pinned", key.kid));
+        }
+        if !self.allowed_fingerprints.is_empty() {
+            let fingerprint = jwks_fingerprint(keys);
+            if !self.allowed_fingerprints.contains(&fingerprint) {
+                return Err(format!("fingerprint {} is not pinned", fingerprint));
+            }
+        }
+        match previous {
+            Some(previous)
+                if self.require_overlap && !keys.iter().any(|key| previous.contains(key)) =>
+            {
+                Err("no key of the previous key set is left".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Checks the keys fetched by `fetcher` against `pinning`.
+pub(crate) struct PinningFetcher {
+    pub fetcher: Arc<dyn Fetcher + Send + Sync>,
+    pub pinning: KeyPinning,
+    /// The last key set passed on, to check for overlap.
+    pub previous: Mutex<Option<Vec<Jwk>>>,
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl Fetcher for PinningFetcher {
+    fn new(url: String) -> Self {
+        PinningFetcher {
+            fetcher: Arc::new(JwkFetcher::new(url)),
+            pinning: KeyPinning::default(),
+            previous: Mutex::new(None),
+        }
+    }
+    async fn fetch_keys(&self) -> Result<Jwks, KeyFetchError> {
+        self.checked(self.fetcher.fetch_keys().await?)
+    }
+    async fn fetch_fresh_keys(&self) -> Result<Jwks, KeyFetchError> {
+        self.checked(self.fetcher.fetch_fresh_keys().await?)
+    }
+    fn key_source(&self) -> Option<String> {
+        self.fetcher.key_source()
+    }
+}
+
+impl PinningFetcher {
+    fn checked(&self, jwks: Jwks) -> Result<Jwks, KeyFetchError> {
+        let mut previous = self.previous.lock().unwrap();
+        if let Err(reason) = self.pinning.check(&jwks.keys, previous.as_deref()) {
+            if self.pinning.enforce {
+                warn!("Refusing unexpected JWK Keys: {}", reason);
+                return Err(KeyFetchError::UnexpectedKeys(reason));
+            }
+            warn!("Fetched unexpected JWK Keys: {}", reason);
+        }
+        *previous = Some(jwks.keys.clone());
+        Ok(jwks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use crate::time::Duration;
+
+    struct QueueFetcher {
+        key_sets: Mutex<Vec<Vec<Jwk>>>,
+    }
+
+    #[async_trait]
+    impl Fetcher for QueueFetcher {
+        fn new(_url: String) -> Self {
+            QueueFetcher {
+                key_sets: Mutex::new(Vec::new()),
+            }
+        }
+        async fn fetch_keys(&self) -> Result<Jwks, KeyFetchError> {
+            Ok(Jwks {
+                keys: self.key_sets.lock().unwrap().remove(0),
+                validity: Duration::from_secs(3600),
+                stale_while_revalidate: Duration::ZERO,
+            })
+        }
+    }
+
+    fn pinning_fetcher(pinning: KeyPinning, key_sets: Vec<Vec<Jwk>>) -> PinningFetcher {
+        PinningFetcher {
+            fetcher: Arc::new(QueueFetcher {
+                key_sets: Mutex::new(key_sets),
+            }),
+            pinning,
+            previous: Mutex::new(None),
+        }
+    }
+
+    #[test]
+    fn test_jwks_fingerprint() {
+        let mut keys = get_test_keys();
+        let fingerprint = jwks_fingerprint(&keys);
+        assert_eq!(fingerprint.len(), 64);
+        keys.reverse();
+        assert_eq!(jwks_fingerprint(&keys), fingerprint);
+        keys.pop();
+        assert_ne!(jwks_fingerprint(&keys), fingerprint);
+    }
+
+    #[test]
+    fn test_key_pinning_check() {
+        let keys = get_test_keys();
+        assert_eq!(KeyPinning::new().check(&keys, None), Ok(()));
+
+        let kids = KeyPinning::new().allow_kid("kid-0".to_string());
+        assert_eq!(
+            kids.check(&keys, None),
+            Err("key id kid-1 is not pinned".to_string())
+        );
+        let kids = kids.allow_kid("kid-1".to_string());
+        assert_eq!(kids.check(&keys, None), Ok(()));
+
+        let fingerprint = KeyPinning::new().allow_fingerprint(jwks_fingerprint(&keys));
+        assert_eq!(fingerprint.check(&keys, None), Ok(()));
+        assert!(fingerprint.check(&keys[..1], None).is_err());
+
+        let overlap = KeyPinning::new().require_overlap();
+        assert_eq!(overlap.check(&keys[1..], Some(&keys[..])), Ok(()));
+        assert_eq!(
+            overlap.check(&[get_signing_key()], Some(&keys[..])),
+            Err("no key of the previous key set is left".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pinning_fetcher() {
+        let keys = get_test_keys();
+        let rotated = vec![keys[1].clone(), get_signing_key()];
+        let replaced = vec![get_ec_signing_key()];
+        let key_sets = vec![keys.clone(), rotated.clone(), replaced.clone()];
+
+        let logging = pinning_fetcher(KeyPinning::new().require_overlap(), key_sets.clone());
+        for key_set in &key_sets {
+            assert_eq!(&logging.fetch_keys().await.unwrap().keys, key_set);
+        }
+
+        let enforcing = pinning_fetcher(
+            KeyPinning::new().require_overlap().enforce(),
+            key_sets.clone(),
+        );
+        assert_eq!(enforcing.fetch_keys().await.unwrap().keys, keys);
+        assert_eq!(enforcing.fetch_keys().await.unwrap().keys, rotated);
+        assert!(matches!(
+            enforcing.fetch_keys().await,
+            Err(KeyFetchError::UnexpectedKeys(_))
+        ));
+        assert_eq!(enforcing.previous.lock().unwrap().as_ref(), Some(&rotated));
+    }
+}
@@ -1,36 +1,222 @@
+#[cfg(feature = "reqwest")]
+pub mod action_code;
+#[cfg(feature = "actix")]
+pub mod actix;
+pub mod app_check;
+#[cfg(feature = "axum")]
+pub mod axum;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+#[cfg(feature = "reqwest")]
+pub mod credentials;
+#[cfg(feature = "reqwest")]
+pub mod custom_token;
+pub mod error;
 mod header_parser;
+pub mod http;
+#[cfg(feature = "reqwest")]
+pub mod identity_toolkit;
+pub mod integration;
 mod jwk;
 pub mod jwk_auth;
+pub mod key_cache;
+pub mod key_pinning;
+#[cfg(feature = "poem")]
+pub mod poem;
+pub mod prelude;
+#[cfg(feature = "reqwest")]
+pub mod provider_config;
+#[cfg(feature = "reqwest")]
+pub mod rate_limit;
+mod runtime;
+#[cfg(feature = "salvo")]
+pub mod salvo;
+mod single_flight;
+mod telemetry;
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
+/// `std::time`, or its browser based counterpart on wasm32, where
+/// `std::time` panics.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use std::time;
+#[cfg(target_arch = "wasm32")]
+pub(crate) use web_time as time;
+pub mod token;
+mod token_cache;
+#[cfg(feature = "reqwest")]
+pub mod token_exchange;
+#[cfg(feature = "tonic")]
+pub mod tonic;
+#[cfg(feature = "tower")]
+pub mod tower;
+#[cfg(feature = "reqwest")]
+pub mod user_import;
+#[cfg(feature = "reqwest")]
+pub mod user_management;
 mod verifier;
+#[cfg(feature = "warp")]
+pub mod warp;
+mod x509;
+
+pub use error::Error;
+pub use jwk_auth::{
+    decode_unverified, verify_id_token, Algorithm, Audience, Claims, ClaimsPolicy, DecodedToken,
+    FirebaseClaims, FirebaseUser, Jwk, JwkAuth, JwkAuthBuilder, JwkAuthError, JwkAuthHandle,
+    KeyFetchError, KeySource, MultiProjectJwkAuth, OptionalFirebaseUser, ProjectIdentifier,
+    RequireClaim, StrictMode, TenantAwareAuth, TokenHeader, TokenVerifier, VerifiedToken,
+    VerifyError,
+};
 
 #[cfg(test)]
 mod tests {
-    use crate::jwk::{Jwk, KeyResponse};
+    use crate::jwk::{Jwk, JwkParams, Jwks, KeyResponse};
+    use crate::jwk_auth::{Fetcher, KeyFetchError};
+    use crate::verifier::Claims;
+    use async_trait::async_trait;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde::Serialize;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
     use wiremock::matchers::{method, path};
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
     pub const MAXAGE: u64 = 20045;
-    pub const PATH: &'static str = "/test";
+    pub const PATH: &str = "/test";
+    pub const SIGNING_KID: &str = "kid-signing";
+    pub const EC_SIGNING_KID: &str = "kid-signing-ec";
+    pub const ED_SIGNING_KID: &str = "kid-signing-ed";
+    pub const PRIVATE_KEY_PEM: &str = include_str!("testdata/rsa_private.pem");
+    pub const CERT_PEM: &str = include_str!("testdata/rsa_cert.pem");
+    pub const EC_PRIVATE_KEY_PEM: &str = include_str!("testdata/ec_private.pem");
+    pub const ED_PRIVATE_KEY_PEM: &str = include_str!("testdata/ed25519_private.pem");
     pub fn get_test_keys() -> Vec<Jwk> {
         vec![
             Jwk {
-                alg: "RS256".to_string(),
+                alg: Some("RS256".to_string()),
                 kid: "kid-0".to_string(),
-                e: "AQAB".to_string(),
-                n: "n-string".to_string(),
-                kty: "RSA".to_string(),
-                r#use: "sig".to_string(),
+                r#use: Some("sig".to_string()),
+                params: JwkParams::Rsa {
+                    n: "n-string".to_string(),
+                    e: "AQAB".to_string(),
+                },
             },
             Jwk {
-                e: "AQAB".to_string(),
-                kty: "RSA".to_string(),
-                n: "n-string".to_string(),
+                alg: Some("RS256".to_string()),
                 kid: "kid-1".to_string(),
-                alg: "RS256".to_string(),
-                r#use: "sig".to_string(),
+                r#use: Some("sig".to_string()),
+                params: JwkParams::Rsa {
+                    n: "n-string".to_string(),
+                    e: "AQAB".to_string(),
+                },
             },
         ]
     }
+    pub fn get_signing_key() -> Jwk {
+        Jwk {
+            alg: Some("RS256".to_string()),
+            kid: SIGNING_KID.to_string(),
+            r#use: Some("sig".to_string()),
+            params: JwkParams::Rsa {
+                n: "vpwg-OcFj5thOiyXIWJJ4-dIQMlRryHHOw_A6fciVsIlz-dl9erteeEWRjW9A3FgLDp4hfLtqFGLfpBqQXwO85u9nmU5FTAeAVxGUunxaH5bntyRDkhwrDcorZxekhLnts0yQVpVkeD3EinapBo8wYt78psJkDmm4LzuBq4VC306254CVLJ22JwpmCP3oslnAy5CyJ3ufqV4ykCnl6vDjMge1A9HSydW7tNDgGK2snVZa8UkwQ9EMMjWARi7kO2GumIuhh0G-xUi18lQclmvR67JZtISUFKX42paS4moBzvHR9FnYS8IyG5SaIWAKI_0YG-8vzJG0juFxQ_tcbR64w".to_string(),
+                e: "AQAB".to_string(),
+            },
+        }
+    }
+    /// Serves fixed keys without the network and counts how often it was asked.
+    pub struct CountingFetcher {
+        pub calls: Arc<AtomicUsize>,
+        pub keys: Vec<Jwk>,
+        pub validity: Duration,
+    }
+
+    impl CountingFetcher {
+        /// Serves the signing key for an hour and counts into `calls`.
+        pub fn counting(calls: &Arc<AtomicUsize>) -> CountingFetcher {
+            CountingFetcher {
+                calls: Arc::clone(calls),
+                keys: vec![get_signing_key()],
+                validity: Duration::from_secs(3600),
+            }
+        }
+        pub fn keys(mut self, keys: Vec<Jwk>) -> CountingFetcher {
+            self.keys = keys;
+            self
+        }
+        pub fn validity(mut self, validity: Duration) -> CountingFetcher {
+            self.validity = validity;
+            self
+        }
+    }
+
+    #[async_trait]
+    impl Fetcher for CountingFetcher {
+        fn new(_url: String) -> Self {
+            CountingFetcher::counting(&Arc::default())
+        }
+        async fn fetch_keys(&self) -> Result<Jwks, KeyFetchError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(Jwks {
+                keys: self.keys.clone(),
+                validity: self.validity,
+                stale_while_revalidate: Duration::ZERO,
+            })
+        }
+    }
+    pub fn get_ec_signing_key() -> Jwk {
+        Jwk {
+            alg: Some("ES256".to_string()),
+            kid: EC_SIGNING_KID.to_string(),
+            r#use: Some("sig".to_string()),
+            params: JwkParams::Ec {
+                crv: "P-256".to_string(),
+                x: "1zrK-c4m7BVv-TN6B74grsezv0AHcTZixBde2m_swDo".to_string(),
+                y: "UoEm6J8JWkGCS6--AftpT0SsYhJB7j7HIoV1awLh5pc".to_string(),
+            },
+        }
+    }
+    pub fn get_ed_signing_key() -> Jwk {
+        Jwk {
+            alg: Some("EdDSA".to_string()),
+            kid: ED_SIGNING_KID.to_string(),
+            r#use: Some("sig".to_string()),
+            params: JwkParams::Okp {
+                crv: "Ed25519".to_string(),
+                x: "7wwzDbZkquX5huE4DZKeKOzv3Me4hmwUYpoOPbuKT98".to_string(),
+            },
+        }
+    }
+    pub fn now() -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64
+    }
+    pub fn get_test_claims(audience: &str, issuer: &str) -> Claims {
+        Claims {
+            aud: audience.into(),
+            exp: now() + 3600,
+            iss: issuer.to_string(),
+            sub: "uid".to_string(),
+            iat: now(),
+            ..Default::default()
+        }
+    }
+    pub fn create_token<T: Serialize>(kid: Option<&str>, claims: &T) -> String {
+        let mut header = Header::new(jsonwebtoken::Algorithm::RS256);
+        header.kid = kid.map(|kid| kid.to_string());
+        let key = EncodingKey::from_rsa_pem(PRIVATE_KEY_PEM.as_bytes()).unwrap();
+        encode(&header, claims, &key).unwrap()
+    }
+    pub fn create_unsigned_token(claims: &Claims) -> String {
+        let encode_segment = |bytes: Vec<u8>| base64::encode_config(bytes, base64::URL_SAFE_NO_PAD);
+        format!(
+            "{}.{}.",
+            encode_segment(br#"{"alg":"none","typ":"JWT"}"#.to_vec()),
+            encode_segment(serde_json::to_vec(claims).unwrap())
+        )
+    }
     pub async fn get_mock_server() -> MockServer {
         let mock_server = MockServer::start().await;
 
@@ -53,6 +239,28 @@ mod tests {
         mock_server
     }
 
+    pub async fn get_mock_cert_server() -> MockServer {
+        let mock_server = MockServer::start().await;
+        let mut certs = HashMap::new();
+        certs.insert(SIGNING_KID.to_string(), CERT_PEM.to_string());
+
+        Mock::given(method("GET"))
+            .and(path(PATH))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header(
+                        "Cache-Control",
+                        &format!("public, max-age={}, must-revalidate, no-transform", MAXAGE)
+                            as &str,
+                    )
+                    .set_body_json(certs),
+            )
+            .mount(&mock_server)
+            .await;
+
+        mock_server
+    }
+
     pub async fn get_mock_server_invalid_response() -> MockServer {
         let mock_server = MockServer::start().await;
 
@@ -65,6 +273,27 @@ mod tests {
         mock_server
     }
 
+    #[cfg(any(
+        feature = "actix",
+        feature = "axum",
+        feature = "poem",
+        feature = "salvo",
+        feature = "tonic",
+        feature = "tower",
+        feature = "warp"
+    ))]
+    pub async fn get_session_cookie_jwk_auth() -> crate::JwkAuth {
+        crate::JwkAuth::builder()
+            .project_id("pj".to_string())
+            .pubkey_url(get_mock_url(&get_mock_cert_server().await))
+            .session_cookie(true)
+            .emulator(false)
+            .periodic_refresh(false)
+            .build()
+            .await
+            .unwrap()
+    }
+
     pub fn get_mock_url(mock_server: &MockServer) -> String {
         format!("{}{}", mock_server.uri(), PATH)
     }
@@ -0,0 +1,163 @@
+use crate::integration::{
+    FirebaseAuthRejection, FirebaseUser, OptionalFirebaseUser, RejectionResponse,
+};
+use crate::jwk_auth::JwkAuth;
+use crate::token::TokenExtractor;
+use poem::error::ResponseError;
+use poem::http::header::WWW_AUTHENTICATE;
+use poem::http::{HeaderValue, StatusCode};
+use poem::web::Json;
+use poem::{async_trait, FromRequest, IntoResponse, Request, RequestBody, Response};
+use std::sync::Arc;
+
+/// Answers with the defaults of [`RejectionResponse`].
+impl ResponseError for FirebaseAuthRejection {
+    fn status(&self) -> StatusCode {
+        let status = RejectionResponse::default().status_code(self);
+        StatusCode::from_u16(status).unwrap_or(StatusCode::UNAUTHORIZED)
+    }
+    fn as_response(&self) -> Response {
+        let config = RejectionResponse::default();
+        let mut response = match config.body(self) {
+            Some(body) => Json(body).with_status(self.status()).into_response(),
+            None => self.status().into_response(),
+        };
+        if let Some(challenge) = config
+            .www_authenticate(self)
+            .and_then(|challenge| HeaderValue::from_str(&challenge).ok())
+        {
+            response.headers_mut().insert(WWW_AUTHENTICATE, challenge);
+        }
+        response
+    }
+}
+
+/// Uses the user authenticated by a middleware if present, and otherwise
+/// verifies the token with the `Arc<JwkAuth>` of the endpoint data, looking
+/// for it where a `TokenExtractor` in the data does, or in the bearer token.
+async fn firebase_user(req: &Request) -> Result<FirebaseUser, FirebaseAuthRejection> {
+    if let Some(user) = req.extensions().get::<FirebaseUser>() {
+        return Ok(user.clone());
+    }
+    let jwk_auth = req
+        .data::<Arc<JwkAuth>>()
+        .ok_or(FirebaseAuthRejection::MissingJwkAuth)?;
+    let default_extractor = TokenExtractor::default();
+    let extractor = req.data::<TokenExtractor>().unwrap_or(&default_extractor);
+    let token_data = extractor
+        .extract_and_verify_async(
+            jwk_auth.as_ref(),
+            |name| {
+                req.headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+            },
+            req.uri().query(),
+        )
+        .await?;
+    Ok(FirebaseUser::from(token_data.claims))
+}
+
+#[async_trait]
+impl<'a> FromRequest<'a> for FirebaseUser {
+    async fn from_request(req: &'a Request, _body: &mut RequestBody) -> poem::Result<Self> {
+        Ok(firebase_user(req).await?)
+    }
+}
+
+#[async_trait]
+impl<'a> FromRequest<'a> for OptionalFirebaseUser {
+    async fn from_request(req: &'a Request, _body: &mut RequestBody) -> poem::Result<Self> {
+        Ok(OptionalFirebaseUser::from_result(firebase_user(req).await)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use poem::http::header::{AUTHORIZATION, COOKIE};
+    use poem::{get, handler, Endpoint, EndpointExt, Route};
+
+    #[handler]
+    fn uid(user: FirebaseUser) -> String {
+        user.uid
+    }
+
+    #[handler]
+    fn optional_uid(user: OptionalFirebaseUser) -> String {
+        user.0
+            .map_or_else(|| "anonymous".to_string(), |user| user.uid)
+    }
+
+    async fn call(authorization: Option<&str>) -> Response {
+        call_path("/uid", authorization).await
+    }
+
+    async fn call_path(path: &str, authorization: Option<&str>) -> Response {
+        let jwk_auth = get_session_cookie_jwk_auth().await;
+        let endpoint = Route::new()
+            .at("/uid", get(uid))
+            .at("/optional", get(optional_uid))
+            .data(Arc::new(jwk_auth));
+        let mut request = Request::builder().uri_str(path);
+        if let Some(authorization) = authorization {
+            request = request.header(AUTHORIZATION, authorization);
+        }
+        endpoint.get_response(request.finish()).await
+    }
+
+    #[tokio::test]
+    async fn test_firebase_user() {
+        let claims = get_test_claims("pj", "https://session.firebase.google.com/pj");
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let response = call(Some(&format!("Bearer {}", token))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.into_body().into_string().await.unwrap(), "uid");
+    }
+
+    #[tokio::test]
+    async fn test_firebase_user_rejected() {
+        let response = call(None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers().get(WWW_AUTHENTICATE).unwrap(), "Bearer");
+        assert_eq!(
+            call(Some("Bearer invalid")).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_optional_firebase_user() {
+        let claims = get_test_claims("pj", "https://session.firebase.google.com/pj");
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let response = call_path("/optional", Some(&format!("Bearer {}", token))).await;
+        assert_eq!(response.into_body().into_string().await.unwrap(), "uid");
+        let response = call_path("/optional", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.into_body().into_string().await.unwrap(),
+            "anonymous"
+        );
+        let response = call_path("/optional", Some("Bearer invalid")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_firebase_user_token_extractor() {
+        let claims = get_test_claims("pj", "https://session.firebase.google.com/pj");
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let jwk_auth = get_session_cookie_jwk_auth().await;
+        let endpoint = Route::new()
+            .at("/uid", get(uid))
+            .data(Arc::new(jwk_auth))
+            .data(TokenExtractor::new().cookie("__session".to_string()));
+        let request = Request::builder()
+            .uri_str("/uid")
+            .header(COOKIE, format!("__session={}", token))
+            .finish();
+        let response = endpoint.get_response(request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.into_body().into_string().await.unwrap(), "uid");
+    }
+}
@@ -0,0 +1,34 @@
+//! The types most code verifying tokens needs, to be glob imported:
+//! `use firebase_admin_auth_rs::prelude::*;`.
+
+pub use crate::error::{Error, JwkAuthError, KeyFetchError, VerifyError};
+pub use crate::jwk_auth::{
+    decode_unverified, verify_id_token, Algorithm, Claims, ClaimsPolicy, DecodedToken,
+    FirebaseClaims, FirebaseUser, Jwk, JwkAuth, JwkAuthBuilder, JwkAuthHandle, KeySource,
+    OptionalFirebaseUser, ProjectIdentifier, RefreshStrategy, RequireClaim, RetryPolicy,
+    StalenessPolicy, StrictMode, TokenHeader, TokenVerifier, VerifiedToken,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+
+    fn verify(jwk_auth: &JwkAuth, token: &str) -> Result<DecodedToken<Claims>, Error> {
+        Ok(jwk_auth.verify(token)?)
+    }
+
+    #[tokio::test]
+    async fn test_prelude() {
+        let jwk_auth = JwkAuth::with_keys(vec![get_signing_key()], "pj".to_string()).unwrap();
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", "https://securetoken.google.com/pj"),
+        );
+        assert_eq!(verify(&jwk_auth, &token).unwrap().claims.sub, "uid");
+        assert!(matches!(
+            verify(&jwk_auth, "invalid"),
+            Err(Error::Verify(VerifyError::Malformed))
+        ));
+    }
+}
@@ -0,0 +1,806 @@
+//! Management of the OIDC and SAML identity providers users can sign in with
+//! through Identity Platform.
+
+use crate::identity_toolkit::{IdentityToolkit, IdentityToolkitError};
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+const MAX_LIST_PROVIDER_CONFIGS_RESULTS: usize = 100;
+const OIDC_PREFIX: &str = "oidc.";
+const SAML_PREFIX: &str = "saml.";
+
+fn check_provider_id(provider_id: &str, prefix: &str) -> Result<(), IdentityToolkitError> {
+    match provider_id.strip_prefix(prefix) {
+        Some(name) if !name.is_empty() && !name.contains('/') => Ok(()),
+        _ => Err(IdentityToolkitError::InvalidArgument(format!(
+            "provider id \"{}\" must start with \"{}\"",
+            provider_id, prefix
+        ))),
+    }
+}
+
+/// The provider id at the end of a resource name like
+/// `projects/pj/oauthIdpConfigs/oidc.provider`.
+fn provider_id(name: &str) -> String {
+    name.rsplit('/').next().unwrap_or_default().to_string()
+}
+
+/// An OpenID Connect identity provider.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct OidcProviderConfig {
+    /// Starts with `oidc.`.
+    pub provider_id: String,
+    pub display_name: Option<String>,
+    pub enabled: bool,
+    pub client_id: String,
+    pub issuer: String,
+    pub client_secret: Option<String>,
+    /// Whether the implicit flow is used.
+    pub id_token_response: bool,
+    /// Whether the authorization code flow is used, which needs the client
+    /// secret.
+    pub code_response: bool,
+}
+
+/// Properties of an OIDC provider to create or update. Unset fields are left
+/// unchanged on update.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct OidcProviderConfigRequest {
+    display_name: Option<String>,
+    enabled: Option<bool>,
+    client_id: Option<String>,
+    issuer: Option<String>,
+    client_secret: Option<String>,
+    id_token_response: Option<bool>,
+    code_response: Option<bool>,
+}
+
+impl OidcProviderConfigRequest {
+    pub fn new() -> OidcProviderConfigRequest {
+        OidcProviderConfigRequest::default()
+    }
+    pub fn display_name(mut self, display_name: String) -> Self {
+        self.display_name = Some(display_name);
+        self
+    }
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.enabled = Some(enabled);
+        self
+    }
+    /// Required on creation.
+    pub fn client_id(mut self, client_id: String) -> Self {
+        self.client_id = Some(client_id);
+        self
+    }
+    /// Required on creation.
+    pub fn issuer(mut self, issuer: String) -> Self {
+        self.issuer = Some(issuer);
+        self
+    }
+    pub fn client_secret(mut self, client_secret: String) -> Self {
+        self.client_secret = Some(client_secret);
+        self
+    }
+    pub fn id_token_response(mut self, id_token_response: bool) -> Self {
+        self.id_token_response = Some(id_token_response);
+        self
+    }
+    pub fn code_response(mut self, code_response: bool) -> Self {
+        self.code_response = Some(code_response);
+        self
+    }
+    fn update_mask(&self) -> String {
+        [
+            ("displayName", self.display_name.is_some()),
+            ("enabled", self.enabled.is_some()),
+            ("clientId", self.client_id.is_some()),
+            ("issuer", self.issuer.is_some()),
+            ("clientSecret", self.client_secret.is_some()),
+            ("responseType.idToken", self.id_token_response.is_some()),
+            ("responseType.code", self.code_response.is_some()),
+        ]
+        .iter()
+        .filter(|(_, set)| *set)
+        .map(|(path, _)| *path)
+        .collect::<Vec<_>>()
+        .join(",")
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+struct ResponseType {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id_token: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+struct OAuthIdpConfig {
+    #[serde(default, skip_serializing)]
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    display_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    issuer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_secret: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_type: Option<ResponseType>,
+}
+
+impl From<OidcProviderConfigRequest> for OAuthIdpConfig {
+    fn from(request: OidcProviderConfigRequest) -> Self {
+        let response_type = match (request.id_token_response, request.code_response) {
+            (None, None) => None,
+            (id_token, code) => Some(ResponseType { id_token, code }),
+        };
+        OAuthIdpConfig {
+            name: String::new(),
+            display_name: request.display_name,
+            enabled: request.enabled,
+            client_id: request.client_id,
+            issuer: request.issuer,
+            client_secret: request.client_secret,
+            response_type,
+        }
+    }
+}
+
+impl From<OAuthIdpConfig> for OidcProviderConfig {
+    fn from(config: OAuthIdpConfig) -> Self {
+        let response_type = config.response_type.unwrap_or_default();
+        OidcProviderConfig {
+            provider_id: provider_id(&config.name),
+            display_name: config.display_name,
+            enabled: config.enabled.unwrap_or_default(),
+            client_id: config.client_id.unwrap_or_default(),
+            issuer: config.issuer.unwrap_or_default(),
+            client_secret: config.client_secret,
+            id_token_response: response_type.id_token.unwrap_or_default(),
+            code_response: response_type.code.unwrap_or_default(),
+        }
+    }
+}
+
+/// A SAML identity provider.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct SamlProviderConfig {
+    /// Starts with `saml.`.
+    pub provider_id: String,
+    pub display_name: Option<String>,
+    pub enabled: bool,
+    pub idp_entity_id: String,
+    pub sso_url: String,
+    /// The PEM certificates the provider signs its responses with.
+    pub x509_certificates: Vec<String>,
+    pub rp_entity_id: String,
+    pub callback_url: String,
+}
+
+/// Properties of a SAML provider to create or update. Unset fields are left
+/// unchanged on update.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct SamlProviderConfigRequest {
+    display_name: Option<String>,
+    enabled: Option<bool>,
+    idp_entity_id: Option<String>,
+    sso_url: Option<String>,
+    x509_certificates: Option<Vec<String>>,
+    rp_entity_id: Option<String>,
+    callback_url: Option<String>,
+}
+
+impl SamlProviderConfigRequest {
+    pub fn new() -> SamlProviderConfigRequest {
+        SamlProviderConfigRequest::default()
+    }
+    pub fn display_name(mut self, display_name: String) -> Self {
+        self.display_name = Some(display_name);
+        self
+    }
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.enabled = Some(enabled);
+        self
+    }
+    /// Required on creation.
+    pub fn idp_entity_id(mut self, idp_entity_id: String) -> Self {
+        self.idp_entity_id = Some(idp_entity_id);
+        self
+    }
+    /// Required on creation.
+    pub fn sso_url(mut self, sso_url: String) -> Self {
+        self.sso_url = Some(sso_url);
+        self
+    }
+    /// Required on creation. Replaces all certificates on update.
+    pub fn x509_certificates(mut self, x509_certificates: Vec<String>) -> Self {
+        self.x509_certificates = Some(x509_certificates);
+        self
+    }
+    /// Required on creation.
+    pub fn rp_entity_id(mut self, rp_entity_id: String) -> Self {
+        self.rp_entity_id = Some(rp_entity_id);
+        self
+    }
+    /// Required on creation.
+    pub fn callback_url(mut self, callback_url: String) -> Self {
+        self.callback_url = Some(callback_url);
+        self
+    }
+    fn update_mask(&self) -> String {
+        [
+            ("displayName", self.display_name.is_some()),
+            ("enabled", self.enabled.is_some()),
+            ("idpConfig.idpEntityId", self.idp_entity_id.is_some()),
+            ("idpConfig.ssoUrl", self.sso_url.is_some()),
+            (
+                "idpConfig.idpCertificates",
+                self.x509_certificates.is_some(),
+            ),
+            ("spConfig.spEntityId", self.rp_entity_id.is_some()),
+            ("spConfig.callbackUri", self.callback_url.is_some()),
+        ]
+        .iter()
+        .filter(|(_, set)| *set)
+        .map(|(path, _)| *path)
+        .collect::<Vec<_>>()
+        .join(",")
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct IdpCertificate {
+    x509_certificate: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+struct IdpConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    idp_entity_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sso_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    idp_certificates: Option<Vec<IdpCertificate>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+struct SpConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sp_entity_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    callback_uri: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+struct InboundSamlConfig {
+    #[serde(default, skip_serializing)]
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    display_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    idp_config: Option<IdpConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sp_config: Option<SpConfig>,
+}
+
+impl From<SamlProviderConfigRequest> for InboundSamlConfig {
+    fn from(request: SamlProviderConfigRequest) -> Self {
+        let idp_config = IdpConfig {
+            idp_entity_id: request.idp_entity_id,
+            sso_url: request.sso_url,
+            idp_certificates: request.x509_certificates.map(|certificates| {
+                certificates
+                    .into_iter()
+                    .map(|x509_certificate| IdpCertificate { x509_certificate })
+                    .collect()
+            }),
+        };
+        let sp_config = SpConfig {
+            sp_entity_id: request.rp_entity_id,
+            callback_uri: request.callback_url,
+        };
+        InboundSamlConfig {
+            name: String::new(),
+            display_name: request.display_name,
+            enabled: request.enabled,
+            idp_config: Some(idp_config).filter(|config| config != &IdpConfig::default()),
+            sp_config: Some(sp_config).filter(|config| config != &SpConfig::default()),
+        }
+    }
+}
+
+impl From<InboundSamlConfig> for SamlProviderConfig {
+    fn from(config: InboundSamlConfig) -> Self {
+        let idp_config = config.idp_config.unwrap_or_default();
+        let sp_config = config.sp_config.unwrap_or_default();
+        SamlProviderConfig {
+            provider_id: provider_id(&config.name),
+            display_name: config.display_name,
+            enabled: config.enabled.unwrap_or_default(),
+            idp_entity_id: idp_config.idp_entity_id.unwrap_or_default(),
+            sso_url: idp_config.sso_url.unwrap_or_default(),
+            x509_certificates: idp_config
+                .idp_certificates
+                .unwrap_or_default()
+                .into_iter()
+                .map(|certificate| certificate.x509_certificate)
+                .collect(),
+            rp_entity_id: sp_config.sp_entity_id.unwrap_or_default(),
+            callback_url: sp_config.callback_uri.unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ListProviderConfigsQuery<'a> {
+    page_size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page_token: Option<&'a str>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListOAuthIdpConfigsResponse {
+    #[serde(default)]
+    oauth_idp_configs: Vec<OAuthIdpConfig>,
+    next_page_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListInboundSamlConfigsResponse {
+    #[serde(default)]
+    inbound_saml_configs: Vec<InboundSamlConfig>,
+    next_page_token: Option<String>,
+}
+
+/// One page of provider configurations. `next_page_token` is `None` on the
+/// last page.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ProviderConfigPage<T> {
+    pub provider_configs: Vec<T>,
+    pub next_page_token: Option<String>,
+}
+
+impl IdentityToolkit {
+    async fn create_provider_config<B, R>(
+        &self,
+        collection: &str,
+        id_param: &str,
+        provider_id: &str,
+        body: &B,
+    ) -> Result<R, IdentityToolkitError>
+    where
+        B: Serialize,
+        R: DeserializeOwned,
+    {
+        let request = self
+            .admin_request(Method::POST, &format!("/{}", collection))
+            .query(&[(id_param, provider_id)])
+            .json(body);
+        self.send(request).await
+    }
+    async fn update_provider_config<B, R>(
+        &self,
+        collection: &str,
+        provider_id: &str,
+        update_mask: String,
+        body: &B,
+    ) -> Result<R, IdentityToolkitError>
+    where
+        B: Serialize,
+        R: DeserializeOwned,
+    {
+        if update_mask.is_empty() {
+            return Err(IdentityToolkitError::InvalidArgument(
+                "the update must change at least one property".to_string(),
+            ));
+        }
+        let request = self
+            .admin_request(Method::PATCH, &format!("/{}/{}", collection, provider_id))
+            .query(&[("updateMask", update_mask)])
+            .json(body);
+        self.send(request).await
+    }
+    async fn provider_config_request<R: DeserializeOwned>(
+        &self,
+        method: Method,
+        collection: &str,
+        provider_id: &str,
+    ) -> Result<R, IdentityToolkitError> {
+        let path = format!("/{}/{}", collection, provider_id);
+        self.send(self.admin_request(method, &path)).await
+    }
+    async fn list_provider_configs<R: DeserializeOwned>(
+        &self,
+        collection: &str,
+        page_size: usize,
+        page_token: Option<&str>,
+    ) -> Result<R, IdentityToolkitError> {
+        if page_size == 0 || page_size > MAX_LIST_PROVIDER_CONFIGS_RESULTS {
+            return Err(IdentityToolkitError::InvalidArgument(format!(
+                "page size must be between 1 and {}",
+                MAX_LIST_PROVIDER_CONFIGS_RESULTS
+            )));
+        }
+        let query = ListProviderConfigsQuery {
+            page_size,
+            page_token,
+        };
+        let request = self
+            .admin_request(Method::GET, &format!("/{}", collection))
+            .query(&query);
+        self.send(request).await
+    }
+    /// Creates an OIDC provider, whose id must start with `oidc.`.
+    pub async fn create_oidc_provider_config(
+        &self,
+        provider_id: &str,
+        request: OidcProviderConfigRequest,
+    ) -> Result<OidcProviderConfig, IdentityToolkitError> {
+        check_provider_id(provider_id, OIDC_PREFIX)?;
+        if request.client_id.is_none() || request.issuer.is_none() {
+            return Err(IdentityToolkitError::InvalidArgument(
+                "client_id and issuer are required".to_string(),
+            ));
+        }
+        let config: OAuthIdpConfig = self
+            .create_provider_config(
+                "oauthIdpConfigs",
+                "oauthIdpConfigId",
+                provider_id,
+                &OAuthIdpConfig::from(request),
+            )
+            .await?;
+        Ok(config.into())
+    }
+    pub async fn get_oidc_provider_config(
+        &self,
+        provider_id: &str,
+    ) -> Result<OidcProviderConfig, IdentityToolkitError> {
+        check_provider_id(provider_id, OIDC_PREFIX)?;
+        let config: OAuthIdpConfig = self
+            .provider_config_request(Method::GET, "oauthIdpConfigs", provider_id)
+            .await?;
+        Ok(config.into())
+    }
+    pub async fn update_oidc_provider_config(
+        &self,
+        provider_id: &str,
+        request: OidcProviderConfigRequest,
+    ) -> Result<OidcProviderConfig, IdentityToolkitError> {
+        check_provider_id(provider_id, OIDC_PREFIX)?;
+        let update_mask = request.update_mask();
+        let config: OAuthIdpConfig = self
+            .update_provider_config(
+                "oauthIdpConfigs",
+                provider_id,
+                update_mask,
+                &OAuthIdpConfig::from(request),
+            )
+            .await?;
+        Ok(config.into())
+    }
+    pub async fn delete_oidc_provider_config(
+        &self,
+        provider_id: &str,
+    ) -> Result<(), IdentityToolkitError> {
+        check_provider_id(provider_id, OIDC_PREFIX)?;
+        let _: serde_json::Value = self
+            .provider_config_request(Method::DELETE, "oauthIdpConfigs", provider_id)
+            .await?;
+        Ok(())
+    }
+    /// Fetches a single page of at most `page_size` (1 to 100) OIDC providers.
+    pub async fn list_oidc_provider_configs(
+        &self,
+        page_size: usize,
+        page_token: Option<&str>,
+    ) -> Result<ProviderConfigPage<OidcProviderConfig>, IdentityToolkitError> {
+        let response: ListOAuthIdpConfigsResponse = self
+            .list_provider_configs("oauthIdpConfigs", page_size, page_token)
+            .await?;
+        Ok(ProviderConfigPage {
+            provider_configs: response
+                .oauth_idp_configs
+                .into_iter()
+                .map(OidcProviderConfig::from)
+                .collect(),
+            next_page_token: response.next_page_token.filter(|token| !token.is_empty()),
+        })
+    }
+    /// Creates a SAML provider, whose id must start with `saml.`.
+    pub async fn create_saml_provider_config(
+        &self,
+        provider_id: &str,
+        request: SamlProviderConfigRequest,
+    ) -> Result<SamlProviderConfig, IdentityToolkitError> {
+        check_provider_id(provider_id, SAML_PREFIX)?;
+        if request.idp_entity_id.is_none()
+            || request.sso_url.is_none()
+            || request.x509_certificates.is_none()
+            || request.rp_entity_id.is_none()
+            || request.callback_url.is_none()
+        {
+            return Err(IdentityToolkitError::InvalidArgument(
+                "idp_entity_id, sso_url, x509_certificates, rp_entity_id and callback_url are required"
+                    .to_string(),
+            ));
+        }
+        let config: InboundSamlConfig = self
+            .create_provider_config(
+                "inboundSamlConfigs",
+                "inboundSamlConfigId",
+                provider_id,
+                &InboundSamlConfig::from(request),
+            )
+            .await?;
+        Ok(config.into())
+    }
+    pub async fn get_saml_provider_config(
+        &self,
+        provider_id: &str,
+    ) -> Result<SamlProviderConfig, IdentityToolkitError> {
+        check_provider_id(provider_id, SAML_PREFIX)?;
+        let config: InboundSamlConfig = self
+            .provider_config_request(Method::GET, "inboundSamlConfigs", provider_id)
+            .await?;
+        Ok(config.into())
+    }
+    pub async fn update_saml_provider_config(
+        &self,
+        provider_id: &str,
+        request: SamlProviderConfigRequest,
+    ) -> Result<SamlProviderConfig, IdentityToolkitError> {
+        check_provider_id(provider_id, SAML_PREFIX)?;
+        let update_mask = request.update_mask();
+        let config: InboundSamlConfig = self
+            .update_provider_config(
+                "inboundSamlConfigs",
+                provider_id,
+                update_mask,
+                &InboundSamlConfig::from(request),
+            )
+            .await?;
+        Ok(config.into())
+    }
+    pub async fn delete_saml_provider_config(
+        &self,
+        provider_id: &str,
+    ) -> Result<(), IdentityToolkitError> {
+        check_provider_id(provider_id, SAML_PREFIX)?;
+        let _: serde_json::Value = self
+            .provider_config_request(Method::DELETE, "inboundSamlConfigs", provider_id)
+            .await?;
+        Ok(())
+    }
+    /// Fetches a single page of at most `page_size` (1 to 100) SAML providers.
+    pub async fn list_saml_provider_configs(
+        &self,
+        page_size: usize,
+        page_token: Option<&str>,
+    ) -> Result<ProviderConfigPage<SamlProviderConfig>, IdentityToolkitError> {
+        let response: ListInboundSamlConfigsResponse = self
+            .list_provider_configs("inboundSamlConfigs", page_size, page_token)
+            .await?;
+        Ok(ProviderConfigPage {
+            provider_configs: response
+                .inbound_saml_configs
+                .into_iter()
+                .map(SamlProviderConfig::from)
+                .collect(),
+            next_page_token: response.next_page_token.filter(|token| !token.is_empty()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity_toolkit::tests::get_test_client;
+    use serde_json::json;
+    use wiremock::matchers::{body_json, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn oidc_config() -> serde_json::Value {
+        json!({
+            "name": "projects/pj/oauthIdpConfigs/oidc.provider",
+            "clientId": "client-id",
+            "issuer": "https://oidc.example.com",
+            "displayName": "Example",
+            "enabled": true,
+            "responseType": { "idToken": true }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_create_oidc_provider_config() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/projects/pj/oauthIdpConfigs"))
+            .and(query_param("oauthIdpConfigId", "oidc.provider"))
+            .and(body_json(json!({
+                "clientId": "client-id",
+                "issuer": "https://oidc.example.com",
+                "displayName": "Example",
+                "enabled": true,
+                "responseType": { "idToken": true }
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(oidc_config()))
+            .mount(&mock_server)
+            .await;
+        let request = OidcProviderConfigRequest::new()
+            .client_id("client-id".to_string())
+            .issuer("https://oidc.example.com".to_string())
+            .display_name("Example".to_string())
+            .enabled(true)
+            .id_token_response(true);
+        let config = get_test_client(&mock_server)
+            .create_oidc_provider_config("oidc.provider", request)
+            .await
+            .unwrap();
+        assert_eq!(
+            config,
+            OidcProviderConfig {
+                provider_id: "oidc.provider".to_string(),
+                display_name: Some("Example".to_string()),
+                enabled: true,
+                client_id: "client-id".to_string(),
+                issuer: "https://oidc.example.com".to_string(),
+                client_secret: None,
+                id_token_response: true,
+                code_response: false,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_and_list_oidc_provider_configs() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/projects/pj/oauthIdpConfigs/oidc.provider"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(oidc_config()))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/projects/pj/oauthIdpConfigs/oidc.missing"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+                "error": { "code": 404, "message": "CONFIGURATION_NOT_FOUND" }
+            })))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/projects/pj/oauthIdpConfigs"))
+            .and(query_param("pageSize", "10"))
+            .and(query_param("pageToken", "token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "oauthIdpConfigs": [oidc_config()],
+                "nextPageToken": ""
+            })))
+            .mount(&mock_server)
+            .await;
+        let client = get_test_client(&mock_server);
+        let config = client
+            .get_oidc_provider_config("oidc.provider")
+            .await
+            .unwrap();
+        assert_eq!(config.client_id, "client-id");
+        assert!(matches!(
+            client.get_oidc_provider_config("oidc.missing").await,
+            Err(IdentityToolkitError::ProviderConfigNotFound)
+        ));
+        let page = client
+            .list_oidc_provider_configs(10, Some("token"))
+            .await
+            .unwrap();
+        assert_eq!(page.provider_configs, vec![config]);
+        assert_eq!(page.next_page_token, None);
+    }
+
+    #[tokio::test]
+    async fn test_update_saml_provider_config() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("PATCH"))
+            .and(path("/projects/pj/inboundSamlConfigs/saml.provider"))
+            .and(query_param(
+                "updateMask",
+                "enabled,idpConfig.idpCertificates,spConfig.callbackUri",
+            ))
+            .and(body_json(json!({
+                "enabled": false,
+                "idpConfig": { "idpCertificates": [{ "x509Certificate": "cert" }] },
+                "spConfig": { "callbackUri": "https://pj.firebaseapp.com/__/auth/handler" }
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "name": "projects/pj/inboundSamlConfigs/saml.provider",
+                "enabled": false,
+                "idpConfig": {
+                    "idpEntityId": "idp",
+                    "ssoUrl": "https://idp.example.com/sso",
+                    "idpCertificates": [{ "x509Certificate": "cert" }]
+                },
+                "spConfig": {
+                    "spEntityId": "rp",
+                    "callbackUri": "https://pj.firebaseapp.com/__/auth/handler"
+                }
+            })))
+            .mount(&mock_server)
+            .await;
+        let request = SamlProviderConfigRequest::new()
+            .enabled(false)
+            .x509_certificates(vec!["cert".to_string()])
+            .callback_url("https://pj.firebaseapp.com/__/auth/handler".to_string());
+        let config = get_test_client(&mock_server)
+            .update_saml_provider_config("saml.provider", request)
+            .await
+            .unwrap();
+        assert_eq!(
+            config,
+            SamlProviderConfig {
+                provider_id: "saml.provider".to_string(),
+                display_name: None,
+                enabled: false,
+                idp_entity_id: "idp".to_string(),
+                sso_url: "https://idp.example.com/sso".to_string(),
+                x509_certificates: vec!["cert".to_string()],
+                rp_entity_id: "rp".to_string(),
+                callback_url: "https://pj.firebaseapp.com/__/auth/handler".to_string(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_saml_provider_config() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("DELETE"))
+            .and(path("/projects/pj/inboundSamlConfigs/saml.provider"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        get_test_client(&mock_server)
+            .delete_saml_provider_config("saml.provider")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_invalid_provider_config_requests() {
+        let mock_server = MockServer::start().await;
+        let client = get_test_client(&mock_server);
+        let results = vec![
+            client.get_oidc_provider_config("saml.provider").await.err(),
+            client.delete_saml_provider_config("saml.").await.err(),
+            client
+                .create_oidc_provider_config("oidc.provider", OidcProviderConfigRequest::new())
+                .await
+                .err(),
+            client
+                .update_saml_provider_config("saml.provider", SamlProviderConfigRequest::new())
+                .await
+                .err(),
+            client.list_oidc_provider_configs(101, None).await.err(),
+        ];
+        for result in results {
+            assert!(matches!(
+                result,
+                Some(IdentityToolkitError::InvalidArgument(_))
+            ));
+        }
+    }
+}
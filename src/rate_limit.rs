@@ -0,0 +1,222 @@
+//! Client side limits on admin API calls, so that bulk operations stay within
+//! the Identity Toolkit quotas instead of failing halfway with 429s.
+
+use crate::runtime::{default_runtime, Runtime};
+use crate::time::{Duration, Instant};
+use std::cmp::min;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Limits on the requests of an
+/// [`IdentityToolkit`](crate::identity_toolkit::IdentityToolkit), set with
+/// [`with_rate_limit`](crate::identity_toolkit::IdentityToolkit::with_rate_limit).
+#[derive(Debug, PartialEq, Clone)]
+pub struct RateLimit {
+    /// How many requests may be in flight at once; unlimited if `None`.
+    pub max_concurrent_requests: Option<usize>,
+    /// The sustained request rate; unlimited if `None`.
+    pub requests_per_second: Option<f64>,
+    /// How many requests may be sent at once after an idle period.
+    pub burst: u32,
+    /// How often a request answered with 429 is retried.
+    pub max_retries: u32,
+    /// The longest wait before a retry, however long `Retry-After` asks for.
+    pub max_retry_delay: Duration,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            max_concurrent_requests: None,
+            requests_per_second: None,
+            burst: 1,
+            max_retries: 3,
+            max_retry_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RateLimit {
+    pub fn new() -> RateLimit {
+        RateLimit::default()
+    }
+    /// Caps the requests in flight. A limit of zero would block every
+    /// request forever and leaves concurrency unlimited instead.
+    pub fn max_concurrent_requests(mut self, max_concurrent_requests: usize) -> Self {
+        self.max_concurrent_requests = valid_concurrency(Some(max_concurrent_requests));
+        self
+    }
+    /// Allows `requests_per_second` on average, with bursts of `burst`. A rate
+    /// that is not positive and finite leaves the rate unlimited.
+    pub fn requests_per_second(mut self, requests_per_second: f64, burst: u32) -> Self {
+        self.requests_per_second = valid_rate(Some(requests_per_second));
+        self.burst = burst.max(1);
+        self
+    }
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+    pub fn max_retry_delay(mut self, max_retry_delay: Duration) -> Self {
+        self.max_retry_delay = max_retry_delay;
+        self
+    }
+    /// How long to wait before retrying the `attempt`th time: as long as
+    /// `Retry-After` asks, or a second doubling with every attempt.
+    pub(crate) fn retry_delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        let backoff = Duration::from_secs(1 << min(attempt, 16));
+        min(retry_after.unwrap_or(backoff), self.max_retry_delay)
+    }
+}
+
+fn valid_concurrency(max_concurrent_requests: Option<usize>) -> Option<usize> {
+    max_concurrent_requests.filter(|&max| max > 0)
+}
+
+fn valid_rate(requests_per_second: Option<f64>) -> Option<f64> {
+    requests_per_second.filter(|&rate| rate.is_finite() && rate > 0.0)
+}
+
+/// Parses a `Retry-After` header given in seconds. HTTP dates are not
+/// supported and fall back to the default backoff.
+pub(crate) fn parse_retry_after(value: &str) -> Option<Duration> {
+    value.trim().parse().ok().map(Duration::from_secs)
+}
+
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Enforces a [`RateLimit`].
+pub(crate) struct RateLimiter {
+    pub limit: RateLimit,
+    semaphore: Option<Semaphore>,
+    bucket: Mutex<TokenBucket>,
+    runtime: Arc<dyn Runtime + Send + Sync>,
+}
+
+impl RateLimiter {
+    /// Creates a limiter for `limit`. The fields of [`RateLimit`] are public,
+    /// so invalid values the builder would have refused are treated as
+    /// unlimited here too.
+    pub fn new(mut limit: RateLimit) -> RateLimiter {
+        limit.max_concurrent_requests = valid_concurrency(limit.max_concurrent_requests);
+        limit.requests_per_second = valid_rate(limit.requests_per_second);
+        limit.burst = limit.burst.max(1);
+        RateLimiter {
+            semaphore: limit.max_concurrent_requests.map(Semaphore::new),
+            bucket: Mutex::new(TokenBucket {
+                tokens: limit.burst as f64,
+                updated_at: Instant::now(),
+            }),
+            runtime: default_runtime(),
+            limit,
+        }
+    }
+    /// Waits until a request may be sent. The request counts as in flight
+    /// until the returned permit is dropped.
+    pub async fn acquire(&self) -> Option<SemaphorePermit<'_>> {
+        let permit = match &self.semaphore {
+            Some(semaphore) => semaphore.acquire().await.ok(),
+            None => None,
+        };
+        while let Some(wait) = self.take_token() {
+            self.sleep(wait).await;
+        }
+        permit
+    }
+    /// Takes a token from the bucket, or tells how long until one is left.
+    fn take_token(&self) -> Option<Duration> {
+        let rate = self.limit.requests_per_second?;
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(self.limit.burst as f64);
+        bucket.updated_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+    pub async fn sleep(&self, duration: Duration) {
+        self.runtime.sleep(duration).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        let limit = RateLimit::new().max_retry_delay(Duration::from_secs(10));
+        assert_eq!(limit.retry_delay(0, None), Duration::from_secs(1));
+        assert_eq!(limit.retry_delay(2, None), Duration::from_secs(4));
+        assert_eq!(limit.retry_delay(8, None), Duration::from_secs(10));
+        assert_eq!(
+            limit.retry_delay(0, parse_retry_after(" 3")),
+            Duration::from_secs(3)
+        );
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+    }
+
+    #[tokio::test]
+    async fn test_token_bucket() {
+        let limiter = RateLimiter::new(RateLimit::new().requests_per_second(20.0, 2));
+        let start = Instant::now();
+        for _ in 0..4 {
+            limiter.acquire().await;
+        }
+        // Two requests in the burst, then one every 50ms.
+        assert!(start.elapsed() >= Duration::from_millis(90));
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_cap() {
+        let limiter = RateLimiter::new(RateLimit::new().max_concurrent_requests(1));
+        let permit = limiter.acquire().await;
+        assert!(permit.is_some());
+        assert_eq!(limiter.semaphore.as_ref().unwrap().available_permits(), 0);
+        drop(permit);
+        assert_eq!(limiter.semaphore.as_ref().unwrap().available_permits(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_rate_is_unlimited() {
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert_eq!(
+                RateLimit::new()
+                    .requests_per_second(rate, 1)
+                    .requests_per_second,
+                None
+            );
+            let limiter = RateLimiter::new(RateLimit {
+                requests_per_second: Some(rate),
+                ..RateLimit::default()
+            });
+            for _ in 0..3 {
+                limiter.acquire().await;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_zero_concurrency_is_unlimited() {
+        assert_eq!(
+            RateLimit::new()
+                .max_concurrent_requests(0)
+                .max_concurrent_requests,
+            None
+        );
+        let limiter = RateLimiter::new(RateLimit {
+            max_concurrent_requests: Some(0),
+            ..RateLimit::default()
+        });
+        let first = limiter.acquire().await;
+        let second = limiter.acquire().await;
+        assert!(first.is_none() && second.is_none());
+    }
+}
@@ -0,0 +1,72 @@
+use crate::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use futures_util::future::BoxFuture;
+#[cfg(target_arch = "wasm32")]
+use futures_util::future::LocalBoxFuture as BoxFuture;
+use std::sync::Arc;
+
+/// Runs the background key refresh and provides its timers, so that
+/// [`JwkAuth`](crate::jwk_auth::JwkAuth) can be used with executors other
+/// than tokio, e.g. async-std or smol.
+///
+/// The default [`Fetcher`](crate::jwk_auth::Fetcher) uses reqwest, which
+/// needs a tokio reactor; use a custom fetcher on other executors.
+///
+/// On wasm32, futures need not be `Send`.
+pub trait Runtime {
+    fn spawn(&self, task: BoxFuture<'static, ()>);
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// Spawns onto the current tokio runtime.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioRuntime;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Runtime for TokioRuntime {
+    fn spawn(&self, task: BoxFuture<'static, ()>) {
+        tokio::spawn(task);
+    }
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Spawns onto the runtime of the handle, e.g. a dedicated runtime for
+/// background work, instead of the runtime current when the refresh starts.
+/// Works without a current runtime.
+#[cfg(not(target_arch = "wasm32"))]
+impl Runtime for tokio::runtime::Handle {
+    fn spawn(&self, task: BoxFuture<'static, ()>) {
+        tokio::runtime::Handle::spawn(self, task);
+    }
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        // Timers register with the runtime entered when they are created.
+        let _guard = self.enter();
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Spawns onto the JavaScript event loop, with `setTimeout` based timers.
+#[cfg(target_arch = "wasm32")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WasmRuntime;
+
+#[cfg(target_arch = "wasm32")]
+impl Runtime for WasmRuntime {
+    fn spawn(&self, task: BoxFuture<'static, ()>) {
+        wasm_bindgen_futures::spawn_local(task);
+    }
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(gloo_timers::future::sleep(duration))
+    }
+}
+
+/// [`TokioRuntime`], or [`WasmRuntime`] on wasm32.
+pub(crate) fn default_runtime() -> Arc<dyn Runtime + Send + Sync> {
+    #[cfg(not(target_arch = "wasm32"))]
+    return Arc::new(TokioRuntime);
+    #[cfg(target_arch = "wasm32")]
+    return Arc::new(WasmRuntime);
+}
@@ -0,0 +1,217 @@
+use crate::integration::{
+    FirebaseAuthRejection, FirebaseUser, OptionalFirebaseUser, RejectionResponse,
+};
+use crate::jwk_auth::JwkAuth;
+use crate::token::TokenExtractor;
+use salvo::http::header::WWW_AUTHENTICATE;
+use salvo::http::{HeaderValue, StatusCode};
+use salvo::writing::Json;
+use salvo::{async_trait, Depot, FlowCtrl, Handler, Request, Response, Writer};
+use std::sync::Arc;
+
+/// Answers with the defaults of [`RejectionResponse`].
+#[async_trait]
+impl Writer for FirebaseAuthRejection {
+    async fn write(self, _req: &mut Request, _depot: &mut Depot, res: &mut Response) {
+        let config = RejectionResponse::default();
+        let status =
+            StatusCode::from_u16(config.status_code(&self)).unwrap_or(StatusCode::UNAUTHORIZED);
+        res.status_code(status);
+        if let Some(challenge) = config
+            .www_authenticate(&self)
+            .and_then(|challenge| HeaderValue::from_str(&challenge).ok())
+        {
+            res.headers_mut().insert(WWW_AUTHENTICATE, challenge);
+        }
+        if let Some(body) = config.body(&self) {
+            res.render(Json(body));
+        }
+    }
+}
+
+/// Verifies the token of each request and stores the [`FirebaseUser`] in the
+/// depot, where handlers read it through [`FirebaseAuthDepotExt`].
+#[derive(Clone)]
+pub struct FirebaseAuth {
+    jwk_auth: Arc<JwkAuth>,
+    extractor: TokenExtractor,
+    optional: bool,
+}
+
+impl FirebaseAuth {
+    pub fn new(jwk_auth: Arc<JwkAuth>) -> FirebaseAuth {
+        FirebaseAuth {
+            jwk_auth,
+            extractor: TokenExtractor::default(),
+            optional: false,
+        }
+    }
+    /// Lets requests without a token through unauthenticated, so that
+    /// handlers can take an [`OptionalFirebaseUser`]. Requests with an invalid
+    /// token are still rejected.
+    pub fn optional(mut self, optional: bool) -> FirebaseAuth {
+        self.optional = optional;
+        self
+    }
+    /// Looks for the token where `extractor` does instead of only in the
+    /// `Authorization` header.
+    pub fn token_extractor(mut self, extractor: TokenExtractor) -> FirebaseAuth {
+        self.extractor = extractor;
+        self
+    }
+    async fn firebase_user(&self, req: &Request) -> Result<FirebaseUser, FirebaseAuthRejection> {
+        let token_data = self
+            .extractor
+            .extract_and_verify_async(
+                self.jwk_auth.as_ref(),
+                |name| {
+                    req.headers()
+                        .get(name)
+                        .and_then(|value| value.to_str().ok())
+                },
+                req.uri().query(),
+            )
+            .await?;
+        Ok(FirebaseUser::from(token_data.claims))
+    }
+}
+
+#[async_trait]
+impl Handler for FirebaseAuth {
+    async fn handle(
+        &self,
+        req: &mut Request,
+        depot: &mut Depot,
+        res: &mut Response,
+        ctrl: &mut FlowCtrl,
+    ) {
+        let result = self.firebase_user(req).await;
+        let result = if self.optional {
+            OptionalFirebaseUser::from_result(result)
+        } else {
+            result.map(|user| OptionalFirebaseUser(Some(user)))
+        };
+        match result {
+            Ok(OptionalFirebaseUser(Some(user))) => {
+                depot.inject(user);
+            }
+            Ok(OptionalFirebaseUser(None)) => {}
+            Err(rejection) => {
+                rejection.write(req, depot, res).await;
+                ctrl.skip_rest();
+            }
+        }
+    }
+}
+
+/// Reads the user stored by [`FirebaseAuth`] from the depot.
+pub trait FirebaseAuthDepotExt {
+    /// The authenticated user, or [`FirebaseAuthRejection::MissingToken`] if
+    /// the request went through unauthenticated.
+    fn firebase_user(&self) -> Result<FirebaseUser, FirebaseAuthRejection>;
+    /// The authenticated user, if any.
+    fn optional_firebase_user(&self) -> OptionalFirebaseUser;
+}
+
+impl FirebaseAuthDepotExt for Depot {
+    fn firebase_user(&self) -> Result<FirebaseUser, FirebaseAuthRejection> {
+        self.obtain::<FirebaseUser>()
+            .cloned()
+            .map_err(|_| FirebaseAuthRejection::MissingToken)
+    }
+    fn optional_firebase_user(&self) -> OptionalFirebaseUser {
+        OptionalFirebaseUser(self.firebase_user().ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use salvo::http::header::{AUTHORIZATION, COOKIE};
+    use salvo::test::{ResponseExt, TestClient};
+    use salvo::{handler, Router, Service};
+
+    #[handler]
+    async fn uid(depot: &mut Depot) -> Result<String, FirebaseAuthRejection> {
+        Ok(depot.firebase_user()?.uid)
+    }
+
+    #[handler]
+    async fn optional_uid(depot: &mut Depot) -> String {
+        depot
+            .optional_firebase_user()
+            .0
+            .map_or_else(|| "anonymous".to_string(), |user| user.uid)
+    }
+
+    async fn service(auth: impl FnOnce(FirebaseAuth) -> FirebaseAuth) -> Service {
+        let jwk_auth = get_session_cookie_jwk_auth().await;
+        let router = Router::new()
+            .hoop(auth(FirebaseAuth::new(Arc::new(jwk_auth))))
+            .push(Router::with_path("uid").get(uid))
+            .push(Router::with_path("optional").get(optional_uid));
+        Service::new(router)
+    }
+
+    async fn call(service: &Service, path: &str, authorization: Option<&str>) -> Response {
+        let mut request = TestClient::get(format!("http://127.0.0.1{}", path));
+        if let Some(authorization) = authorization {
+            request = request.add_header(AUTHORIZATION, authorization, true);
+        }
+        request.send(service).await
+    }
+
+    #[tokio::test]
+    async fn test_firebase_user() {
+        let claims = get_test_claims("pj", "https://session.firebase.google.com/pj");
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let service = service(|auth| auth).await;
+        let mut response = call(&service, "/uid", Some(&format!("Bearer {}", token))).await;
+        assert_eq!(response.status_code, Some(StatusCode::OK));
+        assert_eq!(response.take_string().await.unwrap(), "uid");
+    }
+
+    #[tokio::test]
+    async fn test_firebase_user_rejected() {
+        let service = service(|auth| auth).await;
+        let response = call(&service, "/uid", None).await;
+        assert_eq!(response.status_code, Some(StatusCode::UNAUTHORIZED));
+        assert_eq!(response.headers().get(WWW_AUTHENTICATE).unwrap(), "Bearer");
+        let response = call(&service, "/uid", Some("Bearer invalid")).await;
+        assert_eq!(response.status_code, Some(StatusCode::UNAUTHORIZED));
+    }
+
+    #[tokio::test]
+    async fn test_optional_firebase_user() {
+        let claims = get_test_claims("pj", "https://session.firebase.google.com/pj");
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let service = service(|auth| auth.optional(true)).await;
+        let authorization = format!("Bearer {}", token);
+        let mut response = call(&service, "/optional", Some(&authorization)).await;
+        assert_eq!(response.take_string().await.unwrap(), "uid");
+        let mut response = call(&service, "/optional", None).await;
+        assert_eq!(response.status_code, Some(StatusCode::OK));
+        assert_eq!(response.take_string().await.unwrap(), "anonymous");
+        let response = call(&service, "/optional", Some("Bearer invalid")).await;
+        assert_eq!(response.status_code, Some(StatusCode::UNAUTHORIZED));
+        let response = call(&service, "/uid", None).await;
+        assert_eq!(response.status_code, Some(StatusCode::UNAUTHORIZED));
+    }
+
+    #[tokio::test]
+    async fn test_firebase_user_token_extractor() {
+        let claims = get_test_claims("pj", "https://session.firebase.google.com/pj");
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let service = service(|auth| {
+            auth.token_extractor(TokenExtractor::new().cookie("__session".to_string()))
+        })
+        .await;
+        let mut response = TestClient::get("http://127.0.0.1/uid")
+            .add_header(COOKIE, format!("__session={}", token), true)
+            .send(&service)
+            .await;
+        assert_eq!(response.status_code, Some(StatusCode::OK));
+        assert_eq!(response.take_string().await.unwrap(), "uid");
+    }
+}
@@ -0,0 +1,171 @@
+use crate::jwk::{Fetcher, JwkFetcher, Jwks, KeyFetchError};
+use async_trait::async_trait;
+#[cfg(not(target_arch = "wasm32"))]
+use futures_util::future::BoxFuture;
+#[cfg(target_arch = "wasm32")]
+use futures_util::future::LocalBoxFuture as BoxFuture;
+use futures_util::future::{FutureExt, Shared};
+use std::sync::{Arc, Mutex};
+
+type Flight = Shared<BoxFuture<'static, Result<Jwks, Arc<KeyFetchError>>>>;
+
+/// Lets concurrent callers share one fetch: whoever calls while a fetch is in
+/// flight awaits its result instead of starting another, so that the
+/// background refresh, lazy refreshes and unknown kids arriving together
+/// after a key rotation make a single request.
+/// Fresh fetches, which bypass caches, are shared among themselves only.
+pub(crate) struct SingleFlightFetcher {
+    pub fetcher: Arc<dyn Fetcher + Send + Sync>,
+    in_flight: Mutex<Option<Flight>>,
+    fresh_in_flight: Mutex<Option<Flight>>,
+}
+
+impl SingleFlightFetcher {
+    pub fn wrap(fetcher: Arc<dyn Fetcher + Send + Sync>) -> SingleFlightFetcher {
+        SingleFlightFetcher {
+            fetcher,
+            in_flight: Mutex::new(None),
+            fresh_in_flight: Mutex::new(None),
+        }
+    }
+    fn slot(&self, fresh: bool) -> &Mutex<Option<Flight>> {
+        if fresh {
+            &self.fresh_in_flight
+        } else {
+            &self.in_flight
+        }
+    }
+    /// The fetch in flight, or a new one.
+    fn flight(&self, fresh: bool) -> Flight {
+        let mut in_flight = self.slot(fresh).lock().unwrap();
+        if let Some(flight) = &*in_flight {
+            return flight.clone();
+        }
+        let fetcher = Arc::clone(&self.fetcher);
+        let future: BoxFuture<'static, _> = Box::pin(async move {
+            let result = if fresh {
+                fetcher.fetch_fresh_keys().await
+            } else {
+                fetcher.fetch_keys().await
+            };
+            result.map_err(Arc::new)
+        });
+        let flight = future.shared();
+        *in_flight = Some(flight.clone());
+        flight
+    }
+    async fn fetch(&self, fresh: bool) -> Result<Jwks, KeyFetchError> {
+        let flight = self.flight(fresh);
+        let result = flight.clone().await;
+        // Any caller may finish first, e.g. if the one who started the fetch
+        // was cancelled, so whoever does makes way for the next fetch.
+        let mut in_flight = self.slot(fresh).lock().unwrap();
+        if in_flight
+            .as_ref()
+            .is_some_and(|current| Shared::ptr_eq(current, &flight))
+        {
+            *in_flight = None;
+        }
+        drop(in_flight);
+        drop(flight);
+        result.map_err(|error| Arc::try_unwrap(error).unwrap_or_else(KeyFetchError::Shared))
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl Fetcher for SingleFlightFetcher {
+    fn new(url: String) -> Self {
+        SingleFlightFetcher::wrap(Arc::new(JwkFetcher::new(url)))
+    }
+    async fn fetch_keys(&self) -> Result<Jwks, KeyFetchError> {
+        self.fetch(false).await
+    }
+    async fn fetch_fresh_keys(&self) -> Result<Jwks, KeyFetchError> {
+        self.fetch(true).await
+    }
+    fn key_source(&self) -> Option<String> {
+        self.fetcher.key_source()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use crate::time::Duration;
+    use futures_util::future::join_all;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct SlowFetcher {
+        calls: Arc<AtomicUsize>,
+        fail: bool,
+    }
+
+    #[async_trait]
+    impl Fetcher for SlowFetcher {
+        fn new(_url: String) -> Self {
+            SlowFetcher {
+                calls: Arc::new(AtomicUsize::new(0)),
+                fail: false,
+            }
+        }
+        async fn fetch_keys(&self) -> Result<Jwks, KeyFetchError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            if self.fail {
+                return Err(KeyFetchError::StatusError(503));
+            }
+            Ok(Jwks {
+                keys: get_test_keys(),
+                validity: Duration::from_secs(3600),
+                stale_while_revalidate: Duration::ZERO,
+            })
+        }
+    }
+
+    fn single_flight(fail: bool) -> (SingleFlightFetcher, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let fetcher = SingleFlightFetcher::wrap(Arc::new(SlowFetcher {
+            calls: Arc::clone(&calls),
+            fail,
+        }));
+        (fetcher, calls)
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_fetches_are_shared() {
+        let (fetcher, calls) = single_flight(false);
+        let results = join_all((0..10).map(|_| fetcher.fetch_keys())).await;
+        assert!(results
+            .iter()
+            .all(|result| result.as_ref().unwrap().keys == get_test_keys()));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(fetcher.in_flight.lock().unwrap().is_none());
+
+        fetcher.fetch_keys().await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_shared_fetch_errors() {
+        let (fetcher, calls) = single_flight(true);
+        let results = join_all((0..3).map(|_| fetcher.fetch_keys())).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        for result in results {
+            let error = result.unwrap_err();
+            assert_eq!(error.to_string(), "unexpected status: 503");
+            assert!(std::error::Error::source(&error).is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_fetch() {
+        let (fetcher, calls) = single_flight(false);
+        let cancelled = fetcher.fetch_keys();
+        let _ = tokio::time::timeout(Duration::from_millis(10), cancelled).await;
+        fetcher.fetch_keys().await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(fetcher.in_flight.lock().unwrap().is_none());
+    }
+}
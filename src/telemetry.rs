@@ -0,0 +1,450 @@
+//! Metrics and traces about key fetches and verifications.
+//!
+//! With the `metrics` feature, these are emitted through the `metrics` facade:
+//!
+//! - `firebase_auth_key_fetches_total` (counter, `outcome`: `success` or `failure`)
+//! - `firebase_auth_key_fetch_duration_seconds` (histogram)
+//! - `firebase_auth_verifications_total` (counter, `outcome`: `success` or the failure reason)
+//! - `firebase_auth_keys` (gauge, number of keys in use)
+//! - `firebase_auth_keys_age_seconds` (gauge, time since the keys in use were fetched)
+//!
+//! With the `tracing` feature, key fetches run in a `fetch_keys` span, and
+//! failed verifications emit a debug event with the token's `kid`, `issuer`,
+//! `audience` and the failure `reason`.
+//!
+//! Independently of both, [`JwkAuth::on_verification`](crate::jwk_auth::JwkAuth::on_verification)
+//! callbacks get a [`VerificationRecord`] of every verification attempt.
+
+use crate::jwk::{Fetcher, JwkFetcher, Jwks, KeyFetchError};
+use crate::time::{Duration, Instant, SystemTime};
+use crate::verifier::{Audience, VerifyError};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// Records every key fetch of the wrapped fetcher.
+pub(crate) struct InstrumentedFetcher {
+    pub fetcher: Arc<dyn Fetcher + Send + Sync>,
+}
+
+impl InstrumentedFetcher {
+    async fn fetch(&self, fresh: bool) -> Result<Jwks, KeyFetchError> {
+        let fetch = async {
+            let started = Instant::now();
+            let result = if fresh {
+                self.fetcher.fetch_fresh_keys().await
+            } else {
+                self.fetcher.fetch_keys().await
+            };
+            record_fetch(&result, started.elapsed());
+            result
+        };
+        #[cfg(feature = "tracing")]
+        let fetch = tracing::Instrument::instrument(fetch, tracing::info_span!("fetch_keys"));
+        fetch.await
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl Fetcher for InstrumentedFetcher {
+    fn new(url: String) -> Self {
+        InstrumentedFetcher {
+            fetcher: Arc::new(JwkFetcher::new(url)),
+        }
+    }
+    async fn fetch_keys(&self) -> Result<Jwks, KeyFetchError> {
+        self.fetch(false).await
+    }
+    async fn fetch_fresh_keys(&self) -> Result<Jwks, KeyFetchError> {
+        self.fetch(true).await
+    }
+    fn key_source(&self) -> Option<String> {
+        self.fetcher.key_source()
+    }
+}
+
+#[cfg_attr(
+    not(any(feature = "metrics", feature = "tracing")),
+    allow(unused_variables)
+)]
+fn record_fetch(result: &Result<Jwks, KeyFetchError>, latency: Duration) {
+    #[cfg(feature = "metrics")]
+    {
+        let outcome = if result.is_ok() { "success" } else { "failure" };
+        metrics::counter!("firebase_auth_key_fetches_total", "outcome" => outcome).increment(1);
+        metrics::histogram!("firebase_auth_key_fetch_duration_seconds")
+            .record(latency.as_secs_f64());
+    }
+    #[cfg(feature = "tracing")]
+    match result {
+        Ok(jwks) => tracing::debug!(keys = jwks.keys.len(), ?latency, "Fetched JWK Keys"),
+        Err(error) => tracing::debug!(%error, ?latency, "Failed to fetch JWK Keys"),
+    }
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn record_keys(count: usize) {
+    #[cfg(feature = "metrics")]
+    metrics::gauge!("firebase_auth_keys").set(count as f64);
+}
+
+#[cfg_attr(
+    not(all(feature = "metrics", feature = "tracing")),
+    allow(unused_variables)
+)]
+pub(crate) fn record_verification<T>(
+    token: &str,
+    result: &Result<T, VerifyError>,
+    keys_age: Duration,
+) {
+    #[cfg(feature = "metrics")]
+    {
+        let outcome = match result {
+            Ok(_) => "success",
+            Err(error) => failure_reason(error),
+        };
+        metrics::counter!("firebase_auth_verifications_total", "outcome" => outcome).increment(1);
+        metrics::gauge!("firebase_auth_keys_age_seconds").set(keys_age.as_secs_f64());
+    }
+    #[cfg(feature = "tracing")]
+    if let Err(error) = result {
+        let fields = UnverifiedFields::of(token);
+        tracing::debug!(
+            kid = fields.kid.as_deref(),
+            issuer = fields.iss.as_deref(),
+            audience = fields.aud.as_ref().map(|aud| aud.to_string()),
+            reason = failure_reason(error),
+            ?error,
+            "Token verification failed"
+        );
+    }
+}
+
+/// The fields of a token worth logging, read without verifying it.
+#[derive(Debug, Default, serde::Deserialize)]
+struct UnverifiedFields {
+    #[serde(skip)]
+    kid: Option<String>,
+    #[cfg_attr(not(feature = "tracing"), allow(dead_code))]
+    iss: Option<String>,
+    aud: Option<Audience>,
+    sub: Option<String>,
+}
+
+impl UnverifiedFields {
+    fn of(token: &str) -> UnverifiedFields {
+        let fields = crate::verifier::unverified_claims(token).unwrap_or_default();
+        UnverifiedFields {
+            kid: jsonwebtoken::decode_header(token)
+                .ok()
+                .and_then(|header| header.kid),
+            ..fields
+        }
+    }
+}
+
+/// A verification attempt, as passed to
+/// [`JwkAuth::on_verification`](crate::jwk_auth::JwkAuth::on_verification)
+/// callbacks, e.g. to forward to an audit log. The token itself is left out.
+#[derive(Debug, PartialEq, Clone)]
+pub struct VerificationRecord {
+    pub timestamp: SystemTime,
+    /// The `kid` of the token header, if any.
+    pub kid: Option<String>,
+    /// The hex encoded SHA-256 hash of the token's `aud`, so that attempts can
+    /// be grouped by audience without logging it.
+    pub audience_hash: Option<String>,
+    /// Why verification failed; `None` if the token was verified.
+    pub error: Option<VerifyError>,
+    /// The uid of a verified token.
+    pub uid: Option<String>,
+}
+
+impl VerificationRecord {
+    pub(crate) fn of<T>(token: &str, result: &Result<T, VerifyError>) -> VerificationRecord {
+        let fields = UnverifiedFields::of(token);
+        VerificationRecord {
+            timestamp: SystemTime::now(),
+            kid: fields.kid,
+            audience_hash: fields.aud.map(|aud| {
+                let digest =
+                    ring::digest::digest(&ring::digest::SHA256, aud.to_string().as_bytes());
+                digest
+                    .as_ref()
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect()
+            }),
+            error: result.as_ref().err().cloned(),
+            uid: fields.sub.filter(|_| result.is_ok()),
+        }
+    }
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+    /// A short name for the failure, e.g. `expired`, the same as the
+    /// `outcome` label of the `metrics` feature.
+    pub fn failure_reason(&self) -> Option<&'static str> {
+        self.error.as_ref().map(failure_reason)
+    }
+}
+
+/// A label value for `error`, without the details that would make the
+/// label set unbounded.
+fn failure_reason(error: &VerifyError) -> &'static str {
+    match error {
+        VerifyError::Malformed => "malformed",
+        VerifyError::MissingKid => "missing_kid",
+        VerifyError::UnknownKid(_) => "unknown_kid",
+        VerifyError::UnknownKeyAlgorithm => "unknown_key_algorithm",
+        VerifyError::AlgorithmNotAllowed(_) => "algorithm_not_allowed",
+        VerifyError::InvalidKey => "invalid_key",
+        VerifyError::AlgorithmMismatch => "algorithm_mismatch",
+        VerifyError::InvalidSignature => "invalid_signature",
+        VerifyError::Expired => "expired",
+        VerifyError::NotYetValid => "not_yet_valid",
+        VerifyError::WrongAudience => "wrong_audience",
+        VerifyError::WrongIssuer => "wrong_issuer",
+        VerifyError::MissingClaim(_) => "missing_claim",
+        VerifyError::EmailNotVerified => "email_not_verified",
+        VerifyError::SignInProviderNotAllowed(_) => "sign_in_provider_not_allowed",
+        VerifyError::InvalidSubject => "invalid_subject",
+        VerifyError::InvalidAuthTime => "invalid_auth_time",
+        VerifyError::WrongTenant => "wrong_tenant",
+        VerifyError::StaleKeys => "stale_keys",
+        VerifyError::Revoked => "revoked",
+        VerifyError::UserDisabled => "user_disabled",
+        VerifyError::RevocationCheckUnavailable => "revocation_check_unavailable",
+        VerifyError::RevocationCheckFailed(_) => "revocation_check_failed",
+        VerifyError::KeyFetchFailed(_) => "key_fetch_failed",
+        VerifyError::TokenTooLarge => "token_too_large",
+        VerifyError::CriticalHeader => "critical_header",
+        VerifyError::WrongTokenType => "wrong_token_type",
+        VerifyError::MultiFactorRequired => "multi_factor_required",
+    }
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use metrics::{
+        Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName, Metadata,
+        Recorder, SharedString, Unit,
+    };
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Mutex;
+
+    /// Keeps the last value of every metric by name and labels, e.g.
+    /// `firebase_auth_keys` or `firebase_auth_key_fetches_total{outcome=success}`.
+    #[derive(Default)]
+    struct TestRecorder {
+        values: Mutex<HashMap<String, Arc<TestMetric>>>,
+    }
+
+    #[derive(Default)]
+    struct TestMetric(AtomicU64);
+
+    impl CounterFn for TestMetric {
+        fn increment(&self, value: u64) {
+            self.0.fetch_add(value, Ordering::SeqCst);
+        }
+        fn absolute(&self, value: u64) {
+            self.0.store(value, Ordering::SeqCst);
+        }
+    }
+
+    impl GaugeFn for TestMetric {
+        fn increment(&self, _value: f64) {}
+        fn decrement(&self, _value: f64) {}
+        fn set(&self, value: f64) {
+            self.0.store(value as u64, Ordering::SeqCst);
+        }
+    }
+
+    impl HistogramFn for TestMetric {
+        fn record(&self, _value: f64) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    impl TestRecorder {
+        fn metric(&self, key: &Key) -> Arc<TestMetric> {
+            let labels: Vec<String> = key
+                .labels()
+                .map(|label| format!("{}={}", label.key(), label.value()))
+                .collect();
+            let name = if labels.is_empty() {
+                key.name().to_string()
+            } else {
+                format!("{}{{{}}}", key.name(), labels.join(","))
+            };
+            let mut values = self.values.lock().unwrap();
+            Arc::clone(values.entry(name).or_default())
+        }
+        fn value(&self, name: &str) -> Option<u64> {
+            let values = self.values.lock().unwrap();
+            values
+                .get(name)
+                .map(|metric| metric.0.load(Ordering::SeqCst))
+        }
+    }
+
+    impl Recorder for TestRecorder {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+            Counter::from_arc(self.metric(key))
+        }
+        fn register_gauge(&self, key: &Key, _: &Metadata<'_>) -> Gauge {
+            Gauge::from_arc(self.metric(key))
+        }
+        fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+            Histogram::from_arc(self.metric(key))
+        }
+    }
+
+    struct TestFetcher {
+        fail: bool,
+    }
+
+    #[async_trait]
+    impl Fetcher for TestFetcher {
+        fn new(_url: String) -> Self {
+            TestFetcher { fail: false }
+        }
+        async fn fetch_keys(&self) -> Result<Jwks, KeyFetchError> {
+            if self.fail {
+                return Err(KeyFetchError::Custom("unavailable".into()));
+            }
+            Ok(Jwks {
+                keys: get_test_keys(),
+                validity: Duration::from_secs(3600),
+                stale_while_revalidate: Duration::ZERO,
+            })
+        }
+    }
+
+    #[test]
+    fn test_record_verification() {
+        let recorder = TestRecorder::default();
+        metrics::with_local_recorder(&recorder, || {
+            record_verification("token", &Ok(()), Duration::from_secs(42));
+            let expired = Err::<(), _>(VerifyError::Expired);
+            record_verification("token", &expired, Duration::from_secs(43));
+            record_verification("token", &expired, Duration::from_secs(44));
+            record_keys(2);
+        });
+        assert_eq!(
+            recorder.value("firebase_auth_verifications_total{outcome=success}"),
+            Some(1)
+        );
+        assert_eq!(
+            recorder.value("firebase_auth_verifications_total{outcome=expired}"),
+            Some(2)
+        );
+        assert_eq!(recorder.value("firebase_auth_keys_age_seconds"), Some(44));
+        assert_eq!(recorder.value("firebase_auth_keys"), Some(2));
+    }
+
+    #[test]
+    fn test_instrumented_fetcher() {
+        let recorder = TestRecorder::default();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        metrics::with_local_recorder(&recorder, || {
+            runtime.block_on(async {
+                for fail in [false, false, true] {
+                    let fetcher = InstrumentedFetcher {
+                        fetcher: Arc::new(TestFetcher { fail }),
+                    };
+                    assert_eq!(fetcher.fetch_keys().await.is_err(), fail);
+                }
+            })
+        });
+        assert_eq!(
+            recorder.value("firebase_auth_key_fetches_total{outcome=success}"),
+            Some(2)
+        );
+        assert_eq!(
+            recorder.value("firebase_auth_key_fetches_total{outcome=failure}"),
+            Some(1)
+        );
+        assert_eq!(
+            recorder.value("firebase_auth_key_fetch_duration_seconds"),
+            Some(3)
+        );
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tracing_tests {
+    use super::*;
+    use crate::tests::*;
+    use std::collections::HashMap;
+    use std::fmt;
+    use std::sync::Mutex;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    /// Keeps the fields of every event as strings.
+    #[derive(Default)]
+    struct TestSubscriber {
+        events: Arc<Mutex<Vec<HashMap<String, String>>>>,
+    }
+
+    struct FieldVisitor<'a>(&'a mut HashMap<String, String>);
+
+    impl Visit for FieldVisitor<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+    }
+
+    impl Subscriber for TestSubscriber {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, _: &Attributes<'_>) -> Id {
+            Id::from_u64(1)
+        }
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, event: &Event<'_>) {
+            let mut fields = HashMap::new();
+            event.record(&mut FieldVisitor(&mut fields));
+            self.events.lock().unwrap().push(fields);
+        }
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn test_record_verification_failure() {
+        let subscriber = TestSubscriber::default();
+        let events = Arc::clone(&subscriber.events);
+        let token = create_token(Some(SIGNING_KID), &get_test_claims("aud", "iss"));
+        tracing::subscriber::with_default(subscriber, || {
+            record_verification(&token, &Ok(()), Duration::from_secs(1));
+            let expired = Err::<(), _>(VerifyError::Expired);
+            record_verification(&token, &expired, Duration::from_secs(1));
+            record_verification("malformed", &expired, Duration::from_secs(1));
+        });
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["kid"], SIGNING_KID);
+        assert_eq!(events[0]["issuer"], "iss");
+        assert_eq!(events[0]["audience"], "aud");
+        assert_eq!(events[0]["reason"], "expired");
+        assert!(!events[1].contains_key("kid"));
+        assert_eq!(events[1]["reason"], "expired");
+    }
+}
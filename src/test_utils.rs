@@ -0,0 +1,283 @@
+//! Signed ID tokens and a fake key server for testing code that verifies
+//! tokens, e.g. auth middleware, without a Firebase project.
+//!
+//! ```ignore
+//! let issuer = TestIdTokenIssuer::new("my-project");
+//! let auth = JwkAuth::builder()
+//!     .project_id("my-project".to_string())
+//!     .http_backend(issuer.key_server())
+//!     .build()
+//!     .await?;
+//! assert!(auth.verify(&issuer.token("uid")).is_ok());
+//! ```
+
+use crate::http::{HttpClient, HttpError, HttpResponse};
+use crate::jwk::{Jwk, JwkParams};
+use crate::jwk_auth::TokenVerifier;
+use crate::time::{SystemTime, UNIX_EPOCH};
+use crate::verifier::{Claims, DecodedToken, FirebaseClaims, VerifyError};
+use async_trait::async_trait;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use openssl::rsa::Rsa;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+
+const KEY_BITS: u32 = 2048;
+const TOKEN_LIFETIME: i64 = 3600;
+
+fn unix_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+fn base64url(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+/// Mints ID tokens of a project the way Firebase Auth does, signed with an
+/// RSA key generated for this issuer alone, so that tokens of one issuer are
+/// never accepted with the keys of another.
+#[derive(Clone)]
+pub struct TestIdTokenIssuer {
+    project_id: String,
+    kid: String,
+    key: EncodingKey,
+    modulus: String,
+    exponent: String,
+}
+
+impl fmt::Debug for TestIdTokenIssuer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TestIdTokenIssuer")
+            .field("project_id", &self.project_id)
+            .field("kid", &self.kid)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TestIdTokenIssuer {
+    /// Generates a key pair, whose kid is derived from its public key.
+    pub fn new(project_id: &str) -> TestIdTokenIssuer {
+        let rsa = Rsa::generate(KEY_BITS).expect("an RSA key can be generated");
+        let der = rsa
+            .private_key_to_der()
+            .expect("the generated key can be encoded");
+        let modulus = rsa.n().to_vec();
+        let fingerprint = ring::digest::digest(&ring::digest::SHA256, &modulus);
+        TestIdTokenIssuer {
+            project_id: project_id.to_string(),
+            kid: format!("test-{}", base64url(&fingerprint.as_ref()[..12])),
+            key: EncodingKey::from_rsa_der(&der),
+            modulus: base64url(&modulus),
+            exponent: base64url(&rsa.e().to_vec()),
+        }
+    }
+    /// The id of the key tokens are signed with.
+    pub fn kid(&self) -> &str {
+        &self.kid
+    }
+    /// The keys to verify the tokens with, e.g. for
+    /// [`JwkAuth::with_keys`](crate::jwk_auth::JwkAuth::with_keys).
+    pub fn keys(&self) -> Vec<Jwk> {
+        vec![Jwk {
+            alg: Some("RS256".to_string()),
+            kid: self.kid.clone(),
+            r#use: Some("sig".to_string()),
+            params: JwkParams::Rsa {
+                n: self.modulus.clone(),
+                e: self.exponent.clone(),
+            },
+        }]
+    }
+    /// The keys as served by the key server: `{"keys": [...]}`.
+    pub fn jwks(&self) -> String {
+        serde_json::json!({ "keys": self.keys() }).to_string()
+    }
+    /// An [`HttpClient`] answering every request with [`jwks`](Self::jwks), for
+    /// [`JwkAuthBuilder::http_backend`](crate::jwk_auth::JwkAuthBuilder::http_backend).
+    pub fn key_server(&self) -> TestKeyServer {
+        TestKeyServer { jwks: self.jwks() }
+    }
+    /// The claims of a valid token of `uid`, issued now, to adjust before
+    /// [`sign`](Self::sign)ing them.
+    pub fn claims(&self, uid: &str) -> Claims {
+        let now = unix_timestamp();
+        Claims {
+            aud: self.project_id.clone().into(),
+            exp: now + TOKEN_LIFETIME,
+            iss: format!("https://securetoken.google.com/{}", self.project_id),
+            sub: uid.to_string(),
+            iat: now,
+            auth_time: Some(now),
+            firebase: Some(FirebaseClaims {
+                sign_in_provider: "password".to_string(),
+                ..FirebaseClaims::default()
+            }),
+            ..Claims::default()
+        }
+    }
+    /// Signs any claims, e.g. ones missing required fields.
+    pub fn sign<C: Serialize>(&self, claims: &C) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(self.kid.clone());
+        encode(&header, claims, &self.key).expect("claims can be serialized")
+    }
+    pub fn token(&self, uid: &str) -> String {
+        self.sign(&self.claims(uid))
+    }
+    /// A token that expired an hour ago.
+    pub fn expired_token(&self, uid: &str) -> String {
+        let mut claims = self.claims(uid);
+        claims.iat -= 2 * TOKEN_LIFETIME;
+        claims.auth_time = Some(claims.iat);
+        claims.exp = claims.iat + TOKEN_LIFETIME;
+        self.sign(&claims)
+    }
+    /// A token issued to another project.
+    pub fn wrong_audience_token(&self, uid: &str) -> String {
+        let mut claims = self.claims(uid);
+        claims.aud = format!("not-{}", self.project_id).into();
+        self.sign(&claims)
+    }
+}
+
+/// Serves the keys of a [`TestIdTokenIssuer`] without any network.
+#[derive(Debug, Clone)]
+pub struct TestKeyServer {
+    jwks: String,
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl HttpClient for TestKeyServer {
+    async fn get(&self, _url: &str) -> Result<HttpResponse, HttpError> {
+        let headers = HashMap::from([
+            ("content-type".to_string(), "application/json".to_string()),
+            (
+                "cache-control".to_string(),
+                format!("public, max-age={}", TOKEN_LIFETIME),
+            ),
+        ]);
+        Ok(HttpResponse {
+            status: 200,
+            headers,
+            body: self.jwks.clone().into_bytes(),
+        })
+    }
+}
+
+/// A [`TokenVerifier`] with canned results, for testing code that verifies
+/// tokens without keys or a runtime. Tokens not set up are rejected with
+/// `VerifyError::InvalidSignature`.
+#[derive(Debug, Clone, Default)]
+pub struct MockTokenVerifier {
+    results: HashMap<String, Result<Claims, VerifyError>>,
+}
+
+impl MockTokenVerifier {
+    pub fn new() -> MockTokenVerifier {
+        MockTokenVerifier::default()
+    }
+    /// Verifies `token` as a token with `claims`.
+    pub fn accept(mut self, token: &str, claims: Claims) -> Self {
+        self.results.insert(token.to_string(), Ok(claims));
+        self
+    }
+    /// Fails to verify `token` with `error`.
+    pub fn reject(mut self, token: &str, error: VerifyError) -> Self {
+        self.results.insert(token.to_string(), Err(error));
+        self
+    }
+}
+
+impl TokenVerifier for MockTokenVerifier {
+    fn verify(&self, token: &str) -> Result<DecodedToken<Claims>, VerifyError> {
+        match self.results.get(token) {
+            Some(result) => result.clone().map(|claims| DecodedToken {
+                header: Header::new(Algorithm::RS256).into(),
+                claims,
+            }),
+            None => Err(VerifyError::InvalidSignature),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jwk_auth::JwkAuth;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_issuer_tokens() {
+        let issuer = TestIdTokenIssuer::new("pj");
+        let auth = JwkAuth::with_keys(issuer.keys(), "pj".to_string()).unwrap();
+        let token_data = auth.verify(&issuer.token("uid")).unwrap();
+        assert_eq!(token_data.claims.sub, "uid");
+        assert_eq!(
+            auth.verify(&issuer.expired_token("uid")).unwrap_err(),
+            VerifyError::Expired
+        );
+        assert_eq!(
+            auth.verify(&issuer.wrong_audience_token("uid"))
+                .unwrap_err(),
+            VerifyError::WrongAudience
+        );
+    }
+
+    #[tokio::test]
+    async fn test_key_server() {
+        let issuer = TestIdTokenIssuer::new("pj");
+        let auth = JwkAuth::builder()
+            .project_id("pj".to_string())
+            .http_backend(issuer.key_server())
+            .periodic_refresh(false)
+            .build()
+            .await
+            .unwrap();
+        assert!(auth.verify(&issuer.token("uid")).is_ok());
+        assert_eq!(auth.key_ids(), vec![issuer.kid().to_string()]);
+    }
+
+    #[test]
+    fn test_issuers_do_not_share_keys() {
+        let issuer = TestIdTokenIssuer::new("pj");
+        let other = TestIdTokenIssuer::new("pj");
+        assert_ne!(issuer.kid(), other.kid());
+        let mut keys = other.keys();
+        keys[0].kid = issuer.kid().to_string();
+        let auth = JwkAuth::with_keys(keys, "pj".to_string()).unwrap();
+        assert_eq!(
+            auth.verify(&issuer.token("uid")).unwrap_err(),
+            VerifyError::InvalidSignature
+        );
+    }
+
+    fn uid(verifier: &dyn TokenVerifier, token: &str) -> Result<String, VerifyError> {
+        verifier
+            .verify(token)
+            .map(|token_data| token_data.claims.sub)
+    }
+
+    #[test]
+    fn test_mock_token_verifier() {
+        let issuer = TestIdTokenIssuer::new("pj");
+        let mock = MockTokenVerifier::new()
+            .accept("good", issuer.claims("uid"))
+            .reject("revoked", VerifyError::Revoked);
+        assert_eq!(uid(&mock, "good"), Ok("uid".to_string()));
+        assert_eq!(uid(&mock, "revoked"), Err(VerifyError::Revoked));
+        assert_eq!(uid(&mock, "unknown"), Err(VerifyError::InvalidSignature));
+
+        let auth = Arc::new(JwkAuth::with_keys(issuer.keys(), "pj".to_string()).unwrap());
+        assert_eq!(uid(&auth, &issuer.token("uid")), Ok("uid".to_string()));
+        let tenant = auth.for_tenant("tenant-1".to_string());
+        assert_eq!(
+            uid(&tenant, &issuer.token("uid")),
+            Err(VerifyError::WrongTenant)
+        );
+    }
+}
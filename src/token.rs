@@ -0,0 +1,336 @@
+//! Helpers to pull ID tokens and session cookies out of HTTP requests.
+
+use crate::integration::FirebaseAuthRejection;
+use crate::jwk_auth::{Claims, DecodedToken, JwkAuth, TokenVerifier};
+use std::borrow::Cow;
+
+/// Returns the token of an `Authorization: Bearer <token>` header value.
+/// The scheme is matched case-insensitively.
+pub fn extract_bearer_token(header: &str) -> Option<&str> {
+    extract_token(header, Some("Bearer"))
+}
+
+/// Returns the token of an `Authorization` header value. With a `scheme` the
+/// header must be `<scheme> <token>`, matched case-insensitively; without one
+/// any scheme, or none at all, is accepted.
+pub fn extract_token<'a>(header: &'a str, scheme: Option<&str>) -> Option<&'a str> {
+    let header = header.trim();
+    let token = match (header.split_once(' '), scheme) {
+        (Some((actual, token)), Some(expected)) if actual.eq_ignore_ascii_case(expected) => token,
+        (_, Some(_)) => return None,
+        (Some((_, token)), None) => token,
+        (None, None) => header,
+    };
+    let token = token.trim();
+    if token.is_empty() || token.contains(' ') {
+        return None;
+    }
+    Some(token)
+}
+
+/// Returns the value of the cookie `name` in a `Cookie` header value, e.g. a
+/// session cookie created with `IdentityToolkit::create_session_cookie`.
+pub fn extract_cookie<'a>(header: &'a str, name: &str) -> Option<&'a str> {
+    header
+        .split(';')
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.trim_matches('"'))
+        .filter(|value| !value.is_empty())
+}
+
+/// Returns the percent-decoded value of the parameter `name` in a query
+/// string, without the leading `?`. The value is only copied if it had to be
+/// decoded.
+pub fn extract_query_param<'a>(query: &'a str, name: &str) -> Option<Cow<'a, str>> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .and_then(|(_, value)| percent_decode(value))
+        .filter(|value| !value.is_empty())
+}
+
+fn percent_decode(value: &str) -> Option<Cow<'_, str>> {
+    if !value.contains(['%', '+']) {
+        return Some(Cow::Borrowed(value));
+    }
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut input = value.bytes();
+    while let Some(byte) = input.next() {
+        match byte {
+            b'%' => {
+                let hex = [input.next()?, input.next()?];
+                let hex = std::str::from_utf8(&hex).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            b'+' => bytes.push(b' '),
+            byte => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).ok().map(Cow::Owned)
+}
+
+/// A place in a request to look for a token.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum TokenLocation {
+    /// An `Authorization: Bearer <token>` header.
+    Bearer,
+    /// A cookie, e.g. `__session`, the only cookie Firebase Hosting passes
+    /// on to Cloud Functions and Cloud Run.
+    Cookie(String),
+    /// A query parameter, e.g. for WebSocket handshakes, which browsers send
+    /// without an `Authorization` header.
+    Query(String),
+}
+
+/// Looks for a token in several places of a request, taking the first one
+/// found. By default only the bearer token is looked at.
+///
+/// ```ignore
+/// let extractor = TokenExtractor::new()
+///     .bearer()
+///     .cookie("__session".to_string());
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TokenExtractor {
+    locations: Vec<TokenLocation>,
+}
+
+impl Default for TokenExtractor {
+    fn default() -> Self {
+        TokenExtractor {
+            locations: vec![TokenLocation::Bearer],
+        }
+    }
+}
+
+impl TokenExtractor {
+    /// An extractor looking nowhere yet; add locations in order of priority.
+    pub fn new() -> TokenExtractor {
+        TokenExtractor {
+            locations: Vec::new(),
+        }
+    }
+    pub fn bearer(self) -> Self {
+        self.location(TokenLocation::Bearer)
+    }
+    pub fn cookie(self, name: String) -> Self {
+        self.location(TokenLocation::Cookie(name))
+    }
+    pub fn query(self, name: String) -> Self {
+        self.location(TokenLocation::Query(name))
+    }
+    pub fn location(mut self, location: TokenLocation) -> Self {
+        self.locations.push(location);
+        self
+    }
+    /// Returns the first token found, given a lookup of header values by
+    /// lowercase name and the query string of the request, if any. The token
+    /// borrows from the request unless it had to be percent-decoded.
+    pub fn extract<'a, H>(&self, header: H, query: Option<&'a str>) -> Option<Cow<'a, str>>
+    where
+        H: Fn(&str) -> Option<&'a str>,
+    {
+        self.locations.iter().find_map(|location| match location {
+            TokenLocation::Bearer => header("authorization")
+                .and_then(extract_bearer_token)
+                .map(Cow::Borrowed),
+            TokenLocation::Cookie(name) => header("cookie")
+                .and_then(|cookie| extract_cookie(cookie, name))
+                .map(Cow::Borrowed),
+            TokenLocation::Query(name) => query.and_then(|query| extract_query_param(query, name)),
+        })
+    }
+    /// Extracts the token like [`extract`](Self::extract) and verifies it.
+    pub fn extract_and_verify<'a, H, V>(
+        &self,
+        verifier: &V,
+        header: H,
+        query: Option<&'a str>,
+    ) -> Result<DecodedToken<Claims>, FirebaseAuthRejection>
+    where
+        H: Fn(&str) -> Option<&'a str>,
+        V: TokenVerifier + ?Sized,
+    {
+        let token = self
+            .extract(header, query)
+            .ok_or(FirebaseAuthRejection::MissingToken)?;
+        verifier
+            .verify(&token)
+            .map_err(FirebaseAuthRejection::InvalidToken)
+    }
+    /// Like [`extract_and_verify`](Self::extract_and_verify), but verifies
+    /// with [`JwkAuth::verify_async`], so that expired keys are refreshed
+    /// in `lazy_refresh` mode.
+    pub async fn extract_and_verify_async<'a, H>(
+        &self,
+        jwk_auth: &JwkAuth,
+        header: H,
+        query: Option<&'a str>,
+    ) -> Result<DecodedToken<Claims>, FirebaseAuthRejection>
+    where
+        H: Fn(&str) -> Option<&'a str>,
+    {
+        let token = self
+            .extract(header, query)
+            .ok_or(FirebaseAuthRejection::MissingToken)?;
+        jwk_auth
+            .verify_async(&token)
+            .await
+            .map_err(FirebaseAuthRejection::InvalidToken)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jwk_auth::{JwkAuth, VerifyError};
+    use crate::tests::*;
+
+    #[test]
+    fn test_extract_bearer_token() {
+        assert_eq!(extract_bearer_token("Bearer token"), Some("token"));
+        assert_eq!(extract_bearer_token("bearer  token "), Some("token"));
+        assert_eq!(extract_bearer_token("BEARER token"), Some("token"));
+        assert_eq!(extract_bearer_token("Basic token"), None);
+        assert_eq!(extract_bearer_token("Bearertoken"), None);
+        assert_eq!(extract_bearer_token("Bearer "), None);
+        assert_eq!(extract_bearer_token("Bearer a b"), None);
+        assert_eq!(extract_bearer_token("token"), None);
+        assert_eq!(extract_bearer_token(""), None);
+    }
+
+    #[test]
+    fn test_extract_token() {
+        assert_eq!(
+            extract_token("Firebase token", Some("firebase")),
+            Some("token")
+        );
+        assert_eq!(extract_token("Bearer token", Some("Firebase")), None);
+        assert_eq!(extract_token("Bearer token", None), Some("token"));
+        assert_eq!(extract_token("token", None), Some("token"));
+        assert_eq!(extract_token(" ", None), None);
+    }
+
+    #[test]
+    fn test_extract_cookie() {
+        let header = "theme=dark; session=abc.def.ghi; empty=";
+        assert_eq!(extract_cookie(header, "session"), Some("abc.def.ghi"));
+        assert_eq!(
+            extract_cookie("session=\"quoted\"", "session"),
+            Some("quoted")
+        );
+        assert_eq!(extract_cookie(header, "empty"), None);
+        assert_eq!(extract_cookie(header, "missing"), None);
+    }
+
+    #[test]
+    fn test_extract_query_param() {
+        let query = "a=1&token=abc.def%2Dghi&empty=";
+        assert_eq!(
+            extract_query_param(query, "token").as_deref(),
+            Some("abc.def-ghi")
+        );
+        assert!(matches!(
+            extract_query_param("token=abc.def.ghi", "token"),
+            Some(Cow::Borrowed("abc.def.ghi"))
+        ));
+        assert_eq!(
+            extract_query_param("token=a+b", "token").as_deref(),
+            Some("a b")
+        );
+        assert_eq!(extract_query_param(query, "empty"), None);
+        assert_eq!(extract_query_param(query, "missing"), None);
+        assert_eq!(extract_query_param("token=%zz", "token"), None);
+    }
+
+    fn headers<'a>(headers: &'a [(&str, &'a str)]) -> impl Fn(&str) -> Option<&'a str> + 'a {
+        move |name| {
+            headers
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| *value)
+        }
+    }
+
+    #[test]
+    fn test_token_extractor_priority() {
+        let extractor = TokenExtractor::new()
+            .bearer()
+            .cookie("__session".to_string())
+            .query("token".to_string());
+        let all = [
+            ("authorization", "Bearer from-header"),
+            ("cookie", "__session=from-cookie"),
+        ];
+        assert!(matches!(
+            extractor.extract(headers(&all), Some("token=from-query")),
+            Some(Cow::Borrowed("from-header"))
+        ));
+        assert!(matches!(
+            extractor.extract(headers(&all[1..]), Some("token=from-query")),
+            Some(Cow::Borrowed("from-cookie"))
+        ));
+        assert!(matches!(
+            extractor.extract(headers(&[]), Some("token=from-query")),
+            Some(Cow::Borrowed("from-query"))
+        ));
+        assert_eq!(extractor.extract(headers(&[]), None), None);
+        assert_eq!(
+            TokenExtractor::default().extract(headers(&all[1..]), None),
+            None
+        );
+    }
+
+    #[test]
+    fn test_extract_and_verify() {
+        let jwk_auth = JwkAuth::with_keys(vec![get_signing_key()], "pj".to_string()).unwrap();
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", "https://securetoken.google.com/pj"),
+        );
+        let cookie = format!("__session={}", token);
+        let cookie_headers = [("cookie", cookie.as_str())];
+        let extractor = TokenExtractor::new().cookie("__session".to_string());
+        let token_data = extractor
+            .extract_and_verify(&jwk_auth, headers(&cookie_headers), None)
+            .unwrap();
+        assert_eq!(token_data.claims.sub, "uid");
+        assert_eq!(
+            extractor
+                .extract_and_verify(&jwk_auth, headers(&[]), None)
+                .unwrap_err(),
+            FirebaseAuthRejection::MissingToken
+        );
+        assert_eq!(
+            extractor
+                .extract_and_verify(&jwk_auth, headers(&[("cookie", "__session=x")]), None)
+                .unwrap_err(),
+            FirebaseAuthRejection::InvalidToken(VerifyError::Malformed)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_extract_and_verify_async() {
+        let jwk_auth = JwkAuth::with_keys(vec![get_signing_key()], "pj".to_string()).unwrap();
+        let token = create_token(
+            Some(SIGNING_KID),
+            &get_test_claims("pj", "https://securetoken.google.com/pj"),
+        );
+        let query = format!("token={}", token);
+        let extractor = TokenExtractor::new().query("token".to_string());
+        let token_data = extractor
+            .extract_and_verify_async(&jwk_auth, headers(&[]), Some(&query))
+            .await
+            .unwrap();
+        assert_eq!(token_data.claims.sub, "uid");
+        assert_eq!(
+            extractor
+                .extract_and_verify_async(&jwk_auth, headers(&[]), None)
+                .await
+                .unwrap_err(),
+            FirebaseAuthRejection::MissingToken
+        );
+    }
+}
@@ -0,0 +1,154 @@
+//! Results of recent verifications, so that verifying the same token again,
+//! e.g. on every request of a client, skips the signature check.
+
+use crate::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::verifier::{Claims, DecodedToken, TokenHeader};
+use lru::LruCache;
+use ring::digest::{digest, SHA256};
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+/// Tokens are identified by their SHA-256 hash rather than kept around.
+type TokenHash = [u8; 32];
+
+struct Entry {
+    header: TokenHeader,
+    claims: Claims,
+}
+
+/// Holds up to `capacity` verified tokens, evicting the least recently used
+/// token when full. Expired tokens are dropped when looked up.
+pub(crate) struct TokenCache {
+    /// `None` with a capacity of zero.
+    entries: Option<Mutex<LruCache<TokenHash, Entry>>>,
+    /// The leeway the tokens were verified with, so that a token is served
+    /// from the cache for as long as verifying it would succeed.
+    leeway: Duration,
+}
+
+fn hash(token: &str) -> TokenHash {
+    let mut hash = [0; 32];
+    hash.copy_from_slice(digest(&SHA256, token.as_bytes()).as_ref());
+    hash
+}
+
+fn unix_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+impl TokenCache {
+    pub(crate) fn new(capacity: usize, leeway: Duration) -> TokenCache {
+        TokenCache {
+            entries: NonZeroUsize::new(capacity)
+                .map(|capacity| Mutex::new(LruCache::new(capacity))),
+            leeway,
+        }
+    }
+    fn is_expired(&self, claims: &Claims) -> bool {
+        claims.exp + (self.leeway.as_secs() as i64) < unix_timestamp()
+    }
+    /// The result of verifying `token`, unless it was not verified recently
+    /// or has expired since.
+    pub(crate) fn get(&self, token: &str) -> Option<DecodedToken<Claims>> {
+        let key = hash(token);
+        let mut entries = self.entries.as_ref()?.lock().unwrap();
+        let entry = entries.get(&key)?;
+        if self.is_expired(&entry.claims) {
+            entries.pop(&key);
+            return None;
+        }
+        Some(DecodedToken {
+            header: entry.header.clone(),
+            claims: entry.claims.clone(),
+        })
+    }
+    pub(crate) fn insert(&self, token: &str, token_data: &DecodedToken<Claims>) {
+        let entries = match &self.entries {
+            Some(entries) if !self.is_expired(&token_data.claims) => entries,
+            _ => return,
+        };
+        let entry = Entry {
+            header: token_data.header.clone(),
+            claims: token_data.claims.clone(),
+        };
+        entries.lock().unwrap().put(hash(token), entry);
+    }
+    #[cfg(any(feature = "reqwest", test))]
+    pub(crate) fn remove(&self, token: &str) {
+        if let Some(entries) = &self.entries {
+            entries.lock().unwrap().pop(&hash(token));
+        }
+    }
+    pub(crate) fn clear(&self) {
+        if let Some(entries) = &self.entries {
+            entries.lock().unwrap().clear();
+        }
+    }
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.entries
+            .as_ref()
+            .map_or(0, |entries| entries.lock().unwrap().len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+
+    fn token_data(exp: i64) -> DecodedToken<Claims> {
+        let mut claims = get_test_claims("aud", "iss");
+        claims.exp = exp;
+        DecodedToken {
+            header: jsonwebtoken::Header::default().into(),
+            claims,
+        }
+    }
+
+    #[test]
+    fn test_token_cache_get_and_expiry() {
+        let cache = TokenCache::new(10, Duration::ZERO);
+        let valid = token_data(now() + 3600);
+        cache.insert("valid", &valid);
+        cache.insert("expired", &token_data(now() - 1));
+        assert_eq!(cache.get("valid").unwrap().claims, valid.claims);
+        assert!(cache.get("expired").is_none());
+        assert!(cache.get("unknown").is_none());
+        assert_eq!(cache.len(), 1);
+
+        cache.remove("valid");
+        assert!(cache.get("valid").is_none());
+    }
+
+    #[test]
+    fn test_token_cache_leeway() {
+        let cache = TokenCache::new(10, Duration::from_secs(60));
+        let expired = token_data(now() - 30);
+        cache.insert("within leeway", &expired);
+        assert_eq!(cache.get("within leeway").unwrap().claims, expired.claims);
+        cache.insert("past leeway", &token_data(now() - 90));
+        assert!(cache.get("past leeway").is_none());
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_token_cache_evicts_least_recently_used() {
+        let cache = TokenCache::new(2, Duration::ZERO);
+        cache.insert("a", &token_data(now() + 3600));
+        cache.insert("b", &token_data(now() + 3600));
+        assert!(cache.get("a").is_some());
+        cache.insert("c", &token_data(now() + 3600));
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+
+        cache.clear();
+        assert_eq!(cache.len(), 0);
+        assert!(TokenCache::new(0, Duration::ZERO).get("a").is_none());
+    }
+}
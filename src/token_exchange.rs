@@ -0,0 +1,337 @@
+//! Signing in the way client SDKs do, with a project's Web API key: exchanging
+//! a custom token for an ID token and a refresh token, and refreshing ID
+//! tokens. Meant for integration tests and service-to-service calls that
+//! need a real ID token, e.g. one minted from a
+//! [`CustomTokenCreator`](crate::custom_token::CustomTokenCreator) token.
+
+use crate::http::{api_client, DEFAULT_REQUEST_TIMEOUT};
+use crate::identity_toolkit::{error_message, server_code};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use thiserror::Error;
+
+const IDENTITY_TOOLKIT_URL: &str = "https://identitytoolkit.googleapis.com/v1";
+const SECURE_TOKEN_URL: &str = "https://securetoken.googleapis.com/v1";
+
+#[derive(Debug, Error)]
+pub enum TokenExchangeError {
+    #[error("request failed: {0}")]
+    RequestError(#[source] reqwest::Error),
+    #[error("invalid response body: {0}")]
+    ResponseBodyError(#[source] reqwest::Error),
+    #[error("token exchange error ({status}): {message}")]
+    ApiError { status: u16, message: String },
+    #[error("invalid custom token")]
+    InvalidCustomToken,
+    /// The refresh token is malformed, expired or revoked.
+    #[error("invalid refresh token")]
+    InvalidRefreshToken,
+    #[error("user disabled")]
+    UserDisabled,
+    #[error("user not found")]
+    UserNotFound,
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+}
+
+impl TokenExchangeError {
+    /// Maps the error code at the start of an API error message to a typed
+    /// error where one exists.
+    fn from_api_error(status: u16, message: String) -> TokenExchangeError {
+        match server_code(&message) {
+            Some("INVALID_CUSTOM_TOKEN") | Some("CREDENTIAL_MISMATCH") => {
+                TokenExchangeError::InvalidCustomToken
+            }
+            Some("INVALID_REFRESH_TOKEN") | Some("TOKEN_EXPIRED") => {
+                TokenExchangeError::InvalidRefreshToken
+            }
+            Some("USER_DISABLED") => TokenExchangeError::UserDisabled,
+            Some("USER_NOT_FOUND") => TokenExchangeError::UserNotFound,
+            _ => TokenExchangeError::ApiError { status, message },
+        }
+    }
+}
+
+/// An ID token along with the refresh token to get the next one with.
+#[derive(Debug, PartialEq, Clone)]
+pub struct IdTokenResponse {
+    pub id_token: String,
+    pub refresh_token: String,
+    /// How long the ID token is valid for, usually an hour.
+    pub expires_in: Duration,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SignInWithCustomTokenRequest<'a> {
+    token: &'a str,
+    return_secure_token: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tenant_id: Option<&'a str>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SignInWithCustomTokenResponse {
+    id_token: String,
+    refresh_token: String,
+    expires_in: String,
+}
+
+#[derive(Debug, Serialize)]
+struct RefreshRequest<'a> {
+    grant_type: &'a str,
+    refresh_token: &'a str,
+}
+
+/// The secure token endpoint answers in snake case, unlike Identity Toolkit.
+#[derive(Debug, Deserialize)]
+struct RefreshResponse {
+    id_token: String,
+    refresh_token: String,
+    expires_in: String,
+}
+
+fn parse_expires_in(expires_in: &str) -> Result<Duration, TokenExchangeError> {
+    expires_in
+        .parse()
+        .map(Duration::from_secs)
+        .map_err(|_| TokenExchangeError::ApiError {
+            status: 200,
+            message: format!("invalid expiresIn: {}", expires_in),
+        })
+}
+
+/// A client for the sign-in endpoints of Identity Toolkit and the secure
+/// token service, authenticated with a Web API key rather than credentials.
+#[derive(Clone)]
+pub struct TokenExchange {
+    api_key: String,
+    tenant_id: Option<String>,
+    identity_toolkit_url: String,
+    secure_token_url: String,
+    client: reqwest::Client,
+}
+
+impl TokenExchange {
+    /// `api_key` is the project's Web API key, as in the Firebase console's
+    /// project settings.
+    pub fn new(api_key: String) -> TokenExchange {
+        TokenExchange {
+            api_key,
+            tenant_id: None,
+            identity_toolkit_url: IDENTITY_TOOLKIT_URL.to_string(),
+            secure_token_url: SECURE_TOKEN_URL.to_string(),
+            client: api_client(DEFAULT_REQUEST_TIMEOUT),
+        }
+    }
+    /// Points the client at other endpoints, e.g. at
+    /// `http://localhost:9099/identitytoolkit.googleapis.com/v1` and
+    /// `http://localhost:9099/securetoken.googleapis.com/v1` for the emulator.
+    pub fn with_base_urls(
+        mut self,
+        identity_toolkit_url: String,
+        secure_token_url: String,
+    ) -> TokenExchange {
+        self.identity_toolkit_url = identity_toolkit_url;
+        self.secure_token_url = secure_token_url;
+        self
+    }
+    /// Replaces the default client, which times requests out after 30 seconds.
+    pub fn with_client(mut self, client: reqwest::Client) -> TokenExchange {
+        self.client = client;
+        self
+    }
+    /// Signs in to an Identity Platform tenant, whose custom tokens must
+    /// carry the same tenant id.
+    pub fn with_tenant_id(mut self, tenant_id: String) -> TokenExchange {
+        self.tenant_id = Some(tenant_id);
+        self
+    }
+    async fn send<R>(&self, request: reqwest::RequestBuilder) -> Result<R, TokenExchangeError>
+    where
+        R: DeserializeOwned,
+    {
+        let response = request
+            .query(&[("key", &self.api_key)])
+            .send()
+            .await
+            .map_err(TokenExchangeError::RequestError)?;
+        let status = response.status();
+        if !status.is_success() {
+            let message = error_message(response).await;
+            return Err(TokenExchangeError::from_api_error(status.as_u16(), message));
+        }
+        response
+            .json::<R>()
+            .await
+            .map_err(TokenExchangeError::ResponseBodyError)
+    }
+    /// Exchanges a custom token for an ID token and a refresh token.
+    pub async fn sign_in_with_custom_token(
+        &self,
+        custom_token: &str,
+    ) -> Result<IdTokenResponse, TokenExchangeError> {
+        if custom_token.is_empty() {
+            return Err(TokenExchangeError::InvalidArgument(
+                "custom_token must be a non-empty string".to_string(),
+            ));
+        }
+        let request = SignInWithCustomTokenRequest {
+            token: custom_token,
+            return_secure_token: true,
+            tenant_id: self.tenant_id.as_deref(),
+        };
+        let url = format!(
+            "{}/accounts:signInWithCustomToken",
+            self.identity_toolkit_url
+        );
+        let response: SignInWithCustomTokenResponse =
+            self.send(self.client.post(&url).json(&request)).await?;
+        Ok(IdTokenResponse {
+            expires_in: parse_expires_in(&response.expires_in)?,
+            id_token: response.id_token,
+            refresh_token: response.refresh_token,
+        })
+    }
+    /// Gets a fresh ID token with a refresh token. The returned refresh token
+    /// replaces the given one.
+    pub async fn refresh_id_token(
+        &self,
+        refresh_token: &str,
+    ) -> Result<IdTokenResponse, TokenExchangeError> {
+        if refresh_token.is_empty() {
+            return Err(TokenExchangeError::InvalidArgument(
+                "refresh_token must be a non-empty string".to_string(),
+            ));
+        }
+        let request = RefreshRequest {
+            grant_type: "refresh_token",
+            refresh_token,
+        };
+        let url = format!("{}/token", self.secure_token_url);
+        let response: RefreshResponse = self.send(self.client.post(&url).form(&request)).await?;
+        Ok(IdTokenResponse {
+            expires_in: parse_expires_in(&response.expires_in)?,
+            id_token: response.id_token,
+            refresh_token: response.refresh_token,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{body_json, body_string, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn get_test_client(mock_server: &MockServer) -> TokenExchange {
+        TokenExchange::new("api-key".to_string()).with_base_urls(
+            format!("{}/identitytoolkit", mock_server.uri()),
+            format!("{}/securetoken", mock_server.uri()),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_sign_in_with_custom_token() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/identitytoolkit/accounts:signInWithCustomToken"))
+            .and(query_param("key", "api-key"))
+            .and(body_json(json!({
+                "token": "custom-token",
+                "returnSecureToken": true,
+                "tenantId": "tenant-1"
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "idToken": "id-token",
+                "refreshToken": "refresh-token",
+                "expiresIn": "3600",
+                "isNewUser": false
+            })))
+            .mount(&mock_server)
+            .await;
+        let response = get_test_client(&mock_server)
+            .with_tenant_id("tenant-1".to_string())
+            .sign_in_with_custom_token("custom-token")
+            .await
+            .unwrap();
+        assert_eq!(
+            response,
+            IdTokenResponse {
+                id_token: "id-token".to_string(),
+                refresh_token: "refresh-token".to_string(),
+                expires_in: Duration::from_secs(3600),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sign_in_with_invalid_custom_token() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/identitytoolkit/accounts:signInWithCustomToken"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "error": { "code": 400, "message": "INVALID_CUSTOM_TOKEN" }
+            })))
+            .mount(&mock_server)
+            .await;
+        let client = get_test_client(&mock_server);
+        assert!(matches!(
+            client.sign_in_with_custom_token("custom-token").await,
+            Err(TokenExchangeError::InvalidCustomToken)
+        ));
+        assert!(matches!(
+            client.sign_in_with_custom_token("").await,
+            Err(TokenExchangeError::InvalidArgument(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_id_token() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/securetoken/token"))
+            .and(query_param("key", "api-key"))
+            .and(body_string(
+                "grant_type=refresh_token&refresh_token=refresh-token",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id_token": "new-id-token",
+                "refresh_token": "new-refresh-token",
+                "expires_in": "3600",
+                "token_type": "Bearer",
+                "user_id": "uid"
+            })))
+            .mount(&mock_server)
+            .await;
+        let response = get_test_client(&mock_server)
+            .refresh_id_token("refresh-token")
+            .await
+            .unwrap();
+        assert_eq!(response.id_token, "new-id-token");
+        assert_eq!(response.refresh_token, "new-refresh-token");
+        assert_eq!(response.expires_in, Duration::from_secs(3600));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_id_token_expired() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/securetoken/token"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "error": { "code": 400, "message": "TOKEN_EXPIRED" }
+            })))
+            .mount(&mock_server)
+            .await;
+        let result = get_test_client(&mock_server)
+            .refresh_id_token("refresh-token")
+            .await;
+        assert!(matches!(
+            result,
+            Err(TokenExchangeError::InvalidRefreshToken)
+        ));
+    }
+}
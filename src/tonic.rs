@@ -0,0 +1,188 @@
+use crate::integration::{FirebaseAuthRejection, FirebaseUser, RejectionResponse};
+use crate::jwk_auth::{JwkAuth, RequireClaim};
+use crate::token::TokenExtractor;
+use std::sync::Arc;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+/// Verifies with [`JwkAuth::verify`], as interceptors cannot await: keys
+/// are kept fresh by the periodic refresh, not by `lazy_refresh`.
+#[derive(Clone)]
+pub struct FirebaseAuthInterceptor {
+    jwk_auth: Arc<JwkAuth>,
+    extractor: Arc<TokenExtractor>,
+    optional: bool,
+    requirements: Arc<Vec<RequireClaim>>,
+    rejection_response: Arc<RejectionResponse>,
+}
+
+impl FirebaseAuthInterceptor {
+    pub fn new(jwk_auth: Arc<JwkAuth>) -> FirebaseAuthInterceptor {
+        FirebaseAuthInterceptor {
+            jwk_auth,
+            extractor: Arc::default(),
+            optional: false,
+            requirements: Arc::default(),
+            rejection_response: Arc::default(),
+        }
+    }
+    /// Lets requests without a token through unauthenticated, without a
+    /// [`FirebaseUser`] in the extensions. Requests with an invalid token are
+    /// still rejected.
+    pub fn optional(mut self, optional: bool) -> FirebaseAuthInterceptor {
+        self.optional = optional;
+        self
+    }
+    /// Answers `permission_denied` to authenticated users whose claims do not
+    /// meet `requirement`.
+    pub fn require(mut self, requirement: RequireClaim) -> FirebaseAuthInterceptor {
+        Arc::make_mut(&mut self.requirements).push(requirement);
+        self
+    }
+    /// Looks for the token where `extractor` does instead of only in the
+    /// `authorization` metadata. gRPC requests have no query string.
+    pub fn token_extractor(mut self, extractor: TokenExtractor) -> FirebaseAuthInterceptor {
+        self.extractor = Arc::new(extractor);
+        self
+    }
+    /// Only [`RejectionResponse::describe_errors`] applies: whether the
+    /// status message tells clients why their token was rejected.
+    pub fn rejection_response(mut self, config: RejectionResponse) -> FirebaseAuthInterceptor {
+        self.rejection_response = Arc::new(config);
+        self
+    }
+    fn status(&self, rejection: FirebaseAuthRejection) -> Status {
+        let message = self.rejection_response.message(&rejection);
+        match self.rejection_response.status_code(&rejection) {
+            403 => Status::permission_denied(message),
+            503 => Status::unavailable(message),
+            _ => Status::unauthenticated(message),
+        }
+    }
+}
+
+impl Interceptor for FirebaseAuthInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let result = self.extractor.extract_and_verify(
+            self.jwk_auth.as_ref(),
+            |name| {
+                request
+                    .metadata()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+            },
+            None,
+        );
+        let token_data = match result {
+            Ok(token_data) => token_data,
+            Err(FirebaseAuthRejection::MissingToken) if self.optional => return Ok(request),
+            Err(rejection) => return Err(self.status(rejection)),
+        };
+        if !self
+            .requirements
+            .iter()
+            .all(|requirement| requirement.check(&token_data.claims))
+        {
+            return Err(self.status(FirebaseAuthRejection::Forbidden));
+        }
+        request
+            .extensions_mut()
+            .insert(FirebaseUser::from(token_data.claims.clone()));
+        request.extensions_mut().insert(token_data);
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use tonic::Code;
+
+    async fn get_interceptor() -> FirebaseAuthInterceptor {
+        let jwk_auth = get_session_cookie_jwk_auth().await;
+        FirebaseAuthInterceptor::new(Arc::new(jwk_auth))
+    }
+
+    #[tokio::test]
+    async fn test_interceptor() {
+        let claims = get_test_claims("pj", "https://session.firebase.google.com/pj");
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let mut request = Request::new(());
+        request.metadata_mut().insert(
+            "authorization",
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+        let request = get_interceptor().await.call(request).unwrap();
+        let user = request.extensions().get::<FirebaseUser>().unwrap();
+        assert_eq!(user.uid, "uid");
+    }
+
+    #[tokio::test]
+    async fn test_interceptor_rejected() {
+        let mut interceptor = get_interceptor().await;
+        let status = interceptor.call(Request::new(())).unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert("authorization", "Bearer invalid".parse().unwrap());
+        let status = interceptor.call(request).unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+        assert_eq!(status.message(), "invalid token");
+
+        let mut interceptor =
+            interceptor.rejection_response(RejectionResponse::new().describe_errors(true));
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert("authorization", "Bearer invalid".parse().unwrap());
+        let status = interceptor.call(request).unwrap_err();
+        assert_eq!(status.message(), "verification failed: malformed token");
+    }
+
+    #[tokio::test]
+    async fn test_optional_interceptor() {
+        let mut interceptor = get_interceptor().await.optional(true);
+        let request = interceptor.call(Request::new(())).unwrap();
+        assert!(request.extensions().get::<FirebaseUser>().is_none());
+
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert("authorization", "Bearer invalid".parse().unwrap());
+        let status = interceptor.call(request).unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn test_interceptor_require() {
+        let claims = get_test_claims("pj", "https://session.firebase.google.com/pj");
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let mut request = Request::new(());
+        request.metadata_mut().insert(
+            "authorization",
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+        let mut interceptor = get_interceptor().await.require(RequireClaim::role("admin"));
+        let status = interceptor.call(request).unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn test_interceptor_token_extractor() {
+        let claims = get_test_claims("pj", "https://session.firebase.google.com/pj");
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let mut interceptor = get_interceptor()
+            .await
+            .token_extractor(TokenExtractor::new().cookie("__session".to_string()));
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert("cookie", format!("__session={}", token).parse().unwrap());
+        let request = interceptor.call(request).unwrap();
+        let user = request.extensions().get::<FirebaseUser>().unwrap();
+        assert_eq!(user.uid, "uid");
+    }
+}
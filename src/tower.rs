@@ -0,0 +1,310 @@
+use crate::integration::{
+    is_under, FirebaseAuthRejection, FirebaseUser, OptionalFirebaseUser, RejectionResponse,
+};
+use crate::jwk_auth::{JwkAuth, RequireClaim};
+use crate::token::TokenExtractor;
+use futures_util::future::BoxFuture;
+use http::header::WWW_AUTHENTICATE;
+use http::{HeaderValue, Request, Response, StatusCode};
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower_layer::Layer;
+use tower_service::Service;
+
+#[derive(Clone)]
+pub struct FirebaseAuthLayer {
+    jwk_auth: Arc<JwkAuth>,
+    extractor: Arc<TokenExtractor>,
+    excluded_paths: Arc<Vec<String>>,
+    optional: bool,
+    requirements: Arc<Vec<RequireClaim>>,
+    rejection_response: Arc<RejectionResponse>,
+}
+
+impl FirebaseAuthLayer {
+    pub fn new(jwk_auth: Arc<JwkAuth>) -> FirebaseAuthLayer {
+        FirebaseAuthLayer {
+            jwk_auth,
+            extractor: Arc::default(),
+            excluded_paths: Arc::default(),
+            optional: false,
+            requirements: Arc::default(),
+            rejection_response: Arc::default(),
+        }
+    }
+    /// Lets requests whose path is `prefix`, or lies below it, through
+    /// unauthenticated: `/public` matches `/public/page` but not `/publications`.
+    pub fn exclude(mut self, prefix: String) -> FirebaseAuthLayer {
+        Arc::make_mut(&mut self.excluded_paths).push(prefix);
+        self
+    }
+    /// Lets requests without a token through unauthenticated, with an
+    /// [`OptionalFirebaseUser`] of `None` in the request extensions. Requests
+    /// with an invalid token are still rejected.
+    pub fn optional(mut self, optional: bool) -> FirebaseAuthLayer {
+        self.optional = optional;
+        self
+    }
+    /// Answers `403 Forbidden` to authenticated users whose claims do not
+    /// meet `requirement`.
+    pub fn require(mut self, requirement: RequireClaim) -> FirebaseAuthLayer {
+        Arc::make_mut(&mut self.requirements).push(requirement);
+        self
+    }
+    /// Sets the status and `WWW-Authenticate` challenge of rejections. The
+    /// responses have no body, whatever `json_body` says.
+    pub fn rejection_response(mut self, config: RejectionResponse) -> FirebaseAuthLayer {
+        self.rejection_response = Arc::new(config);
+        self
+    }
+    /// Looks for the token where `extractor` does instead of only in the
+    /// `Authorization` header.
+    pub fn token_extractor(mut self, extractor: TokenExtractor) -> FirebaseAuthLayer {
+        self.extractor = Arc::new(extractor);
+        self
+    }
+}
+
+impl FirebaseAuthLayer {
+    /// An empty response with the status and `WWW-Authenticate` challenge of
+    /// the layer's [`RejectionResponse`].
+    fn reject<ResBody: Default>(&self, rejection: &FirebaseAuthRejection) -> Response<ResBody> {
+        let mut response = Response::new(ResBody::default());
+        *response.status_mut() =
+            StatusCode::from_u16(self.rejection_response.status_code(rejection))
+                .unwrap_or(StatusCode::UNAUTHORIZED);
+        if let Some(challenge) = self
+            .rejection_response
+            .www_authenticate(rejection)
+            .and_then(|challenge| HeaderValue::from_str(&challenge).ok())
+        {
+            response.headers_mut().insert(WWW_AUTHENTICATE, challenge);
+        }
+        response
+    }
+}
+
+impl<S> Layer<S> for FirebaseAuthLayer {
+    type Service = FirebaseAuth<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        FirebaseAuth {
+            inner,
+            config: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct FirebaseAuth<S> {
+    inner: S,
+    config: FirebaseAuthLayer,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for FirebaseAuth<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send,
+    ReqBody: Send + 'static,
+    ResBody: Default,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        let excluded = self
+            .config
+            .excluded_paths
+            .iter()
+            .any(|prefix| is_under(req.uri().path(), prefix));
+        if excluded {
+            return Box::pin(self.inner.call(req));
+        }
+        let token = self
+            .config
+            .extractor
+            .extract(
+                |name| {
+                    req.headers()
+                        .get(name)
+                        .and_then(|value| value.to_str().ok())
+                },
+                req.uri().query(),
+            )
+            .map(Cow::into_owned);
+        // The inner service was driven to readiness, the clone may not be.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let config = self.config.clone();
+        Box::pin(async move {
+            let result = match token {
+                Some(token) => config
+                    .jwk_auth
+                    .verify_async(&token)
+                    .await
+                    .map_err(FirebaseAuthRejection::InvalidToken),
+                None => Err(FirebaseAuthRejection::MissingToken),
+            };
+            match result {
+                Ok(token_data)
+                    if !config
+                        .requirements
+                        .iter()
+                        .all(|requirement| requirement.check(&token_data.claims)) =>
+                {
+                    Ok(config.reject(&FirebaseAuthRejection::Forbidden))
+                }
+                Ok(token_data) => {
+                    req.extensions_mut()
+                        .insert(FirebaseUser::from(token_data.claims.clone()));
+                    req.extensions_mut().insert(token_data);
+                    inner.call(req).await
+                }
+                Err(FirebaseAuthRejection::MissingToken) if config.optional => {
+                    req.extensions_mut().insert(OptionalFirebaseUser(None));
+                    inner.call(req).await
+                }
+                Err(rejection) => Ok(config.reject(&rejection)),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jwk_auth::{Claims, DecodedToken};
+    use crate::tests::*;
+    use http::header::{AUTHORIZATION, COOKIE};
+    use std::convert::Infallible;
+    use tower::{service_fn, ServiceExt};
+
+    async fn call_with(extractor: TokenExtractor, request: Request<()>) -> Response<String> {
+        let layer = get_layer().await.token_extractor(extractor);
+        call_layer(layer, request).await
+    }
+
+    async fn get_layer() -> FirebaseAuthLayer {
+        let jwk_auth = get_session_cookie_jwk_auth().await;
+        FirebaseAuthLayer::new(Arc::new(jwk_auth))
+    }
+
+    async fn call_layer(layer: FirebaseAuthLayer, request: Request<()>) -> Response<String> {
+        let service = layer.layer(service_fn(|req: Request<()>| async move {
+            let uid = match req.extensions().get::<DecodedToken<Claims>>() {
+                Some(token_data) => token_data.claims.sub.clone(),
+                None => "anonymous".to_string(),
+            };
+            Ok::<_, Infallible>(Response::new(uid))
+        }));
+        service.oneshot(request).await.unwrap()
+    }
+
+    async fn call(authorization: Option<&str>) -> Response<String> {
+        let mut request = Request::builder();
+        if let Some(authorization) = authorization {
+            request = request.header(AUTHORIZATION, authorization);
+        }
+        call_with(TokenExtractor::default(), request.body(()).unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn test_firebase_auth_layer() {
+        let claims = get_test_claims("pj", "https://session.firebase.google.com/pj");
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let response = call(Some(&format!("Bearer {}", token))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), "uid");
+    }
+
+    #[tokio::test]
+    async fn test_firebase_auth_layer_rejected() {
+        let response = call(None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[WWW_AUTHENTICATE], "Bearer");
+        let response = call(Some("Bearer invalid")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers()[WWW_AUTHENTICATE],
+            "Bearer error=\"invalid_token\""
+        );
+    }
+
+    #[tokio::test]
+    async fn test_firebase_auth_layer_session_cookie() {
+        let claims = get_test_claims("pj", "https://session.firebase.google.com/pj");
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let request = || {
+            Request::builder()
+                .header(COOKIE, format!("__session={}", token))
+                .body(())
+                .unwrap()
+        };
+        let extractor = TokenExtractor::new()
+            .bearer()
+            .cookie("__session".to_string());
+        let response = call_with(extractor, request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), "uid");
+        assert_eq!(
+            call_with(TokenExtractor::default(), request())
+                .await
+                .status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_firebase_auth_layer_excluded_and_optional() {
+        let request = |uri: &str| Request::builder().uri(uri).body(()).unwrap();
+        let layer = get_layer().await.exclude("/public".to_string());
+        let response = call_layer(layer.clone(), request("/public/page")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), "anonymous");
+        let response = call_layer(layer.clone(), request("/private")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = call_layer(layer.clone(), request("/publications")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let optional = layer.optional(true);
+        let response = call_layer(optional.clone(), request("/private")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), "anonymous");
+        let invalid = Request::builder()
+            .uri("/private")
+            .header(AUTHORIZATION, "Bearer invalid")
+            .body(())
+            .unwrap();
+        let response = call_layer(optional, invalid).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_firebase_auth_layer_require() {
+        let claims = get_test_claims("pj", "https://session.firebase.google.com/pj");
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let request = || {
+            Request::builder()
+                .header(AUTHORIZATION, format!("Bearer {}", token))
+                .body(())
+                .unwrap()
+        };
+        let layer = get_layer().await;
+        let allowed = layer.clone().require(RequireClaim::equals("sub", "uid"));
+        let response = call_layer(allowed, request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let forbidden = layer.require(RequireClaim::role("admin"));
+        let response = call_layer(forbidden, request()).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            response.headers()[WWW_AUTHENTICATE],
+            "Bearer error=\"insufficient_scope\""
+        );
+    }
+}
@@ -0,0 +1,463 @@
+//! Importing users in bulk, e.g. when migrating from another auth system,
+//! along with their password hashes so they keep signing in with the same
+//! password.
+
+use crate::custom_token::MAX_UID_LENGTH;
+use crate::identity_toolkit::{IdentityToolkit, IdentityToolkitError};
+use crate::user_management::custom_attributes;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+const MAX_IMPORT_USERS: usize = 1000;
+
+fn encode(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE)
+}
+
+/// How the password hashes of imported users were computed.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum UserImportHash {
+    /// Firebase's modified scrypt, with the parameters shown in the Firebase
+    /// console's password hash settings of the source project.
+    Scrypt {
+        key: Vec<u8>,
+        salt_separator: Vec<u8>,
+        /// 1 to 8.
+        rounds: u32,
+        /// 1 to 14.
+        memory_cost: u32,
+    },
+    StandardScrypt {
+        memory_cost: u32,
+        parallelization: u32,
+        block_size: u32,
+        derived_key_length: u32,
+    },
+    Bcrypt,
+    HmacSha256 {
+        key: Vec<u8>,
+    },
+    HmacSha512 {
+        key: Vec<u8>,
+    },
+    HmacSha1 {
+        key: Vec<u8>,
+    },
+    HmacMd5 {
+        key: Vec<u8>,
+    },
+}
+
+/// Options applying to every user of an import.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct UserImportOptions {
+    /// Required when any user has a password hash.
+    pub hash: Option<UserImportHash>,
+}
+
+impl UserImportOptions {
+    pub fn new() -> UserImportOptions {
+        UserImportOptions::default()
+    }
+    pub fn hash(mut self, hash: UserImportHash) -> Self {
+        self.hash = Some(hash);
+        self
+    }
+}
+
+/// A user to import. Unlike [`CreateUserRequest`](crate::user_management::CreateUserRequest),
+/// the uid is required and passwords are given as hashes.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ImportUserRecord {
+    uid: String,
+    email: Option<String>,
+    email_verified: Option<bool>,
+    display_name: Option<String>,
+    photo_url: Option<String>,
+    phone_number: Option<String>,
+    disabled: Option<bool>,
+    password_hash: Option<Vec<u8>>,
+    password_salt: Option<Vec<u8>>,
+    custom_claims: Option<Value>,
+}
+
+impl ImportUserRecord {
+    pub fn new(uid: String) -> ImportUserRecord {
+        ImportUserRecord {
+            uid,
+            ..ImportUserRecord::default()
+        }
+    }
+    pub fn email(mut self, email: String) -> Self {
+        self.email = Some(email);
+        self
+    }
+    pub fn email_verified(mut self, email_verified: bool) -> Self {
+        self.email_verified = Some(email_verified);
+        self
+    }
+    pub fn display_name(mut self, display_name: String) -> Self {
+        self.display_name = Some(display_name);
+        self
+    }
+    pub fn photo_url(mut self, photo_url: String) -> Self {
+        self.photo_url = Some(photo_url);
+        self
+    }
+    pub fn phone_number(mut self, phone_number: String) -> Self {
+        self.phone_number = Some(phone_number);
+        self
+    }
+    pub fn disabled(mut self, disabled: bool) -> Self {
+        self.disabled = Some(disabled);
+        self
+    }
+    /// The raw password hash, computed as set in [`UserImportOptions::hash`].
+    pub fn password_hash(mut self, password_hash: Vec<u8>) -> Self {
+        self.password_hash = Some(password_hash);
+        self
+    }
+    pub fn password_salt(mut self, password_salt: Vec<u8>) -> Self {
+        self.password_salt = Some(password_salt);
+        self
+    }
+    /// A JSON object of at most 1000 bytes, as for
+    /// [`IdentityToolkit::set_custom_user_claims`].
+    pub fn custom_claims(mut self, custom_claims: Value) -> Self {
+        self.custom_claims = Some(custom_claims);
+        self
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct UploadUser {
+    local_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email_verified: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    display_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    photo_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    phone_number: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    password_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    salt: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    custom_attributes: Option<String>,
+}
+
+impl UploadUser {
+    fn from_record(record: ImportUserRecord) -> Result<UploadUser, IdentityToolkitError> {
+        if record.uid.is_empty() || record.uid.chars().count() > MAX_UID_LENGTH {
+            return Err(IdentityToolkitError::InvalidArgument(format!(
+                "uid \"{}\" must be a non-empty string of at most {} characters",
+                record.uid, MAX_UID_LENGTH
+            )));
+        }
+        let uid = &record.uid;
+        let custom_attributes = match record.custom_claims {
+            Some(claims) => Some(custom_attributes(claims).map_err(|error| match error {
+                IdentityToolkitError::InvalidArgument(message) => {
+                    IdentityToolkitError::InvalidArgument(format!("user \"{}\": {}", uid, message))
+                }
+                error => error,
+            })?),
+            None => None,
+        };
+        Ok(UploadUser {
+            local_id: record.uid,
+            email: record.email,
+            email_verified: record.email_verified,
+            display_name: record.display_name,
+            photo_url: record.photo_url,
+            phone_number: record.phone_number,
+            disabled: record.disabled,
+            password_hash: record.password_hash.as_deref().map(encode),
+            salt: record.password_salt.as_deref().map(encode),
+            custom_attributes,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+struct UploadAccountRequest {
+    users: Vec<UploadUser>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hash_algorithm: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signer_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    salt_separator: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rounds: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memory_cost: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parallelization: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    block_size: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dk_len: Option<u32>,
+}
+
+impl UploadAccountRequest {
+    fn with_hash(mut self, hash: &UserImportHash) -> Result<Self, IdentityToolkitError> {
+        let invalid = |message: &str| IdentityToolkitError::InvalidArgument(message.to_string());
+        let hmac = |algorithm, key: &[u8]| {
+            if key.is_empty() {
+                return Err(invalid("the HMAC key must not be empty"));
+            }
+            Ok((algorithm, Some(encode(key))))
+        };
+        let (algorithm, signer_key) = match hash {
+            UserImportHash::Scrypt {
+                key,
+                salt_separator,
+                rounds,
+                memory_cost,
+            } => {
+                if key.is_empty() {
+                    return Err(invalid("the scrypt key must not be empty"));
+                }
+                if !(1..=8).contains(rounds) {
+                    return Err(invalid("scrypt rounds must be between 1 and 8"));
+                }
+                if !(1..=14).contains(memory_cost) {
+                    return Err(invalid("scrypt memory cost must be between 1 and 14"));
+                }
+                self.salt_separator = Some(encode(salt_separator));
+                self.rounds = Some(*rounds);
+                self.memory_cost = Some(*memory_cost);
+                ("SCRYPT", Some(encode(key)))
+            }
+            UserImportHash::StandardScrypt {
+                memory_cost,
+                parallelization,
+                block_size,
+                derived_key_length,
+            } => {
+                if [memory_cost, parallelization, block_size, derived_key_length].contains(&&0) {
+                    return Err(invalid("standard scrypt parameters must be positive"));
+                }
+                self.memory_cost = Some(*memory_cost);
+                self.parallelization = Some(*parallelization);
+                self.block_size = Some(*block_size);
+                self.dk_len = Some(*derived_key_length);
+                ("STANDARD_SCRYPT", None)
+            }
+            UserImportHash::Bcrypt => ("BCRYPT", None),
+            UserImportHash::HmacSha256 { key } => hmac("HMAC_SHA256", key)?,
+            UserImportHash::HmacSha512 { key } => hmac("HMAC_SHA512", key)?,
+            UserImportHash::HmacSha1 { key } => hmac("HMAC_SHA1", key)?,
+            UserImportHash::HmacMd5 { key } => hmac("HMAC_MD5", key)?,
+        };
+        self.hash_algorithm = Some(algorithm);
+        self.signer_key = signer_key;
+        Ok(self)
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+pub struct ImportUserError {
+    /// The position of the user in the imported list.
+    pub index: usize,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct UploadAccountResponse {
+    #[serde(default)]
+    error: Vec<ImportUserError>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct UserImportResult {
+    pub success_count: usize,
+    pub failure_count: usize,
+    pub errors: Vec<ImportUserError>,
+}
+
+impl IdentityToolkit {
+    /// Imports up to 1000 users at once, overwriting existing users with the
+    /// same uid. Users the server refuses are reported in the result rather
+    /// than as an error.
+    pub async fn import_users(
+        &self,
+        users: Vec<ImportUserRecord>,
+        options: UserImportOptions,
+    ) -> Result<UserImportResult, IdentityToolkitError> {
+        if users.len() > MAX_IMPORT_USERS {
+            return Err(IdentityToolkitError::InvalidArgument(format!(
+                "at most {} users can be imported at once",
+                MAX_IMPORT_USERS
+            )));
+        }
+        if users.is_empty() {
+            return Ok(UserImportResult {
+                success_count: 0,
+                failure_count: 0,
+                errors: vec![],
+            });
+        }
+        let has_password = users.iter().any(|user| user.password_hash.is_some());
+        let count = users.len();
+        let request = UploadAccountRequest {
+            users: users
+                .into_iter()
+                .map(UploadUser::from_record)
+                .collect::<Result<_, _>>()?,
+            ..UploadAccountRequest::default()
+        };
+        let request = match &options.hash {
+            Some(hash) => request.with_hash(hash)?,
+            None if has_password => {
+                return Err(IdentityToolkitError::InvalidArgument(
+                    "a hash algorithm is required to import password hashes".to_string(),
+                ))
+            }
+            None => request,
+        };
+        let response: UploadAccountResponse = self.post("/accounts:batchCreate", &request).await?;
+        Ok(UserImportResult {
+            success_count: count - response.error.len(),
+            failure_count: response.error.len(),
+            errors: response.error,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity_toolkit::tests::get_test_client;
+    use serde_json::json;
+    use wiremock::matchers::{body_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_import_users() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/projects/pj/accounts:batchCreate"))
+            .and(body_json(json!({
+                "users": [
+                    {
+                        "localId": "a",
+                        "email": "a@example.com",
+                        "emailVerified": true,
+                        "passwordHash": "aGFzaA==",
+                        "salt": "c2FsdA==",
+                        "customAttributes": "{\"role\":\"admin\"}"
+                    },
+                    { "localId": "b" }
+                ],
+                "hashAlgorithm": "SCRYPT",
+                "signerKey": "a2V5",
+                "saltSeparator": "Bw==",
+                "rounds": 8,
+                "memoryCost": 14
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "error": [{ "index": 1, "message": "INVALID_LOCAL_ID" }]
+            })))
+            .mount(&mock_server)
+            .await;
+        let users = vec![
+            ImportUserRecord::new("a".to_string())
+                .email("a@example.com".to_string())
+                .email_verified(true)
+                .password_hash(b"hash".to_vec())
+                .password_salt(b"salt".to_vec())
+                .custom_claims(json!({ "role": "admin" })),
+            ImportUserRecord::new("b".to_string()),
+        ];
+        let options = UserImportOptions::new().hash(UserImportHash::Scrypt {
+            key: b"key".to_vec(),
+            salt_separator: vec![7],
+            rounds: 8,
+            memory_cost: 14,
+        });
+        let result = get_test_client(&mock_server)
+            .import_users(users, options)
+            .await
+            .unwrap();
+        assert_eq!(result.success_count, 1);
+        assert_eq!(result.failure_count, 1);
+        assert_eq!(
+            result.errors,
+            vec![ImportUserError {
+                index: 1,
+                message: Some("INVALID_LOCAL_ID".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_hash_parameters() {
+        let request = |hash| UploadAccountRequest::default().with_hash(&hash);
+        let bcrypt = request(UserImportHash::Bcrypt).unwrap();
+        assert_eq!(bcrypt.hash_algorithm, Some("BCRYPT"));
+        assert_eq!(bcrypt.signer_key, None);
+
+        let hmac = request(UserImportHash::HmacSha256 {
+            key: b"key".to_vec(),
+        })
+        .unwrap();
+        assert_eq!(hmac.hash_algorithm, Some("HMAC_SHA256"));
+        assert_eq!(hmac.signer_key, Some("a2V5".to_string()));
+        assert!(request(UserImportHash::HmacMd5 { key: vec![] }).is_err());
+
+        let standard = request(UserImportHash::StandardScrypt {
+            memory_cost: 1024,
+            parallelization: 16,
+            block_size: 8,
+            derived_key_length: 64,
+        })
+        .unwrap();
+        assert_eq!(standard.hash_algorithm, Some("STANDARD_SCRYPT"));
+        assert_eq!(standard.dk_len, Some(64));
+
+        assert!(request(UserImportHash::Scrypt {
+            key: b"key".to_vec(),
+            salt_separator: vec![],
+            rounds: 9,
+            memory_cost: 14,
+        })
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_import_users_invalid() {
+        let mock_server = MockServer::start().await;
+        let client = get_test_client(&mock_server);
+        let import = |users: Vec<ImportUserRecord>| {
+            let client = client.clone();
+            async move { client.import_users(users, UserImportOptions::new()).await }
+        };
+        let with_password = ImportUserRecord::new("a".to_string()).password_hash(b"hash".to_vec());
+        let reserved = ImportUserRecord::new("a".to_string()).custom_claims(json!({ "sub": "x" }));
+        let too_many = (0..1001)
+            .map(|i| ImportUserRecord::new(i.to_string()))
+            .collect();
+        for users in [
+            vec![with_password],
+            vec![ImportUserRecord::new(String::new())],
+            vec![reserved],
+            too_many,
+        ] {
+            assert!(matches!(
+                import(users).await,
+                Err(IdentityToolkitError::InvalidArgument(_))
+            ));
+        }
+        assert_eq!(import(vec![]).await.unwrap().success_count, 0);
+    }
+}
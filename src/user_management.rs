@@ -0,0 +1,616 @@
+use crate::custom_token::RESERVED_CLAIMS;
+use crate::identity_toolkit::{IdentityToolkit, IdentityToolkitError, UserRecord};
+use crate::time::{SystemTime, UNIX_EPOCH};
+use futures_util::stream::{self, Stream, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+const MAX_DELETE_USERS: usize = 1000;
+const MAX_LIST_USERS_RESULTS: usize = 1000;
+const MAX_CUSTOM_CLAIMS_LENGTH: usize = 1000;
+
+/// Serializes custom claims to the `customAttributes` of an account. They
+/// must be a JSON object without reserved claims, no longer than 1000 bytes
+/// when serialized; `Value::Null` stands for no claims.
+pub(crate) fn custom_attributes(claims: Value) -> Result<String, IdentityToolkitError> {
+    let claims = match claims {
+        Value::Null => serde_json::Map::new(),
+        Value::Object(claims) => claims,
+        _ => {
+            return Err(IdentityToolkitError::InvalidArgument(
+                "custom claims must be a JSON object".to_string(),
+            ))
+        }
+    };
+    if let Some(name) = claims
+        .keys()
+        .find(|name| RESERVED_CLAIMS.contains(&name.as_str()))
+    {
+        return Err(IdentityToolkitError::InvalidArgument(format!(
+            "claim \"{}\" is reserved",
+            name
+        )));
+    }
+    let custom_attributes = Value::Object(claims).to_string();
+    if custom_attributes.len() > MAX_CUSTOM_CLAIMS_LENGTH {
+        return Err(IdentityToolkitError::InvalidArgument(format!(
+            "custom claims must not exceed {} bytes",
+            MAX_CUSTOM_CLAIMS_LENGTH
+        )));
+    }
+    Ok(custom_attributes)
+}
+
+/// Properties of a user to create. Unset fields are left to the server.
+#[derive(Debug, Serialize, PartialEq, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateUserRequest {
+    #[serde(rename = "localId", skip_serializing_if = "Option::is_none")]
+    uid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email_verified: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    phone_number: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    display_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    photo_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disabled: Option<bool>,
+}
+
+impl CreateUserRequest {
+    pub fn new() -> CreateUserRequest {
+        CreateUserRequest::default()
+    }
+    pub fn uid(mut self, uid: String) -> Self {
+        self.uid = Some(uid);
+        self
+    }
+    pub fn email(mut self, email: String) -> Self {
+        self.email = Some(email);
+        self
+    }
+    pub fn email_verified(mut self, email_verified: bool) -> Self {
+        self.email_verified = Some(email_verified);
+        self
+    }
+    pub fn phone_number(mut self, phone_number: String) -> Self {
+        self.phone_number = Some(phone_number);
+        self
+    }
+    pub fn password(mut self, password: String) -> Self {
+        self.password = Some(password);
+        self
+    }
+    pub fn display_name(mut self, display_name: String) -> Self {
+        self.display_name = Some(display_name);
+        self
+    }
+    pub fn photo_url(mut self, photo_url: String) -> Self {
+        self.photo_url = Some(photo_url);
+        self
+    }
+    pub fn disabled(mut self, disabled: bool) -> Self {
+        self.disabled = Some(disabled);
+        self
+    }
+}
+
+/// Changes to apply to an existing user. Unset fields are left unchanged;
+/// passing `None` to `display_name`, `photo_url` or `phone_number` removes them.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct UpdateUserRequest {
+    email: Option<String>,
+    email_verified: Option<bool>,
+    phone_number: Option<Option<String>>,
+    password: Option<String>,
+    display_name: Option<Option<String>>,
+    photo_url: Option<Option<String>>,
+    disabled: Option<bool>,
+}
+
+impl UpdateUserRequest {
+    pub fn new() -> UpdateUserRequest {
+        UpdateUserRequest::default()
+    }
+    pub fn email(mut self, email: String) -> Self {
+        self.email = Some(email);
+        self
+    }
+    pub fn email_verified(mut self, email_verified: bool) -> Self {
+        self.email_verified = Some(email_verified);
+        self
+    }
+    pub fn phone_number(mut self, phone_number: Option<String>) -> Self {
+        self.phone_number = Some(phone_number);
+        self
+    }
+    pub fn password(mut self, password: String) -> Self {
+        self.password = Some(password);
+        self
+    }
+    pub fn display_name(mut self, display_name: Option<String>) -> Self {
+        self.display_name = Some(display_name);
+        self
+    }
+    pub fn photo_url(mut self, photo_url: Option<String>) -> Self {
+        self.photo_url = Some(photo_url);
+        self
+    }
+    pub fn disabled(mut self, disabled: bool) -> Self {
+        self.disabled = Some(disabled);
+        self
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SetAccountInfoRequest {
+    pub local_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phone_number: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub photo_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disable_user: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom_attributes: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub valid_since: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub delete_attribute: Vec<&'static str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub delete_provider: Vec<&'static str>,
+}
+
+impl SetAccountInfoRequest {
+    fn from_update(uid: &str, update: UpdateUserRequest) -> SetAccountInfoRequest {
+        let mut request = SetAccountInfoRequest {
+            local_id: uid.to_string(),
+            email: update.email,
+            email_verified: update.email_verified,
+            password: update.password,
+            disable_user: update.disabled,
+            ..SetAccountInfoRequest::default()
+        };
+        match update.display_name {
+            Some(Some(display_name)) => request.display_name = Some(display_name),
+            Some(None) => request.delete_attribute.push("DISPLAY_NAME"),
+            None => {}
+        }
+        match update.photo_url {
+            Some(Some(photo_url)) => request.photo_url = Some(photo_url),
+            Some(None) => request.delete_attribute.push("PHOTO_URL"),
+            None => {}
+        }
+        match update.phone_number {
+            Some(Some(phone_number)) => request.phone_number = Some(phone_number),
+            Some(None) => request.delete_provider.push("phone"),
+            None => {}
+        }
+        request
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct LocalIdResponse {
+    pub local_id: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DeleteAccountRequest<'a> {
+    local_id: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchDeleteRequest<'a> {
+    local_ids: &'a [String],
+    force: bool,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteUserError {
+    pub index: usize,
+    pub local_id: Option<String>,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct BatchDeleteResponse {
+    #[serde(default)]
+    errors: Vec<DeleteUserError>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ListUsersQuery<'a> {
+    max_results: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_page_token: Option<&'a str>,
+}
+
+/// One page of users. `next_page_token` is `None` on the last page.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ListUsersPage {
+    #[serde(default)]
+    pub users: Vec<UserRecord>,
+    pub next_page_token: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct DeleteUsersResult {
+    pub success_count: usize,
+    pub failure_count: usize,
+    pub errors: Vec<DeleteUserError>,
+}
+
+impl IdentityToolkit {
+    pub async fn create_user(
+        &self,
+        request: CreateUserRequest,
+    ) -> Result<UserRecord, IdentityToolkitError> {
+        let response: LocalIdResponse = self.post("/accounts:signUp", &request).await?;
+        self.get_user(&response.local_id).await
+    }
+    pub async fn update_user(
+        &self,
+        uid: &str,
+        request: UpdateUserRequest,
+    ) -> Result<UserRecord, IdentityToolkitError> {
+        let request = SetAccountInfoRequest::from_update(uid, request);
+        let response: LocalIdResponse = self.post("/accounts:update", &request).await?;
+        self.get_user(&response.local_id).await
+    }
+    /// Replaces the user's custom claims, which must be a JSON object no
+    /// longer than 1000 bytes when serialized. `Value::Null` clears them.
+    pub async fn set_custom_user_claims(
+        &self,
+        uid: &str,
+        claims: Value,
+    ) -> Result<(), IdentityToolkitError> {
+        let request = SetAccountInfoRequest {
+            local_id: uid.to_string(),
+            custom_attributes: Some(custom_attributes(claims)?),
+            ..SetAccountInfoRequest::default()
+        };
+        let _: LocalIdResponse = self.post("/accounts:update", &request).await?;
+        Ok(())
+    }
+    /// Invalidates all refresh tokens issued to the user before now. ID tokens
+    /// already issued stay valid until they expire unless checked with
+    /// `JwkAuth::verify_checked`.
+    pub async fn revoke_refresh_tokens(&self, uid: &str) -> Result<(), IdentityToolkitError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let request = SetAccountInfoRequest {
+            local_id: uid.to_string(),
+            valid_since: Some(now.to_string()),
+            ..SetAccountInfoRequest::default()
+        };
+        let _: LocalIdResponse = self.post("/accounts:update", &request).await?;
+        Ok(())
+    }
+    /// Fetches a single page of at most `max_results` (1 to 1000) users.
+    pub async fn list_users_page(
+        &self,
+        max_results: usize,
+        page_token: Option<&str>,
+    ) -> Result<ListUsersPage, IdentityToolkitError> {
+        if max_results == 0 || max_results > MAX_LIST_USERS_RESULTS {
+            return Err(IdentityToolkitError::InvalidArgument(format!(
+                "page size must be between 1 and {}",
+                MAX_LIST_USERS_RESULTS
+            )));
+        }
+        let query = ListUsersQuery {
+            max_results,
+            next_page_token: page_token,
+        };
+        let mut page: ListUsersPage = self.get("/accounts:batchGet", &query).await?;
+        page.next_page_token = page.next_page_token.filter(|token| !token.is_empty());
+        Ok(page)
+    }
+    /// Streams every user of the project, fetching `page_size` users per request.
+    pub fn list_users(
+        &self,
+        page_size: usize,
+    ) -> impl Stream<Item = Result<UserRecord, IdentityToolkitError>> + '_ {
+        stream::try_unfold(
+            Some(None),
+            move |page_token: Option<Option<String>>| async move {
+                let page_token = match page_token {
+                    Some(page_token) => page_token,
+                    None => return Ok(None),
+                };
+                let page = self
+                    .list_users_page(page_size, page_token.as_deref())
+                    .await?;
+                let users = stream::iter(page.users.into_iter().map(Ok));
+                Ok(Some((users, page.next_page_token.map(Some))))
+            },
+        )
+        .try_flatten()
+    }
+    pub async fn delete_user(&self, uid: &str) -> Result<(), IdentityToolkitError> {
+        let request = DeleteAccountRequest { local_id: uid };
+        let _: serde_json::Value = self.post("/accounts:delete", &request).await?;
+        Ok(())
+    }
+    /// Deletes up to 1000 users at once. Users that fail to be deleted are
+    /// reported in the result rather than as an error.
+    pub async fn delete_users(
+        &self,
+        uids: Vec<String>,
+    ) -> Result<DeleteUsersResult, IdentityToolkitError> {
+        if uids.len() > MAX_DELETE_USERS {
+            return Err(IdentityToolkitError::InvalidArgument(format!(
+                "at most {} users can be deleted at once",
+                MAX_DELETE_USERS
+            )));
+        }
+        if uids.is_empty() {
+            return Ok(DeleteUsersResult {
+                success_count: 0,
+                failure_count: 0,
+                errors: vec![],
+            });
+        }
+        let request = BatchDeleteRequest {
+            local_ids: &uids,
+            force: true,
+        };
+        let response: BatchDeleteResponse = self.post("/accounts:batchDelete", &request).await?;
+        Ok(DeleteUsersResult {
+            success_count: uids.len() - response.errors.len(),
+            failure_count: response.errors.len(),
+            errors: response.errors,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity_toolkit::tests::{get_mock_lookup_server, get_test_client};
+    use serde_json::json;
+    use wiremock::matchers::{body_json, method, path, query_param};
+    use wiremock::{Mock, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_create_user() {
+        let mock_server = get_mock_lookup_server(json!({
+            "localId": "uid",
+            "email": "user@example.com",
+        }))
+        .await;
+        Mock::given(method("POST"))
+            .and(path("/projects/pj/accounts:signUp"))
+            .and(body_json(json!({
+                "localId": "uid",
+                "email": "user@example.com",
+                "password": "secret",
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "localId": "uid" })))
+            .mount(&mock_server)
+            .await;
+        let request = CreateUserRequest::new()
+            .uid("uid".to_string())
+            .email("user@example.com".to_string())
+            .password("secret".to_string());
+        let user = get_test_client(&mock_server)
+            .create_user(request)
+            .await
+            .unwrap();
+        assert_eq!(user.local_id, "uid");
+        assert_eq!(user.email, Some("user@example.com".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_update_user() {
+        let mock_server = get_mock_lookup_server(json!({ "localId": "uid" })).await;
+        Mock::given(method("POST"))
+            .and(path("/projects/pj/accounts:update"))
+            .and(body_json(json!({
+                "localId": "uid",
+                "email": "new@example.com",
+                "disableUser": true,
+                "deleteAttribute": ["DISPLAY_NAME"],
+                "deleteProvider": ["phone"],
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "localId": "uid" })))
+            .mount(&mock_server)
+            .await;
+        let request = UpdateUserRequest::new()
+            .email("new@example.com".to_string())
+            .disabled(true)
+            .display_name(None)
+            .phone_number(None);
+        let user = get_test_client(&mock_server)
+            .update_user("uid", request)
+            .await
+            .unwrap();
+        assert_eq!(user.local_id, "uid");
+    }
+
+    #[tokio::test]
+    async fn test_delete_user() {
+        let mock_server = wiremock::MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/projects/pj/accounts:delete"))
+            .and(body_json(json!({ "localId": "uid" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        get_test_client(&mock_server)
+            .delete_user("uid")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_delete_users() {
+        let mock_server = wiremock::MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/projects/pj/accounts:batchDelete"))
+            .and(body_json(json!({ "localIds": ["a", "b"], "force": true })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "errors": [{ "index": 1, "localId": "b", "message": "NOT_DISABLED" }]
+            })))
+            .mount(&mock_server)
+            .await;
+        let result = get_test_client(&mock_server)
+            .delete_users(vec!["a".to_string(), "b".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(result.success_count, 1);
+        assert_eq!(result.failure_count, 1);
+        assert_eq!(result.errors[0].local_id, Some("b".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_delete_users_too_many() {
+        let mock_server = wiremock::MockServer::start().await;
+        let uids = (0..1001).map(|i| i.to_string()).collect();
+        let result = get_test_client(&mock_server).delete_users(uids).await;
+        assert!(matches!(
+            result,
+            Err(IdentityToolkitError::InvalidArgument(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_create_user_email_exists() {
+        let mock_server = wiremock::MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/projects/pj/accounts:signUp"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "error": { "code": 400, "message": "EMAIL_EXISTS" }
+            })))
+            .mount(&mock_server)
+            .await;
+        let request = CreateUserRequest::new().email("user@example.com".to_string());
+        let result = get_test_client(&mock_server).create_user(request).await;
+        assert!(matches!(
+            result,
+            Err(IdentityToolkitError::EmailAlreadyExists)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_set_custom_user_claims() {
+        let mock_server = wiremock::MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/projects/pj/accounts:update"))
+            .and(body_json(json!({
+                "localId": "uid",
+                "customAttributes": "{\"role\":\"admin\"}",
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "localId": "uid" })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        get_test_client(&mock_server)
+            .set_custom_user_claims("uid", json!({ "role": "admin" }))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_set_custom_user_claims_invalid() {
+        let mock_server = wiremock::MockServer::start().await;
+        let client = get_test_client(&mock_server);
+        for claims in [
+            json!("admin"),
+            json!({ "sub": "other" }),
+            json!({ "role": "a".repeat(1000) }),
+        ] {
+            let result = client.set_custom_user_claims("uid", claims).await;
+            assert!(matches!(
+                result,
+                Err(IdentityToolkitError::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_revoke_refresh_tokens() {
+        let mock_server = wiremock::MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/projects/pj/accounts:update"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "localId": "uid" })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let before = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        get_test_client(&mock_server)
+            .revoke_refresh_tokens("uid")
+            .await
+            .unwrap();
+        let requests = mock_server.received_requests().await.unwrap();
+        let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(body["localId"], "uid");
+        let valid_since: u64 = body["validSince"].as_str().unwrap().parse().unwrap();
+        assert!(valid_since >= before);
+    }
+
+    #[tokio::test]
+    async fn test_list_users() {
+        let mock_server = wiremock::MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/projects/pj/accounts:batchGet"))
+            .and(query_param("nextPageToken", "page-2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "users": [{ "localId": "c" }]
+            })))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/projects/pj/accounts:batchGet"))
+            .and(query_param("maxResults", "2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "users": [{ "localId": "a" }, { "localId": "b" }],
+                "nextPageToken": "page-2"
+            })))
+            .mount(&mock_server)
+            .await;
+        let client = get_test_client(&mock_server);
+        let users: Vec<UserRecord> = client.list_users(2).try_collect().await.unwrap();
+        let uids: Vec<&str> = users.iter().map(|u| u.local_id.as_str()).collect();
+        assert_eq!(uids, vec!["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn test_list_users_invalid_page_size() {
+        let mock_server = wiremock::MockServer::start().await;
+        let client = get_test_client(&mock_server);
+        let result: Result<Vec<UserRecord>, _> = client.list_users(0).try_collect().await;
+        assert!(matches!(
+            result,
+            Err(IdentityToolkitError::InvalidArgument(_))
+        ));
+    }
+}
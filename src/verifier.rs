@@ -1,53 +1,968 @@
-use crate::jwk::Jwk;
+use crate::jwk::{Jwk, JwkParams};
+use crate::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::token_cache::TokenCache;
 use jsonwebtoken::decode_header;
-use jsonwebtoken::TokenData;
-use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use jsonwebtoken::errors::{Error as JwtError, ErrorKind};
+use jsonwebtoken::{decode, DecodingKey, Validation};
+use jsonwebtoken::{Header, TokenData};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::HashMap;
+use std::fmt;
 use std::str::FromStr;
+use thiserror::Error;
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+const MAX_SUBJECT_LENGTH: usize = 128;
+/// Several times the size of an ID token with the maximum 1000 bytes of
+/// custom claims.
+const DEFAULT_MAX_TOKEN_LENGTH: usize = 8192;
+
+/// The `aud` claim: a single audience, or several, as RFC 7519 allows and
+/// e.g. Auth0, Keycloak and Cognito access tokens use.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(untagged)]
+pub enum Audience {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl Audience {
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        let audiences = match self {
+            Audience::Single(audience) => std::slice::from_ref(audience),
+            Audience::Multiple(audiences) => audiences.as_slice(),
+        };
+        audiences.iter().map(String::as_str)
+    }
+    pub fn contains(&self, audience: &str) -> bool {
+        self.iter().any(|member| member == audience)
+    }
+}
+
+impl Default for Audience {
+    fn default() -> Self {
+        Audience::Single(String::new())
+    }
+}
+
+impl From<String> for Audience {
+    fn from(audience: String) -> Self {
+        Audience::Single(audience)
+    }
+}
+
+impl From<&str> for Audience {
+    fn from(audience: &str) -> Self {
+        Audience::Single(audience.to_string())
+    }
+}
+
+impl PartialEq<str> for Audience {
+    fn eq(&self, other: &str) -> bool {
+        matches!(self, Audience::Single(audience) if audience == other)
+    }
+}
+
+impl PartialEq<&str> for Audience {
+    fn eq(&self, other: &&str) -> bool {
+        self == *other
+    }
+}
+
+/// The members, separated by commas.
+impl fmt::Display for Audience {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.iter().collect::<Vec<_>>().join(","))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
 pub struct Claims {
-    pub aud: String,
+    pub aud: Audience,
     pub exp: i64,
     pub iss: String,
     pub sub: String,
     pub iat: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_time: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phone_number: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub picture: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub firebase: Option<FirebaseClaims>,
+    /// Any claim not listed above, e.g. custom claims set via the Admin SDK.
+    #[serde(flatten)]
+    pub custom: HashMap<String, Value>,
 }
 
-#[derive(Debug)]
-enum VerificationError {
-    InvalidSignature,
+impl Claims {
+    /// Decodes the custom claims into `T`.
+    pub fn custom_claims<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        let map = self
+            .custom
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        serde_json::from_value(Value::Object(map))
+    }
+    /// The user's uid, i.e. `sub`.
+    pub fn uid(&self) -> &str {
+        &self.sub
+    }
+    pub fn email(&self) -> Option<&str> {
+        self.email.as_deref()
+    }
+    /// Whether the email is verified; `false` without an email.
+    pub fn email_verified(&self) -> bool {
+        self.email_verified == Some(true)
+    }
+    pub fn phone_number(&self) -> Option<&str> {
+        self.phone_number.as_deref()
+    }
+    /// `firebase.sign_in_provider`, e.g. `"password"`, `"google.com"` or
+    /// `"custom"` for custom tokens.
+    pub fn sign_in_provider(&self) -> Option<&str> {
+        self.firebase
+            .as_ref()
+            .map(|firebase| firebase.sign_in_provider.as_str())
+    }
+    pub fn is_anonymous(&self) -> bool {
+        self.sign_in_provider() == Some("anonymous")
+    }
+    /// `firebase.sign_in_second_factor`, set if the user signed in with
+    /// multi-factor authentication.
+    pub fn sign_in_second_factor(&self) -> Option<&str> {
+        self.firebase
+            .as_ref()
+            .and_then(|firebase| firebase.sign_in_second_factor.as_deref())
+    }
+    /// Whether the user completed a second factor: `firebase.sign_in_second_factor`
+    /// is set, or, for tokens of other OIDC providers, the `amr` claim lists `mfa`.
+    pub fn is_multi_factor(&self) -> bool {
+        self.sign_in_second_factor().is_some()
+            || self
+                .custom
+                .get("amr")
+                .and_then(Value::as_array)
+                .is_some_and(|amr| amr.iter().any(|method| method == "mfa"))
+    }
+    /// `firebase.tenant`, the Identity Platform tenant of the user.
+    pub fn tenant(&self) -> Option<&str> {
+        self.firebase
+            .as_ref()
+            .and_then(|firebase| firebase.tenant.as_deref())
+    }
+}
+
+/// The `firebase` claim of an ID token.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+pub struct FirebaseClaims {
+    pub sign_in_provider: String,
+    /// The identifiers of the user per provider, e.g. `"email"` or
+    /// `"google.com"`.
+    #[serde(default)]
+    pub identities: HashMap<String, Vec<String>>,
+    /// The second factor the user signed in with, e.g. `"phone"` or `"totp"`,
+    /// if they signed in with multi-factor authentication.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sign_in_second_factor: Option<String>,
+    /// The id of the enrolled second factor the user signed in with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub second_factor_identifier: Option<String>,
+    /// Identity Platform tenant the user belongs to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+}
+
+/// Which identifier of a project a token's `aud` names. Some Google-issued
+/// tokens, e.g. App Check tokens, name projects by number rather than id.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ProjectIdentifier {
+    /// The project id, or any other configured audience.
+    Id,
+    Number,
+}
+
+/// A signature algorithm. Owned by this crate rather than
+/// `jsonwebtoken::Algorithm` for the same reason as [`DecodedToken`]; convert
+/// with `From` where a jsonwebtoken one is needed.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Algorithm {
+    HS256,
+    HS384,
+    HS512,
+    ES256,
+    ES384,
+    RS256,
+    RS384,
+    RS512,
+    PS256,
+    PS384,
+    PS512,
+    EdDSA,
+}
+
+impl From<jsonwebtoken::Algorithm> for Algorithm {
+    fn from(algorithm: jsonwebtoken::Algorithm) -> Self {
+        match algorithm {
+            jsonwebtoken::Algorithm::HS256 => Algorithm::HS256,
+            jsonwebtoken::Algorithm::HS384 => Algorithm::HS384,
+            jsonwebtoken::Algorithm::HS512 => Algorithm::HS512,
+            jsonwebtoken::Algorithm::ES256 => Algorithm::ES256,
+            jsonwebtoken::Algorithm::ES384 => Algorithm::ES384,
+            jsonwebtoken::Algorithm::RS256 => Algorithm::RS256,
+            jsonwebtoken::Algorithm::RS384 => Algorithm::RS384,
+            jsonwebtoken::Algorithm::RS512 => Algorithm::RS512,
+            jsonwebtoken::Algorithm::PS256 => Algorithm::PS256,
+            jsonwebtoken::Algorithm::PS384 => Algorithm::PS384,
+            jsonwebtoken::Algorithm::PS512 => Algorithm::PS512,
+            jsonwebtoken::Algorithm::EdDSA => Algorithm::EdDSA,
+        }
+    }
+}
+
+impl From<Algorithm> for jsonwebtoken::Algorithm {
+    fn from(algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::HS256 => jsonwebtoken::Algorithm::HS256,
+            Algorithm::HS384 => jsonwebtoken::Algorithm::HS384,
+            Algorithm::HS512 => jsonwebtoken::Algorithm::HS512,
+            Algorithm::ES256 => jsonwebtoken::Algorithm::ES256,
+            Algorithm::ES384 => jsonwebtoken::Algorithm::ES384,
+            Algorithm::RS256 => jsonwebtoken::Algorithm::RS256,
+            Algorithm::RS384 => jsonwebtoken::Algorithm::RS384,
+            Algorithm::RS512 => jsonwebtoken::Algorithm::RS512,
+            Algorithm::PS256 => jsonwebtoken::Algorithm::PS256,
+            Algorithm::PS384 => jsonwebtoken::Algorithm::PS384,
+            Algorithm::PS512 => jsonwebtoken::Algorithm::PS512,
+            Algorithm::EdDSA => jsonwebtoken::Algorithm::EdDSA,
+        }
+    }
+}
+
+/// Parses the JWA name, e.g. `RS256`.
+impl FromStr for Algorithm {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        jsonwebtoken::Algorithm::from_str(s)
+            .map(Algorithm::from)
+            .map_err(|_| ())
+    }
+}
+
+/// The header fields of a verified token.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct TokenHeader {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub typ: Option<String>,
+    /// `None` for the unsigned tokens of the emulator.
+    pub alg: Option<Algorithm>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+}
+
+impl From<Header> for TokenHeader {
+    fn from(header: Header) -> Self {
+        TokenHeader {
+            typ: header.typ,
+            alg: Some(header.alg.into()),
+            kid: header.kid,
+        }
+    }
+}
+
+/// The header of an unsigned token gets jsonwebtoken's default algorithm.
+impl From<TokenHeader> for Header {
+    fn from(header: TokenHeader) -> Self {
+        Header {
+            typ: header.typ,
+            alg: header.alg.map(Into::into).unwrap_or_default(),
+            kid: header.kid,
+            ..Header::default()
+        }
+    }
+}
+
+/// The header and claims of a verified token. Owned by this crate rather than
+/// `jsonwebtoken::TokenData`, so that upgrading jsonwebtoken does not change
+/// the signatures of code verifying tokens; convert with `From` where a
+/// `TokenData` is needed.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct DecodedToken<C = Claims> {
+    pub header: TokenHeader,
+    pub claims: C,
+}
+
+impl<C> From<TokenData<C>> for DecodedToken<C> {
+    fn from(token_data: TokenData<C>) -> Self {
+        DecodedToken {
+            header: token_data.header.into(),
+            claims: token_data.claims,
+        }
+    }
+}
+
+impl<C> From<DecodedToken<C>> for TokenData<C> {
+    fn from(token: DecodedToken<C>) -> Self {
+        TokenData {
+            header: token.header.into(),
+            claims: token.claims,
+        }
+    }
+}
+
+/// A verified token along with how it was verified, e.g. to log which key
+/// verified it or to watch for anomalies per key.
+#[derive(Debug, PartialEq, Clone)]
+pub struct VerifiedToken {
+    pub claims: Claims,
+    pub header: TokenHeader,
+    /// The id of the key that verified the signature; `None` in emulator mode,
+    /// where tokens are unsigned.
+    pub kid: Option<String>,
+    /// `None` in emulator mode too.
+    pub algorithm: Option<Algorithm>,
+    pub verified_at: SystemTime,
+    /// Whether `aud` matched the project number or the project id.
+    pub project_identifier: ProjectIdentifier,
+}
+
+impl VerifiedToken {
+    pub(crate) fn new(token_data: DecodedToken<Claims>, config: &JwkConfig) -> VerifiedToken {
+        VerifiedToken {
+            project_identifier: config.project_identifier(&token_data.claims.aud),
+            kid: token_data.header.kid.clone(),
+            algorithm: token_data.header.alg,
+            header: token_data.header,
+            claims: token_data.claims,
+            verified_at: SystemTime::now(),
+        }
+    }
+}
+
+impl From<VerifiedToken> for DecodedToken<Claims> {
+    fn from(token: VerifiedToken) -> Self {
+        DecodedToken {
+            header: token.header,
+            claims: token.claims,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Error)]
+pub enum VerifyError {
+    #[error("malformed token")]
+    Malformed,
+    #[error("missing kid")]
+    MissingKid,
+    #[error("unknown kid: {0}")]
+    UnknownKid(String),
+    #[error("unknown key algorithm")]
     UnknownKeyAlgorithm,
+    /// The token or key uses an algorithm that is not in `allowed_algorithms`.
+    #[error("algorithm not allowed: {0:?}")]
+    AlgorithmNotAllowed(Algorithm),
+    #[error("invalid key")]
+    InvalidKey,
+    #[error("algorithm does not match the key")]
+    AlgorithmMismatch,
+    #[error("invalid signature")]
+    InvalidSignature,
+    #[error("token expired")]
+    Expired,
+    #[error("token not yet valid")]
+    NotYetValid,
+    #[error("wrong audience")]
+    WrongAudience,
+    #[error("wrong issuer")]
+    WrongIssuer,
+    #[error("missing claim: {0}")]
+    MissingClaim(String),
+    #[error("email not verified")]
+    EmailNotVerified,
+    /// The token's sign-in provider is not allowed by the claims policy.
+    #[error("sign-in provider not allowed: {0}")]
+    SignInProviderNotAllowed(String),
+    /// `sub` is empty or longer than 128 characters.
+    #[error("invalid subject")]
+    InvalidSubject,
+    /// `auth_time` is in the future.
+    #[error("invalid auth_time")]
+    InvalidAuthTime,
+    /// The token's `firebase.tenant` does not match the configured tenant.
+    #[error("wrong tenant")]
+    WrongTenant,
+    /// The keys have not been refreshed for longer than the configured
+    /// maximum staleness.
+    #[error("keys are stale")]
+    StaleKeys,
+    #[error("token revoked")]
+    Revoked,
+    #[error("user disabled")]
+    UserDisabled,
+    /// `check_revoked` was requested but no identity toolkit client is configured.
+    #[error("revocation check unavailable")]
+    RevocationCheckUnavailable,
+    #[error("revocation check failed: {0}")]
+    RevocationCheckFailed(String),
+    /// The token's kid is unknown and fetching new keys failed.
+    #[error("key fetch failed: {0}")]
+    KeyFetchFailed(String),
+    /// The token is longer than `StrictMode::max_token_length`.
+    #[error("token too large")]
+    TokenTooLarge,
+    /// The token header has a `crit` parameter, rejected in strict mode.
+    #[error("critical header parameters not supported")]
+    CriticalHeader,
+    /// The token header's `typ` is not `JWT`, rejected in strict mode.
+    #[error("token type is not JWT")]
+    WrongTokenType,
+    /// The user signed in with a single factor, but the claims policy
+    /// requires multi-factor authentication.
+    #[error("multi-factor authentication required")]
+    MultiFactorRequired,
+}
+
+impl From<JwtError> for VerifyError {
+    fn from(error: JwtError) -> Self {
+        match error.kind() {
+            ErrorKind::InvalidSignature => VerifyError::InvalidSignature,
+            ErrorKind::ExpiredSignature => VerifyError::Expired,
+            ErrorKind::ImmatureSignature => VerifyError::NotYetValid,
+            ErrorKind::InvalidAudience => VerifyError::WrongAudience,
+            ErrorKind::InvalidIssuer => VerifyError::WrongIssuer,
+            ErrorKind::InvalidAlgorithm => VerifyError::AlgorithmMismatch,
+            ErrorKind::MissingRequiredClaim(name) => VerifyError::MissingClaim(name.clone()),
+            ErrorKind::InvalidRsaKey(_)
+            | ErrorKind::InvalidEcdsaKey
+            | ErrorKind::InvalidKeyFormat
+            | ErrorKind::Crypto(_) => VerifyError::InvalidKey,
+            _ => VerifyError::Malformed,
+        }
+    }
+}
+
+/// Additional constraints on the claims of otherwise valid tokens.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ClaimsPolicy {
+    pub require_email_verified: bool,
+    /// Reject tokens of users who did not complete a second factor, see
+    /// [`Claims::is_multi_factor`].
+    pub require_mfa: bool,
+    /// Accepted `firebase.sign_in_provider` values; any provider if empty.
+    pub allowed_sign_in_providers: Vec<String>,
+    /// Claims that must be present (and not null) in every verified token.
+    pub required_claims: Vec<String>,
+}
+
+impl ClaimsPolicy {
+    pub fn new() -> ClaimsPolicy {
+        ClaimsPolicy::default()
+    }
+    pub fn require_email_verified(mut self) -> Self {
+        self.require_email_verified = true;
+        self
+    }
+    pub fn require_mfa(mut self) -> Self {
+        self.require_mfa = true;
+        self
+    }
+    /// e.g. `"password"` or `"google.com"`; leave out `"anonymous"` to reject
+    /// anonymous users.
+    pub fn allow_sign_in_provider(mut self, provider: String) -> Self {
+        self.allowed_sign_in_providers.push(provider);
+        self
+    }
+    pub fn require_claim(mut self, claim: String) -> Self {
+        self.required_claims.push(claim);
+        self
+    }
+    pub fn check(&self, claims: &Claims) -> Result<(), VerifyError> {
+        if self.require_email_verified && !claims.email_verified() {
+            return Err(VerifyError::EmailNotVerified);
+        }
+        if self.require_mfa && !claims.is_multi_factor() {
+            return Err(VerifyError::MultiFactorRequired);
+        }
+        if !self.allowed_sign_in_providers.is_empty() {
+            let provider = claims.sign_in_provider().unwrap_or_default();
+            if !self.allowed_sign_in_providers.iter().any(|p| p == provider) {
+                return Err(VerifyError::SignInProviderNotAllowed(provider.to_string()));
+            }
+        }
+        if self.required_claims.is_empty() {
+            return Ok(());
+        }
+        let claims = serde_json::to_value(claims).map_err(|_| VerifyError::Malformed)?;
+        for name in &self.required_claims {
+            match claims.get(name) {
+                Some(Value::Null) | None => return Err(VerifyError::MissingClaim(name.clone())),
+                Some(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How [`RequireClaim`] matches the claim it looks up.
+#[derive(Debug, PartialEq, Clone)]
+enum ClaimMatcher {
+    /// Present, and neither null nor `false`.
+    Present,
+    Equals(Value),
+    /// An array containing the value, or the value itself.
+    Contains(Value),
+}
+
+/// A permission check on the claims of a verified token, e.g. on a role set
+/// as a custom claim with the Admin SDK. Unlike [`ClaimsPolicy`], which makes
+/// a token invalid (401), a failed check means the user is known but not
+/// allowed (403). Applied by the framework integrations, or in a handler with
+/// [`FirebaseUser::require`](crate::integration::FirebaseUser::require).
+///
+/// Claims are looked up by a dot separated path, e.g. `"roles"` or
+/// `"firebase.sign_in_provider"`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RequireClaim {
+    path: String,
+    matcher: ClaimMatcher,
+}
+
+impl RequireClaim {
+    /// Requires the claim to be present and neither null nor `false`, e.g. a
+    /// custom `admin: true` claim.
+    pub fn present(path: &str) -> RequireClaim {
+        RequireClaim {
+            path: path.to_string(),
+            matcher: ClaimMatcher::Present,
+        }
+    }
+    pub fn equals<V: Into<Value>>(path: &str, value: V) -> RequireClaim {
+        RequireClaim {
+            path: path.to_string(),
+            matcher: ClaimMatcher::Equals(value.into()),
+        }
+    }
+    /// Requires the claim to be an array containing `value`, or `value` itself.
+    pub fn contains<V: Into<Value>>(path: &str, value: V) -> RequireClaim {
+        RequireClaim {
+            path: path.to_string(),
+            matcher: ClaimMatcher::Contains(value.into()),
+        }
+    }
+    /// Requires `role` in the `roles` custom claim, which may be a single
+    /// role or an array of roles.
+    pub fn role(role: &str) -> RequireClaim {
+        RequireClaim::contains("roles", role)
+    }
+    pub fn check(&self, claims: &Claims) -> bool {
+        let claims = match serde_json::to_value(claims) {
+            Ok(claims) => claims,
+            Err(_) => return false,
+        };
+        let claim = self
+            .path
+            .split('.')
+            .try_fold(&claims, |value, segment| value.get(segment));
+        match (&self.matcher, claim) {
+            (_, None) | (_, Some(Value::Null)) => false,
+            (ClaimMatcher::Present, Some(claim)) => claim != &Value::Bool(false),
+            (ClaimMatcher::Equals(expected), Some(claim)) => claim == expected,
+            (ClaimMatcher::Contains(expected), Some(Value::Array(values))) => {
+                values.contains(expected)
+            }
+            (ClaimMatcher::Contains(expected), Some(claim)) => claim == expected,
+        }
+    }
+}
+
+/// Opt-in checks on top of those of the official Admin SDKs, run before the
+/// signature is checked:
+/// - the token is at most `max_token_length` bytes long,
+/// - its header has no `crit` parameter and a `typ` of `JWT`,
+/// - its `alg` is the `alg` of the key it was signed with, so keys without
+///   `alg` verify no tokens.
+#[derive(Debug, PartialEq, Clone)]
+pub struct StrictMode {
+    pub max_token_length: usize,
+}
+
+impl Default for StrictMode {
+    fn default() -> Self {
+        StrictMode {
+            max_token_length: DEFAULT_MAX_TOKEN_LENGTH,
+        }
+    }
+}
+
+impl StrictMode {
+    pub fn new() -> StrictMode {
+        StrictMode::default()
+    }
+    pub fn max_token_length(mut self, max_token_length: usize) -> Self {
+        self.max_token_length = max_token_length;
+        self
+    }
+    /// Checks the length and header of `token`.
+    fn check_token(&self, token: &str) -> Result<(), VerifyError> {
+        if token.len() > self.max_token_length {
+            return Err(VerifyError::TokenTooLarge);
+        }
+        let header: Value = decode_segment(token.split('.').next().unwrap_or_default())?;
+        if header.get("crit").is_some() {
+            return Err(VerifyError::CriticalHeader);
+        }
+        if header.get("typ").and_then(Value::as_str) != Some("JWT") {
+            return Err(VerifyError::WrongTokenType);
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct JwkConfig {
-    pub audience: String,
+    /// Tokens are accepted if their `aud` is any of these.
+    pub audiences: Vec<String>,
     pub issuer: String,
+    /// Clock skew tolerated when checking `exp`, `nbf` and `iat`.
+    pub leeway: Duration,
+    pub policy: ClaimsPolicy,
+    /// Tenant tokens must belong to; tokens of any tenant are rejected if unset.
+    pub tenant_id: Option<String>,
+    /// Algorithms accepted for both the token header and the signing key.
+    pub allowed_algorithms: Vec<Algorithm>,
+    /// Stricter checks of the token, off by default.
+    pub strict: Option<StrictMode>,
+    /// The number of the project, accepted as an audience besides `audiences`.
+    pub project_number: Option<String>,
+}
+
+impl Default for JwkConfig {
+    fn default() -> Self {
+        JwkConfig {
+            audiences: Vec::new(),
+            issuer: String::new(),
+            leeway: Duration::from_secs(0),
+            policy: ClaimsPolicy::default(),
+            tenant_id: None,
+            allowed_algorithms: vec![Algorithm::RS256],
+            strict: None,
+            project_number: None,
+        }
+    }
+}
+
+impl JwkConfig {
+    pub fn new(audience: String, issuer: String) -> JwkConfig {
+        JwkConfig {
+            audiences: vec![audience],
+            issuer,
+            ..JwkConfig::default()
+        }
+    }
+    fn validation(&self, algorithms: Vec<Algorithm>) -> Validation {
+        let mut validation = Validation::default();
+        validation.algorithms = algorithms.into_iter().map(Into::into).collect();
+        let audiences: Vec<&String> = self
+            .audiences
+            .iter()
+            .chain(self.project_number.as_ref())
+            .collect();
+        validation.set_audience(&audiences);
+        validation.set_issuer(&[&self.issuer]);
+        validation.leeway = self.leeway.as_secs();
+        validation
+    }
+    /// Whether any member of `audience` is accepted.
+    pub(crate) fn accepts_audience(&self, audience: &Audience) -> bool {
+        audience.iter().any(|member| {
+            self.audiences.iter().any(|accepted| accepted == member)
+                || self.project_number.as_deref() == Some(member)
+        })
+    }
+    fn project_identifier(&self, audience: &Audience) -> ProjectIdentifier {
+        if self
+            .project_number
+            .as_deref()
+            .is_some_and(|number| audience.contains(number))
+        {
+            ProjectIdentifier::Number
+        } else {
+            ProjectIdentifier::Id
+        }
+    }
+    fn check_algorithm(&self, algorithm: Algorithm) -> Result<(), VerifyError> {
+        if self.allowed_algorithms.contains(&algorithm) {
+            Ok(())
+        } else {
+            Err(VerifyError::AlgorithmNotAllowed(algorithm))
+        }
+    }
+    /// The allowed ones of the algorithms a key can be used with.
+    fn allowed_key_algorithms(
+        &self,
+        algorithms: &[Algorithm],
+    ) -> Result<Vec<Algorithm>, VerifyError> {
+        let allowed: Vec<Algorithm> = algorithms
+            .iter()
+            .copied()
+            .filter(|algorithm| self.allowed_algorithms.contains(algorithm))
+            .collect();
+        match algorithms.first() {
+            Some(&algorithm) if allowed.is_empty() => {
+                Err(VerifyError::AlgorithmNotAllowed(algorithm))
+            }
+            _ => Ok(allowed),
+        }
+    }
+    /// Checks `sub`, `iat` and `auth_time` the way the official Admin SDKs do.
+    fn check_standard_claims(&self, claims: &Claims) -> Result<(), VerifyError> {
+        if claims.sub.is_empty() || claims.sub.chars().count() > MAX_SUBJECT_LENGTH {
+            return Err(VerifyError::InvalidSubject);
+        }
+        let latest = current_timestamp()? + self.leeway.as_secs() as i64;
+        if claims.iat > latest {
+            return Err(VerifyError::NotYetValid);
+        }
+        if claims.auth_time.is_some_and(|auth_time| auth_time > latest) {
+            return Err(VerifyError::InvalidAuthTime);
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug, PartialEq)]
 pub struct JwkVerifier {
     keys: HashMap<String, Jwk>,
+    /// Built from `keys` whenever they are set, so that verifying a token
+    /// does not parse the key again.
+    prepared_keys: HashMap<String, Result<PreparedKey, VerifyError>>,
     config: JwkConfig,
+    emulator: bool,
+    /// Tokens recently verified by [`verify`](Self::verify), cleared when the
+    /// keys change.
+    token_cache: Option<TokenCache>,
+}
+
+impl fmt::Debug for JwkVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwkVerifier")
+            .field("keys", &self.keys)
+            .field("config", &self.config)
+            .field("emulator", &self.emulator)
+            .finish_non_exhaustive()
+    }
+}
+
+/// `prepared_keys` follow from `keys` and `config`, and `token_cache` only
+/// holds results.
+impl PartialEq for JwkVerifier {
+    fn eq(&self, other: &Self) -> bool {
+        self.keys == other.keys && self.config == other.config && self.emulator == other.emulator
+    }
+}
+
+/// Which config a token is verified against.
+#[derive(Clone, Copy)]
+enum VerifyConfig<'a> {
+    /// The verifier's own, whose validation is prepared with its keys.
+    Own,
+    /// Another one, whose validation is built for every token.
+    Other(&'a JwkConfig),
+}
+
+/// A key ready to verify tokens with.
+struct PreparedKey {
+    alg: Option<Algorithm>,
+    /// The key's `alg`, or every algorithm of its key type if it has none.
+    algorithms: Vec<Algorithm>,
+    decoding_key: DecodingKey,
+    /// The validation for the verifier's own config.
+    validation: Validation,
+}
+
+impl PreparedKey {
+    fn new(key: &Jwk, config: &JwkConfig) -> Result<PreparedKey, VerifyError> {
+        let alg = match &key.alg {
+            Some(alg) => {
+                Some(Algorithm::from_str(alg).map_err(|_| VerifyError::UnknownKeyAlgorithm)?)
+            }
+            None => None,
+        };
+        let algorithms = match alg {
+            Some(alg) => vec![alg],
+            None => key_type_algorithms(&key.params),
+        };
+        if algorithms.is_empty() {
+            return Err(VerifyError::UnknownKeyAlgorithm);
+        }
+        let validation = config.validation(
+            config
+                .allowed_key_algorithms(&algorithms)
+                .unwrap_or_default(),
+        );
+        Ok(PreparedKey {
+            alg,
+            algorithms,
+            // The token is not to blame for a key it cannot be decoded with.
+            decoding_key: key.decoding_key().map_err(|_| VerifyError::InvalidKey)?,
+            validation,
+        })
+    }
+}
+
+/// The algorithms a key without `alg` can be used with, by key type and curve.
+fn key_type_algorithms(params: &JwkParams) -> Vec<Algorithm> {
+    match params {
+        JwkParams::Rsa { .. } => vec![
+            Algorithm::RS256,
+            Algorithm::RS384,
+            Algorithm::RS512,
+            Algorithm::PS256,
+            Algorithm::PS384,
+            Algorithm::PS512,
+        ],
+        JwkParams::Ec { crv, .. } if crv == "P-256" => vec![Algorithm::ES256],
+        JwkParams::Ec { crv, .. } if crv == "P-384" => vec![Algorithm::ES384],
+        JwkParams::Okp { crv, .. } if crv == "Ed25519" => vec![Algorithm::EdDSA],
+        _ => Vec::new(),
+    }
+}
+
+fn prepare_keys(
+    keys: &HashMap<String, Jwk>,
+    config: &JwkConfig,
+) -> HashMap<String, Result<PreparedKey, VerifyError>> {
+    keys.iter()
+        .map(|(kid, key)| (kid.clone(), PreparedKey::new(key, config)))
+        .collect()
 }
 
 fn keys_to_map(keys: Vec<Jwk>) -> HashMap<String, Jwk> {
     let mut keys_as_map = HashMap::new();
-    for key in keys {
+    for key in keys.into_iter().filter(Jwk::is_signing_key) {
         keys_as_map.insert(key.kid.clone(), key);
     }
     keys_as_map
 }
 
+fn current_timestamp() -> Result<i64, VerifyError> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .map_err(|_| VerifyError::Expired)
+}
+
+fn decode_segment<T: DeserializeOwned>(segment: &str) -> Result<T, VerifyError> {
+    let decoded = base64::decode_config(segment, base64::URL_SAFE_NO_PAD)
+        .map_err(|_| VerifyError::Malformed)?;
+    serde_json::from_slice(&decoded).map_err(|_| VerifyError::Malformed)
+}
+
+/// Replaces the claims of already verified `token_data` by the token's payload
+/// deserialized into `C`.
+pub(crate) fn into_token_data<C: DeserializeOwned>(
+    token: &str,
+    token_data: DecodedToken<Claims>,
+) -> Result<DecodedToken<C>, VerifyError> {
+    let payload = token.split('.').nth(1).ok_or(VerifyError::Malformed)?;
+    Ok(DecodedToken {
+        header: token_data.header,
+        claims: decode_segment(payload)?,
+    })
+}
+
+#[derive(Deserialize)]
+struct AudienceClaim {
+    aud: Audience,
+}
+
+/// A token header as sent, including the `"alg":"none"` of unsigned tokens,
+/// which jsonwebtoken refuses to decode.
+#[derive(Deserialize)]
+struct RawHeader {
+    typ: Option<String>,
+    alg: String,
+    kid: Option<String>,
+}
+
+impl RawHeader {
+    fn into_header(self) -> Result<TokenHeader, VerifyError> {
+        let alg = match self.alg.as_str() {
+            "none" => None,
+            alg => Some(
+                alg.parse::<jsonwebtoken::Algorithm>()
+                    .map_err(|_| VerifyError::Malformed)?
+                    .into(),
+            ),
+        };
+        Ok(TokenHeader {
+            typ: self.typ,
+            alg,
+            kid: self.kid,
+        })
+    }
+}
+
+/// Reads the claims of a token without verifying it.
+pub(crate) fn unverified_claims<C: DeserializeOwned>(token: &str) -> Result<C, VerifyError> {
+    let payload = token.split('.').nth(1).ok_or(VerifyError::Malformed)?;
+    decode_segment(payload)
+}
+
+/// Reads the `aud` claim of a token without verifying it, e.g. to pick the
+/// config to verify the token with.
+pub(crate) fn unverified_audience(token: &str) -> Result<Audience, VerifyError> {
+    unverified_claims::<AudienceClaim>(token).map(|claim| claim.aud)
+}
+
+/// Decodes the header and claims of a token **without verifying it**.
+///
+/// Never use the result to authenticate or authorize anything: anyone can
+/// craft a token decoding to any claims. It is meant for logging the `kid`
+/// or `aud` of tokens that failed verification, routing a request to the
+/// project or tenant to verify it with, and building better error messages.
+pub fn decode_unverified(token: &str) -> Result<(TokenHeader, Claims), VerifyError> {
+    let header = token.split('.').next().ok_or(VerifyError::Malformed)?;
+    let header = decode_segment::<RawHeader>(header)?.into_header()?;
+    let claims = unverified_claims(token)?;
+    Ok((header, claims))
+}
+
 impl JwkVerifier {
+    #[cfg(test)]
     pub fn new(keys: Vec<Jwk>, audience: String, issuer: String) -> JwkVerifier {
+        Self::with_config(keys, JwkConfig::new(audience, issuer))
+    }
+    pub fn with_config(keys: Vec<Jwk>, config: JwkConfig) -> JwkVerifier {
+        let keys = keys_to_map(keys);
+        JwkVerifier {
+            prepared_keys: prepare_keys(&keys, &config),
+            keys,
+            config,
+            emulator: false,
+            token_cache: None,
+        }
+    }
+    /// A verifier for the Firebase Auth emulator, which issues unsigned tokens.
+    /// Signatures are not checked, but aud/iss/exp still are.
+    pub fn emulator_with_config(config: JwkConfig) -> JwkVerifier {
         JwkVerifier {
-            keys: keys_to_map(keys),
-            config: JwkConfig { audience, issuer },
+            keys: HashMap::new(),
+            prepared_keys: HashMap::new(),
+            config,
+            emulator: true,
+            token_cache: None,
         }
     }
+    #[cfg(test)]
     pub fn get_key(&self, key_id: &str) -> Option<&Jwk> {
         self.keys.get(key_id)
     }
@@ -55,38 +970,177 @@ impl JwkVerifier {
     pub fn get_config(&self) -> Option<&JwkConfig> {
         Some(&self.config)
     }
+    pub(crate) fn config(&self) -> &JwkConfig {
+        &self.config
+    }
+    fn resolve<'a>(&'a self, config: VerifyConfig<'a>) -> &'a JwkConfig {
+        match config {
+            VerifyConfig::Own => &self.config,
+            VerifyConfig::Other(config) => config,
+        }
+    }
     fn decode_token_with_key(
         &self,
-        key: &Jwk,
-        token: &String,
-    ) -> Result<TokenData<Claims>, VerificationError> {
-        let algorithm = match Algorithm::from_str(&key.alg) {
-            Ok(alg) => alg,
-            Err(_error) => return Err(VerificationError::UnknownKeyAlgorithm),
+        key: &Result<PreparedKey, VerifyError>,
+        token: &str,
+        config: VerifyConfig<'_>,
+    ) -> Result<DecodedToken<Claims>, VerifyError> {
+        let key = key.as_ref().map_err(Clone::clone)?;
+        let algorithms = self
+            .resolve(config)
+            .allowed_key_algorithms(&key.algorithms)?;
+        let decoded = match config {
+            VerifyConfig::Own => decode::<Claims>(token, &key.decoding_key, &key.validation),
+            VerifyConfig::Other(config) => {
+                decode::<Claims>(token, &key.decoding_key, &config.validation(algorithms))
+            }
         };
-        let mut validation = Validation::new(algorithm);
-        validation.set_audience(&[&self.config.audience]);
-        validation.iss = Some(self.config.issuer.clone());
-        let key = DecodingKey::from_rsa_components(&key.n, &key.e);
-        return decode::<Claims>(token, &key, &validation)
-            .map_err(|_| VerificationError::InvalidSignature);
+        decoded.map(DecodedToken::from).map_err(VerifyError::from)
+    }
+    /// Caches the results of [`verify`](Self::verify) for up to `capacity`
+    /// tokens.
+    pub(crate) fn cache_tokens(&mut self, capacity: usize) {
+        self.token_cache = Some(TokenCache::new(capacity, self.config.leeway));
+    }
+    /// Drops the cached result of `token`, e.g. once it was found revoked.
+    #[cfg(feature = "reqwest")]
+    pub(crate) fn forget_token(&self, token: &str) {
+        if let Some(cache) = &self.token_cache {
+            cache.remove(token);
+        }
     }
     pub fn set_keys(&mut self, keys: Vec<Jwk>) {
+        if let Some(cache) = self.token_cache.as_ref().filter(|_| !self.has_keys(&keys)) {
+            cache.clear();
+        }
         self.keys = keys_to_map(keys);
+        self.prepared_keys = prepare_keys(&self.keys, &self.config);
+    }
+    /// The ids of the keys in use, sorted.
+    pub(crate) fn key_ids(&self) -> Vec<String> {
+        let mut key_ids: Vec<String> = self.keys.keys().cloned().collect();
+        key_ids.sort();
+        key_ids
+    }
+    /// Whether `keys` are exactly the keys in use.
+    pub(crate) fn has_keys(&self, keys: &[Jwk]) -> bool {
+        let keys: Vec<&Jwk> = keys.iter().filter(|key| key.is_signing_key()).collect();
+        self.keys.len() == keys.len() && keys.iter().all(|key| self.keys.get(&key.kid) == Some(key))
+    }
+    fn decode_unsigned_token(
+        &self,
+        token: &str,
+        config: &JwkConfig,
+    ) -> Result<DecodedToken<Claims>, VerifyError> {
+        let segments: Vec<&str> = token.split('.').collect();
+        if segments.len() != 3 {
+            return Err(VerifyError::Malformed);
+        }
+        let header: Value = decode_segment(segments[0])?;
+        let claims: Claims = decode_segment(segments[1])?;
+        if claims.exp + (config.leeway.as_secs() as i64) < current_timestamp()? {
+            return Err(VerifyError::Expired);
+        }
+        if claims.iss != config.issuer {
+            return Err(VerifyError::WrongIssuer);
+        }
+        if !config.accepts_audience(&claims.aud) {
+            return Err(VerifyError::WrongAudience);
+        }
+        let field = |name: &str| {
+            header
+                .get(name)
+                .and_then(|value| value.as_str())
+                .map(|value| value.to_string())
+        };
+        Ok(DecodedToken {
+            header: TokenHeader {
+                typ: field("typ"),
+                alg: None,
+                kid: field("kid"),
+            },
+            claims,
+        })
     }
-    pub fn verify(&self, token: &String) -> Option<TokenData<Claims>> {
-        let token_kid = match decode_header(token).map(|header| header.kid) {
-            Ok(Some(header)) => header,
-            _ => return None,
+    pub fn verify(&self, token: &str) -> Result<DecodedToken<Claims>, VerifyError> {
+        let cache = match &self.token_cache {
+            Some(cache) => cache,
+            None => return self.verify_for_tenant(token, self.config.tenant_id.as_deref()),
         };
-        let jwk_key = match self.get_key(&token_kid) {
+        if let Some(token_data) = cache.get(token) {
+            return Ok(token_data);
+        }
+        let token_data = self.verify_for_tenant(token, self.config.tenant_id.as_deref())?;
+        cache.insert(token, &token_data);
+        Ok(token_data)
+    }
+    /// Verifies the token like [`verify`](Self::verify), then deserializes its
+    /// payload into `C`.
+    pub fn verify_into<C: DeserializeOwned>(
+        &self,
+        token: &str,
+    ) -> Result<DecodedToken<C>, VerifyError> {
+        let token_data = self.verify(token)?;
+        into_token_data(token, token_data)
+    }
+    pub fn verify_for_tenant(
+        &self,
+        token: &str,
+        tenant_id: Option<&str>,
+    ) -> Result<DecodedToken<Claims>, VerifyError> {
+        self.verify_checked(token, VerifyConfig::Own, tenant_id)
+    }
+    /// Verifies the token against `config` instead of this verifier's own
+    /// config, still using its keys.
+    pub fn verify_with_config(
+        &self,
+        token: &str,
+        config: &JwkConfig,
+        tenant_id: Option<&str>,
+    ) -> Result<DecodedToken<Claims>, VerifyError> {
+        self.verify_checked(token, VerifyConfig::Other(config), tenant_id)
+    }
+    fn verify_checked(
+        &self,
+        token: &str,
+        config: VerifyConfig<'_>,
+        tenant_id: Option<&str>,
+    ) -> Result<DecodedToken<Claims>, VerifyError> {
+        let token_data = self.verify_token(token, config)?;
+        let config = self.resolve(config);
+        config.check_standard_claims(&token_data.claims)?;
+        config.policy.check(&token_data.claims)?;
+        if token_data.claims.tenant() != tenant_id {
+            return Err(VerifyError::WrongTenant);
+        }
+        Ok(token_data)
+    }
+    fn verify_token(
+        &self,
+        token: &str,
+        verify_config: VerifyConfig<'_>,
+    ) -> Result<DecodedToken<Claims>, VerifyError> {
+        let config = self.resolve(verify_config);
+        if let Some(strict) = &config.strict {
+            strict.check_token(token)?;
+        }
+        if self.emulator {
+            return self.decode_unsigned_token(token, config);
+        }
+        let header = decode_header(token).map_err(|_| VerifyError::Malformed)?;
+        let alg = Algorithm::from(header.alg);
+        config.check_algorithm(alg)?;
+        let token_kid = header.kid.ok_or(VerifyError::MissingKid)?;
+        let key = match self.prepared_keys.get(&token_kid) {
             Some(key) => key,
-            _ => return None,
+            None => return Err(VerifyError::UnknownKid(token_kid)),
         };
-        match self.decode_token_with_key(jwk_key, token) {
-            Ok(token_data) => Some(token_data),
-            _ => None,
+        if let (Some(_), Ok(key)) = (&config.strict, key) {
+            if key.alg != Some(alg) {
+                return Err(VerifyError::AlgorithmMismatch);
+            }
         }
+        self.decode_token_with_key(key, token, verify_config)
     }
 }
 
@@ -94,6 +1148,103 @@ impl JwkVerifier {
 mod tests {
     use super::*;
     use crate::tests::*;
+    use jsonwebtoken::{encode, EncodingKey};
+    use serde_json::json;
+
+    #[test]
+    fn test_claims_accessors() {
+        let mut claims = Claims {
+            sub: "uid".to_string(),
+            email: Some("user@example.com".to_string()),
+            firebase: Some(FirebaseClaims {
+                sign_in_provider: "password".to_string(),
+                tenant: Some("tenant-1".to_string()),
+                ..FirebaseClaims::default()
+            }),
+            ..Claims::default()
+        };
+        assert_eq!(claims.uid(), "uid");
+        assert_eq!(claims.email(), Some("user@example.com"));
+        assert!(!claims.email_verified());
+        assert_eq!(claims.phone_number(), None);
+        assert_eq!(claims.sign_in_provider(), Some("password"));
+        assert!(!claims.is_anonymous());
+        assert_eq!(claims.tenant(), Some("tenant-1"));
+
+        claims.firebase = Some(FirebaseClaims {
+            sign_in_provider: "anonymous".to_string(),
+            ..FirebaseClaims::default()
+        });
+        assert!(claims.is_anonymous());
+        assert_eq!(claims.tenant(), None);
+        claims.firebase = None;
+        assert_eq!(claims.sign_in_provider(), None);
+        assert!(!claims.is_anonymous());
+    }
+
+    #[test]
+    fn test_firebase_claims_second_factor() {
+        let firebase: FirebaseClaims = serde_json::from_value(json!({
+            "identities": { "email": ["user@example.com"] },
+            "sign_in_provider": "password",
+            "sign_in_second_factor": "phone",
+            "second_factor_identifier": "factor-1"
+        }))
+        .unwrap();
+        assert_eq!(
+            firebase,
+            FirebaseClaims {
+                sign_in_provider: "password".to_string(),
+                identities: HashMap::from([(
+                    "email".to_string(),
+                    vec!["user@example.com".to_string()]
+                )]),
+                sign_in_second_factor: Some("phone".to_string()),
+                second_factor_identifier: Some("factor-1".to_string()),
+                tenant: None,
+            }
+        );
+        let claims = Claims {
+            firebase: Some(firebase),
+            ..Claims::default()
+        };
+        assert_eq!(claims.sign_in_second_factor(), Some("phone"));
+    }
+
+    #[test]
+    fn test_decoded_token() {
+        let mut header = Header::new(Algorithm::RS256.into());
+        header.kid = Some("kid".to_string());
+        let token_data = TokenData {
+            header,
+            claims: get_test_claims("aud", "iss"),
+        };
+        let decoded = DecodedToken::from(token_data.clone());
+        assert_eq!(
+            decoded.header,
+            TokenHeader {
+                typ: Some("JWT".to_string()),
+                alg: Some(Algorithm::RS256),
+                kid: Some("kid".to_string()),
+            }
+        );
+        let json = serde_json::to_string(&decoded).unwrap();
+        assert_eq!(
+            serde_json::from_str::<DecodedToken>(&json).unwrap(),
+            decoded
+        );
+        let back: TokenData<Claims> = decoded.into();
+        assert_eq!(back.header, token_data.header);
+        assert_eq!(back.claims, token_data.claims);
+    }
+
+    #[test]
+    fn test_algorithm() {
+        assert_eq!(Algorithm::from_str("PS384"), Ok(Algorithm::PS384));
+        assert_eq!(Algorithm::from_str("none"), Err(()));
+        let algorithm: jsonwebtoken::Algorithm = Algorithm::EdDSA.into();
+        assert_eq!(Algorithm::from(algorithm), Algorithm::EdDSA);
+    }
 
     #[test]
     fn test_keys_to_map() {
@@ -109,12 +1260,13 @@ mod tests {
     fn test_jwk_verifier_new() {
         let keys = get_test_keys();
         let map = keys_to_map(keys.clone());
+        let config = JwkConfig::new("aud".to_string(), "iss".to_string());
         let expected = JwkVerifier {
+            prepared_keys: prepare_keys(&map, &config),
             keys: map,
-            config: JwkConfig {
-                audience: "aud".to_string(),
-                issuer: "iss".to_string(),
-            },
+            config,
+            emulator: false,
+            token_cache: None,
         };
         let obtained = JwkVerifier::new(keys, "aud".to_string(), "iss".to_string());
         assert_eq!(expected, obtained);
@@ -134,10 +1286,7 @@ mod tests {
         let verifier = JwkVerifier::new(keys.clone(), "aud".to_string(), "iss".to_string());
         assert_eq!(
             verifier.get_config(),
-            Some(&JwkConfig {
-                audience: "aud".to_string(),
-                issuer: "iss".to_string(),
-            })
+            Some(&JwkConfig::new("aud".to_string(), "iss".to_string()))
         );
     }
 
@@ -148,4 +1297,618 @@ mod tests {
         verifier.set_keys(vec![]);
         assert!(verifier.get_key("kid-0").is_none());
     }
+
+    #[test]
+    fn test_prepared_keys() {
+        let mut unknown_algorithm = get_signing_key();
+        unknown_algorithm.kid = "unknown-algorithm".to_string();
+        unknown_algorithm.alg = Some("XX256".to_string());
+        let mut invalid = get_signing_key();
+        invalid.kid = "invalid".to_string();
+        invalid.params = JwkParams::Rsa {
+            n: "!".to_string(),
+            e: "AQAB".to_string(),
+        };
+        let mut verifier = JwkVerifier::new(
+            vec![unknown_algorithm, invalid],
+            "aud".to_string(),
+            "iss".to_string(),
+        );
+        let claims = get_test_claims("aud", "iss");
+        let verify =
+            |verifier: &JwkVerifier, kid| verifier.verify(&create_token(Some(kid), &claims));
+        assert_eq!(
+            verify(&verifier, "unknown-algorithm").unwrap_err(),
+            VerifyError::UnknownKeyAlgorithm
+        );
+        assert_eq!(
+            verify(&verifier, "invalid").unwrap_err(),
+            VerifyError::InvalidKey
+        );
+
+        verifier.set_keys(vec![get_signing_key()]);
+        assert!(verify(&verifier, SIGNING_KID).is_ok());
+        let other_config = JwkConfig::new("other".to_string(), "iss".to_string());
+        let token = create_token(Some(SIGNING_KID), &get_test_claims("other", "iss"));
+        assert!(verifier
+            .verify_with_config(&token, &other_config, None)
+            .is_ok());
+        assert_eq!(
+            verifier.verify(&token).unwrap_err(),
+            VerifyError::WrongAudience
+        );
+    }
+
+    fn get_signing_verifier() -> JwkVerifier {
+        JwkVerifier::new(
+            vec![get_signing_key()],
+            "aud".to_string(),
+            "iss".to_string(),
+        )
+    }
+
+    #[test]
+    fn test_verify() {
+        let claims = get_test_claims("aud", "iss");
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let result = get_signing_verifier().verify(&token);
+        assert_eq!(result.unwrap().claims, claims);
+    }
+
+    #[test]
+    fn test_verify_firebase_claims() {
+        let mut claims = get_test_claims("aud", "iss");
+        claims.auth_time = Some(now());
+        claims.email = Some("user@example.com".to_string());
+        claims.email_verified = Some(true);
+        let mut identities = HashMap::new();
+        identities.insert("email".to_string(), vec!["user@example.com".to_string()]);
+        claims.firebase = Some(FirebaseClaims {
+            sign_in_provider: "password".to_string(),
+            identities,
+            ..FirebaseClaims::default()
+        });
+        claims
+            .custom
+            .insert("role".to_string(), Value::String("admin".to_string()));
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let result = get_signing_verifier().verify(&token).unwrap();
+        assert_eq!(result.claims, claims);
+        assert_eq!(
+            result.claims.custom.get("role"),
+            Some(&Value::String("admin".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_custom_claims() {
+        #[derive(Deserialize)]
+        struct Roles {
+            role: String,
+            #[serde(default)]
+            premium: bool,
+        }
+        let mut claims = get_test_claims("aud", "iss");
+        claims
+            .custom
+            .insert("role".to_string(), Value::String("admin".to_string()));
+        let roles: Roles = claims.custom_claims().unwrap();
+        assert_eq!(roles.role, "admin");
+        assert!(!roles.premium);
+    }
+
+    #[test]
+    fn test_verify_into() {
+        #[derive(Debug, Deserialize)]
+        struct MyClaims {
+            sub: String,
+            role: String,
+        }
+        let mut claims = get_test_claims("aud", "iss");
+        claims
+            .custom
+            .insert("role".to_string(), Value::String("admin".to_string()));
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let verifier = get_signing_verifier();
+        let result = verifier.verify_into::<MyClaims>(&token).unwrap();
+        assert_eq!(result.claims.sub, "uid");
+        assert_eq!(result.claims.role, "admin");
+
+        let token = create_token(Some(SIGNING_KID), &get_test_claims("aud", "iss"));
+        assert_eq!(
+            verifier.verify_into::<MyClaims>(&token).unwrap_err(),
+            VerifyError::Malformed
+        );
+    }
+
+    #[test]
+    fn test_verify_ec_and_ed_keys() {
+        let mut config = JwkConfig::new("aud".to_string(), "iss".to_string());
+        config.allowed_algorithms = vec![Algorithm::ES256, Algorithm::EdDSA];
+        let verifier =
+            JwkVerifier::with_config(vec![get_ec_signing_key(), get_ed_signing_key()], config);
+        let claims = get_test_claims("aud", "iss");
+        let keys = [
+            (
+                Algorithm::ES256,
+                EC_SIGNING_KID,
+                EncodingKey::from_ec_pem(EC_PRIVATE_KEY_PEM.as_bytes()).unwrap(),
+            ),
+            (
+                Algorithm::EdDSA,
+                ED_SIGNING_KID,
+                EncodingKey::from_ed_pem(ED_PRIVATE_KEY_PEM.as_bytes()).unwrap(),
+            ),
+        ];
+        for (algorithm, kid, key) in keys {
+            let mut header = Header::new(algorithm.into());
+            header.kid = Some(kid.to_string());
+            let token = encode(&header, &claims, &key).unwrap();
+            assert_eq!(verifier.verify(&token).unwrap().claims, claims);
+        }
+    }
+
+    #[test]
+    fn test_verify_algorithm_not_allowed() {
+        let claims = get_test_claims("aud", "iss");
+        let mut header = Header::new(Algorithm::ES256.into());
+        header.kid = Some(EC_SIGNING_KID.to_string());
+        let key = EncodingKey::from_ec_pem(EC_PRIVATE_KEY_PEM.as_bytes()).unwrap();
+        let token = encode(&header, &claims, &key).unwrap();
+        let verifier = JwkVerifier::new(
+            vec![get_ec_signing_key()],
+            "aud".to_string(),
+            "iss".to_string(),
+        );
+        assert_eq!(
+            verifier.verify(&token).unwrap_err(),
+            VerifyError::AlgorithmNotAllowed(Algorithm::ES256)
+        );
+
+        // A key advertising a disallowed algorithm is rejected even if the
+        // token header claims an allowed one.
+        let mut key = get_signing_key();
+        key.alg = Some("RS512".to_string());
+        let verifier = JwkVerifier::new(vec![key], "aud".to_string(), "iss".to_string());
+        let token = create_token(Some(SIGNING_KID), &claims);
+        assert_eq!(
+            verifier.verify(&token).unwrap_err(),
+            VerifyError::AlgorithmNotAllowed(Algorithm::RS512)
+        );
+    }
+
+    #[test]
+    fn test_verify_key_without_alg_and_use() {
+        let claims = get_test_claims("aud", "iss");
+        let mut rsa_key = get_signing_key();
+        rsa_key.alg = None;
+        rsa_key.r#use = None;
+        let mut ec_key = get_ec_signing_key();
+        ec_key.alg = None;
+        let mut config = JwkConfig::new("aud".to_string(), "iss".to_string());
+        config.allowed_algorithms = vec![Algorithm::RS256, Algorithm::ES256];
+        let verifier = JwkVerifier::with_config(vec![rsa_key, ec_key.clone()], config);
+        let token = create_token(Some(SIGNING_KID), &claims);
+        assert_eq!(verifier.verify(&token).unwrap().claims, claims);
+
+        let mut header = Header::new(Algorithm::ES256.into());
+        header.kid = Some(EC_SIGNING_KID.to_string());
+        let key = EncodingKey::from_ec_pem(EC_PRIVATE_KEY_PEM.as_bytes()).unwrap();
+        let ec_token = encode(&header, &claims, &key).unwrap();
+        assert_eq!(verifier.verify(&ec_token).unwrap().claims, claims);
+
+        // The algorithm inferred from the key type must still be allowed.
+        let verifier = JwkVerifier::new(vec![ec_key], "aud".to_string(), "iss".to_string());
+        let mut token = create_token(Some(EC_SIGNING_KID), &claims);
+        assert_eq!(
+            verifier.verify(&token).unwrap_err(),
+            VerifyError::AlgorithmNotAllowed(Algorithm::ES256)
+        );
+
+        // Keys not meant for signatures are ignored.
+        let mut encryption_key = get_signing_key();
+        encryption_key.r#use = Some("enc".to_string());
+        let keys = vec![encryption_key];
+        let verifier = JwkVerifier::new(keys.clone(), "aud".to_string(), "iss".to_string());
+        token = create_token(Some(SIGNING_KID), &claims);
+        assert_eq!(
+            verifier.verify(&token).unwrap_err(),
+            VerifyError::UnknownKid(SIGNING_KID.to_string())
+        );
+        assert!(verifier.has_keys(&keys));
+    }
+
+    #[test]
+    fn test_verify_strict_mode() {
+        let claims = get_test_claims("aud", "iss");
+        let mut config = JwkConfig::new("aud".to_string(), "iss".to_string());
+        config.strict = Some(StrictMode::new());
+        let verifier = JwkVerifier::with_config(vec![get_signing_key()], config.clone());
+        let token = create_token(Some(SIGNING_KID), &claims);
+        assert_eq!(verifier.verify(&token).unwrap().claims, claims);
+
+        let encode_segment = |bytes: &[u8]| base64::encode_config(bytes, base64::URL_SAFE_NO_PAD);
+        let signature = token.rsplit('.').next().unwrap();
+        let with_header = |header: &str| {
+            format!(
+                "{}.{}.{}",
+                encode_segment(header.as_bytes()),
+                encode_segment(&serde_json::to_vec(&claims).unwrap()),
+                signature
+            )
+        };
+        let header = format!(
+            r#"{{"alg":"RS256","typ":"JWT","kid":"{}","crit":["exp"]}}"#,
+            SIGNING_KID
+        );
+        assert_eq!(
+            verifier.verify(&with_header(&header)).unwrap_err(),
+            VerifyError::CriticalHeader
+        );
+        let header = format!(r#"{{"alg":"RS256","kid":"{}"}}"#, SIGNING_KID);
+        assert_eq!(
+            verifier.verify(&with_header(&header)).unwrap_err(),
+            VerifyError::WrongTokenType
+        );
+
+        let mut key = get_signing_key();
+        key.alg = None;
+        let verifier = JwkVerifier::with_config(vec![key], config.clone());
+        assert_eq!(
+            verifier.verify(&token).unwrap_err(),
+            VerifyError::AlgorithmMismatch
+        );
+
+        config.strict = Some(StrictMode::new().max_token_length(token.len() - 1));
+        let verifier = JwkVerifier::with_config(vec![get_signing_key()], config);
+        assert_eq!(
+            verifier.verify(&token).unwrap_err(),
+            VerifyError::TokenTooLarge
+        );
+    }
+
+    #[test]
+    fn test_verify_malformed() {
+        let result = get_signing_verifier().verify("not-a-token");
+        assert_eq!(result.unwrap_err(), VerifyError::Malformed);
+    }
+
+    #[test]
+    fn test_verify_tenant() {
+        let mut claims = get_test_claims("aud", "iss");
+        claims.firebase = Some(FirebaseClaims {
+            sign_in_provider: "password".to_string(),
+            tenant: Some("tenant-1".to_string()),
+            ..FirebaseClaims::default()
+        });
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let verifier = get_signing_verifier();
+        assert_eq!(
+            verifier.verify(&token).unwrap_err(),
+            VerifyError::WrongTenant
+        );
+        assert!(verifier.verify_for_tenant(&token, Some("tenant-1")).is_ok());
+        assert_eq!(
+            verifier
+                .verify_for_tenant(&token, Some("tenant-2"))
+                .unwrap_err(),
+            VerifyError::WrongTenant
+        );
+        let project_token = create_token(Some(SIGNING_KID), &get_test_claims("aud", "iss"));
+        assert_eq!(
+            verifier
+                .verify_for_tenant(&project_token, Some("tenant-1"))
+                .unwrap_err(),
+            VerifyError::WrongTenant
+        );
+    }
+
+    #[test]
+    fn test_verify_missing_kid() {
+        let token = create_token(None, &get_test_claims("aud", "iss"));
+        let result = get_signing_verifier().verify(&token);
+        assert_eq!(result.unwrap_err(), VerifyError::MissingKid);
+    }
+
+    #[test]
+    fn test_verify_unknown_kid() {
+        let token = create_token(Some("kid-unknown"), &get_test_claims("aud", "iss"));
+        let result = get_signing_verifier().verify(&token);
+        assert_eq!(
+            result.unwrap_err(),
+            VerifyError::UnknownKid("kid-unknown".to_string())
+        );
+    }
+
+    #[test]
+    fn test_verify_expired() {
+        let mut claims = get_test_claims("aud", "iss");
+        claims.exp = now() - 3600;
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let result = get_signing_verifier().verify(&token);
+        assert_eq!(result.unwrap_err(), VerifyError::Expired);
+    }
+
+    #[test]
+    fn test_verify_wrong_audience() {
+        let token = create_token(Some(SIGNING_KID), &get_test_claims("other", "iss"));
+        let result = get_signing_verifier().verify(&token);
+        assert_eq!(result.unwrap_err(), VerifyError::WrongAudience);
+    }
+
+    #[test]
+    fn test_verify_multiple_audiences() {
+        let mut config = JwkConfig::new("aud".to_string(), "iss".to_string());
+        config.audiences.push("other-aud".to_string());
+        let verifier = JwkVerifier::with_config(vec![get_signing_key()], config.clone());
+        let emulator = JwkVerifier::emulator_with_config(config);
+        for aud in ["aud", "other-aud"] {
+            let claims = get_test_claims(aud, "iss");
+            let token = create_token(Some(SIGNING_KID), &claims);
+            assert!(verifier.verify(&token).is_ok());
+            assert!(emulator.verify(&create_unsigned_token(&claims)).is_ok());
+        }
+        let token = create_token(Some(SIGNING_KID), &get_test_claims("other", "iss"));
+        assert_eq!(
+            verifier.verify(&token).unwrap_err(),
+            VerifyError::WrongAudience
+        );
+    }
+
+    #[test]
+    fn test_verify_wrong_issuer() {
+        let token = create_token(Some(SIGNING_KID), &get_test_claims("aud", "other"));
+        let result = get_signing_verifier().verify(&token);
+        assert_eq!(result.unwrap_err(), VerifyError::WrongIssuer);
+    }
+
+    #[test]
+    fn test_verify_invalid_signature() {
+        let token = create_token(Some(SIGNING_KID), &get_test_claims("aud", "iss"));
+        let mut key = get_signing_key();
+        key.params = get_test_keys()[0].params.clone();
+        let verifier = JwkVerifier::new(vec![key], "aud".to_string(), "iss".to_string());
+        assert_eq!(
+            verifier.verify(&token).unwrap_err(),
+            VerifyError::InvalidSignature
+        );
+    }
+
+    #[test]
+    fn test_verify_emulator() {
+        let verifier =
+            JwkVerifier::emulator_with_config(JwkConfig::new("aud".to_string(), "iss".to_string()));
+        let claims = get_test_claims("aud", "iss");
+        let token = create_unsigned_token(&claims);
+        let token_data = verifier.verify(&token).unwrap();
+        assert_eq!(token_data.claims, claims);
+        assert_eq!(token_data.header.alg, None);
+    }
+
+    #[test]
+    fn test_verify_emulator_validates_claims() {
+        let verifier =
+            JwkVerifier::emulator_with_config(JwkConfig::new("aud".to_string(), "iss".to_string()));
+        let mut claims = get_test_claims("aud", "iss");
+        claims.exp = now() - 3600;
+        assert_eq!(
+            verifier
+                .verify(&create_unsigned_token(&claims))
+                .unwrap_err(),
+            VerifyError::Expired
+        );
+        let claims = get_test_claims("other", "iss");
+        assert_eq!(
+            verifier
+                .verify(&create_unsigned_token(&claims))
+                .unwrap_err(),
+            VerifyError::WrongAudience
+        );
+        let claims = get_test_claims("aud", "other");
+        assert_eq!(
+            verifier
+                .verify(&create_unsigned_token(&claims))
+                .unwrap_err(),
+            VerifyError::WrongIssuer
+        );
+        assert_eq!(
+            verifier.verify("not-a-token").unwrap_err(),
+            VerifyError::Malformed
+        );
+    }
+
+    #[test]
+    fn test_verify_signed_token_rejects_unsigned() {
+        let token = create_unsigned_token(&get_test_claims("aud", "iss"));
+        assert!(get_signing_verifier().verify(&token).is_err());
+    }
+
+    #[test]
+    fn test_verify_with_leeway() {
+        let mut claims = get_test_claims("aud", "iss");
+        claims.exp = now() - 30;
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let mut config = JwkConfig::new("aud".to_string(), "iss".to_string());
+        config.leeway = Duration::from_secs(60);
+        let verifier = JwkVerifier::with_config(vec![get_signing_key()], config);
+        assert!(verifier.verify(&token).is_ok());
+    }
+
+    #[test]
+    fn test_verify_invalid_subject() {
+        for sub in ["".to_string(), "a".repeat(129)] {
+            let mut claims = get_test_claims("aud", "iss");
+            claims.sub = sub;
+            let token = create_token(Some(SIGNING_KID), &claims);
+            assert_eq!(
+                get_signing_verifier().verify(&token).unwrap_err(),
+                VerifyError::InvalidSubject
+            );
+        }
+    }
+
+    #[test]
+    fn test_verify_auth_time_in_future() {
+        let mut claims = get_test_claims("aud", "iss");
+        claims.auth_time = Some(now() + 60);
+        let token = create_token(Some(SIGNING_KID), &claims);
+        assert_eq!(
+            get_signing_verifier().verify(&token).unwrap_err(),
+            VerifyError::InvalidAuthTime
+        );
+    }
+
+    #[test]
+    fn test_verify_issued_in_future() {
+        let mut claims = get_test_claims("aud", "iss");
+        claims.iat = now() + 30;
+        let token = create_token(Some(SIGNING_KID), &claims);
+        assert_eq!(
+            get_signing_verifier().verify(&token).unwrap_err(),
+            VerifyError::NotYetValid
+        );
+
+        let mut config = JwkConfig::new("aud".to_string(), "iss".to_string());
+        config.leeway = Duration::from_secs(60);
+        let verifier = JwkVerifier::with_config(vec![get_signing_key()], config);
+        assert!(verifier.verify(&token).is_ok());
+    }
+
+    #[test]
+    fn test_verify_required_claims() {
+        let mut config = JwkConfig::new("aud".to_string(), "iss".to_string());
+        config.policy = ClaimsPolicy::new()
+            .require_claim("email".to_string())
+            .require_claim("role".to_string());
+        let verifier = JwkVerifier::with_config(vec![get_signing_key()], config);
+
+        let mut claims = get_test_claims("aud", "iss");
+        claims.email = Some("user@example.com".to_string());
+        let token = create_token(Some(SIGNING_KID), &claims);
+        assert_eq!(
+            verifier.verify(&token).unwrap_err(),
+            VerifyError::MissingClaim("role".to_string())
+        );
+
+        claims
+            .custom
+            .insert("role".to_string(), Value::String("admin".to_string()));
+        let token = create_token(Some(SIGNING_KID), &claims);
+        assert!(verifier.verify(&token).is_ok());
+    }
+
+    #[test]
+    fn test_claims_policy() {
+        let policy = ClaimsPolicy::new()
+            .require_email_verified()
+            .allow_sign_in_provider("password".to_string());
+        let mut claims = get_test_claims("aud", "iss");
+        assert_eq!(policy.check(&claims), Err(VerifyError::EmailNotVerified));
+
+        claims.email_verified = Some(true);
+        assert_eq!(
+            policy.check(&claims),
+            Err(VerifyError::SignInProviderNotAllowed(String::new()))
+        );
+
+        claims.firebase = Some(FirebaseClaims {
+            sign_in_provider: "anonymous".to_string(),
+            ..FirebaseClaims::default()
+        });
+        assert_eq!(
+            policy.check(&claims),
+            Err(VerifyError::SignInProviderNotAllowed(
+                "anonymous".to_string()
+            ))
+        );
+
+        claims.firebase = Some(FirebaseClaims {
+            sign_in_provider: "password".to_string(),
+            ..FirebaseClaims::default()
+        });
+        assert_eq!(policy.check(&claims), Ok(()));
+    }
+
+    #[test]
+    fn test_claims_policy_require_mfa() {
+        let policy = ClaimsPolicy::new().require_mfa();
+        let mut claims = get_test_claims("aud", "iss");
+        assert!(!claims.is_multi_factor());
+        assert_eq!(policy.check(&claims), Err(VerifyError::MultiFactorRequired));
+
+        claims.firebase = Some(FirebaseClaims {
+            sign_in_provider: "password".to_string(),
+            sign_in_second_factor: Some("totp".to_string()),
+            ..FirebaseClaims::default()
+        });
+        assert!(claims.is_multi_factor());
+        assert_eq!(policy.check(&claims), Ok(()));
+
+        let mut oidc_claims = get_test_claims("aud", "iss");
+        oidc_claims
+            .custom
+            .insert("amr".to_string(), json!(["pwd", "otp"]));
+        assert!(!oidc_claims.is_multi_factor());
+        oidc_claims
+            .custom
+            .insert("amr".to_string(), json!(["pwd", "mfa"]));
+        assert_eq!(policy.check(&oidc_claims), Ok(()));
+    }
+
+    #[test]
+    fn test_require_claim() {
+        let mut claims = get_test_claims("aud", "iss");
+        claims.custom.insert("admin".to_string(), json!(false));
+        claims
+            .custom
+            .insert("roles".to_string(), json!(["editor", "viewer"]));
+        claims
+            .custom
+            .insert("org".to_string(), json!({ "id": "org-1" }));
+        claims.firebase = Some(FirebaseClaims {
+            sign_in_provider: "password".to_string(),
+            ..FirebaseClaims::default()
+        });
+        assert!(!RequireClaim::present("admin").check(&claims));
+        assert!(!RequireClaim::present("missing").check(&claims));
+        assert!(RequireClaim::role("editor").check(&claims));
+        assert!(!RequireClaim::role("admin").check(&claims));
+        assert!(RequireClaim::equals("org.id", "org-1").check(&claims));
+        assert!(RequireClaim::equals("firebase.sign_in_provider", "password").check(&claims));
+
+        claims.custom.insert("admin".to_string(), json!(true));
+        claims.custom.insert("roles".to_string(), json!("admin"));
+        assert!(RequireClaim::present("admin").check(&claims));
+        assert!(RequireClaim::role("admin").check(&claims));
+    }
+
+    #[test]
+    fn test_decode_unverified() {
+        let mut claims = get_test_claims("pj", "https://securetoken.google.com/pj");
+        claims.exp = 0;
+        let token = create_token(Some("unknown-kid"), &claims);
+        let (header, decoded) = decode_unverified(&token).unwrap();
+        assert_eq!(header.kid.as_deref(), Some("unknown-kid"));
+        assert_eq!(header.alg, Some(Algorithm::RS256));
+        assert_eq!(decoded, claims);
+
+        assert_eq!(
+            decode_unverified("invalid").unwrap_err(),
+            VerifyError::Malformed
+        );
+        let header = token.split('.').next().unwrap();
+        assert_eq!(
+            decode_unverified(&format!("{}.e30.", header)).unwrap_err(),
+            VerifyError::Malformed
+        );
+    }
+
+    #[test]
+    fn test_decode_unverified_unsigned_token() {
+        let claims = get_test_claims("pj", "https://securetoken.google.com/pj");
+        let (header, decoded) = decode_unverified(&create_unsigned_token(&claims)).unwrap();
+        assert_eq!(header.alg, None);
+        assert_eq!(header.typ.as_deref(), Some("JWT"));
+        assert_eq!(decoded, claims);
+    }
 }
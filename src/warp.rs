@@ -0,0 +1,308 @@
+use crate::integration::{FirebaseAuthRejection, RejectionResponse};
+use crate::jwk_auth::{Claims, JwkAuth, RequireClaim};
+use crate::token::TokenExtractor;
+use futures_util::future::{ready, Ready};
+use std::borrow::Cow;
+use std::convert::Infallible;
+use std::sync::Arc;
+use warp::http::header::WWW_AUTHENTICATE;
+use warp::http::{HeaderMap, HeaderValue, StatusCode};
+use warp::reject::{Reject, Rejection};
+use warp::reply::Response;
+use warp::{Filter, Reply};
+
+impl Reject for FirebaseAuthRejection {}
+
+/// The token found where `extractor` looks, if any.
+fn token(
+    extractor: TokenExtractor,
+) -> impl Filter<Extract = (Option<String>,), Error = Infallible> + Clone {
+    let extractor = Arc::new(extractor);
+    warp::header::headers_cloned()
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .map(move |headers: HeaderMap, query: String| {
+            let query = Some(query.as_str()).filter(|query| !query.is_empty());
+            extractor
+                .extract(
+                    |name| headers.get(name).and_then(|value| value.to_str().ok()),
+                    query,
+                )
+                .map(Cow::into_owned)
+        })
+}
+
+async fn authenticate(
+    jwk_auth: &JwkAuth,
+    token: Option<&str>,
+) -> Result<Claims, FirebaseAuthRejection> {
+    let token = token.ok_or(FirebaseAuthRejection::MissingToken)?;
+    jwk_auth
+        .verify_async(token)
+        .await
+        .map(|token_data| token_data.claims)
+        .map_err(FirebaseAuthRejection::InvalidToken)
+}
+
+/// Extracts the claims of the bearer token, rejecting with a
+/// [`FirebaseAuthRejection`] when it is missing or invalid.
+pub fn firebase_auth(
+    jwk_auth: Arc<JwkAuth>,
+) -> impl Filter<Extract = (Claims,), Error = Rejection> + Clone {
+    firebase_auth_with(jwk_auth, TokenExtractor::default())
+}
+
+/// Like [`firebase_auth`], looking for the token where `extractor` does.
+pub fn firebase_auth_with(
+    jwk_auth: Arc<JwkAuth>,
+    extractor: TokenExtractor,
+) -> impl Filter<Extract = (Claims,), Error = Rejection> + Clone {
+    token(extractor).and_then(move |token: Option<String>| {
+        let jwk_auth = Arc::clone(&jwk_auth);
+        async move {
+            authenticate(&jwk_auth, token.as_deref())
+                .await
+                .map_err(warp::reject::custom)
+        }
+    })
+}
+
+/// Like [`firebase_auth`], and also rejects with
+/// [`FirebaseAuthRejection::Forbidden`] when the claims do not meet
+/// `requirement`.
+pub fn require_claim(
+    jwk_auth: Arc<JwkAuth>,
+    requirement: RequireClaim,
+) -> impl Filter<Extract = (Claims,), Error = Rejection> + Clone {
+    require_claim_with(jwk_auth, requirement, TokenExtractor::default())
+}
+
+/// Like [`require_claim`], looking for the token where `extractor` does.
+pub fn require_claim_with(
+    jwk_auth: Arc<JwkAuth>,
+    requirement: RequireClaim,
+    extractor: TokenExtractor,
+) -> impl Filter<Extract = (Claims,), Error = Rejection> + Clone {
+    let requirement = Arc::new(requirement);
+    token(extractor).and_then(move |token: Option<String>| {
+        let jwk_auth = Arc::clone(&jwk_auth);
+        let requirement = Arc::clone(&requirement);
+        async move {
+            let claims = authenticate(&jwk_auth, token.as_deref())
+                .await
+                .map_err(warp::reject::custom)?;
+            if requirement.check(&claims) {
+                Ok(claims)
+            } else {
+                Err(warp::reject::custom(FirebaseAuthRejection::Forbidden))
+            }
+        }
+    })
+}
+
+/// Like [`firebase_auth`], but extracts `None` instead of rejecting requests
+/// without a bearer token. Requests with an invalid token are still rejected.
+pub fn optional_firebase_auth(
+    jwk_auth: Arc<JwkAuth>,
+) -> impl Filter<Extract = (Option<Claims>,), Error = Rejection> + Clone {
+    optional_firebase_auth_with(jwk_auth, TokenExtractor::default())
+}
+
+/// Like [`optional_firebase_auth`], looking for the token where `extractor`
+/// does.
+pub fn optional_firebase_auth_with(
+    jwk_auth: Arc<JwkAuth>,
+    extractor: TokenExtractor,
+) -> impl Filter<Extract = (Option<Claims>,), Error = Rejection> + Clone {
+    token(extractor).and_then(move |token: Option<String>| {
+        let jwk_auth = Arc::clone(&jwk_auth);
+        async move {
+            match authenticate(&jwk_auth, token.as_deref()).await {
+                Ok(claims) => Ok(Some(claims)),
+                Err(FirebaseAuthRejection::MissingToken) => Ok(None),
+                Err(rejection) => Err(warp::reject::custom(rejection)),
+            }
+        }
+    })
+}
+
+/// Replies `401 Unauthorized`, or `403 Forbidden` to
+/// [`FirebaseAuthRejection::Forbidden`], with a `WWW-Authenticate` challenge
+/// and a JSON error body to a [`FirebaseAuthRejection`] and passes on any
+/// other rejection.
+pub async fn handle_rejection(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    reply(rejection, &RejectionResponse::default())
+}
+
+/// Like [`handle_rejection`], answering as `config` says, e.g.
+/// `.recover(rejection_handler(RejectionResponse::new().realm(..)))`.
+pub fn rejection_handler(
+    config: RejectionResponse,
+) -> impl Fn(Rejection) -> Ready<Result<Response, Rejection>> + Clone {
+    let config = Arc::new(config);
+    move |rejection| ready(reply(rejection, &config))
+}
+
+fn reply(rejection: Rejection, config: &RejectionResponse) -> Result<Response, Rejection> {
+    let e = match rejection.find::<FirebaseAuthRejection>() {
+        Some(e) => e,
+        None => return Err(rejection),
+    };
+    let status = StatusCode::from_u16(config.status_code(e)).unwrap_or(StatusCode::UNAUTHORIZED);
+    let mut response = match config.body(e) {
+        Some(body) => warp::reply::with_status(warp::reply::json(&body), status).into_response(),
+        None => status.into_response(),
+    };
+    if let Some(challenge) = config
+        .www_authenticate(e)
+        .and_then(|challenge| HeaderValue::from_str(&challenge).ok())
+    {
+        response.headers_mut().insert(WWW_AUTHENTICATE, challenge);
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+
+    async fn get_filter() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        let jwk_auth = get_session_cookie_jwk_auth().await;
+        warp::path("uid")
+            .and(firebase_auth(Arc::new(jwk_auth)))
+            .map(|claims: Claims| claims.sub)
+            .recover(handle_rejection)
+    }
+
+    #[tokio::test]
+    async fn test_firebase_auth() {
+        let claims = get_test_claims("pj", "https://session.firebase.google.com/pj");
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let response = warp::test::request()
+            .path("/uid")
+            .header("authorization", format!("Bearer {}", token))
+            .reply(&get_filter().await)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), "uid");
+    }
+
+    #[tokio::test]
+    async fn test_firebase_auth_rejected() {
+        let filter = get_filter().await;
+        let response = warp::test::request().path("/uid").reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = warp::test::request()
+            .path("/uid")
+            .header("authorization", "Bearer invalid")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_optional_firebase_auth() {
+        let jwk_auth = get_session_cookie_jwk_auth().await;
+        let filter = warp::path("uid")
+            .and(optional_firebase_auth(Arc::new(jwk_auth)))
+            .map(|claims: Option<Claims>| {
+                claims.map_or_else(|| "anonymous".to_string(), |claims| claims.sub)
+            })
+            .recover(handle_rejection);
+        let claims = get_test_claims("pj", "https://session.firebase.google.com/pj");
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let response = warp::test::request()
+            .path("/uid")
+            .header("authorization", format!("Bearer {}", token))
+            .reply(&filter)
+            .await;
+        assert_eq!(response.body(), "uid");
+        let response = warp::test::request().path("/uid").reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), "anonymous");
+        let response = warp::test::request()
+            .path("/uid")
+            .header("authorization", "Bearer invalid")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_require_claim() {
+        let jwk_auth = get_session_cookie_jwk_auth().await;
+        let filter = warp::path("admin")
+            .and(require_claim(
+                Arc::new(jwk_auth),
+                RequireClaim::role("admin"),
+            ))
+            .map(|claims: Claims| claims.sub)
+            .recover(handle_rejection);
+        let claims = get_test_claims("pj", "https://session.firebase.google.com/pj");
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let response = warp::test::request()
+            .path("/admin")
+            .header("authorization", format!("Bearer {}", token))
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = warp::test::request().path("/admin").reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[WWW_AUTHENTICATE], "Bearer");
+    }
+
+    #[tokio::test]
+    async fn test_rejection_handler() {
+        let jwk_auth = get_session_cookie_jwk_auth().await;
+        let config = RejectionResponse::new()
+            .realm("api".to_string())
+            .describe_errors(true)
+            .json_body(false);
+        let filter = warp::path("uid")
+            .and(firebase_auth(Arc::new(jwk_auth)))
+            .map(|claims: Claims| claims.sub)
+            .recover(rejection_handler(config));
+        let response = warp::test::request()
+            .path("/uid")
+            .header("authorization", "Bearer invalid")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers()[WWW_AUTHENTICATE],
+            "Bearer realm=\"api\", error=\"invalid_token\", error_description=\"malformed token\""
+        );
+        assert!(response.body().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_firebase_auth_with() {
+        let jwk_auth = get_session_cookie_jwk_auth().await;
+        let extractor = TokenExtractor::new()
+            .cookie("__session".to_string())
+            .query("token".to_string());
+        let filter = warp::path("uid")
+            .and(firebase_auth_with(Arc::new(jwk_auth), extractor))
+            .map(|claims: Claims| claims.sub)
+            .recover(handle_rejection);
+        let claims = get_test_claims("pj", "https://session.firebase.google.com/pj");
+        let token = create_token(Some(SIGNING_KID), &claims);
+        let response = warp::test::request()
+            .path("/uid")
+            .header("cookie", format!("__session={}", token))
+            .reply(&filter)
+            .await;
+        assert_eq!(response.body(), "uid");
+        let response = warp::test::request()
+            .path(&format!("/uid?token={}", token))
+            .reply(&filter)
+            .await;
+        assert_eq!(response.body(), "uid");
+        let response = warp::test::request()
+            .path("/uid")
+            .header("authorization", format!("Bearer {}", token))
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
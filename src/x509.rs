@@ -0,0 +1,68 @@
+use crate::jwk::{Jwk, JwkParams};
+use thiserror::Error;
+use x509_parser::pem::parse_x509_pem;
+use x509_parser::public_key::PublicKey;
+
+#[derive(Debug, PartialEq, Error)]
+pub enum CertParseError {
+    #[error("invalid PEM")]
+    InvalidPem,
+    #[error("invalid x509 certificate")]
+    InvalidCertificate,
+    #[error("unsupported public key type")]
+    UnsupportedKeyType,
+}
+
+fn trim_leading_zeros(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+fn encode_component(bytes: &[u8]) -> String {
+    base64::encode_config(trim_leading_zeros(bytes), base64::URL_SAFE_NO_PAD)
+}
+
+pub fn cert_to_jwk(kid: &str, cert: &str) -> Result<Jwk, CertParseError> {
+    let (_, pem) = parse_x509_pem(cert.as_bytes()).map_err(|_| CertParseError::InvalidPem)?;
+    let x509 = pem
+        .parse_x509()
+        .map_err(|_| CertParseError::InvalidCertificate)?;
+    match x509.public_key().parsed() {
+        Ok(PublicKey::RSA(key)) => Ok(Jwk {
+            alg: Some("RS256".to_string()),
+            kid: kid.to_string(),
+            r#use: Some("sig".to_string()),
+            params: JwkParams::Rsa {
+                n: encode_component(key.modulus),
+                e: encode_component(key.exponent),
+            },
+        }),
+        _ => Err(CertParseError::UnsupportedKeyType),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+
+    #[test]
+    fn test_cert_to_jwk() {
+        let jwk = cert_to_jwk(SIGNING_KID, CERT_PEM).unwrap();
+        assert_eq!(jwk, get_signing_key());
+    }
+
+    #[test]
+    fn test_cert_to_jwk_invalid_pem() {
+        assert_eq!(
+            cert_to_jwk(SIGNING_KID, "not a cert"),
+            Err(CertParseError::InvalidPem)
+        );
+    }
+
+    #[test]
+    fn test_trim_leading_zeros() {
+        assert_eq!(trim_leading_zeros(&[0, 0, 1, 0]), &[1, 0]);
+        assert_eq!(trim_leading_zeros(&[1, 2]), &[1, 2]);
+    }
+}